@@ -552,6 +552,274 @@ async fn subscribe_1000() {
     ).await;
 }
 
+/// Connect with the server and create monitored items across two subscriptions in one batch
+#[tokio::test]
+async fn subscribe_batched() {
+    let port = next_port();
+    let client_endpoint = endpoint_none(port);
+    let identity_token = IdentityToken::Anonymous;
+
+    connect_with_client_test(
+        port,
+        move |_rx_client_command: mpsc::UnboundedReceiver<ClientCommand>, mut client: Client| async move {
+            let (session, event_loop) = client
+                .new_session_from_endpoint(client_endpoint, identity_token)
+                .await
+                .unwrap();
+
+            let handle = event_loop.spawn();
+            session.wait_for_connection().await;
+
+            let mut subscription_ids = Vec::new();
+            for _ in 0..2 {
+                let subscription_id = session
+                    .create_subscription(
+                        std::time::Duration::from_secs(2),
+                        100,
+                        100,
+                        0,
+                        0,
+                        true,
+                        DataChangeCallback::new(|_, _| {}),
+                    )
+                    .await
+                    .unwrap();
+                subscription_ids.push(subscription_id);
+            }
+
+            let items = |range: std::ops::RangeInclusive<usize>| {
+                range
+                    .map(|i| MonitoredItemCreateRequest {
+                        item_to_monitor: stress_node_id(i).into(),
+                        monitoring_mode: MonitoringMode::Reporting,
+                        requested_parameters: MonitoringParameters {
+                            client_handle: i as u32,
+                            sampling_interval: 1000.0f64,
+                            filter: ExtensionObject::null(),
+                            queue_size: 1,
+                            discard_oldest: true,
+                        },
+                    })
+                    .collect::<Vec<_>>()
+            };
+
+            // The second batch ends with a var that does not exist, and the last batch is for a
+            // subscription that does not exist
+            let results = session
+                .create_monitored_items_batched(
+                    TimestampsToReturn::Both,
+                    vec![
+                        (subscription_ids[0], items(1..=10)),
+                        (subscription_ids[1], items(991..=1000)),
+                        (999, items(11..=12)),
+                    ],
+                )
+                .await;
+            assert_eq!(results.len(), 3);
+
+            let (subscription_id, result) = &results[0];
+            assert_eq!(*subscription_id, subscription_ids[0]);
+            let result = result.as_ref().unwrap();
+            assert_eq!(result.len(), 10);
+            assert!(result.iter().all(|r| r.status_code.is_good()));
+
+            let (subscription_id, result) = &results[1];
+            assert_eq!(*subscription_id, subscription_ids[1]);
+            let result = result.as_ref().unwrap();
+            assert_eq!(result.len(), 10);
+            assert!(result[..9].iter().all(|r| r.status_code.is_good()));
+            assert!(result[9].status_code.is_bad());
+
+            assert_eq!(results[2], (999, Err(StatusCode::BadInvalidArgument)));
+
+            // Only the items that were created are tracked by the session
+            {
+                let subscription_state = session.subscription_state.lock();
+                assert_eq!(
+                    subscription_state
+                        .monitored_item_ids(subscription_ids[0])
+                        .unwrap()
+                        .len(),
+                    10
+                );
+                assert_eq!(
+                    subscription_state
+                        .monitored_item_ids(subscription_ids[1])
+                        .unwrap()
+                        .len(),
+                    9
+                );
+            }
+
+            session.disconnect().await.unwrap();
+            handle.await.unwrap();
+        },
+        false
+    ).await;
+}
+
+/// Connect with the server and create subscriptions together with their monitored items, one of
+/// which is rolled back because an item cannot be created
+#[tokio::test]
+async fn subscribe_with_items() {
+    let port = next_port();
+    let client_endpoint = endpoint_none(port);
+    let identity_token = IdentityToken::Anonymous;
+
+    connect_with_client_test(
+        port,
+        move |_rx_client_command: mpsc::UnboundedReceiver<ClientCommand>, mut client: Client| async move {
+            let (session, event_loop) = client
+                .new_session_from_endpoint(client_endpoint, identity_token)
+                .await
+                .unwrap();
+
+            let handle = event_loop.spawn();
+            session.wait_for_connection().await;
+
+            let items = |range: std::ops::RangeInclusive<usize>| {
+                range
+                    .map(|i| MonitoredItemCreateRequest {
+                        item_to_monitor: stress_node_id(i).into(),
+                        monitoring_mode: MonitoringMode::Reporting,
+                        requested_parameters: MonitoringParameters {
+                            client_handle: i as u32,
+                            sampling_interval: 1000.0f64,
+                            filter: ExtensionObject::null(),
+                            queue_size: 1,
+                            discard_oldest: true,
+                        },
+                    })
+                    .collect::<Vec<_>>()
+            };
+            let create = |items_to_create| {
+                session.create_subscription_with_items(
+                    std::time::Duration::from_secs(2),
+                    100,
+                    100,
+                    0,
+                    0,
+                    true,
+                    DataChangeCallback::new(|_, _| {}),
+                    TimestampsToReturn::Both,
+                    items_to_create,
+                )
+            };
+
+            // Every item exists, so the subscription and its items are created
+            let (subscription_id, results) = create(items(1..=10)).await.unwrap();
+            assert_eq!(results.len(), 10);
+            assert!(results.iter().all(|r| r.status_code.is_good()));
+
+            // The last var does not exist, so the subscription is deleted again
+            let status_code = create(items(991..=1000)).await.unwrap_err();
+            assert_eq!(status_code, StatusCode::BadNodeIdUnknown);
+
+            // Only the first subscription and its items are tracked by the session, with the
+            // parameters the server granted
+            {
+                let subscription_state = session.subscription_state.lock();
+                assert_eq!(subscription_state.subscription_ids(), Some(vec![subscription_id]));
+                let revised_parameters = subscription_state.revised_parameters(subscription_id).unwrap();
+                assert_eq!(revised_parameters.publishing_interval, std::time::Duration::from_secs(2));
+                assert_eq!(revised_parameters.max_keep_alive_count, 100);
+                let mut client_handles = subscription_state
+                    .get(subscription_id)
+                    .unwrap()
+                    .monitored_items()
+                    .values()
+                    .map(|item| item.client_handle())
+                    .collect::<Vec<u32>>();
+                client_handles.sort_unstable();
+                assert_eq!(client_handles, (1..=10).collect::<Vec<u32>>());
+            }
+
+            session.disconnect().await.unwrap();
+            handle.await.unwrap();
+        },
+        false
+    ).await;
+}
+
+#[tokio::test]
+async fn import_blueprints() {
+    let port = next_port();
+    let client_endpoint = endpoint_none(port);
+    let identity_token = IdentityToken::Anonymous;
+
+    connect_with_client_test(
+        port,
+        move |_rx_client_command: mpsc::UnboundedReceiver<ClientCommand>, mut client: Client| async move {
+            let (session, event_loop) = client
+                .new_session_from_endpoint(client_endpoint, identity_token)
+                .await
+                .unwrap();
+
+            let handle = event_loop.spawn();
+            session.wait_for_connection().await;
+
+            let items_to_create = (1..=4)
+                .map(|i| MonitoredItemCreateRequest {
+                    item_to_monitor: stress_node_id(i).into(),
+                    monitoring_mode: MonitoringMode::Reporting,
+                    requested_parameters: MonitoringParameters {
+                        client_handle: i as u32,
+                        sampling_interval: 1000.0f64,
+                        filter: ExtensionObject::null(),
+                        queue_size: 1,
+                        discard_oldest: true,
+                    },
+                })
+                .collect::<Vec<_>>();
+            let (subscription_id, results) = session
+                .create_subscription_with_items(
+                    std::time::Duration::from_secs(2),
+                    100,
+                    100,
+                    0,
+                    0,
+                    true,
+                    DataChangeCallback::new(|_, _| {}),
+                    TimestampsToReturn::Both,
+                    items_to_create,
+                )
+                .await
+                .unwrap();
+            let _ = session
+                .set_triggering(
+                    subscription_id,
+                    results[0].monitored_item_id,
+                    &[results[1].monitored_item_id, results[2].monitored_item_id],
+                    &[],
+                )
+                .await
+                .unwrap();
+
+            let blueprints = session.export_blueprints();
+            assert_eq!(blueprints.len(), 1);
+            assert_eq!(blueprints[0].monitored_items.len(), 4);
+            assert_eq!(blueprints[0].monitored_items[0].triggered_items, vec![2, 3]);
+            session.delete_subscription(subscription_id).await.unwrap();
+            assert!(session.export_blueprints().is_empty());
+
+            // The subscription, its items and triggering links are recreated on the server
+            let results = session
+                .import_blueprints(&blueprints, |_| Box::new(DataChangeCallback::new(|_, _| {})))
+                .await;
+            assert_eq!(results.len(), 1);
+            let new_subscription_id = results[0].unwrap();
+            let imported = session.export_blueprints();
+            assert_eq!(imported.len(), 1);
+            assert_eq!(imported[0].subscription_id, new_subscription_id);
+            assert_eq!(imported[0].monitored_items, blueprints[0].monitored_items);
+
+            session.disconnect().await.unwrap();
+            handle.await.unwrap();
+        },
+        false
+    ).await;
+}
+
 #[tokio::test]
 async fn method_call() {
     // Call a method on the server, one exercising some parameters in and out
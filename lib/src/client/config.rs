@@ -23,6 +23,7 @@ use super::retry::SessionRetryPolicy;
 pub const ANONYMOUS_USER_TOKEN_ID: &str = "ANONYMOUS";
 
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct ClientUserToken {
     /// Username
     pub user: String,
@@ -68,38 +69,40 @@ impl ClientUserToken {
     /// Test if the token, i.e. that it has a name, and either a password OR a cert path and key path.
     /// The paths are not validated.
     pub fn is_valid(&self) -> bool {
-        let mut valid = true;
+        self.validate().is_empty()
+    }
+
+    /// Returns the problems with the token, or an empty list if it is valid.
+    pub fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
         if self.user.is_empty() {
-            error!("User token has an empty name.");
-            valid = false;
+            errors.push("User token has an empty name.".to_string());
         }
         // A token must properly represent one kind of token or it is not valid
         if self.password.is_some() {
             if self.cert_path.is_some() || self.private_key_path.is_some() {
-                error!(
+                errors.push(format!(
                     "User token {} holds a password and certificate info - it cannot be both.",
                     self.user
-                );
-                valid = false;
+                ));
             }
         } else {
             if self.cert_path.is_none() && self.private_key_path.is_none() {
-                error!(
+                errors.push(format!(
                     "User token {} fails to provide a password or certificate info.",
                     self.user
-                );
-                valid = false;
+                ));
             } else if self.cert_path.is_none() || self.private_key_path.is_none() {
-                error!("User token {} fails to provide both a certificate path and a private key path.", self.user);
-                valid = false;
+                errors.push(format!("User token {} fails to provide both a certificate path and a private key path.", self.user));
             }
         }
-        valid
+        errors
     }
 }
 
 /// Describes an endpoint, it's url security policy, mode and user token
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct ClientEndpoint {
     /// Endpoint path
     pub url: String,
@@ -137,6 +140,7 @@ impl ClientEndpoint {
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct DecodingOptions {
     /// Maximum size of a message chunk in bytes. 0 means no limit
     pub(crate) max_message_size: usize,
@@ -155,6 +159,7 @@ pub struct DecodingOptions {
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct Performance {
     /// Ignore clock skew allows the client to make a successful connection to the server, even
     /// when the client and server clocks are out of sync.
@@ -167,6 +172,7 @@ pub struct Performance {
 
 /// Client OPC UA configuration
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct ClientConfig {
     /// Name of the application that the client presents itself as to the server
     pub(crate) application_name: String,
@@ -231,50 +237,42 @@ pub struct ClientConfig {
 }
 
 impl Config for ClientConfig {
-    /// Test if the config is valid, which requires at the least that
-    fn is_valid(&self) -> bool {
-        let mut valid = true;
+    /// Returns the problems with the config, or an empty list if it is valid.
+    fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
 
         if self.application_name.is_empty() {
-            error!("Application name is empty");
-            valid = false;
+            errors.push("Application name is empty".to_string());
         }
         if self.application_uri.is_empty() {
-            error!("Application uri is empty");
-            valid = false;
+            errors.push("Application uri is empty".to_string());
         }
         if self.user_tokens.contains_key(ANONYMOUS_USER_TOKEN_ID) {
-            error!(
+            errors.push(format!(
                 "User tokens contains the reserved \"{}\" id",
                 ANONYMOUS_USER_TOKEN_ID
-            );
-            valid = false;
+            ));
         }
         if self.user_tokens.contains_key("") {
-            error!("User tokens contains an endpoint with an empty id");
-            valid = false;
+            errors.push("User tokens contains an endpoint with an empty id".to_string());
         }
         self.user_tokens.iter().for_each(|(_, token)| {
-            if !token.is_valid() {
-                valid = false;
-            }
+            errors.append(&mut token.validate());
         });
         if self.endpoints.is_empty() {
             warn!("Endpoint config contains no endpoints");
         } else {
             // Check for invalid ids in endpoints
             if self.endpoints.contains_key("") {
-                error!("Endpoints contains an endpoint with an empty id");
-                valid = false;
+                errors.push("Endpoints contains an endpoint with an empty id".to_string());
             }
             if !self.default_endpoint.is_empty()
                 && !self.endpoints.contains_key(&self.default_endpoint)
             {
-                error!(
+                errors.push(format!(
                     "Default endpoint id {} does not exist in list of endpoints",
                     self.default_endpoint
-                );
-                valid = false;
+                ));
             }
             // Check for invalid security policy and modes in endpoints
             self.endpoints.iter().for_each(|(id, e)| {
@@ -283,26 +281,23 @@ impl Config for ClientConfig {
                     if MessageSecurityMode::Invalid
                         == MessageSecurityMode::from(e.security_mode.as_ref())
                     {
-                        error!(
+                        errors.push(format!(
                             "Endpoint {} security mode {} is invalid",
                             id, e.security_mode
-                        );
-                        valid = false;
+                        ));
                     }
                 } else {
-                    error!(
+                    errors.push(format!(
                         "Endpoint {} security policy {} is invalid",
                         id, e.security_policy
-                    );
-                    valid = false;
+                    ));
                 }
             });
         }
         if self.session_retry_limit < 0 && self.session_retry_limit != -1 {
-            error!("Session retry limit of {} is invalid - must be -1 (infinite), 0 (never) or a positive value", self.session_retry_limit);
-            valid = false;
+            errors.push(format!("Session retry limit of {} is invalid - must be -1 (infinite), 0 (never) or a positive value", self.session_retry_limit));
         }
-        valid
+        errors
     }
 
     fn application_name(&self) -> UAString {
@@ -384,7 +379,7 @@ mod tests {
     use std::{self, collections::BTreeMap, path::PathBuf};
 
     use crate::client::ClientBuilder;
-    use crate::core::config::Config;
+    use crate::core::config::{Config, ConfigError};
     use crate::crypto::SecurityPolicy;
     use crate::types::*;
 
@@ -483,13 +478,29 @@ mod tests {
         let saved = config.save(&path);
         println!("Saved = {:?}", saved);
         assert!(config.save(&path).is_ok());
-        if let Ok(config2) = ClientConfig::load(&path) {
+        if let Ok(config2) = ClientConfig::try_load(&path) {
             assert_eq!(config, config2);
         } else {
             panic!("Cannot load config from file");
         }
     }
 
+    #[test]
+    fn client_config_unknown_field() {
+        let path = make_test_file("client_config_unknown_field.yaml");
+        let config = default_sample_config();
+        let yaml = serde_yaml::to_string(&config)
+            .unwrap()
+            .replace("security_mode:", "security_mdoe:");
+        std::fs::write(&path, yaml).unwrap();
+        let err = ClientConfig::try_load::<ClientConfig>(&path).unwrap_err();
+        assert!(matches!(err, ConfigError::Parse(_)));
+        let message = err.to_string();
+        assert!(message.contains(": unknown field `security_mdoe`"));
+        assert!(message.contains("endpoints."));
+        assert!(message.contains(" at line "));
+    }
+
     #[test]
     fn client_invalid_security_policy_config() {
         let mut config = default_sample_config();
@@ -522,6 +533,10 @@ mod tests {
             },
         );
         assert!(!config.is_valid());
+        assert_eq!(
+            config.validate(),
+            vec!["Endpoint sample_none security mode SingAndEncrypt is invalid".to_string()]
+        );
     }
 
     #[test]
@@ -539,5 +554,13 @@ mod tests {
             },
         );
         assert!(!config.is_valid());
+        // Both the reserved id and the empty user name are reported
+        assert_eq!(
+            config.validate(),
+            vec![
+                "User tokens contains the reserved \"ANONYMOUS\" id".to_string(),
+                "User token has an empty name.".to_string(),
+            ]
+        );
     }
 }
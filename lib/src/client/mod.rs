@@ -121,9 +121,11 @@ use std::path::PathBuf;
 pub use builder::ClientBuilder;
 pub use config::{ClientConfig, ClientEndpoint, ClientUserToken, ANONYMOUS_USER_TOKEN_ID};
 pub use session::{
-    Client, DataChangeCallback, EventCallback, MonitoredItem, OnSubscriptionNotification, Session,
+    Client, DataChangeCallback, EventCallback, ItemDataChangeCallback, MonitoredItem,
+    MonitoredItemBlueprint, OnSubscriptionNotification, RevisedSubscriptionParams, Session,
     SessionActivity, SessionConnectMode, SessionEventLoop, SessionPollResult, Subscription,
-    SubscriptionCallbacks,
+    SubscriptionBlueprint, SubscriptionCallbacks, SubscriptionClock, SubscriptionIdGenerator,
+    SubscriptionStats,
 };
 pub use transport::AsyncSecureChannel;
 
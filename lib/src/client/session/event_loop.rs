@@ -131,6 +131,12 @@ impl SessionEventLoop {
                                         return Ok(None);
                                     }
 
+                                    // Subscriptions must be transferred or recreated on reconnect
+                                    {
+                                        let mut subscription_state = trace_lock!(slf.inner.subscription_state);
+                                        subscription_state.mark_all_for_recreation();
+                                    }
+
                                     Ok((
                                         SessionPollResult::ConnectionLost(code),
                                         SessionEventLoopState::Disconnected,
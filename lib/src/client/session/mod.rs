@@ -39,9 +39,11 @@ impl From<(EndpointDescription, IdentityToken)> for SessionInfo {
 pub use client::Client;
 pub use connect::SessionConnectMode;
 pub use event_loop::{SessionActivity, SessionEventLoop, SessionPollResult};
+pub use services::subscriptions::state::{SubscriptionClock, SubscriptionIdGenerator};
 pub use services::subscriptions::{
-    DataChangeCallback, EventCallback, MonitoredItem, OnSubscriptionNotification, Subscription,
-    SubscriptionCallbacks,
+    DataChangeCallback, EventCallback, ItemDataChangeCallback, MonitoredItem,
+    MonitoredItemBlueprint, OnSubscriptionNotification, RevisedSubscriptionParams, Subscription,
+    SubscriptionBlueprint, SubscriptionCallbacks, SubscriptionStats,
 };
 pub use session::Session;
 
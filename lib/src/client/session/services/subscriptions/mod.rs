@@ -8,11 +8,13 @@ use std::{
 };
 
 use crate::types::{
-    DataChangeNotification, DataValue, DecodingOptions, EventNotificationList, ExtensionObject,
-    Identifier, MonitoringMode, NotificationMessage, ObjectId, ReadValueId,
-    StatusChangeNotification, Variant,
+    DataChangeNotification, DataValue, DateTime, DecodingOptions, EventNotificationList,
+    ExtensionObject, Identifier, MonitoredItemCreateRequest, MonitoringMode, MonitoringParameters,
+    NotificationMessage, ObjectId, ReadValueId, StatusChangeNotification, StatusCode,
+    TimestampsToReturn, Variant,
 };
 
+#[derive(Clone)]
 pub(crate) struct CreateMonitoredItem {
     pub id: u32,
     pub client_handle: u32,
@@ -22,12 +24,89 @@ pub(crate) struct CreateMonitoredItem {
     pub discard_oldest: bool,
     pub sampling_interval: f64,
     pub filter: ExtensionObject,
+    pub timestamps_to_return: TimestampsToReturn,
 }
 
 pub(crate) struct ModifyMonitoredItem {
     pub id: u32,
     pub sampling_interval: f64,
     pub queue_size: u32,
+    pub filter: ExtensionObject,
+    pub timestamps_to_return: TimestampsToReturn,
+}
+
+/// The definition of a subscription and its monitored items, without the state that the server
+/// or its notifications give it, such as the last values received. Blueprints can be saved, e.g.
+/// to disk, and used to recreate the subscriptions on another session. They are also what a
+/// subscription that could not be transferred to a new session is recreated from.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SubscriptionBlueprint {
+    /// Id of the subscription on the session it was created on
+    pub subscription_id: u32,
+    pub publishing_interval: Duration,
+    pub lifetime_count: u32,
+    pub max_keep_alive_count: u32,
+    pub max_notifications_per_publish: u32,
+    pub publishing_enabled: bool,
+    pub priority: u8,
+    /// Monitored items, in order of client handle
+    pub monitored_items: Vec<MonitoredItemBlueprint>,
+}
+
+/// The definition of a monitored item in a [`SubscriptionBlueprint`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MonitoredItemBlueprint {
+    pub client_handle: u32,
+    pub item_to_monitor: ReadValueId,
+    pub monitoring_mode: MonitoringMode,
+    pub sampling_interval: f64,
+    pub queue_size: u32,
+    pub discard_oldest: bool,
+    pub filter: ExtensionObject,
+    pub timestamps_to_return: TimestampsToReturn,
+    /// Client handles of the monitored items that this item triggers. Server ids are not kept
+    /// since a recreated item gets a new one.
+    pub triggered_items: Vec<u32>,
+}
+
+impl MonitoredItemBlueprint {
+    /// Makes the request that creates this monitored item
+    pub fn create_request(&self) -> MonitoredItemCreateRequest {
+        MonitoredItemCreateRequest {
+            item_to_monitor: self.item_to_monitor.clone(),
+            monitoring_mode: self.monitoring_mode,
+            requested_parameters: MonitoringParameters {
+                client_handle: self.client_handle,
+                sampling_interval: self.sampling_interval,
+                filter: self.filter.clone(),
+                queue_size: self.queue_size,
+                discard_oldest: self.discard_oldest,
+            },
+        }
+    }
+}
+
+/// The most notification messages that are tracked as missing on a subscription. Each one costs
+/// a republish round trip and servers only keep a few messages to republish, so a bigger jump in
+/// sequence numbers, e.g. from a server restart, is treated as the sequence starting over.
+const MAX_MISSING_SEQUENCE_NUMBERS: u32 = 20;
+
+/// The sequence number after this one. Sequence numbers wrap back to 1 after `u32::MAX`.
+fn next_sequence_number(sequence_number: u32) -> u32 {
+    if sequence_number == u32::MAX {
+        1
+    } else {
+        sequence_number + 1
+    }
+}
+
+/// The number of steps from one sequence number forward to another, allowing for the wrap.
+fn sequence_distance(from: u32, to: u32) -> u32 {
+    if to >= from {
+        to - from
+    } else {
+        to + (u32::MAX - from)
+    }
 }
 
 /// A set of callbacks for notifications on a subscription.
@@ -89,6 +168,10 @@ impl OnSubscriptionNotification for SubscriptionCallbacks {
     }
 }
 
+/// A callback registered on a single monitored item with [`Subscription::on_data_change`], called
+/// with the item and its new value each time a data change for the item arrives.
+pub type ItemDataChangeCallback = Box<dyn Fn(&MonitoredItem, &DataValue) + Send + Sync>;
+
 /// A wrapper around a data change callback that implements [OnSubscriptionNotification]
 pub struct DataChangeCallback {
     data_value: Box<dyn FnMut(DataValue, &MonitoredItem) + Send + Sync>,
@@ -160,6 +243,14 @@ pub struct MonitoredItem {
     discard_oldest: bool,
     /// Active filter
     filter: ExtensionObject,
+    /// Timestamps requested from the server for each value
+    timestamps_to_return: TimestampsToReturn,
+    /// Source timestamp of the last value received for the item
+    source_timestamp: Option<DateTime>,
+    /// Server timestamp of the last value received for the item
+    server_timestamp: Option<DateTime>,
+    /// Sequence number of the notification message that carried the last value
+    last_value_sequence_number: Option<u32>,
 }
 
 impl MonitoredItem {
@@ -174,6 +265,10 @@ impl MonitoredItem {
             triggered_items: BTreeSet::new(),
             discard_oldest: true,
             filter: ExtensionObject::null(),
+            timestamps_to_return: TimestampsToReturn::Both,
+            source_timestamp: None,
+            server_timestamp: None,
+            last_value_sequence_number: None,
         }
     }
 
@@ -207,6 +302,34 @@ impl MonitoredItem {
         self.discard_oldest
     }
 
+    /// Monitoring mode of the item.
+    pub fn monitoring_mode(&self) -> MonitoringMode {
+        self.monitoring_mode
+    }
+
+    /// Filter applied to the monitored item, e.g. an `EventFilter` for event items, or a
+    /// null extension object if there is none.
+    pub fn filter(&self) -> &ExtensionObject {
+        &self.filter
+    }
+
+    /// Timestamps requested from the server for each value of the item.
+    pub fn timestamps_to_return(&self) -> TimestampsToReturn {
+        self.timestamps_to_return
+    }
+
+    /// Source timestamp of the last value received for the item. This is `None` until a value
+    /// arrives, or if it was not requested through `timestamps_to_return`.
+    pub fn source_timestamp(&self) -> Option<DateTime> {
+        self.source_timestamp
+    }
+
+    /// Server timestamp of the last value received for the item. This is `None` until a value
+    /// arrives, or if it was not requested through `timestamps_to_return`.
+    pub fn server_timestamp(&self) -> Option<DateTime> {
+        self.server_timestamp
+    }
+
     pub(crate) fn set_sampling_interval(&mut self, value: f64) {
         self.sampling_interval = value;
     }
@@ -215,6 +338,36 @@ impl MonitoredItem {
         self.queue_size = value;
     }
 
+    pub(crate) fn set_filter(&mut self, filter: ExtensionObject) {
+        self.filter = filter;
+    }
+
+    pub(crate) fn set_timestamps_to_return(&mut self, timestamps_to_return: TimestampsToReturn) {
+        self.timestamps_to_return = timestamps_to_return;
+    }
+
+    /// Tests if the item's last value came from a notification message after this one.
+    fn has_value_newer_than(&self, sequence_number: u32) -> bool {
+        self.last_value_sequence_number.is_some_and(|last| {
+            let distance = sequence_distance(sequence_number, last);
+            distance > 0 && distance < u32::MAX / 2
+        })
+    }
+
+    /// Records the timestamps of the value from the notification message with this sequence
+    /// number as those of the item's last value, leaving out any that were not requested.
+    pub(crate) fn set_last_value(&mut self, value: &DataValue, sequence_number: u32) {
+        let (source, server) = match self.timestamps_to_return {
+            TimestampsToReturn::Source => (true, false),
+            TimestampsToReturn::Server => (false, true),
+            TimestampsToReturn::Neither => (false, false),
+            _ => (true, true),
+        };
+        self.source_timestamp = value.source_timestamp.filter(|_| source);
+        self.server_timestamp = value.server_timestamp.filter(|_| server);
+        self.last_value_sequence_number = Some(sequence_number);
+    }
+
     pub(crate) fn set_monitoring_mode(&mut self, monitoring_mode: MonitoringMode) {
         self.monitoring_mode = monitoring_mode;
     }
@@ -233,10 +386,40 @@ impl MonitoredItem {
     }
 }
 
+/// Counters describing the notifications received for a subscription.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SubscriptionStats {
+    /// Number of notification messages received, including keep-alives
+    pub notifications_received: u64,
+    /// Number of data change values delivered to the subscription's monitored items
+    pub data_changes: u64,
+    /// Number of keep-alive messages received
+    pub keep_alives: u64,
+    /// Sequence number of the last notification message
+    pub last_sequence_number: u32,
+    /// Publish time of the last notification message
+    pub last_publish_time: Option<DateTime>,
+    /// Number of notification messages that were skipped over in the sequence, i.e. dropped
+    /// somewhere between the server and the client
+    pub missed_notifications: u32,
+}
+
+/// The parameters of a subscription as revised by the server when it was created or last
+/// modified.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RevisedSubscriptionParams {
+    /// Publishing interval
+    pub publishing_interval: Duration,
+    /// Lifetime count
+    pub lifetime_count: u32,
+    /// Max keep alive count
+    pub max_keep_alive_count: u32,
+}
+
 pub struct Subscription {
     /// Subscription id, supplied by server
     subscription_id: u32,
-    /// Publishing interval in seconds
+    /// Publishing interval, revised by server
     publishing_interval: Duration,
     /// Lifetime count, revised by server
     lifetime_count: u32,
@@ -253,6 +436,24 @@ pub struct Subscription {
     monitored_items: HashMap<u32, MonitoredItem>,
     /// A map of client handle to monitored item id
     client_handles: HashMap<u32, u32>,
+    /// Notification counters
+    stats: SubscriptionStats,
+    /// Time that the last notification or keep-alive was received, or the subscription was
+    /// created if nothing has been received yet
+    last_notification_time: DateTime,
+    /// Sequence number expected on the next notification message, once one has been received
+    next_sequence_number: Option<u32>,
+    /// Sequence numbers of notification messages detected as missing, that have not yet been
+    /// recovered with a republish
+    missing_sequence_numbers: BTreeSet<u32>,
+    /// Set when the connection was lost and the subscription must be transferred or recreated
+    /// once the session is reconnected
+    marked_for_recreation: bool,
+    /// Callbacks registered on individual monitored items (key = monitored_item_id)
+    data_change_callbacks: HashMap<u32, Vec<ItemDataChangeCallback>>,
+    /// Callbacks restored onto a recreated subscription for items that have yet to be created
+    /// (key = client_handle)
+    restored_data_change_callbacks: HashMap<u32, Vec<ItemDataChangeCallback>>,
 
     callback: Box<dyn OnSubscriptionNotification>,
 }
@@ -279,6 +480,13 @@ impl Subscription {
             priority,
             monitored_items: HashMap::new(),
             client_handles: HashMap::new(),
+            stats: SubscriptionStats::default(),
+            last_notification_time: DateTime::now(),
+            next_sequence_number: None,
+            missing_sequence_numbers: BTreeSet::new(),
+            marked_for_recreation: false,
+            data_change_callbacks: HashMap::new(),
+            restored_data_change_callbacks: HashMap::new(),
             callback: status_change_callback,
         }
     }
@@ -315,6 +523,77 @@ impl Subscription {
         self.publishing_enabled
     }
 
+    pub fn revised_parameters(&self) -> RevisedSubscriptionParams {
+        RevisedSubscriptionParams {
+            publishing_interval: self.publishing_interval,
+            lifetime_count: self.lifetime_count,
+            max_keep_alive_count: self.max_keep_alive_count,
+        }
+    }
+
+    pub fn stats(&self) -> &SubscriptionStats {
+        &self.stats
+    }
+
+    pub fn last_notification_time(&self) -> DateTime {
+        self.last_notification_time
+    }
+
+    /// Tests if the server has been silent on this subscription for longer than it should be.
+    /// The server sends a keep-alive after `max_keep_alive_count` publishing intervals with nothing
+    /// to report, so the subscription is stale once that has elapsed plus `max_silence` as grace.
+    pub fn is_stale(&self, now: DateTime, max_silence: Duration) -> bool {
+        let expected_keep_alive = self.publishing_interval * self.max_keep_alive_count;
+        let silence = (now - self.last_notification_time)
+            .to_std()
+            .unwrap_or(Duration::ZERO);
+        silence > expected_keep_alive + max_silence
+    }
+
+    /// Tests if the subscription must be transferred or recreated after a reconnect.
+    pub fn is_marked_for_recreation(&self) -> bool {
+        self.marked_for_recreation
+    }
+
+    /// Makes the blueprint of this subscription and its monitored items.
+    pub fn blueprint(&self) -> SubscriptionBlueprint {
+        let mut monitored_items = self
+            .monitored_items
+            .values()
+            .map(|item| MonitoredItemBlueprint {
+                client_handle: item.client_handle(),
+                item_to_monitor: item.item_to_monitor().clone(),
+                monitoring_mode: item.monitoring_mode(),
+                sampling_interval: item.sampling_interval(),
+                queue_size: item.queue_size() as u32,
+                discard_oldest: item.discard_oldest(),
+                filter: item.filter().clone(),
+                timestamps_to_return: item.timestamps_to_return(),
+                triggered_items: item
+                    .triggered_items()
+                    .iter()
+                    .filter_map(|id| self.monitored_items.get(id))
+                    .map(|item| item.client_handle())
+                    .collect(),
+            })
+            .collect::<Vec<MonitoredItemBlueprint>>();
+        monitored_items.sort_by_key(|item| item.client_handle);
+        SubscriptionBlueprint {
+            subscription_id: self.subscription_id,
+            publishing_interval: self.publishing_interval,
+            lifetime_count: self.lifetime_count,
+            max_keep_alive_count: self.max_keep_alive_count,
+            max_notifications_per_publish: self.max_notifications_per_publish,
+            publishing_enabled: self.publishing_enabled,
+            priority: self.priority,
+            monitored_items,
+        }
+    }
+
+    pub(crate) fn set_marked_for_recreation(&mut self, marked_for_recreation: bool) {
+        self.marked_for_recreation = marked_for_recreation;
+    }
+
     pub(crate) fn set_publishing_interval(&mut self, publishing_interval: Duration) {
         self.publishing_interval = publishing_interval;
     }
@@ -351,6 +630,10 @@ impl Subscription {
                 triggered_items: BTreeSet::new(),
                 discard_oldest: i.discard_oldest,
                 filter: i.filter,
+                timestamps_to_return: i.timestamps_to_return,
+                source_timestamp: None,
+                server_timestamp: None,
+                last_value_sequence_number: None,
             };
 
             let client_handle = monitored_item.client_handle();
@@ -358,6 +641,12 @@ impl Subscription {
             self.monitored_items
                 .insert(monitored_item_id, monitored_item);
             self.client_handles.insert(client_handle, monitored_item_id);
+            if let Some(callbacks) = self.restored_data_change_callbacks.remove(&client_handle) {
+                self.data_change_callbacks
+                    .entry(monitored_item_id)
+                    .or_default()
+                    .extend(callbacks);
+            }
         });
     }
 
@@ -366,6 +655,8 @@ impl Subscription {
             if let Some(ref mut monitored_item) = self.monitored_items.get_mut(&i.id) {
                 monitored_item.set_sampling_interval(i.sampling_interval);
                 monitored_item.set_queue_size(i.queue_size as usize);
+                monitored_item.set_filter(i.filter.clone());
+                monitored_item.set_timestamps_to_return(i.timestamps_to_return);
             }
         });
     }
@@ -376,9 +667,79 @@ impl Subscription {
             if let Some(monitored_item) = self.monitored_items.remove(id) {
                 let _ = self.client_handles.remove(&monitored_item.client_handle());
             }
+            let _ = self.data_change_callbacks.remove(id);
         })
     }
 
+    /// Register a callback that is called with the monitored item and its new value each time a
+    /// data change for the item arrives. Any number of callbacks may be registered on an item,
+    /// and they are called in the order they were registered, after the subscription's own
+    /// callback. The callbacks are dropped when the item is deleted.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` - The callback was registered.
+    /// * `Err(StatusCode)` - `BadMonitoredItemIdInvalid` if the item is not in the subscription.
+    pub fn on_data_change(
+        &mut self,
+        monitored_item_id: u32,
+        callback: ItemDataChangeCallback,
+    ) -> Result<(), StatusCode> {
+        if !self.monitored_items.contains_key(&monitored_item_id) {
+            return Err(StatusCode::BadMonitoredItemIdInvalid);
+        }
+        self.data_change_callbacks
+            .entry(monitored_item_id)
+            .or_default()
+            .push(callback);
+        Ok(())
+    }
+
+    /// Take the callbacks registered on monitored items, keyed by the client handle of their item so
+    /// they can be registered again on a recreated subscription where the items have new ids.
+    pub(crate) fn take_data_change_callbacks(
+        &mut self,
+    ) -> HashMap<u32, Vec<ItemDataChangeCallback>> {
+        let mut callbacks = std::mem::take(&mut self.restored_data_change_callbacks);
+        for (id, item_callbacks) in std::mem::take(&mut self.data_change_callbacks) {
+            if let Some(item) = self.monitored_items.get(&id) {
+                callbacks
+                    .entry(item.client_handle())
+                    .or_default()
+                    .extend(item_callbacks);
+            }
+        }
+        callbacks
+    }
+
+    /// Register callbacks taken with [`Subscription::take_data_change_callbacks`] on the items with
+    /// the same client handles. This is done as soon as the recreated subscription exists, so
+    /// callbacks for items that are yet to be created are held until the items are inserted, and
+    /// no data change is delivered without them.
+    pub(crate) fn restore_data_change_callbacks(
+        &mut self,
+        callbacks: HashMap<u32, Vec<ItemDataChangeCallback>>,
+    ) {
+        for (client_handle, callbacks) in callbacks {
+            if let Some(id) = self.client_handles.get(&client_handle) {
+                self.data_change_callbacks
+                    .entry(*id)
+                    .or_default()
+                    .extend(callbacks);
+            } else {
+                self.restored_data_change_callbacks
+                    .entry(client_handle)
+                    .or_default()
+                    .extend(callbacks);
+            }
+        }
+    }
+
+    /// Drops restored callbacks whose items were not recreated
+    pub(crate) fn forget_restored_data_change_callbacks(&mut self) {
+        self.restored_data_change_callbacks.clear();
+    }
+
     pub(crate) fn set_triggering(
         &mut self,
         triggering_item_id: u32,
@@ -390,14 +751,82 @@ impl Subscription {
         }
     }
 
+    /// Counts any notification messages missing before this sequence number. A keep-alive carries
+    /// the sequence number of the next notification message, so it does not use the number up.
+    ///
+    /// A gap of more than `MAX_MISSING_SEQUENCE_NUMBERS` is treated as the sequence starting over,
+    /// and a message from shortly before the expected one is late and leaves the sequence alone.
+    fn check_sequence_number(&mut self, sequence_number: u32, keep_alive: bool) {
+        if let Some(expected) = self.next_sequence_number {
+            let gap = sequence_distance(expected, sequence_number);
+            if gap == 0 {
+                // The expected message
+            } else if gap <= MAX_MISSING_SEQUENCE_NUMBERS {
+                self.stats.missed_notifications += gap;
+                let mut missing = expected;
+                for _ in 0..gap {
+                    self.missing_sequence_numbers.insert(missing);
+                    missing = next_sequence_number(missing);
+                }
+            } else if sequence_distance(sequence_number, expected) <= MAX_MISSING_SEQUENCE_NUMBERS {
+                return;
+            } else {
+                warn!(
+                    "Subscription {} sequence number jumped from {} to {}, so the sequence is restarted",
+                    self.subscription_id, expected, sequence_number
+                );
+                self.missing_sequence_numbers.clear();
+            }
+        }
+        self.next_sequence_number = Some(if keep_alive {
+            sequence_number
+        } else {
+            next_sequence_number(sequence_number)
+        });
+    }
+
+    /// Sequence numbers of notification messages that were detected as missing and have not been
+    /// recovered yet.
+    pub(crate) fn missing_sequence_numbers(&self) -> &BTreeSet<u32> {
+        &self.missing_sequence_numbers
+    }
+
+    /// Stops waiting for a missing notification message, e.g. because the server no longer has it
+    /// to republish.
+    pub(crate) fn forget_missing_sequence_number(&mut self, sequence_number: u32) {
+        self.missing_sequence_numbers.remove(&sequence_number);
+    }
+
     pub(crate) fn on_notification(
         &mut self,
         notification: NotificationMessage,
+        now: DateTime,
         decoding_options: &DecodingOptions,
     ) {
-        let Some(notifications) = notification.notification_data else {
-            return;
+        // A recovered message fills a gap that was already counted, so it only delivers its
+        // notifications and leaves the sequence and message counters alone
+        let sequence_number = notification.sequence_number;
+        let recovered = self.missing_sequence_numbers.remove(&sequence_number);
+        if !recovered {
+            self.last_notification_time = now;
+            self.stats.notifications_received += 1;
+            self.stats.last_sequence_number = sequence_number;
+            self.stats.last_publish_time = Some(notification.publish_time);
+        }
+
+        let notifications = match notification.notification_data {
+            Some(notifications) if !notifications.is_empty() => notifications,
+            _ => {
+                if !recovered {
+                    self.stats.keep_alives += 1;
+                    self.check_sequence_number(sequence_number, true);
+                }
+                return;
+            }
         };
+        if !recovered {
+            self.check_sequence_number(sequence_number, false);
+        }
 
         for obj in notifications {
             if obj.node_id.namespace != 0 {
@@ -415,9 +844,19 @@ impl Subscription {
                             let item = self
                                 .client_handles
                                 .get(&notif.client_handle)
-                                .and_then(|handle| self.monitored_items.get(handle));
+                                .and_then(|handle| self.monitored_items.get_mut(handle));
 
                             if let Some(item) = item {
+                                // A recovered message was sent before the messages that came
+                                // after the gap, so it must not replace a newer value
+                                if recovered && item.has_value_newer_than(sequence_number) {
+                                    continue;
+                                }
+                                self.stats.data_changes += 1;
+                                item.set_last_value(&notif.value, sequence_number);
+                                if let Some(callbacks) = self.data_change_callbacks.get(&item.id) {
+                                    callbacks.iter().for_each(|cb| cb(item, &notif.value));
+                                }
                                 self.callback.on_data_value(notif.value, item);
                             }
                         }
@@ -1,5 +1,5 @@
 use std::{
-    collections::HashSet,
+    collections::HashMap,
     time::{Duration, Instant},
 };
 
@@ -7,7 +7,10 @@ use crate::{
     client::{
         session::{
             process_service_result, process_unexpected_response,
-            services::subscriptions::{CreateMonitoredItem, ModifyMonitoredItem, Subscription},
+            services::subscriptions::{
+                CreateMonitoredItem, ModifyMonitoredItem, MonitoredItemBlueprint, Subscription,
+                SubscriptionBlueprint,
+            },
             session_debug, session_error, session_trace, session_warn,
         },
         Session,
@@ -15,15 +18,16 @@ use crate::{
     core::supported_message::SupportedMessage,
     types::{
         CreateMonitoredItemsRequest, CreateSubscriptionRequest, DeleteMonitoredItemsRequest,
-        DeleteSubscriptionsRequest, ModifyMonitoredItemsRequest, ModifySubscriptionRequest,
-        MonitoredItemCreateRequest, MonitoredItemCreateResult, MonitoredItemModifyRequest,
-        MonitoredItemModifyResult, MonitoringMode, MonitoringParameters, PublishRequest,
+        DeleteSubscriptionsRequest, ExtensionObject, ModifyMonitoredItemsRequest,
+        ModifySubscriptionRequest, MonitoredItemCreateRequest, MonitoredItemCreateResult,
+        MonitoredItemModifyRequest, MonitoredItemModifyResult, MonitoringMode,
+        MonitoringParameters, NotificationMessage, PublishRequest, RepublishRequest,
         SetMonitoringModeRequest, SetPublishingModeRequest, SetTriggeringRequest, StatusCode,
         TimestampsToReturn, TransferResult, TransferSubscriptionsRequest,
     },
 };
 
-use super::OnSubscriptionNotification;
+use super::{ItemDataChangeCallback, OnSubscriptionNotification};
 
 impl Session {
     async fn create_subscription_inner(
@@ -38,7 +42,7 @@ impl Session {
     ) -> Result<u32, StatusCode> {
         let request = CreateSubscriptionRequest {
             request_header: self.make_request_header(),
-            requested_publishing_interval: publishing_interval.as_secs_f64(),
+            requested_publishing_interval: publishing_interval.as_secs_f64() * 1000.0,
             requested_lifetime_count: lifetime_count,
             requested_max_keep_alive_count: max_keep_alive_count,
             max_notifications_per_publish,
@@ -143,6 +147,73 @@ impl Session {
         .await
     }
 
+    /// Creates a subscription and then its monitored items, as a [`CreateSubscriptionRequest`]
+    /// followed by a [`CreateMonitoredItemsRequest`]. If any of the items cannot be created, the
+    /// subscription is deleted again so the session is left as it was.
+    ///
+    /// See [`Session::create_subscription`] and [`Session::create_monitored_items`] for a
+    /// description of the arguments.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok((u32, Vec<MonitoredItemCreateResult>))` - identifier for the new subscription and the
+    ///   result of creating each item, in the same order as `items_to_create`.
+    /// * `Err(StatusCode)` - Request failed, [Status code](StatusCode) is the reason for failure,
+    ///   or the status of the first item that could not be created.
+    ///
+    #[allow(clippy::too_many_arguments)]
+    pub async fn create_subscription_with_items(
+        &self,
+        publishing_interval: Duration,
+        lifetime_count: u32,
+        max_keep_alive_count: u32,
+        max_notifications_per_publish: u32,
+        priority: u8,
+        publishing_enabled: bool,
+        callback: impl OnSubscriptionNotification + 'static,
+        timestamps_to_return: TimestampsToReturn,
+        items_to_create: Vec<MonitoredItemCreateRequest>,
+    ) -> Result<(u32, Vec<MonitoredItemCreateResult>), StatusCode> {
+        let subscription_id = self
+            .create_subscription(
+                publishing_interval,
+                lifetime_count,
+                max_keep_alive_count,
+                max_notifications_per_publish,
+                priority,
+                publishing_enabled,
+                callback,
+            )
+            .await?;
+
+        let status_code = match self
+            .create_monitored_items(subscription_id, timestamps_to_return, items_to_create)
+            .await
+        {
+            Ok(results) => match results.iter().find(|r| r.status_code.is_bad()) {
+                Some(result) => result.status_code,
+                None => return Ok((subscription_id, results)),
+            },
+            Err(status_code) => status_code,
+        };
+
+        session_warn!(
+            self,
+            "create_subscription_with_items, could not create items, status {}, deleting subscription {}",
+            status_code,
+            subscription_id
+        );
+        if let Err(err) = self.delete_subscription(subscription_id).await {
+            session_error!(
+                self,
+                "create_subscription_with_items, could not delete subscription {}, error {}",
+                subscription_id,
+                err
+            );
+        }
+        Err(status_code)
+    }
+
     fn subscription_exists(&self, subscription_id: u32) -> bool {
         let subscription_state = trace_lock!(self.subscription_state);
         subscription_state.subscription_exists(subscription_id)
@@ -406,9 +477,7 @@ impl Session {
                 {
                     // Clear out deleted subscriptions, assuming the delete worked
                     let mut subscription_state = trace_lock!(self.subscription_state);
-                    subscription_ids.iter().for_each(|id| {
-                        let _ = subscription_state.delete_subscription(*id);
-                    });
+                    let _ = subscription_state.delete_subscriptions(subscription_ids);
                 }
                 session_debug!(self, "delete_subscriptions success");
                 Ok(response.results.unwrap())
@@ -432,7 +501,8 @@ impl Session {
     /// # Returns
     ///
     /// * `Ok(Vec<MonitoredItemCreateResult>)` - A list of [`MonitoredItemCreateResult`] corresponding to the items to create.
-    ///    The size and order of the list matches the size and order of the `items_to_create` request parameter.
+    ///   The size and order of the list matches the size and order of the `items_to_create` request parameter.
+    ///   Items the server rejected have a bad status code in their result and are not added to the subscription.
     /// * `Err(StatusCode)` - Request failed, [Status code](StatusCode) is the reason for failure.
     ///
     pub async fn create_monitored_items(
@@ -441,6 +511,84 @@ impl Session {
         timestamps_to_return: TimestampsToReturn,
         items_to_create: Vec<MonitoredItemCreateRequest>,
     ) -> Result<Vec<MonitoredItemCreateResult>, StatusCode> {
+        let (results, items_to_create) = self
+            .create_monitored_items_inner(subscription_id, timestamps_to_return, items_to_create)
+            .await?;
+        {
+            let mut subscription_state = trace_lock!(self.subscription_state);
+            subscription_state.insert_monitored_items(subscription_id, items_to_create);
+        }
+        Ok(results)
+    }
+
+    /// Creates monitored items on several subscriptions, sending one [`CreateMonitoredItemsRequest`]
+    /// per subscription. The requests are sent concurrently rather than waiting for each response
+    /// in turn.
+    ///
+    /// See OPC UA Part 4 - Services 5.12.2 for complete description of the service and error responses.
+    ///
+    /// # Arguments
+    ///
+    /// * `timestamps_to_return` - An enumeration that specifies the timestamp Attributes to be transmitted for each MonitoredItem.
+    /// * `batches` - Pairs of subscription id and the list of [`MonitoredItemCreateRequest`] to be created on that subscription.
+    ///
+    /// # Returns
+    ///
+    /// A list of subscription id and outcome, in the same order as `batches`. Each outcome is what
+    /// [`Session::create_monitored_items`] would return for that subscription, so a request that fails
+    /// for one subscription does not prevent items being created on the others.
+    ///
+    pub async fn create_monitored_items_batched(
+        &self,
+        timestamps_to_return: TimestampsToReturn,
+        batches: Vec<(u32, Vec<MonitoredItemCreateRequest>)>,
+    ) -> Vec<(u32, Result<Vec<MonitoredItemCreateResult>, StatusCode>)> {
+        session_debug!(
+            self,
+            "create_monitored_items_batched, for {} subscriptions",
+            batches.len()
+        );
+        let requests = batches
+            .into_iter()
+            .map(|(subscription_id, items_to_create)| async move {
+                let result = self
+                    .create_monitored_items_inner(
+                        subscription_id,
+                        timestamps_to_return,
+                        items_to_create,
+                    )
+                    .await;
+                (subscription_id, result)
+            });
+        let responses = futures::future::join_all(requests).await;
+
+        // Set the items in our internal state for every subscription that succeeded
+        let mut items_to_insert = Vec::with_capacity(responses.len());
+        let results = responses
+            .into_iter()
+            .map(|(subscription_id, result)| {
+                let result = result.map(|(results, items)| {
+                    items_to_insert.push((subscription_id, items));
+                    results
+                });
+                (subscription_id, result)
+            })
+            .collect();
+        {
+            let mut subscription_state = trace_lock!(self.subscription_state);
+            subscription_state.insert_monitored_items_batched(items_to_insert);
+        }
+        results
+    }
+
+    /// Sends the create monitored items request, returning the results along with the items that
+    /// were successfully created so the caller can record them.
+    async fn create_monitored_items_inner(
+        &self,
+        subscription_id: u32,
+        timestamps_to_return: TimestampsToReturn,
+        items_to_create: Vec<MonitoredItemCreateRequest>,
+    ) -> Result<(Vec<MonitoredItemCreateResult>, Vec<CreateMonitoredItem>), StatusCode> {
         session_debug!(
             self,
             "create_monitored_items, for subscription {}, {} items",
@@ -486,16 +634,24 @@ impl Session {
 
             if let SupportedMessage::CreateMonitoredItemsResponse(response) = response {
                 process_service_result(&response.response_header)?;
-                if let Some(ref results) = response.results {
-                    session_debug!(
-                        self,
-                        "create_monitored_items, {} items created",
-                        created_items.len()
-                    );
-                    // Set the items in our internal state
+                let items_to_create = if let Some(ref results) = response.results {
+                    // Only the items the server accepted are kept in our internal state. The
+                    // status of the rejected ones is in the results returned to the caller.
                     let items_to_create = created_items
                         .into_iter()
                         .zip(results)
+                        .filter(|(i, r)| {
+                            if r.status_code.is_bad() {
+                                session_warn!(
+                                    self,
+                                    "create_monitored_items, item for node {} with client handle {} was rejected with {}",
+                                    i.item_to_monitor.node_id,
+                                    i.requested_parameters.client_handle,
+                                    r.status_code
+                                );
+                            }
+                            r.status_code.is_good()
+                        })
                         .map(|(i, r)| CreateMonitoredItem {
                             id: r.monitored_item_id,
                             client_handle: i.requested_parameters.client_handle,
@@ -505,19 +661,24 @@ impl Session {
                             queue_size: r.revised_queue_size,
                             sampling_interval: r.revised_sampling_interval,
                             filter: i.requested_parameters.filter,
+                            timestamps_to_return,
                         })
                         .collect::<Vec<CreateMonitoredItem>>();
-                    {
-                        let mut subscription_state = trace_lock!(self.subscription_state);
-                        subscription_state.insert_monitored_items(subscription_id, items_to_create);
-                    }
+                    session_debug!(
+                        self,
+                        "create_monitored_items, {} of {} items created",
+                        items_to_create.len(),
+                        results.len()
+                    );
+                    items_to_create
                 } else {
                     session_debug!(
                         self,
                         "create_monitored_items, success but no monitored items were created"
                     );
-                }
-                Ok(response.results.unwrap())
+                    Vec::new()
+                };
+                Ok((response.results.unwrap_or_default(), items_to_create))
             } else {
                 session_error!(self, "create_monitored_items failed {:?}", response);
                 Err(process_unexpected_response(response))
@@ -570,43 +731,174 @@ impl Session {
             );
             Err(StatusCode::BadNothingToDo)
         } else {
-            let monitored_item_ids = items_to_modify
+            let results = self
+                .send_modify_monitored_items(subscription_id, timestamps_to_return, items_to_modify)
+                .await?;
+            // Set the revised values of the items the server modified in our internal
+            // state, the others are left as they were
+            let items_to_modify = items_to_modify
                 .iter()
-                .map(|i| i.monitored_item_id)
-                .collect::<Vec<u32>>();
-            let request = ModifyMonitoredItemsRequest {
-                request_header: self.make_request_header(),
-                subscription_id,
-                timestamps_to_return,
-                items_to_modify: Some(items_to_modify.to_vec()),
-            };
-            let response = self.send(request).await?;
-            if let SupportedMessage::ModifyMonitoredItemsResponse(response) = response {
-                process_service_result(&response.response_header)?;
-                if let Some(ref results) = response.results {
-                    // Set the items in our internal state
-                    let items_to_modify = monitored_item_ids
-                        .iter()
-                        .zip(results.iter())
-                        .map(|(id, r)| ModifyMonitoredItem {
-                            id: *id,
-                            queue_size: r.revised_queue_size,
-                            sampling_interval: r.revised_sampling_interval,
-                        })
-                        .collect::<Vec<ModifyMonitoredItem>>();
+                .zip(results.iter())
+                .filter(|(_, r)| r.status_code.is_good())
+                .map(|(i, r)| ModifyMonitoredItem {
+                    id: i.monitored_item_id,
+                    queue_size: r.revised_queue_size,
+                    sampling_interval: r.revised_sampling_interval,
+                    filter: i.requested_parameters.filter.clone(),
+                    timestamps_to_return,
+                })
+                .collect::<Vec<ModifyMonitoredItem>>();
+            {
+                let mut subscription_state = trace_lock!(self.subscription_state);
+                subscription_state.modify_monitored_items(subscription_id, &items_to_modify);
+            }
+            Ok(results)
+        }
+    }
+
+    /// Sends a [`ModifyMonitoredItemsRequest`] to the server and returns its results, without
+    /// touching the internal state.
+    async fn send_modify_monitored_items(
+        &self,
+        subscription_id: u32,
+        timestamps_to_return: TimestampsToReturn,
+        items_to_modify: &[MonitoredItemModifyRequest],
+    ) -> Result<Vec<MonitoredItemModifyResult>, StatusCode> {
+        let request = ModifyMonitoredItemsRequest {
+            request_header: self.make_request_header(),
+            subscription_id,
+            timestamps_to_return,
+            items_to_modify: Some(items_to_modify.to_vec()),
+        };
+        let response = self.send(request).await?;
+        if let SupportedMessage::ModifyMonitoredItemsResponse(response) = response {
+            process_service_result(&response.response_header)?;
+            session_debug!(self, "modify_monitored_items, success");
+            Ok(response.results.unwrap_or_default())
+        } else {
+            session_error!(self, "modify_monitored_items failed {:?}", response);
+            Err(process_unexpected_response(response))
+        }
+    }
+
+    /// Changes the sampling intervals of several monitored items of a subscription at once, e.g.
+    /// to sample less often while the client is under load. Each item keeps its other parameters.
+    /// Items are modified with one [`ModifyMonitoredItemsRequest`] for each kind of timestamps
+    /// they return, and the intervals the server revised them to are kept in the internal state.
+    ///
+    /// # Arguments
+    ///
+    /// * `subscription_id` - the subscription identifier containing the monitored items to be modified.
+    /// * `items` - the monitored items to modify, each as `(monitored_item_id, sampling_interval)`.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Vec<(u32, MonitoredItemModifyResult)>)` - The result for each monitored item, with its id. Items
+    ///   that the subscription doesn't have are `BadMonitoredItemIdInvalid`, and the items of a request that
+    ///   failed have the status code it failed with.
+    /// * `Err(StatusCode)` - `BadInvalidArgument` if the subscription does not exist, or `BadNothingToDo` if
+    ///   there are no items.
+    ///
+    pub async fn modify_monitored_items_interval(
+        &self,
+        subscription_id: u32,
+        items: &[(u32, f64)],
+    ) -> Result<Vec<(u32, MonitoredItemModifyResult)>, StatusCode> {
+        if subscription_id == 0 || !self.subscription_exists(subscription_id) {
+            session_error!(
+                self,
+                "modify_monitored_items_interval, subscription id {} does not exist",
+                subscription_id
+            );
+            return Err(StatusCode::BadInvalidArgument);
+        } else if items.is_empty() {
+            session_error!(
+                self,
+                "modify_monitored_items_interval, called with no items to modify"
+            );
+            return Err(StatusCode::BadNothingToDo);
+        }
+
+        let failed_result = |status_code| MonitoredItemModifyResult {
+            status_code,
+            revised_sampling_interval: 0f64,
+            revised_queue_size: 0,
+            filter_result: ExtensionObject::null(),
+        };
+
+        // Group the requests by the timestamps that the items return
+        let mut results = Vec::with_capacity(items.len());
+        let mut requests: Vec<(TimestampsToReturn, Vec<MonitoredItemModifyRequest>)> = Vec::new();
+        {
+            let subscription_state = trace_lock!(self.subscription_state);
+            for (monitored_item_id, sampling_interval) in items {
+                let Some(monitored_item) =
+                    subscription_state.monitored_item(subscription_id, *monitored_item_id)
+                else {
+                    results.push((
+                        *monitored_item_id,
+                        failed_result(StatusCode::BadMonitoredItemIdInvalid),
+                    ));
+                    continue;
+                };
+                let request = MonitoredItemModifyRequest {
+                    monitored_item_id: *monitored_item_id,
+                    requested_parameters: MonitoringParameters {
+                        client_handle: monitored_item.client_handle(),
+                        sampling_interval: *sampling_interval,
+                        filter: monitored_item.filter().clone(),
+                        queue_size: monitored_item.queue_size() as u32,
+                        discard_oldest: monitored_item.discard_oldest(),
+                    },
+                };
+                let timestamps_to_return = monitored_item.timestamps_to_return();
+                match requests
+                    .iter_mut()
+                    .find(|(t, _)| *t == timestamps_to_return)
+                {
+                    Some((_, items_to_modify)) => items_to_modify.push(request),
+                    None => requests.push((timestamps_to_return, vec![request])),
+                }
+            }
+        }
+
+        // Every group is sent even if another one fails
+        for (timestamps_to_return, items_to_modify) in requests {
+            let ids = items_to_modify.iter().map(|i| i.monitored_item_id);
+            match self
+                .send_modify_monitored_items(
+                    subscription_id,
+                    timestamps_to_return,
+                    &items_to_modify,
+                )
+                .await
+            {
+                Ok(modify_results) => {
+                    let revised_intervals = ids
+                        .clone()
+                        .zip(modify_results.iter())
+                        .filter(|(_, r)| r.status_code.is_good())
+                        .map(|(id, r)| (id, r.revised_sampling_interval))
+                        .collect::<Vec<(u32, f64)>>();
                     {
                         let mut subscription_state = trace_lock!(self.subscription_state);
                         subscription_state
-                            .modify_monitored_items(subscription_id, &items_to_modify);
+                            .modify_monitored_items_interval(subscription_id, &revised_intervals);
                     }
+                    results.extend(ids.zip(modify_results));
+                }
+                Err(status_code) => {
+                    session_warn!(
+                        self,
+                        "modify_monitored_items_interval, could not modify {} items, {}",
+                        items_to_modify.len(),
+                        status_code
+                    );
+                    results.extend(ids.map(|id| (id, failed_result(status_code))));
                 }
-                session_debug!(self, "modify_monitored_items, success");
-                Ok(response.results.unwrap())
-            } else {
-                session_error!(self, "modify_monitored_items failed {:?}", response);
-                Err(process_unexpected_response(response))
             }
         }
+        Ok(results)
     }
 
     /// Sets the monitoring mode on one or more monitored items by sending a [`SetMonitoringModeRequest`]
@@ -850,6 +1142,7 @@ impl Session {
                         &decoding_options,
                     );
                 }
+                self.republish_missing().await;
 
                 return Ok(r.more_notifications);
             }
@@ -868,6 +1161,66 @@ impl Session {
         Err(err_status)
     }
 
+    /// Ask the server to send a notification message again.
+    async fn republish(
+        &self,
+        subscription_id: u32,
+        sequence_number: u32,
+    ) -> Result<NotificationMessage, StatusCode> {
+        let request = RepublishRequest {
+            request_header: self.make_request_header(),
+            subscription_id,
+            retransmit_sequence_number: sequence_number,
+        };
+        let response = self.send(request).await?;
+        if let SupportedMessage::RepublishResponse(response) = response {
+            process_service_result(&response.response_header)?;
+            Ok(response.notification_message)
+        } else {
+            session_error!(self, "republish failed {:?}", response);
+            Err(process_unexpected_response(response))
+        }
+    }
+
+    /// Recover notification messages that were detected as missing by having the server
+    /// republish them. Messages the server cannot republish are given up on.
+    async fn republish_missing(&self) {
+        let targets = {
+            let subscription_state = trace_lock!(self.subscription_state);
+            subscription_state.republish_targets()
+        };
+        if targets.is_empty() {
+            return;
+        }
+
+        let decoding_options = {
+            let secure_channel = trace_read_lock!(self.channel.secure_channel);
+            secure_channel.decoding_options()
+        };
+
+        for (subscription_id, sequence_number) in targets {
+            let result = self.republish(subscription_id, sequence_number).await;
+            let mut subscription_state = trace_lock!(self.subscription_state);
+            match result {
+                Ok(notification_message) => subscription_state.handle_notification(
+                    subscription_id,
+                    notification_message,
+                    &decoding_options,
+                ),
+                Err(e) => {
+                    session_warn!(
+                        self,
+                        "Notification message {} of subscription {} could not be republished, {}",
+                        sequence_number,
+                        subscription_id,
+                        e
+                    );
+                    subscription_state.forget_republish_target(subscription_id, sequence_number);
+                }
+            }
+        }
+    }
+
     /// This code attempts to take the existing subscriptions created by a previous session and
     /// either transfer them to this session, or construct them from scratch.
     pub(crate) async fn transfer_subscriptions_from_old_session(&self) {
@@ -875,110 +1228,221 @@ impl Session {
         // customize the process.
         let subscription_ids = {
             let subscription_state = trace_lock!(self.subscription_state);
-            subscription_state.subscription_ids()
+            subscription_state.pending_transfer_ids()
         };
 
         let Some(subscription_ids) = subscription_ids else {
             return;
         };
 
-        // Start by getting the subscription ids
+        // Every subscription has to be transferred or recreated on the new session. The plan to
+        // recreate them is made before they are taken out of the state while the transfer is in
+        // progress.
+        let (plan, mut subscriptions) = {
+            let mut subscription_state = trace_lock!(self.subscription_state);
+            subscription_state.mark_all_for_recreation();
+            let plan = subscription_state.recreation_plan();
+            (plan, subscription_state.drain_subscriptions())
+        };
+
         // Try to use TransferSubscriptions to move subscriptions_ids over. If this
         // works then there is nothing else to do.
-        let mut subscription_ids_to_recreate =
-            subscription_ids.iter().copied().collect::<HashSet<u32>>();
         if let Ok(transfer_results) = self.transfer_subscriptions(&subscription_ids, true).await {
             session_debug!(self, "transfer_results = {:?}", transfer_results);
-            transfer_results.iter().enumerate().for_each(|(i, r)| {
-                if r.status_code.is_good() {
-                    // Subscription was transferred so it does not need to be recreated
-                    subscription_ids_to_recreate.remove(&subscription_ids[i]);
-                }
-            });
+            // Subscriptions that were transferred are put back as they were
+            let transferred = transfer_results
+                .iter()
+                .zip(subscription_ids.iter())
+                .filter(|(r, _)| r.status_code.is_good())
+                .filter_map(|(_, subscription_id)| subscriptions.remove_entry(subscription_id))
+                .collect::<HashMap<u32, Subscription>>();
+            let mut subscription_state = trace_lock!(self.subscription_state);
+            subscription_state.transfer_subscriptions(transferred);
         }
 
         // But if it didn't work, then some or all subscriptions have to be remade.
-        if !subscription_ids_to_recreate.is_empty() {
+        if !subscriptions.is_empty() {
             session_warn!(self, "Some or all of the existing subscriptions could not be transferred and must be created manually");
         }
 
-        for subscription_id in subscription_ids_to_recreate {
-            session_debug!(self, "Recreating subscription {}", subscription_id);
-
-            let deleted_subscription = {
-                let mut subscription_state = trace_lock!(self.subscription_state);
-                subscription_state.delete_subscription(subscription_id)
-            };
-
-            let Some(subscription) = deleted_subscription else {
-                session_warn!(
-                    self,
-                    "Subscription removed from session while transfer in progress"
-                );
+        for blueprint in plan {
+            // Subscriptions that were transferred are no longer held here
+            let Some(mut subscription) = subscriptions.remove(&blueprint.subscription_id) else {
                 continue;
             };
-
-            let Ok(subscription_id) = self
-                .create_subscription_inner(
-                    subscription.publishing_interval,
-                    subscription.lifetime_count,
-                    subscription.max_keep_alive_count,
-                    subscription.max_notifications_per_publish,
-                    subscription.publishing_enabled,
-                    subscription.priority,
-                    subscription.callback,
-                )
+            session_debug!(
+                self,
+                "Recreating subscription {}",
+                blueprint.subscription_id
+            );
+            let item_callbacks = subscription.take_data_change_callbacks();
+            if self
+                .recreate_subscription(&blueprint, subscription.callback, item_callbacks)
                 .await
-            else {
+                .is_err()
+            {
                 session_warn!(
                     self,
                     "Could not create a subscription from the existing subscription {}",
-                    subscription_id
+                    blueprint.subscription_id
                 );
-                continue;
-            };
+            }
+        }
+    }
 
-            let items_to_create = subscription
-                .monitored_items
-                .iter()
-                .map(|(_, item)| MonitoredItemCreateRequest {
-                    item_to_monitor: item.item_to_monitor().clone(),
-                    monitoring_mode: item.monitoring_mode,
-                    requested_parameters: MonitoringParameters {
-                        client_handle: item.client_handle(),
-                        sampling_interval: item.sampling_interval(),
-                        filter: item.filter.clone(),
-                        queue_size: item.queue_size() as u32,
-                        discard_oldest: item.discard_oldest(),
-                    },
-                })
-                .collect::<Vec<MonitoredItemCreateRequest>>();
+    /// Register a callback on a monitored item that is called with the item and its new value each
+    /// time a data change for the item arrives. Any number of callbacks may be registered on an
+    /// item and all of them are called, after the callback of the subscription. The callbacks are
+    /// kept if the subscription has to be recreated on a new session.
+    ///
+    /// # Arguments
+    ///
+    /// * `subscription_id` - The Server-assigned identifier for the Subscription.
+    /// * `monitored_item_id` - The Server-assigned identifier for the monitored item.
+    /// * `callback` - Called with the item and its new value.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` - The callback was registered.
+    /// * `Err(StatusCode)` - `BadSubscriptionIdInvalid` if the subscription is not known, or
+    ///   `BadMonitoredItemIdInvalid` if the item is not in the subscription.
+    ///
+    pub fn on_data_change(
+        &self,
+        subscription_id: u32,
+        monitored_item_id: u32,
+        callback: ItemDataChangeCallback,
+    ) -> Result<(), StatusCode> {
+        let mut subscription_state = trace_lock!(self.subscription_state);
+        subscription_state.on_data_change(subscription_id, monitored_item_id, callback)
+    }
+
+    /// Get the blueprints of the session's subscriptions, in order of subscription id. The
+    /// blueprints can be saved and passed to [`Session::import_blueprints`] to recreate the
+    /// subscriptions, e.g. after the client restarts.
+    pub fn export_blueprints(&self) -> Vec<SubscriptionBlueprint> {
+        let subscription_state = trace_lock!(self.subscription_state);
+        subscription_state.export_blueprints()
+    }
 
-            let mut iter = items_to_create.into_iter();
+    /// Create subscriptions and their monitored items and triggering links on the server from
+    /// blueprints. `callback` is called with each blueprint to make the callback of the
+    /// subscription created from it.
+    ///
+    /// # Returns
+    ///
+    /// * The result of creating each subscription, in the order of the blueprints. `Ok(u32)` is
+    ///   the id of the new subscription. Monitored items and triggering links the server rejects
+    ///   are logged and left out.
+    ///
+    pub async fn import_blueprints(
+        &self,
+        blueprints: &[SubscriptionBlueprint],
+        mut callback: impl FnMut(&SubscriptionBlueprint) -> Box<dyn OnSubscriptionNotification>,
+    ) -> Vec<Result<u32, StatusCode>> {
+        let mut results = Vec::with_capacity(blueprints.len());
+        for blueprint in blueprints {
+            let result = self
+                .recreate_subscription(blueprint, callback(blueprint), HashMap::new())
+                .await;
+            results.push(result);
+        }
+        results
+    }
 
-            loop {
-                let chunk = (&mut iter)
-                    .take(self.recreate_monitored_items_chunk)
-                    .collect::<Vec<_>>();
+    /// Create a subscription from a blueprint, followed by its monitored items in chunks and
+    /// then the triggering links between them. `item_callbacks` are callbacks keyed by client
+    /// handle that are registered on the items as they are created.
+    async fn recreate_subscription(
+        &self,
+        blueprint: &SubscriptionBlueprint,
+        callback: Box<dyn OnSubscriptionNotification>,
+        item_callbacks: HashMap<u32, Vec<ItemDataChangeCallback>>,
+    ) -> Result<u32, StatusCode> {
+        let subscription_id = self
+            .create_subscription_inner(
+                blueprint.publishing_interval,
+                blueprint.lifetime_count,
+                blueprint.max_keep_alive_count,
+                blueprint.max_notifications_per_publish,
+                blueprint.publishing_enabled,
+                blueprint.priority,
+                callback,
+            )
+            .await?;
+        if !item_callbacks.is_empty() {
+            // The callbacks are in place before any of the items can report a data change
+            let mut subscription_state = trace_lock!(self.subscription_state);
+            subscription_state.restore_data_change_callbacks(subscription_id, item_callbacks);
+        }
 
-                if chunk.is_empty() {
-                    break;
+        // Items are created in groups that share timestamps to return, and the new ids are
+        // recorded by client handle to rebuild the triggering links
+        let mut groups: Vec<(TimestampsToReturn, Vec<&MonitoredItemBlueprint>)> = Vec::new();
+        for item in &blueprint.monitored_items {
+            match groups
+                .iter_mut()
+                .find(|(t, _)| *t == item.timestamps_to_return)
+            {
+                Some((_, items)) => items.push(item),
+                None => groups.push((item.timestamps_to_return, vec![item])),
+            }
+        }
+        let mut item_ids = HashMap::new();
+        for (timestamps_to_return, items) in groups {
+            for chunk in items.chunks(self.recreate_monitored_items_chunk.max(1)) {
+                let items_to_create = chunk.iter().map(|item| item.create_request()).collect();
+                match self
+                    .create_monitored_items(subscription_id, timestamps_to_return, items_to_create)
+                    .await
+                {
+                    Ok(results) => {
+                        for (item, result) in chunk.iter().zip(results.iter()) {
+                            if result.status_code.is_good() {
+                                item_ids.insert(item.client_handle, result.monitored_item_id);
+                            } else {
+                                session_warn!(
+                                    self,
+                                    "Could not recreate monitored item with client handle {}, {}",
+                                    item.client_handle,
+                                    result.status_code
+                                );
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        session_warn!(
+                            self,
+                            "Could not recreate monitored items on subscription {}, {}",
+                            subscription_id,
+                            e
+                        );
+                    }
                 }
+            }
+        }
 
+        for item in &blueprint.monitored_items {
+            let Some(triggering_item_id) = item_ids.get(&item.client_handle) else {
+                continue;
+            };
+            let links_to_add = item
+                .triggered_items
+                .iter()
+                .filter_map(|client_handle| item_ids.get(client_handle).copied())
+                .collect::<Vec<u32>>();
+            if !links_to_add.is_empty() {
                 let _ = self
-                    .create_monitored_items(subscription_id, TimestampsToReturn::Both, chunk)
+                    .set_triggering(subscription_id, *triggering_item_id, &links_to_add, &[])
                     .await;
             }
+        }
 
-            for item in subscription.monitored_items.values() {
-                let triggered_items = item.triggered_items();
-                if !triggered_items.is_empty() {
-                    let links_to_add = triggered_items.iter().copied().collect::<Vec<u32>>();
-                    let _ = self
-                        .set_triggering(subscription_id, item.id(), links_to_add.as_slice(), &[])
-                        .await;
-                }
-            }
+        {
+            let mut subscription_state = trace_lock!(self.subscription_state);
+            subscription_state.forget_restored_data_change_callbacks(subscription_id);
         }
+
+        Ok(subscription_id)
     }
 }
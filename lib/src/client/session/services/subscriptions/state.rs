@@ -1,13 +1,62 @@
 use std::{
     collections::HashMap,
+    sync::Arc,
     time::{Duration, Instant},
 };
 
-use crate::types::{
-    DecodingOptions, MonitoringMode, NotificationMessage, SubscriptionAcknowledgement,
+use crate::{
+    core::handle::Handle,
+    types::{
+        DateTime, DecodingOptions, MonitoringMode, NotificationMessage, StatusCode,
+        SubscriptionAcknowledgement,
+    },
 };
 
-use super::{CreateMonitoredItem, ModifyMonitoredItem, Subscription};
+use super::{
+    CreateMonitoredItem, ItemDataChangeCallback, ModifyMonitoredItem, MonitoredItem,
+    RevisedSubscriptionParams, Subscription, SubscriptionBlueprint, SubscriptionStats,
+};
+
+/// Source of the current time used to stamp subscriptions when they are added and when
+/// notifications arrive. Tests can supply their own to control time, e.g. a closure over a time
+/// that they advance.
+pub type SubscriptionClock = Arc<dyn Fn() -> DateTime + Send + Sync>;
+
+/// Allocates subscription ids locally, e.g. to create subscriptions in tests without a live
+/// server. Ids are issued in ascending order from a starting number, skipping any that are
+/// already in use so they never collide with ids assigned by a server.
+pub struct SubscriptionIdGenerator {
+    handle: Handle,
+}
+
+impl Default for SubscriptionIdGenerator {
+    fn default() -> Self {
+        Self::new(Self::FIRST_LOCAL_SUBSCRIPTION_ID)
+    }
+}
+
+impl SubscriptionIdGenerator {
+    /// Servers issue subscription ids counting up from a low number, so local ids start from
+    /// the upper half of the range by default.
+    pub const FIRST_LOCAL_SUBSCRIPTION_ID: u32 = 0x8000_0000;
+
+    /// Creates a generator that issues ids starting from `first`.
+    pub fn new(first: u32) -> Self {
+        Self {
+            handle: Handle::new(first),
+        }
+    }
+
+    /// Returns the next id for which `in_use` is false.
+    pub fn next(&mut self, in_use: impl Fn(u32) -> bool) -> u32 {
+        loop {
+            let subscription_id = self.handle.next();
+            if !in_use(subscription_id) {
+                break subscription_id;
+            }
+        }
+    }
+}
 
 /// State containing all known subscriptions in the session.
 pub struct SubscriptionState {
@@ -16,6 +65,8 @@ pub struct SubscriptionState {
     acknowledgements: Vec<SubscriptionAcknowledgement>,
     keep_alive_timeout: Option<Duration>,
     min_publish_interval: Duration,
+    id_generator: SubscriptionIdGenerator,
+    clock: SubscriptionClock,
 }
 
 impl SubscriptionState {
@@ -32,9 +83,26 @@ impl SubscriptionState {
             acknowledgements: Vec::new(),
             keep_alive_timeout: None,
             min_publish_interval,
+            id_generator: SubscriptionIdGenerator::default(),
+            clock: Arc::new(DateTime::now),
         }
     }
 
+    /// Replace the clock used to stamp subscriptions and notifications.
+    pub fn set_clock(&mut self, clock: SubscriptionClock) {
+        self.clock = clock;
+    }
+
+    /// Allocate a local subscription id that is not used by any known subscription. Ids of
+    /// subscriptions being transferred to a new session are not known while they are drained,
+    /// so pass them in as `reserved_ids`.
+    pub fn next_local_subscription_id(&mut self, reserved_ids: &[u32]) -> u32 {
+        let subscriptions = &self.subscriptions;
+        self.id_generator.next(|subscription_id| {
+            subscriptions.contains_key(&subscription_id) || reserved_ids.contains(&subscription_id)
+        })
+    }
+
     pub(crate) fn next_publish_time(&self) -> Option<Instant> {
         if self.subscriptions.is_empty() {
             return None;
@@ -90,7 +158,194 @@ impl SubscriptionState {
         self.subscriptions.get(&subscription_id)
     }
 
-    pub(crate) fn add_subscription(&mut self, subscription: Subscription) {
+    /// Get the parameters the server granted a subscription when it was created or last modified,
+    /// or `None` if the subscription is not known.
+    pub fn revised_parameters(&self, subscription_id: u32) -> Option<RevisedSubscriptionParams> {
+        self.subscriptions
+            .get(&subscription_id)
+            .map(|s| s.revised_parameters())
+    }
+
+    /// List of monitored item IDs in a subscription, or `None` if the subscription is not known.
+    pub fn monitored_item_ids(&self, subscription_id: u32) -> Option<Vec<u32>> {
+        self.subscriptions
+            .get(&subscription_id)
+            .map(|s| s.monitored_items().keys().cloned().collect())
+    }
+
+    /// Get a reference to a monitored item in a subscription by ID.
+    pub fn monitored_item(
+        &self,
+        subscription_id: u32,
+        monitored_item_id: u32,
+    ) -> Option<&MonitoredItem> {
+        self.subscriptions
+            .get(&subscription_id)
+            .and_then(|s| s.monitored_items().get(&monitored_item_id))
+    }
+
+    /// Find the subscription and monitored item that a client handle belongs to, returned as
+    /// `(subscription_id, monitored_item_id)`.
+    pub fn find_by_client_handle(&self, client_handle: u32) -> Option<(u32, u32)> {
+        self.subscriptions.values().find_map(|s| {
+            s.client_handles
+                .get(&client_handle)
+                .map(|monitored_item_id| (s.subscription_id(), *monitored_item_id))
+        })
+    }
+
+    /// List of subscription IDs that should be transferred to a new session, in ascending
+    /// order. These are all the subscriptions currently known.
+    pub fn pending_transfer_ids(&self) -> Option<Vec<u32>> {
+        let mut subscription_ids = self.subscription_ids()?;
+        subscription_ids.sort_unstable();
+        Some(subscription_ids)
+    }
+
+    /// Register a callback on a monitored item that is called with the item and its new value each
+    /// time a data change for the item arrives. See [`Subscription::on_data_change`].
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` - The callback was registered.
+    /// * `Err(StatusCode)` - `BadSubscriptionIdInvalid` if the subscription is not known, or
+    ///   `BadMonitoredItemIdInvalid` if the item is not in the subscription.
+    pub fn on_data_change(
+        &mut self,
+        subscription_id: u32,
+        monitored_item_id: u32,
+        callback: ItemDataChangeCallback,
+    ) -> Result<(), StatusCode> {
+        self.subscriptions
+            .get_mut(&subscription_id)
+            .ok_or(StatusCode::BadSubscriptionIdInvalid)?
+            .on_data_change(monitored_item_id, callback)
+    }
+
+    /// Register item callbacks taken from a subscription that had to be recreated on the
+    /// subscription that replaced it, matching the items by client handle.
+    pub(crate) fn restore_data_change_callbacks(
+        &mut self,
+        subscription_id: u32,
+        callbacks: HashMap<u32, Vec<ItemDataChangeCallback>>,
+    ) {
+        if let Some(subscription) = self.subscriptions.get_mut(&subscription_id) {
+            subscription.restore_data_change_callbacks(callbacks);
+        }
+    }
+
+    /// Drops the restored callbacks of a recreated subscription whose items were not recreated.
+    pub(crate) fn forget_restored_data_change_callbacks(&mut self, subscription_id: u32) {
+        if let Some(subscription) = self.subscriptions.get_mut(&subscription_id) {
+            subscription.forget_restored_data_change_callbacks();
+        }
+    }
+
+    /// Remove all subscriptions, e.g. while they are being transferred to a new session.
+    pub(crate) fn drain_subscriptions(&mut self) -> HashMap<u32, Subscription> {
+        let subscriptions = std::mem::take(&mut self.subscriptions);
+        self.set_keep_alive_timeout();
+        subscriptions
+    }
+
+    /// Re-insert subscriptions that were successfully transferred to a new session. Their
+    /// monitored items are kept as they were.
+    pub(crate) fn transfer_subscriptions(&mut self, subscriptions: HashMap<u32, Subscription>) {
+        self.subscriptions
+            .extend(subscriptions.into_iter().map(|(subscription_id, mut s)| {
+                s.set_marked_for_recreation(false);
+                (subscription_id, s)
+            }));
+        self.set_keep_alive_timeout();
+    }
+
+    /// Mark every subscription and its monitored items as needing to be transferred or
+    /// recreated, e.g. because the connection to the server was lost.
+    pub fn mark_all_for_recreation(&mut self) {
+        self.subscriptions
+            .values_mut()
+            .for_each(|s| s.set_marked_for_recreation(true));
+    }
+
+    /// The blueprints of the subscriptions marked for recreation, in order of subscription id.
+    /// Subscriptions that cannot be transferred to a new session are recreated from these.
+    pub fn recreation_plan(&self) -> Vec<SubscriptionBlueprint> {
+        let mut plan = self
+            .subscriptions
+            .values()
+            .filter(|s| s.is_marked_for_recreation())
+            .map(|s| s.blueprint())
+            .collect::<Vec<SubscriptionBlueprint>>();
+        plan.sort_by_key(|s| s.subscription_id);
+        plan
+    }
+
+    /// The blueprints of every subscription, in order of subscription id.
+    pub fn export_blueprints(&self) -> Vec<SubscriptionBlueprint> {
+        let mut blueprints = self
+            .subscriptions
+            .values()
+            .map(|s| s.blueprint())
+            .collect::<Vec<SubscriptionBlueprint>>();
+        blueprints.sort_by_key(|s| s.subscription_id);
+        blueprints
+    }
+
+    /// Get the notification counters of a subscription by ID.
+    pub fn stats(&self, subscription_id: u32) -> Option<SubscriptionStats> {
+        self.subscriptions
+            .get(&subscription_id)
+            .map(|s| s.stats().clone())
+    }
+
+    /// Get the number of notification messages that were detected as missing from the sequence
+    /// received on a subscription, or 0 if the subscription is not known.
+    pub fn missed_notifications(&self, subscription_id: u32) -> u32 {
+        self.subscriptions
+            .get(&subscription_id)
+            .map(|s| s.stats().missed_notifications)
+            .unwrap_or(0)
+    }
+
+    /// Get the `(subscription_id, sequence_number)` pairs of notification messages that were
+    /// detected as missing and should be requested from the server with a republish.
+    pub(crate) fn republish_targets(&self) -> Vec<(u32, u32)> {
+        let mut targets = self
+            .subscriptions
+            .values()
+            .flat_map(|s| {
+                s.missing_sequence_numbers()
+                    .iter()
+                    .map(|sequence_number| (s.subscription_id(), *sequence_number))
+            })
+            .collect::<Vec<(u32, u32)>>();
+        targets.sort_unstable();
+        targets
+    }
+
+    /// Gives up on recovering a missing notification message.
+    pub(crate) fn forget_republish_target(&mut self, subscription_id: u32, sequence_number: u32) {
+        if let Some(sub) = self.subscriptions.get_mut(&subscription_id) {
+            sub.forget_missing_sequence_number(sequence_number);
+        }
+    }
+
+    /// Get the ids of subscriptions that have received no notification or keep-alive for longer
+    /// than their expected keep-alive period plus `max_silence`. A server that stops publishing
+    /// shows up here, and the caller may use it to trigger a reconnect.
+    pub fn stale_subscriptions(&self, now: DateTime, max_silence: Duration) -> Vec<u32> {
+        let mut subscription_ids = self
+            .subscriptions
+            .values()
+            .filter(|s| s.is_stale(now, max_silence))
+            .map(|s| s.subscription_id())
+            .collect::<Vec<u32>>();
+        subscription_ids.sort_unstable();
+        subscription_ids
+    }
+
+    pub(crate) fn add_subscription(&mut self, mut subscription: Subscription) {
+        subscription.last_notification_time = (self.clock)();
         self.subscriptions
             .insert(subscription.subscription_id(), subscription);
         self.set_keep_alive_timeout();
@@ -115,10 +370,18 @@ impl SubscriptionState {
         }
     }
 
-    pub(crate) fn delete_subscription(&mut self, subscription_id: u32) -> Option<Subscription> {
-        let subscription = self.subscriptions.remove(&subscription_id);
+    /// Remove several subscriptions at once, returning each id with the subscription that was
+    /// removed, or `None` if it was not known.
+    pub(crate) fn delete_subscriptions(
+        &mut self,
+        subscription_ids: &[u32],
+    ) -> Vec<(u32, Option<Subscription>)> {
+        let removed = subscription_ids
+            .iter()
+            .map(|subscription_id| (*subscription_id, self.subscriptions.remove(subscription_id)))
+            .collect();
         self.set_keep_alive_timeout();
-        subscription
+        removed
     }
 
     pub(crate) fn set_publishing_mode(
@@ -143,6 +406,19 @@ impl SubscriptionState {
         }
     }
 
+    /// Insert monitored items into several subscriptions at once. Batches for subscriptions
+    /// that do not exist are ignored.
+    pub(crate) fn insert_monitored_items_batched(
+        &mut self,
+        batches: Vec<(u32, Vec<CreateMonitoredItem>)>,
+    ) {
+        batches
+            .into_iter()
+            .for_each(|(subscription_id, items_to_create)| {
+                self.insert_monitored_items(subscription_id, items_to_create);
+            });
+    }
+
     pub(crate) fn modify_monitored_items(
         &mut self,
         subscription_id: u32,
@@ -153,6 +429,43 @@ impl SubscriptionState {
         }
     }
 
+    /// Makes the modifications that change the sampling interval of several monitored items in a
+    /// subscription at once, each given as `(monitored_item_id, sampling_interval)`. The items keep
+    /// their other parameters and ids that the subscription doesn't have are skipped. The local
+    /// state is left alone until the server responds with the revised values.
+    pub(crate) fn monitored_items_interval_modifications(
+        &self,
+        subscription_id: u32,
+        items: &[(u32, f64)],
+    ) -> Vec<ModifyMonitoredItem> {
+        items
+            .iter()
+            .filter_map(|(monitored_item_id, sampling_interval)| {
+                self.monitored_item(subscription_id, *monitored_item_id)
+                    .map(|monitored_item| ModifyMonitoredItem {
+                        id: *monitored_item_id,
+                        sampling_interval: *sampling_interval,
+                        queue_size: monitored_item.queue_size() as u32,
+                        filter: monitored_item.filter().clone(),
+                        timestamps_to_return: monitored_item.timestamps_to_return(),
+                    })
+            })
+            .collect::<Vec<ModifyMonitoredItem>>()
+    }
+
+    /// Sets the sampling interval of several monitored items in a subscription at once, each
+    /// given as `(monitored_item_id, sampling_interval)`, e.g. with the intervals the server
+    /// revised them to. The items keep their other parameters and ids that the subscription
+    /// doesn't have are skipped.
+    pub(crate) fn modify_monitored_items_interval(
+        &mut self,
+        subscription_id: u32,
+        items: &[(u32, f64)],
+    ) {
+        let items_to_modify = self.monitored_items_interval_modifications(subscription_id, items);
+        self.modify_monitored_items(subscription_id, &items_to_modify);
+    }
+
     pub(crate) fn delete_monitored_items(&mut self, subscription_id: u32, items_to_delete: &[u32]) {
         if let Some(ref mut subscription) = self.subscriptions.get_mut(&subscription_id) {
             subscription.delete_monitored_items(items_to_delete);
@@ -194,7 +507,7 @@ impl SubscriptionState {
     ) {
         self.add_acknowledgement(subscription_id, notification.sequence_number);
         if let Some(sub) = self.subscriptions.get_mut(&subscription_id) {
-            sub.on_notification(notification, decoding_options);
+            sub.on_notification(notification, (self.clock)(), decoding_options);
         }
     }
 
@@ -206,3 +519,1009 @@ impl SubscriptionState {
             .min()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use crate::sync::Mutex;
+    use crate::types::{
+        ContentFilter, DataChangeFilter, DataChangeTrigger, DataValue, DateTime, DeadbandType,
+        DecodingOptions, EventFieldList, EventFilter, ExtensionObject, MonitoredItemNotification,
+        MonitoringMode, NodeId, NotificationMessage, ObjectId, ReadValueId, StatusCode,
+        TimestampsToReturn, Variant,
+    };
+
+    use super::{
+        super::{
+            CreateMonitoredItem, DataChangeCallback, ItemDataChangeCallback, ModifyMonitoredItem,
+            MonitoredItem, RevisedSubscriptionParams, Subscription, SubscriptionBlueprint,
+            SubscriptionCallbacks,
+        },
+        SubscriptionIdGenerator, SubscriptionState,
+    };
+
+    fn subscription(subscription_id: u32) -> Subscription {
+        Subscription::new(
+            subscription_id,
+            Duration::from_millis(100),
+            100,
+            10,
+            0,
+            0,
+            true,
+            Box::new(DataChangeCallback::new(|_, _| {})),
+        )
+    }
+
+    /// Registers a callback on the item that collects the values it is called with.
+    fn collect_values(
+        state: &mut SubscriptionState,
+        subscription_id: u32,
+        monitored_item_id: u32,
+    ) -> Arc<Mutex<Vec<Variant>>> {
+        let values = Arc::new(Mutex::new(Vec::new()));
+        let values_cb = values.clone();
+        state
+            .on_data_change(
+                subscription_id,
+                monitored_item_id,
+                Box::new(move |_, v: &DataValue| trace_lock!(values_cb).extend(v.value.clone())),
+            )
+            .unwrap();
+        values
+    }
+
+    fn monitored_item(id: u32, client_handle: u32) -> CreateMonitoredItem {
+        CreateMonitoredItem {
+            id,
+            client_handle,
+            item_to_monitor: ReadValueId::from(NodeId::new(2, id)),
+            monitoring_mode: MonitoringMode::Reporting,
+            queue_size: 1,
+            discard_oldest: true,
+            sampling_interval: 100.0,
+            filter: ExtensionObject::null(),
+            timestamps_to_return: TimestampsToReturn::Both,
+        }
+    }
+
+    #[test]
+    fn monitored_items() {
+        let mut state = SubscriptionState::new(Duration::from_millis(50));
+        state.add_subscription(subscription(1));
+        state.insert_monitored_items(1, vec![monitored_item(10, 100), monitored_item(11, 101)]);
+
+        let mut ids = state.monitored_item_ids(1).unwrap();
+        ids.sort();
+        assert_eq!(ids, vec![10, 11]);
+
+        let item = state.monitored_item(1, 11).unwrap();
+        assert_eq!(item.id(), 11);
+        assert_eq!(item.client_handle(), 101);
+        assert_eq!(item.item_to_monitor().node_id, NodeId::new(2, 11));
+
+        assert!(state.monitored_item(1, 12).is_none());
+        assert!(state.monitored_item(2, 10).is_none());
+        assert!(state.monitored_item_ids(2).is_none());
+
+        // A subscription with no items has an empty list rather than none
+        state.delete_monitored_items(1, &[10, 11]);
+        assert_eq!(state.monitored_item_ids(1), Some(vec![]));
+    }
+
+    #[test]
+    fn insert_monitored_items_batched() {
+        let mut state = SubscriptionState::new(Duration::from_millis(50));
+        state.add_subscription(subscription(1));
+        state.add_subscription(subscription(2));
+
+        state.insert_monitored_items_batched(vec![
+            (1, vec![monitored_item(10, 100), monitored_item(11, 101)]),
+            (2, vec![monitored_item(20, 200)]),
+            // No such subscription
+            (3, vec![monitored_item(30, 300)]),
+        ]);
+
+        let mut ids = state.monitored_item_ids(1).unwrap();
+        ids.sort();
+        assert_eq!(ids, vec![10, 11]);
+        assert_eq!(state.monitored_item_ids(2), Some(vec![20]));
+        assert!(state.monitored_item_ids(3).is_none());
+
+        assert_eq!(state.find_by_client_handle(101), Some((1, 11)));
+        assert_eq!(state.find_by_client_handle(200), Some((2, 20)));
+        assert_eq!(state.find_by_client_handle(300), None);
+    }
+
+    #[test]
+    fn modify_monitored_items_filter() {
+        let mut state = SubscriptionState::new(Duration::from_millis(50));
+        state.add_subscription(subscription(1));
+        state.insert_monitored_items(1, vec![monitored_item(10, 100)]);
+        assert!(state.monitored_item(1, 10).unwrap().filter().is_null());
+
+        let filter = ExtensionObject::from_encodable(
+            ObjectId::DataChangeFilter_Encoding_DefaultBinary,
+            &DataChangeFilter {
+                trigger: DataChangeTrigger::StatusValue,
+                deadband_type: DeadbandType::Absolute as u32,
+                deadband_value: 1f64,
+            },
+        );
+        state.modify_monitored_items(
+            1,
+            &[ModifyMonitoredItem {
+                id: 10,
+                sampling_interval: 200.0,
+                queue_size: 5,
+                filter: filter.clone(),
+                timestamps_to_return: TimestampsToReturn::Source,
+            }],
+        );
+
+        let item = state.monitored_item(1, 10).unwrap();
+        assert_eq!(item.sampling_interval(), 200.0);
+        assert_eq!(item.queue_size(), 5);
+        assert_eq!(item.filter(), &filter);
+        assert_eq!(item.timestamps_to_return(), TimestampsToReturn::Source);
+    }
+
+    #[test]
+    fn monitored_items_interval_modifications() {
+        let mut state = SubscriptionState::new(Duration::from_millis(50));
+        state.add_subscription(subscription(1));
+        state.insert_monitored_items(1, vec![monitored_item(10, 100), monitored_item(11, 101)]);
+        let queue_size = state.monitored_item(1, 10).unwrap().queue_size();
+        let sampling_interval = state.monitored_item(1, 10).unwrap().sampling_interval();
+
+        // Unknown items are skipped
+        let items_to_modify = state
+            .monitored_items_interval_modifications(1, &[(10, 1000.0), (12, 1000.0), (11, 250.0)]);
+        assert_eq!(
+            items_to_modify
+                .iter()
+                .map(|i| (i.id, i.sampling_interval))
+                .collect::<Vec<(u32, f64)>>(),
+            vec![(10, 1000.0), (11, 250.0)]
+        );
+        assert!(items_to_modify
+            .iter()
+            .all(|i| i.queue_size as usize == queue_size));
+
+        // The local state is unchanged until the server's revised values are applied
+        assert_eq!(
+            state.monitored_item(1, 10).unwrap().sampling_interval(),
+            sampling_interval
+        );
+        state.modify_monitored_items_interval(1, &[(10, 800.0), (12, 800.0), (11, 250.0)]);
+        assert_eq!(
+            state.monitored_item(1, 10).unwrap().sampling_interval(),
+            800.0
+        );
+        assert_eq!(
+            state.monitored_item(1, 11).unwrap().sampling_interval(),
+            250.0
+        );
+        assert_eq!(
+            state.monitored_item(1, 10).unwrap().queue_size(),
+            queue_size
+        );
+        assert!(state.monitored_item(1, 12).is_none());
+
+        // Nothing is modified in a subscription that doesn't exist
+        assert!(state
+            .monitored_items_interval_modifications(2, &[(10, 500.0)])
+            .is_empty());
+        state.modify_monitored_items_interval(2, &[(10, 500.0)]);
+        assert_eq!(
+            state.monitored_item(1, 10).unwrap().sampling_interval(),
+            800.0
+        );
+    }
+
+    #[test]
+    fn stale_subscriptions() {
+        let mut state = SubscriptionState::new(Duration::from_millis(50));
+        // Keep-alive expected every 100ms * 10
+        state.add_subscription(subscription(1));
+        // Keep-alive expected every 1s * 10
+        state.add_subscription(Subscription::new(
+            2,
+            Duration::from_secs(1),
+            100,
+            10,
+            0,
+            0,
+            true,
+            Box::new(DataChangeCallback::new(|_, _| {})),
+        ));
+        let created = state.get(1).unwrap().last_notification_time();
+
+        assert!(state
+            .stale_subscriptions(created, Duration::ZERO)
+            .is_empty());
+        assert!(state
+            .stale_subscriptions(
+                created + chrono::Duration::milliseconds(900),
+                Duration::ZERO
+            )
+            .is_empty());
+
+        // Past the first subscription's keep-alive but within the grace period
+        let now = created + chrono::Duration::milliseconds(1200);
+        assert!(state
+            .stale_subscriptions(now, Duration::from_millis(500))
+            .is_empty());
+        assert_eq!(state.stale_subscriptions(now, Duration::ZERO), vec![1]);
+
+        // Past both keep-alive periods
+        let now = created + chrono::Duration::seconds(11);
+        assert_eq!(state.stale_subscriptions(now, Duration::ZERO), vec![1, 2]);
+
+        // A keep-alive resets the silence
+        state.handle_notification(
+            1,
+            NotificationMessage::keep_alive(1, DateTime::now()),
+            &DecodingOptions::default(),
+        );
+        let received = state.get(1).unwrap().last_notification_time();
+        assert!(received >= created);
+        assert!(state
+            .stale_subscriptions(received, Duration::ZERO)
+            .is_empty());
+        assert_eq!(
+            state.stale_subscriptions(received + chrono::Duration::seconds(2), Duration::ZERO),
+            vec![1]
+        );
+    }
+
+    #[test]
+    fn subscription_id_generator() {
+        let mut generator = SubscriptionIdGenerator::new(1);
+        assert_eq!(generator.next(|_| false), 1);
+        assert_eq!(generator.next(|_| false), 2);
+        assert_eq!(generator.next(|_| false), 3);
+
+        // Ids the server has assigned are skipped
+        let server_ids = [4, 5, 7];
+        let ids: Vec<u32> = (0..3)
+            .map(|_| generator.next(|id| server_ids.contains(&id)))
+            .collect();
+        assert_eq!(ids, vec![6, 8, 9]);
+
+        let mut generator = SubscriptionIdGenerator::default();
+        assert_eq!(
+            generator.next(|_| false),
+            SubscriptionIdGenerator::FIRST_LOCAL_SUBSCRIPTION_ID
+        );
+    }
+
+    fn fixed_clock() -> DateTime {
+        DateTime::ymd(2024, 1, 1)
+    }
+
+    #[test]
+    fn local_subscriptions() {
+        let mut state = SubscriptionState::new(Duration::from_millis(50));
+        let now = Arc::new(Mutex::new(fixed_clock()));
+        state.set_clock({
+            let now = now.clone();
+            Arc::new(move || *trace_lock!(now))
+        });
+
+        let first = SubscriptionIdGenerator::FIRST_LOCAL_SUBSCRIPTION_ID;
+        // An existing subscription and one being transferred both hold ids to avoid
+        state.add_subscription(subscription(first));
+        let subscription_id = state.next_local_subscription_id(&[first + 1]);
+        assert_eq!(subscription_id, first + 2);
+        state.add_subscription(subscription(subscription_id));
+        assert_eq!(state.next_local_subscription_id(&[]), first + 3);
+
+        // Subscriptions and notifications are stamped by the supplied clock
+        assert_eq!(
+            state.get(subscription_id).unwrap().last_notification_time(),
+            fixed_clock()
+        );
+        state.handle_notification(
+            subscription_id,
+            NotificationMessage::keep_alive(1, DateTime::now()),
+            &DecodingOptions::default(),
+        );
+        assert_eq!(
+            state.get(subscription_id).unwrap().last_notification_time(),
+            fixed_clock()
+        );
+        assert!(state
+            .stale_subscriptions(fixed_clock(), Duration::ZERO)
+            .is_empty());
+        assert_eq!(
+            state.stale_subscriptions(fixed_clock() + chrono::Duration::seconds(2), Duration::ZERO),
+            vec![first, subscription_id]
+        );
+
+        // Advancing the clock stamps later notifications with the new time
+        let later = fixed_clock() + chrono::Duration::seconds(5);
+        *trace_lock!(now) = later;
+        state.handle_notification(
+            subscription_id,
+            NotificationMessage::keep_alive(2, DateTime::now()),
+            &DecodingOptions::default(),
+        );
+        assert_eq!(
+            state.get(subscription_id).unwrap().last_notification_time(),
+            later
+        );
+    }
+
+    #[test]
+    fn delete_subscriptions() {
+        let mut state = SubscriptionState::new(Duration::from_millis(50));
+        state.add_subscription(subscription(1));
+        state.add_subscription(subscription(2));
+        state.add_subscription(subscription(3));
+
+        let removed = state.delete_subscriptions(&[1, 4, 3]);
+        assert_eq!(removed.len(), 3);
+        assert_eq!(removed[0].0, 1);
+        assert_eq!(removed[0].1.as_ref().unwrap().subscription_id(), 1);
+        assert_eq!(removed[1].0, 4);
+        assert!(removed[1].1.is_none());
+        assert_eq!(removed[2].0, 3);
+        assert_eq!(removed[2].1.as_ref().unwrap().subscription_id(), 3);
+
+        assert_eq!(state.subscription_ids(), Some(vec![2]));
+    }
+
+    #[test]
+    fn find_by_client_handle() {
+        let mut state = SubscriptionState::new(Duration::from_millis(50));
+        state.add_subscription(subscription(1));
+        state.add_subscription(subscription(2));
+        // Both subscriptions use the same monitored item ids, but handles are distinct
+        state.insert_monitored_items(1, vec![monitored_item(1, 100), monitored_item(2, 101)]);
+        state.insert_monitored_items(2, vec![monitored_item(1, 200), monitored_item(2, 201)]);
+
+        assert_eq!(state.find_by_client_handle(100), Some((1, 1)));
+        assert_eq!(state.find_by_client_handle(101), Some((1, 2)));
+        assert_eq!(state.find_by_client_handle(200), Some((2, 1)));
+        assert_eq!(state.find_by_client_handle(201), Some((2, 2)));
+        assert_eq!(state.find_by_client_handle(300), None);
+
+        // Deleted items can no longer be found
+        state.delete_monitored_items(2, &[2]);
+        assert_eq!(state.find_by_client_handle(201), None);
+    }
+
+    #[test]
+    fn drain_and_transfer_subscriptions() {
+        let mut state = SubscriptionState::new(Duration::from_millis(50));
+        state.add_subscription(subscription(3));
+        state.add_subscription(subscription(1));
+        state.add_subscription(subscription(2));
+        state.insert_monitored_items(1, vec![monitored_item(10, 100)]);
+        state.insert_monitored_items(2, vec![monitored_item(20, 200), monitored_item(21, 201)]);
+        state.set_triggering(2, 20, &[21], &[]);
+
+        assert_eq!(state.pending_transfer_ids(), Some(vec![1, 2, 3]));
+        assert!(state.next_publish_time().is_some());
+
+        let mut subscriptions = state.drain_subscriptions();
+        assert_eq!(subscriptions.len(), 3);
+        assert!(state.pending_transfer_ids().is_none());
+        assert!(state.next_publish_time().is_none());
+
+        // Only some of the subscriptions are transferred
+        let _ = subscriptions.remove(&3);
+        state.transfer_subscriptions(subscriptions);
+        assert_eq!(state.pending_transfer_ids(), Some(vec![1, 2]));
+        assert!(!state.subscription_exists(3));
+
+        // Monitored items survive the transfer
+        let item = state.monitored_item(2, 20).unwrap();
+        assert_eq!(item.client_handle(), 200);
+        assert!(item.triggered_items().contains(&21));
+        assert_eq!(state.find_by_client_handle(201), Some((2, 21)));
+        assert_eq!(state.find_by_client_handle(100), Some((1, 10)));
+    }
+
+    #[test]
+    fn revised_parameters() {
+        let mut state = SubscriptionState::new(Duration::from_millis(50));
+        state.add_subscription(subscription(1));
+        assert!(state.revised_parameters(2).is_none());
+        assert_eq!(
+            state.revised_parameters(1),
+            Some(RevisedSubscriptionParams {
+                publishing_interval: Duration::from_millis(100),
+                lifetime_count: 100,
+                max_keep_alive_count: 10,
+            })
+        );
+
+        // A modify stores the values revised by the server
+        state.modify_subscription(1, Duration::from_millis(500), 300, 20, 0, 0);
+        assert_eq!(
+            state.revised_parameters(1),
+            Some(RevisedSubscriptionParams {
+                publishing_interval: Duration::from_millis(500),
+                lifetime_count: 300,
+                max_keep_alive_count: 20,
+            })
+        );
+    }
+
+    #[test]
+    fn mark_all_for_recreation() {
+        let mut state = SubscriptionState::new(Duration::from_millis(50));
+        state.add_subscription(subscription(2));
+        state.add_subscription(subscription(1));
+        state.insert_monitored_items(1, vec![monitored_item(10, 100)]);
+        state.insert_monitored_items(2, vec![monitored_item(21, 201), monitored_item(20, 200)]);
+
+        // Nothing is replayed until the subscriptions are marked
+        assert!(state.recreation_plan().is_empty());
+        state.mark_all_for_recreation();
+        assert!(state.get(1).unwrap().is_marked_for_recreation());
+
+        // The plan holds every subscription with all its items and their original handles
+        let plan = state.recreation_plan();
+        assert_eq!(
+            plan.iter().map(|s| s.subscription_id).collect::<Vec<u32>>(),
+            vec![1, 2]
+        );
+        let handles = |i: usize| {
+            plan[i]
+                .monitored_items
+                .iter()
+                .map(|item| (item.item_to_monitor.node_id.clone(), item.client_handle))
+                .collect::<Vec<(NodeId, u32)>>()
+        };
+        assert_eq!(handles(0), vec![(NodeId::new(2, 10u32), 100)]);
+        assert_eq!(
+            handles(1),
+            vec![(NodeId::new(2, 20u32), 200), (NodeId::new(2, 21u32), 201)]
+        );
+        assert_eq!(plan[1].publishing_interval, Duration::from_millis(100));
+        assert_eq!(plan[1].lifetime_count, 100);
+        assert_eq!(plan[1].max_keep_alive_count, 10);
+        assert!(plan[1].publishing_enabled);
+        let item = &plan[1].monitored_items[0];
+        assert_eq!(item.monitoring_mode, MonitoringMode::Reporting);
+        assert_eq!(item.sampling_interval, 100.0);
+        assert_eq!(item.queue_size, 1);
+        assert!(item.discard_oldest);
+        assert_eq!(item.timestamps_to_return, TimestampsToReturn::Both);
+
+        // Subscriptions that are transferred no longer need recreating
+        let mut subscriptions = state.drain_subscriptions();
+        let _ = subscriptions.remove(&2);
+        state.transfer_subscriptions(subscriptions);
+        assert!(!state.get(1).unwrap().is_marked_for_recreation());
+        assert!(state.recreation_plan().is_empty());
+    }
+
+    #[test]
+    fn export_blueprints() {
+        let mut state = SubscriptionState::new(Duration::from_millis(50));
+        state.add_subscription(subscription(2));
+        state.add_subscription(subscription(1));
+        let mut filtered = monitored_item(11, 101);
+        filtered.filter = ExtensionObject::from_encodable(
+            ObjectId::DataChangeFilter_Encoding_DefaultBinary,
+            &DataChangeFilter {
+                trigger: DataChangeTrigger::StatusValue,
+                deadband_type: DeadbandType::Absolute as u32,
+                deadband_value: 1f64,
+            },
+        );
+        filtered.timestamps_to_return = TimestampsToReturn::Source;
+        state.insert_monitored_items(1, vec![filtered, monitored_item(10, 100)]);
+        state.insert_monitored_items(2, vec![monitored_item(20, 200)]);
+        state.set_triggering(1, 10, &[11], &[]);
+
+        // Values received are not part of the blueprint
+        state.handle_notification(
+            1,
+            NotificationMessage::data_change(
+                1,
+                DateTime::now(),
+                vec![MonitoredItemNotification {
+                    client_handle: 100,
+                    value: DataValue::new_now(1i32),
+                }],
+                vec![],
+            ),
+            &DecodingOptions::test(),
+        );
+
+        let blueprints = state.export_blueprints();
+        assert_eq!(
+            blueprints
+                .iter()
+                .map(|s| s.subscription_id)
+                .collect::<Vec<u32>>(),
+            vec![1, 2]
+        );
+        let items = &blueprints[0].monitored_items;
+        assert_eq!(
+            items.iter().map(|i| i.client_handle).collect::<Vec<u32>>(),
+            vec![100, 101]
+        );
+        // Triggering links are kept by client handle
+        assert_eq!(items[0].triggered_items, vec![101]);
+        assert!(items[1].triggered_items.is_empty());
+        assert_eq!(items[1].timestamps_to_return, TimestampsToReturn::Source);
+        assert!(!items[1].filter.is_null());
+        let request = items[1].create_request();
+        assert_eq!(request.requested_parameters.client_handle, 101);
+        assert_eq!(request.requested_parameters.filter, items[1].filter);
+
+        // The blueprints survive a round trip through json and yaml
+        let json = serde_json::to_string(&blueprints).unwrap();
+        let from_json: Vec<SubscriptionBlueprint> = serde_json::from_str(&json).unwrap();
+        assert_eq!(from_json, blueprints);
+        let yaml = serde_yaml::to_string(&blueprints).unwrap();
+        let from_yaml: Vec<SubscriptionBlueprint> = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(from_yaml, blueprints);
+    }
+
+    #[test]
+    fn stats() {
+        let mut state = SubscriptionState::new(Duration::from_millis(50));
+        state.add_subscription(subscription(1));
+        state.insert_monitored_items(1, vec![monitored_item(10, 100), monitored_item(11, 101)]);
+        assert!(state.stats(2).is_none());
+
+        let stats = state.stats(1).unwrap();
+        assert_eq!(stats.notifications_received, 0);
+        assert!(stats.last_publish_time.is_none());
+
+        let decoding_options = DecodingOptions::test();
+        let data_change = |client_handle: u32, value: i32| MonitoredItemNotification {
+            client_handle,
+            value: DataValue::value_only(value),
+        };
+        let publish_time = DateTime::now();
+        state.handle_notification(
+            1,
+            NotificationMessage::data_change(
+                1,
+                publish_time,
+                vec![data_change(100, 1), data_change(101, 2)],
+                vec![],
+            ),
+            &decoding_options,
+        );
+        // A value for a client handle that matches no item is not counted
+        state.handle_notification(
+            1,
+            NotificationMessage::data_change(
+                2,
+                publish_time,
+                vec![data_change(100, 3), data_change(999, 4)],
+                vec![],
+            ),
+            &decoding_options,
+        );
+        state.handle_notification(
+            1,
+            NotificationMessage::keep_alive(3, publish_time),
+            &decoding_options,
+        );
+        state.handle_notification(
+            1,
+            NotificationMessage::status_change(3, publish_time, StatusCode::BadTimeout),
+            &decoding_options,
+        );
+
+        let stats = state.stats(1).unwrap();
+        assert_eq!(stats.notifications_received, 4);
+        assert_eq!(stats.data_changes, 3);
+        assert_eq!(stats.keep_alives, 1);
+        assert_eq!(stats.last_sequence_number, 3);
+        assert_eq!(stats.last_publish_time, Some(publish_time));
+    }
+
+    #[test]
+    fn missed_notifications() {
+        let mut state = SubscriptionState::new(Duration::from_millis(50));
+        state.add_subscription(subscription(1));
+        state.insert_monitored_items(1, vec![monitored_item(10, 100)]);
+        assert_eq!(state.missed_notifications(1), 0);
+        assert_eq!(state.missed_notifications(2), 0);
+
+        let decoding_options = DecodingOptions::test();
+        let publish_time = DateTime::now();
+        let data_change = |sequence_number: u32| {
+            NotificationMessage::data_change(
+                sequence_number,
+                publish_time,
+                vec![MonitoredItemNotification {
+                    client_handle: 100,
+                    value: DataValue::value_only(sequence_number as i32),
+                }],
+                vec![],
+            )
+        };
+
+        // Sequence number 3 never arrives
+        for sequence_number in [1, 2, 4] {
+            state.handle_notification(1, data_change(sequence_number), &decoding_options);
+        }
+        assert_eq!(state.missed_notifications(1), 1);
+
+        // A keep-alive holds the next sequence number without using it up
+        state.handle_notification(
+            1,
+            NotificationMessage::keep_alive(5, publish_time),
+            &decoding_options,
+        );
+        state.handle_notification(1, data_change(5), &decoding_options);
+        assert_eq!(state.missed_notifications(1), 1);
+
+        // A keep-alive reveals that 6 and 7 were dropped
+        state.handle_notification(
+            1,
+            NotificationMessage::keep_alive(8, publish_time),
+            &decoding_options,
+        );
+        assert_eq!(state.missed_notifications(1), 3);
+        assert_eq!(state.stats(1).unwrap().missed_notifications, 3);
+
+        // A late message from before the expected one doesn't move the sequence back
+        state.handle_notification(1, data_change(6), &decoding_options);
+        state.handle_notification(1, data_change(8), &decoding_options);
+        assert_eq!(state.missed_notifications(1), 3);
+
+        // A jump too big to recover, e.g. from a server restart, starts the sequence over
+        state.handle_notification(1, data_change(100_000), &decoding_options);
+        state.handle_notification(1, data_change(100_001), &decoding_options);
+        assert_eq!(state.missed_notifications(1), 3);
+        assert!(state.republish_targets().is_empty());
+    }
+
+    #[test]
+    fn sequence_number_wraps() {
+        let mut state = SubscriptionState::new(Duration::from_millis(50));
+        state.add_subscription(subscription(1));
+        state.insert_monitored_items(1, vec![monitored_item(10, 100)]);
+
+        let decoding_options = DecodingOptions::test();
+        let data_change = |sequence_number: u32, value: i32| {
+            NotificationMessage::data_change(
+                sequence_number,
+                DateTime::now(),
+                vec![MonitoredItemNotification {
+                    client_handle: 100,
+                    value: DataValue::value_only(value),
+                }],
+                vec![],
+            )
+        };
+
+        // Sequence numbers go from u32::MAX back to 1, skipping 0
+        state.handle_notification(1, data_change(u32::MAX - 1, 1), &decoding_options);
+        state.handle_notification(1, data_change(u32::MAX, 2), &decoding_options);
+        state.handle_notification(1, data_change(1, 3), &decoding_options);
+        assert_eq!(state.missed_notifications(1), 0);
+
+        // A gap across the wrap is recovered
+        state.add_subscription(subscription(2));
+        state.insert_monitored_items(2, vec![monitored_item(20, 200)]);
+        let values = collect_values(&mut state, 2, 20);
+        let data_change = |sequence_number: u32, value: i32| {
+            NotificationMessage::data_change(
+                sequence_number,
+                DateTime::now(),
+                vec![MonitoredItemNotification {
+                    client_handle: 200,
+                    value: DataValue::value_only(value),
+                }],
+                vec![],
+            )
+        };
+        state.handle_notification(2, data_change(u32::MAX - 1, 4), &decoding_options);
+        state.handle_notification(2, data_change(2, 5), &decoding_options);
+        assert_eq!(state.republish_targets(), vec![(2, 1), (2, u32::MAX)]);
+        assert_eq!(state.missed_notifications(2), 2);
+
+        // A recovered message from before the wrap is older than the item's value
+        state.handle_notification(2, data_change(u32::MAX, 6), &decoding_options);
+        assert_eq!(state.republish_targets(), vec![(2, 1)]);
+        assert_eq!(
+            *trace_lock!(values),
+            vec![Variant::from(4i32), Variant::from(5i32)]
+        );
+    }
+
+    #[test]
+    fn republish_targets() {
+        let mut state = SubscriptionState::new(Duration::from_millis(50));
+        state.add_subscription(subscription(1));
+        state.add_subscription(subscription(2));
+        state.insert_monitored_items(1, vec![monitored_item(10, 100)]);
+        let values = collect_values(&mut state, 1, 10);
+        assert!(state.republish_targets().is_empty());
+
+        let decoding_options = DecodingOptions::test();
+        let publish_time = DateTime::now();
+        let data_change = |sequence_number: u32| {
+            NotificationMessage::data_change(
+                sequence_number,
+                publish_time,
+                vec![MonitoredItemNotification {
+                    client_handle: 100,
+                    value: DataValue::value_only(sequence_number as i32),
+                }],
+                vec![],
+            )
+        };
+
+        // 2 and 3 go missing on subscription 1, subscription 2 is unaffected
+        for sequence_number in [1, 4] {
+            state.handle_notification(1, data_change(sequence_number), &decoding_options);
+        }
+        state.handle_notification(
+            2,
+            NotificationMessage::keep_alive(1, publish_time),
+            &decoding_options,
+        );
+        assert_eq!(state.republish_targets(), vec![(1, 2), (1, 3)]);
+        let _ = state.take_acknowledgements();
+
+        // The republished message doesn't count as a new message, and its value is older than
+        // the one from message 4 so the item keeps that and it isn't counted as delivered
+        state.handle_notification(1, data_change(2), &decoding_options);
+        assert_eq!(state.republish_targets(), vec![(1, 3)]);
+        let stats = state.stats(1).unwrap();
+        assert_eq!(stats.notifications_received, 2);
+        assert_eq!(stats.data_changes, 2);
+        assert_eq!(stats.last_sequence_number, 4);
+        assert_eq!(stats.missed_notifications, 2);
+        assert_eq!(
+            *trace_lock!(values),
+            vec![Variant::from(1i32), Variant::from(4i32)]
+        );
+        // and is still acknowledged
+        let acks = state.take_acknowledgements();
+        assert_eq!(acks.len(), 1);
+        assert_eq!(acks[0].sequence_number, 2);
+
+        // The next message carries on from the latest one, not the recovered one
+        state.handle_notification(1, data_change(5), &decoding_options);
+        assert_eq!(state.republish_targets(), vec![(1, 3)]);
+        assert_eq!(state.missed_notifications(1), 2);
+
+        // A message the server can't republish is given up on
+        state.forget_republish_target(1, 3);
+        assert!(state.republish_targets().is_empty());
+    }
+
+    #[test]
+    fn data_change_timestamps() {
+        let mut state = SubscriptionState::new(Duration::from_millis(50));
+        state.add_subscription(subscription(1));
+        let mut source_only = monitored_item(11, 101);
+        source_only.timestamps_to_return = TimestampsToReturn::Source;
+        state.insert_monitored_items(1, vec![monitored_item(10, 100), source_only]);
+        assert!(state
+            .monitored_item(1, 10)
+            .unwrap()
+            .source_timestamp()
+            .is_none());
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let received_cb = received.clone();
+        state
+            .on_data_change(
+                1,
+                11,
+                Box::new(move |_, v: &DataValue| trace_lock!(received_cb).push(v.clone())),
+            )
+            .unwrap();
+
+        let source_timestamp = DateTime::ymd_hms(2024, 1, 1, 0, 0, 0);
+        let server_timestamp = DateTime::ymd_hms(2024, 1, 1, 0, 0, 1);
+        let data_change = |client_handle: u32| MonitoredItemNotification {
+            client_handle,
+            value: DataValue {
+                value: Some(Variant::from(1i32)),
+                status: Some(StatusCode::Good),
+                source_timestamp: Some(source_timestamp),
+                source_picoseconds: None,
+                server_timestamp: Some(server_timestamp),
+                server_picoseconds: None,
+            },
+        };
+        state.handle_notification(
+            1,
+            NotificationMessage::data_change(
+                1,
+                DateTime::now(),
+                vec![data_change(100), data_change(101)],
+                vec![],
+            ),
+            &DecodingOptions::test(),
+        );
+
+        // Both timestamps survive the data change
+        let item = state.monitored_item(1, 10).unwrap();
+        assert_eq!(item.source_timestamp(), Some(source_timestamp));
+        assert_eq!(item.server_timestamp(), Some(server_timestamp));
+
+        // Only the source timestamp was asked for
+        let item = state.monitored_item(1, 11).unwrap();
+        assert_eq!(item.source_timestamp(), Some(source_timestamp));
+        assert_eq!(item.server_timestamp(), None);
+
+        // but the callback gets the value as the server sent it
+        assert_eq!(*trace_lock!(received), vec![data_change(101).value]);
+    }
+
+    #[test]
+    fn events_and_data_changes() {
+        // Record which item each notification was delivered to
+        let data_values = Arc::new(Mutex::new(Vec::new()));
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let callbacks = {
+            let data_values = data_values.clone();
+            let events = events.clone();
+            SubscriptionCallbacks::new(
+                |_| {},
+                move |v, item| trace_lock!(data_values).push((item.id(), v.value)),
+                move |f, item| trace_lock!(events).push((item.id(), f)),
+            )
+        };
+
+        let mut state = SubscriptionState::new(Duration::from_millis(50));
+        state.add_subscription(Subscription::new(
+            1,
+            Duration::from_millis(100),
+            100,
+            10,
+            0,
+            0,
+            true,
+            Box::new(callbacks),
+        ));
+        let filter = ExtensionObject::from_encodable(
+            ObjectId::EventFilter_Encoding_DefaultBinary,
+            &EventFilter {
+                select_clauses: None,
+                where_clause: ContentFilter { elements: None },
+            },
+        );
+        let mut event_item = monitored_item(11, 101);
+        event_item.filter = filter.clone();
+        state.insert_monitored_items(1, vec![monitored_item(10, 100), event_item]);
+        assert_eq!(state.monitored_item(1, 11).unwrap().filter(), &filter);
+        assert!(state.monitored_item(1, 10).unwrap().filter().is_null());
+
+        // A single message containing both data changes and events
+        state.handle_notification(
+            1,
+            NotificationMessage::data_change(
+                1,
+                DateTime::now(),
+                vec![MonitoredItemNotification {
+                    client_handle: 100,
+                    value: DataValue::value_only(5i32),
+                }],
+                vec![
+                    EventFieldList {
+                        client_handle: 101,
+                        event_fields: Some(vec![Variant::from("Event 1")]),
+                    },
+                    EventFieldList {
+                        client_handle: 101,
+                        event_fields: Some(vec![Variant::from("Event 2")]),
+                    },
+                    // Unknown handle is ignored
+                    EventFieldList {
+                        client_handle: 999,
+                        event_fields: None,
+                    },
+                ],
+            ),
+            &DecodingOptions::test(),
+        );
+
+        assert_eq!(
+            *trace_lock!(data_values),
+            vec![(10, Some(Variant::from(5i32)))]
+        );
+        assert_eq!(
+            *trace_lock!(events),
+            vec![
+                (11, Some(vec![Variant::from("Event 1")])),
+                (11, Some(vec![Variant::from("Event 2")])),
+            ]
+        );
+    }
+
+    #[test]
+    fn item_data_change_callbacks() {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let callback = |name: &'static str| {
+            let received = received.clone();
+            Box::new(move |item: &MonitoredItem, v: &DataValue| {
+                trace_lock!(received).push((name, item.id(), v.value.clone()))
+            }) as ItemDataChangeCallback
+        };
+        let data_change = |client_handle: u32, value: i32| {
+            NotificationMessage::data_change(
+                1,
+                DateTime::now(),
+                vec![MonitoredItemNotification {
+                    client_handle,
+                    value: DataValue::value_only(value),
+                }],
+                vec![],
+            )
+        };
+
+        let mut state = SubscriptionState::new(Duration::from_millis(50));
+        state.add_subscription(subscription(1));
+        state.insert_monitored_items(1, vec![monitored_item(10, 100), monitored_item(11, 101)]);
+
+        // Every callback on an item is called, in the order they were registered
+        state.on_data_change(1, 10, callback("a")).unwrap();
+        state.on_data_change(1, 10, callback("b")).unwrap();
+        state.on_data_change(1, 11, callback("c")).unwrap();
+        state.handle_notification(1, data_change(100, 5), &DecodingOptions::test());
+        assert_eq!(
+            *trace_lock!(received),
+            vec![
+                ("a", 10, Some(Variant::from(5i32))),
+                ("b", 10, Some(Variant::from(5i32))),
+            ]
+        );
+
+        // Unknown subscriptions and items are rejected
+        assert_eq!(
+            state.on_data_change(2, 10, callback("d")),
+            Err(StatusCode::BadSubscriptionIdInvalid)
+        );
+        assert_eq!(
+            state.on_data_change(1, 12, callback("d")),
+            Err(StatusCode::BadMonitoredItemIdInvalid)
+        );
+
+        // Deleting an item drops its callbacks
+        trace_lock!(received).clear();
+        state.delete_monitored_items(1, &[10]);
+        state.insert_monitored_items(1, vec![monitored_item(10, 100)]);
+        state.handle_notification(1, data_change(100, 6), &DecodingOptions::test());
+        state.handle_notification(1, data_change(101, 7), &DecodingOptions::test());
+        assert_eq!(
+            *trace_lock!(received),
+            vec![("c", 11, Some(Variant::from(7i32)))]
+        );
+
+        // A recreated subscription gets the callbacks back on the items with the same client
+        // handles, even though the items have new ids. The callbacks are restored before the
+        // items are created so the first data change reaches them.
+        trace_lock!(received).clear();
+        let mut old = state.drain_subscriptions().remove(&1).unwrap();
+        let callbacks = old.take_data_change_callbacks();
+        state.add_subscription(subscription(2));
+        state.restore_data_change_callbacks(2, callbacks);
+        state.insert_monitored_items(2, vec![monitored_item(21, 101)]);
+        state.handle_notification(2, data_change(101, 8), &DecodingOptions::test());
+        assert_eq!(
+            *trace_lock!(received),
+            vec![("c", 21, Some(Variant::from(8i32)))]
+        );
+
+        // Callbacks of items that were not recreated are dropped
+        trace_lock!(received).clear();
+        let mut old = state.drain_subscriptions().remove(&2).unwrap();
+        let callbacks = old.take_data_change_callbacks();
+        state.add_subscription(subscription(3));
+        state.restore_data_change_callbacks(3, callbacks);
+        state.forget_restored_data_change_callbacks(3);
+        state.insert_monitored_items(3, vec![monitored_item(31, 101)]);
+        state.handle_notification(3, data_change(101, 9), &DecodingOptions::test());
+        assert!(trace_lock!(received).is_empty());
+    }
+}
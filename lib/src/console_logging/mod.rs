@@ -75,7 +75,71 @@ pub fn init() {
 
             writeln!(f, "{} {} {} {}", time_fmt, level, target, args)
         });
+        #[cfg(not(test))]
         builder.init();
+        #[cfg(test)]
+        capture::init(builder.build());
         info!("Logging is enabled, use RUST_OPCUA_LOG environment variable to control filtering, logging level");
     }
 }
+
+/// Lets tests capture what is logged, e.g. to check what a service logs. In tests the console
+/// logger is wrapped in a logger that also copies each message to the capture of the thread that
+/// logged it.
+#[cfg(test)]
+pub(crate) mod capture {
+    use std::cell::RefCell;
+
+    use log::{Level, LevelFilter, Log, Metadata, Record};
+
+    thread_local! {
+        static CAPTURED: RefCell<Option<Vec<(Level, String)>>> = const { RefCell::new(None) };
+    }
+
+    fn is_capturing() -> bool {
+        CAPTURED.with(|captured| captured.borrow().is_some())
+    }
+
+    struct CapturingLogger {
+        console: env_logger::Logger,
+    }
+
+    impl Log for CapturingLogger {
+        fn enabled(&self, metadata: &Metadata) -> bool {
+            is_capturing() || self.console.enabled(metadata)
+        }
+
+        fn log(&self, record: &Record) {
+            CAPTURED.with(|captured| {
+                if let Some(ref mut captured) = *captured.borrow_mut() {
+                    captured.push((record.level(), record.args().to_string()));
+                }
+            });
+            self.console.log(record);
+        }
+
+        fn flush(&self) {
+            self.console.flush();
+        }
+    }
+
+    pub(super) fn init(console: env_logger::Logger) {
+        // Every level reaches the logger so it can be captured, the console logger still
+        // filters what it writes
+        log::set_max_level(LevelFilter::Trace);
+        log::set_boxed_logger(Box::new(CapturingLogger { console }))
+            .expect("A logger is already set");
+    }
+
+    /// Calls `f` and returns the messages that were logged on this thread while it ran, with
+    /// their levels.
+    pub(crate) fn capture<F>(f: F) -> Vec<(Level, String)>
+    where
+        F: FnOnce(),
+    {
+        super::init();
+        CAPTURED.with(|captured| *captured.borrow_mut() = Some(Vec::new()));
+        f();
+        CAPTURED.with(|captured| captured.borrow_mut().take().unwrap_or_default())
+    }
+}
@@ -24,8 +24,10 @@ pub struct MessageWriter {
     last_sent_sequence_number: u32,
     /// Maximum size of a message, total. Use 0 for no limit
     max_message_size: usize,
-    /// Maximum size of a chunk. Use 0 for no limit
+    /// Maximum number of chunks in a message. Use 0 for no limit
     max_chunk_count: usize,
+    /// Maximum size of each individual chunk
+    send_buffer_size: usize,
 }
 
 impl MessageWriter {
@@ -40,9 +42,23 @@ impl MessageWriter {
             last_sent_sequence_number: DEFAULT_SENT_SEQUENCE_NUMBER,
             max_message_size,
             max_chunk_count,
+            send_buffer_size: buffer_size,
         }
     }
 
+    /// Sets the maximum size of each chunk, of a message and the number of chunks in a message,
+    /// e.g. to the limits negotiated with the other end. Use 0 for no message size or chunk limit.
+    pub fn set_limits(
+        &mut self,
+        send_buffer_size: usize,
+        max_message_size: usize,
+        max_chunk_count: usize,
+    ) {
+        self.send_buffer_size = send_buffer_size;
+        self.max_message_size = max_message_size;
+        self.max_chunk_count = max_chunk_count;
+    }
+
     pub fn write_ack(&mut self, ack: &AcknowledgeMessage) -> EncodingResult<usize> {
         ack.encode(&mut self.buffer)
     }
@@ -61,7 +77,7 @@ impl MessageWriter {
             self.last_sent_sequence_number + 1,
             request_id,
             self.max_message_size,
-            0,
+            self.send_buffer_size,
             secure_channel,
             &message,
         )?;
@@ -72,7 +88,12 @@ impl MessageWriter {
                 chunks.len(),
                 self.max_chunk_count
             );
-            Err(StatusCode::BadCommunicationError)
+            // Client stack should report a BadRequestTooLarge, server BadResponseTooLarge
+            Err(if secure_channel.is_client_role() {
+                StatusCode::BadRequestTooLarge
+            } else {
+                StatusCode::BadResponseTooLarge
+            })
         } else {
             // Sequence number monotonically increases per chunk
             self.last_sent_sequence_number += chunks.len() as u32;
@@ -81,7 +102,7 @@ impl MessageWriter {
 
             // This max chunk size allows the message to be encoded to a chunk with header + encoding
             // which is just slightly larger in size (up to 1024 bytes).
-            let data_buffer_size = self.buffer.get_ref().len() + 1024;
+            let data_buffer_size = self.send_buffer_size + 1024;
             let mut data = vec![0u8; data_buffer_size];
             for chunk in chunks {
                 trace!("Sending chunk {:?}", chunk);
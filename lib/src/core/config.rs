@@ -2,8 +2,9 @@
 // SPDX-License-Identifier: MPL-2.0
 // Copyright (C) 2017-2024 Adam Lock
 
+use std::fmt;
 use std::fs::File;
-use std::io::{Read, Write};
+use std::io::{self, Read, Write};
 use std::path::Path;
 use std::result::Result;
 
@@ -15,6 +16,52 @@ use crate::types::{
     LocalizedText, UAString,
 };
 
+/// The reason a configuration could not be loaded.
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The configuration file could not be opened or read.
+    Io(io::Error),
+    /// The configuration file could not be deserialized, e.g. because a field is misspelled.
+    /// The error names the path of the offending field and its line where possible.
+    Parse(serde_yaml::Error),
+    /// The configuration was read but is not valid. Each entry describes a problem.
+    Invalid(Vec<String>),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(err) => write!(f, "Cannot read configuration, {}", err),
+            ConfigError::Parse(err) => write!(f, "Cannot deserialize configuration, {}", err),
+            ConfigError::Invalid(errors) => {
+                write!(f, "Configuration is invalid, {}", errors.join(", "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigError::Io(err) => Some(err),
+            ConfigError::Parse(err) => Some(err),
+            ConfigError::Invalid(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for ConfigError {
+    fn from(err: io::Error) -> Self {
+        ConfigError::Io(err)
+    }
+}
+
+impl From<serde_yaml::Error> for ConfigError {
+    fn from(err: serde_yaml::Error) -> Self {
+        ConfigError::Parse(err)
+    }
+}
+
 /// A trait that handles the loading / saving and validity of configuration information for a
 /// client and/or server.
 pub trait Config: serde::Serialize {
@@ -37,34 +84,56 @@ pub trait Config: serde::Serialize {
         Err(())
     }
 
+    /// Loads and validates the configuration from a file, logging the reason if it can't be.
+    #[deprecated(
+        since = "0.13.0",
+        note = "use `try_load`, which returns the reason the configuration couldn't be loaded"
+    )]
+    #[allow(clippy::result_unit_err)]
     fn load<A>(path: &Path) -> Result<A, ()>
     where
         for<'de> A: Config + serde::Deserialize<'de>,
     {
-        if let Ok(mut f) = File::open(path) {
-            let mut s = String::new();
-            if f.read_to_string(&mut s).is_ok() {
-                serde_yaml::from_str(&s).map_err(|err| {
-                    error!(
-                        "Cannot deserialize configuration from {}, error reason: {}",
-                        path.display(),
-                        err.to_string()
-                    );
-                })
-            } else {
-                error!(
-                    "Cannot read configuration file {} to string",
-                    path.display()
-                );
-                Err(())
-            }
+        Self::try_load(path).map_err(|err| {
+            error!("Cannot load configuration from {}, {}", path.display(), err);
+        })
+    }
+
+    /// Loads and validates the configuration from a file.
+    fn try_load<A>(path: &Path) -> Result<A, ConfigError>
+    where
+        for<'de> A: Config + serde::Deserialize<'de>,
+    {
+        let mut s = String::new();
+        File::open(path)?.read_to_string(&mut s)?;
+        let config: A = serde_yaml::from_str(&s)?;
+        let errors = config.validate();
+        if errors.is_empty() {
+            Ok(config)
         } else {
-            error!("Cannot open configuration file {}", path.display());
-            Err(())
+            Err(ConfigError::Invalid(errors))
         }
     }
 
-    fn is_valid(&self) -> bool;
+    /// Returns the problems with the configuration, or an empty list if it is valid.
+    ///
+    /// Implementations should provide this rather than `is_valid`. The default is for
+    /// implementations written before it existed and only reports that `is_valid` failed.
+    /// At least one of the two must be implemented.
+    fn validate(&self) -> Vec<String> {
+        if self.is_valid() {
+            Vec::new()
+        } else {
+            vec!["Configuration is not valid".to_string()]
+        }
+    }
+
+    /// Tests if the configuration is valid, logging any problems.
+    fn is_valid(&self) -> bool {
+        let errors = self.validate();
+        errors.iter().for_each(|err| error!("{}", err));
+        errors.is_empty()
+    }
 
     fn application_name(&self) -> UAString;
 
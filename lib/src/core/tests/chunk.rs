@@ -2,7 +2,10 @@ use std::io::{Cursor, Write};
 
 use crate::{
     core::{
-        comms::{chunker::*, message_chunk::*, secure_channel::*, tcp_types::MIN_CHUNK_SIZE},
+        comms::{
+            chunker::*, message_chunk::*, message_writer::MessageWriter, secure_channel::*,
+            tcp_types::MIN_CHUNK_SIZE,
+        },
         supported_message::SupportedMessage,
         tests::*,
     },
@@ -203,6 +206,47 @@ fn max_message_size() {
     assert_eq!(err, StatusCode::BadResponseTooLarge);
 }
 
+/// Write a large message through a message writer and ensure it is split into chunks no larger than
+/// the send buffer, which decode back to the original message.
+#[test]
+fn message_writer_chunks_to_send_buffer_size() {
+    let _ = Test::setup();
+
+    let mut secure_channel = SecureChannel::new_no_certificate_store();
+    secure_channel.set_decoding_options(DecodingOptions {
+        max_array_length: 20000,
+        ..Default::default()
+    });
+
+    let response = make_large_read_response();
+
+    let mut message_writer = MessageWriter::new(MIN_CHUNK_SIZE, 0, 0);
+    message_writer
+        .write(100, response.clone(), &secure_channel)
+        .unwrap();
+    let bytes = message_writer.bytes_to_write();
+
+    let decoding_options = secure_channel.decoding_options();
+    let mut stream = Cursor::new(bytes);
+    let mut chunks = Vec::new();
+    while (stream.position() as usize) < stream.get_ref().len() {
+        let chunk = MessageChunk::decode(&mut stream, &decoding_options).unwrap();
+        assert!(chunk.byte_len() <= MIN_CHUNK_SIZE);
+        chunks.push(chunk);
+    }
+    assert!(chunks.len() > 1);
+
+    let new_response = Chunker::decode(&chunks, &secure_channel, None).unwrap();
+    assert_eq!(response, new_response);
+
+    // A writer with a max message size refuses the same message
+    let mut message_writer = MessageWriter::new(MIN_CHUNK_SIZE, response.byte_len() - 1, 0);
+    let err = message_writer
+        .write(100, response, &secure_channel)
+        .unwrap_err();
+    assert_eq!(err, StatusCode::BadResponseTooLarge);
+}
+
 /// Encode a large message and then ensure verification throws error for secure channel id mismatch
 #[test]
 fn validate_chunks_secure_channel_id() {
@@ -221,6 +221,27 @@ impl CertificateStore {
             hostname,
             application_uri,
         );
+        self.reject_invalid_cert(cert, result)
+    }
+
+    /// Validates the cert supplied in an X509 user identity token as trusted and valid. The
+    /// checks are the same as for an application instance cert, except that the key length is
+    /// not bound to the security policy of the channel. If the cert is unknown, it will be
+    /// written to the rejected folder.
+    ///
+    /// # Errors
+    ///
+    /// A non `Good` status code indicates a failure in the cert or in some action required in
+    /// order to validate it.
+    ///
+    pub fn validate_or_reject_user_token_cert(&self, cert: &X509) -> StatusCode {
+        let result = self.validate_cert(cert, None, None, None);
+        self.reject_invalid_cert(cert, result)
+    }
+
+    /// Stores the cert in the rejected folder if it failed validation for a reason other than
+    /// an internal error or already being rejected
+    fn reject_invalid_cert(&self, cert: &X509, result: StatusCode) -> StatusCode {
         if result.is_bad() {
             match result {
                 StatusCode::BadUnexpectedError | StatusCode::BadSecurityChecksFailed => {
@@ -278,6 +299,17 @@ impl CertificateStore {
         security_policy: SecurityPolicy,
         hostname: Option<&str>,
         application_uri: Option<&str>,
+    ) -> StatusCode {
+        self.validate_cert(cert, Some(security_policy), hostname, application_uri)
+    }
+
+    /// Validates the cert, checking its key length against the security policy if there is one
+    fn validate_cert(
+        &self,
+        cert: &X509,
+        security_policy: Option<SecurityPolicy>,
+        hostname: Option<&str>,
+        application_uri: Option<&str>,
     ) -> StatusCode {
         let cert_file_name = CertificateStore::cert_file_name(cert);
         debug!("Validating cert with name on disk {}", cert_file_name);
@@ -345,7 +377,9 @@ impl CertificateStore {
                     return StatusCode::BadSecurityChecksFailed;
                 }
                 Ok(key_length) => {
-                    if !security_policy.is_valid_keylength(key_length) {
+                    if let Some(security_policy) =
+                        security_policy.filter(|p| !p.is_valid_keylength(key_length))
+                    {
                         warn!(
                             "Certificate {} has an invalid key length {} for the policy {}",
                             cert_file_name, key_length, security_policy
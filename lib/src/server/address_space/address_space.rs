@@ -4,6 +4,7 @@
 
 //! Implementation of `AddressSpace`.
 use std::collections::HashMap;
+use std::str::FromStr;
 use std::sync::Arc;
 
 use chrono::Utc;
@@ -11,17 +12,17 @@ use chrono::Utc;
 use crate::sync::*;
 use crate::types::{
     node_ids::VariableId::*,
-    service_types::{BrowseDirection, CallMethodRequest, CallMethodResult, NodeClass},
+    service_types::{Argument, BrowseDirection, CallMethodRequest, CallMethodResult, NodeClass},
     status_code::StatusCode,
     *,
 };
 
 use crate::server::{
     address_space::{
-        node::{HasNodeId, NodeType},
+        node::{HasNodeId, NodeBase, NodeType},
         object::{Object, ObjectBuilder},
         references::{Reference, ReferenceDirection, References},
-        variable::Variable,
+        variable::{array_fits_value_rank, Variable},
         AttrFnGetter,
     },
     callbacks, constants,
@@ -282,11 +283,20 @@ impl AddressSpace {
             // Servers
             self.set_servers(server_state.clone(), &now);
 
-            // Register the server's application uri as a namespace
+            // Register the server's application uri as a namespace, followed by the namespaces
+            // of its own nodes. New nodes go into the first of those, if there are any.
             {
                 let server_state = trace_read_lock!(server_state);
                 let server_config = trace_read_lock!(server_state.config);
                 let _ = self.register_namespace(&server_config.application_uri);
+                let namespaces = server_state
+                    .namespaces
+                    .iter()
+                    .filter_map(|namespace| self.register_namespace(namespace).ok())
+                    .collect::<Vec<u16>>();
+                if let Some(default_namespace) = namespaces.first() {
+                    self.set_default_namespace(*default_namespace);
+                }
             }
 
             // ServerCapabilities
@@ -588,7 +598,19 @@ impl AddressSpace {
             // ServerRedundancy
 
             // Server_ServerStatus_StartTime
-            self.set_variable_value(Server_ServerStatus_StartTime, now, &now, &now);
+            {
+                let server_state = server_state.clone();
+                self.set_variable_getter(
+                    Server_ServerStatus_StartTime,
+                    move |_, timestamps_to_return, _, _, _, _| {
+                        let start_time = trace_read_lock!(server_state).start_time;
+                        let now = DateTime::now();
+                        let mut value = DataValue::from(start_time);
+                        value.set_timestamps(timestamps_to_return, now, now);
+                        Ok(Some(value))
+                    },
+                );
+            }
 
             // Server_ServerStatus_CurrentTime
             self.set_variable_getter(
@@ -603,25 +625,106 @@ impl AddressSpace {
 
             // State OPC UA Part 5 12.6, Valid states are
             //     State (Server_ServerStatus_State)
-            self.set_variable_getter(
-                Server_ServerStatus_State,
-                move |_, timestamps_to_return, _, _, _, _| {
-                    // let server_state =  trace_read_lock!(server_state);
-                    let now = DateTime::now();
-                    let mut value = DataValue::from(0i32);
-                    value.set_timestamps(timestamps_to_return, now, now);
-                    Ok(Some(value))
-                },
-            );
+            {
+                let server_state = server_state.clone();
+                self.set_variable_getter(
+                    Server_ServerStatus_State,
+                    move |_, timestamps_to_return, _, _, _, _| {
+                        let state = trace_read_lock!(server_state).state();
+                        let now = DateTime::now();
+                        let mut value = DataValue::from(state as i32);
+                        value.set_timestamps(timestamps_to_return, now, now);
+                        Ok(Some(value))
+                    },
+                );
+            }
 
             // ServerStatus_BuildInfo
+            let build_info = {
+                let server_state = trace_read_lock!(server_state);
+                BuildInfo {
+                    product_uri: server_state.product_uri.clone(),
+                    manufacturer_name: UAString::from(constants::BUILD_INFO_MANUFACTURER_NAME),
+                    product_name: server_state.application_name.text.clone(),
+                    software_version: UAString::from(constants::BUILD_INFO_SOFTWARE_VERSION),
+                    build_number: UAString::from(constants::BUILD_INFO_BUILD_NUMBER),
+                    build_date: DateTime::from_str(constants::BUILD_INFO_BUILD_DATE)
+                        .unwrap_or_else(|_| DateTime::null()),
+                }
+            };
+            {
+                self.set_variable_value(
+                    Server_ServerStatus_BuildInfo_ProductUri,
+                    build_info.product_uri.clone(),
+                    &now,
+                    &now,
+                );
+                self.set_variable_value(
+                    Server_ServerStatus_BuildInfo_ManufacturerName,
+                    build_info.manufacturer_name.clone(),
+                    &now,
+                    &now,
+                );
+                self.set_variable_value(
+                    Server_ServerStatus_BuildInfo_ProductName,
+                    build_info.product_name.clone(),
+                    &now,
+                    &now,
+                );
+                self.set_variable_value(
+                    Server_ServerStatus_BuildInfo_SoftwareVersion,
+                    build_info.software_version.clone(),
+                    &now,
+                    &now,
+                );
+                self.set_variable_value(
+                    Server_ServerStatus_BuildInfo_BuildNumber,
+                    build_info.build_number.clone(),
+                    &now,
+                    &now,
+                );
+                self.set_variable_value(
+                    Server_ServerStatus_BuildInfo_BuildDate,
+                    build_info.build_date,
+                    &now,
+                    &now,
+                );
+                self.set_variable_value(
+                    Server_ServerStatus_BuildInfo,
+                    ExtensionObject::from_encodable(
+                        ObjectId::BuildInfo_Encoding_DefaultBinary,
+                        &build_info,
+                    ),
+                    &now,
+                    &now,
+                );
+            }
+
+            // Server_ServerStatus as a whole
             {
-                //    BuildDate
-                //    BuildNumber
-                //    ManufacturerName
-                //    ProductName
-                //    ProductUri
-                //    SoftwareVersion
+                let server_state = server_state.clone();
+                self.set_variable_getter(
+                    Server_ServerStatus,
+                    move |_, timestamps_to_return, _, _, _, _| {
+                        let server_state = trace_read_lock!(server_state);
+                        let now = DateTime::now();
+                        let server_status = ServerStatusDataType {
+                            start_time: server_state.start_time,
+                            current_time: now,
+                            state: server_state.state(),
+                            build_info: build_info.clone(),
+                            seconds_till_shutdown: 0,
+                            shutdown_reason: LocalizedText::null(),
+                        };
+                        let mut value =
+                            DataValue::from(Variant::from(ExtensionObject::from_encodable(
+                                ObjectId::ServerStatusDataType_Encoding_DefaultBinary,
+                                &server_status,
+                            )));
+                        value.set_timestamps(timestamps_to_return, now, now);
+                        Ok(Some(value))
+                    },
+                );
             }
 
             // Server method handlers
@@ -1151,7 +1254,7 @@ impl AddressSpace {
     /// the request refers to a non existent object / method, the function will return an error.
     pub fn call_method(
         &mut self,
-        _server_state: &ServerState,
+        server_state: &ServerState,
         session_id: &NodeId,
         session_manager: Arc<RwLock<SessionManager>>,
         request: &CallMethodRequest,
@@ -1176,6 +1279,8 @@ impl AddressSpace {
                 method_id, object_id
             );
             Err(StatusCode::BadMethodInvalid)
+        } else if let Some(result) = self.validate_method_arguments(server_state, request)? {
+            Ok(result)
         } else if let Some(method) = self.find_mut(method_id) {
             // TODO check security - session / user may not have permission to call methods
             match method {
@@ -1187,6 +1292,112 @@ impl AddressSpace {
         }
     }
 
+    /// Returns the arguments described by the `InputArguments` property of a method, if it has one.
+    fn method_input_arguments(
+        &self,
+        server_state: &ServerState,
+        method_id: &NodeId,
+    ) -> Option<Vec<Argument>> {
+        let input_arguments = QualifiedName::from("InputArguments");
+        let references =
+            self.find_references(method_id, Some((ReferenceTypeId::HasProperty, false)))?;
+        let value = references
+            .iter()
+            .find_map(|r| match self.find_node(&r.target_node) {
+                Some(NodeType::Variable(v)) if v.browse_name() == input_arguments => Some(
+                    v.value(
+                        TimestampsToReturn::Neither,
+                        NumericRange::None,
+                        &QualifiedName::null(),
+                        0.0,
+                    )
+                    .value,
+                ),
+                _ => None,
+            })??;
+        let decoding_options = server_state.decoding_options();
+        match value {
+            Variant::Array(array) => array
+                .values
+                .iter()
+                .map(|v| match v {
+                    Variant::ExtensionObject(eo) => {
+                        eo.decode_inner::<Argument>(&decoding_options).ok()
+                    }
+                    _ => None,
+                })
+                .collect(),
+            _ => None,
+        }
+    }
+
+    /// Validates the input arguments of a method call against the method's `InputArguments`
+    /// property. An error is returned if the number of arguments is wrong. A result is returned
+    /// if any argument is of the wrong type, with `input_argument_results` saying which one. If
+    /// the method has no `InputArguments` then validation is left to the method's callback.
+    fn validate_method_arguments(
+        &self,
+        server_state: &ServerState,
+        request: &CallMethodRequest,
+    ) -> Result<Option<CallMethodResult>, StatusCode> {
+        let Some(expected) = self.method_input_arguments(server_state, &request.method_id) else {
+            return Ok(None);
+        };
+        let actual = request.input_arguments.as_deref().unwrap_or(&[]);
+        if actual.len() != expected.len() {
+            error!(
+                "Method call to {:?} expects {} arguments but received {}",
+                request.method_id,
+                expected.len(),
+                actual.len()
+            );
+            return Err(if actual.len() < expected.len() {
+                StatusCode::BadArgumentsMissing
+            } else {
+                StatusCode::BadTooManyArguments
+            });
+        }
+        let input_argument_results = expected
+            .iter()
+            .zip(actual)
+            .map(|(expected, actual)| {
+                // A scalar may be passed for a scalar, scalar or one dimension, or any value rank.
+                // An array must fit the value rank and array dimensions of the argument.
+                let data_type = if let Some(data_type) = actual.scalar_data_type() {
+                    (-3..=-1)
+                        .contains(&expected.value_rank)
+                        .then_some(data_type)
+                } else if let Some(data_type) = actual.array_data_type() {
+                    array_fits_value_rank(
+                        expected.value_rank,
+                        expected.array_dimensions.as_deref(),
+                        actual,
+                    )
+                    .then_some(data_type)
+                } else {
+                    // Values whose type cannot be determined, e.g. extension objects, are passed through
+                    return StatusCode::Good;
+                };
+                match data_type {
+                    Some(data_type) if self.is_subtype(&data_type, &expected.data_type) => {
+                        StatusCode::Good
+                    }
+                    _ => StatusCode::BadTypeMismatch,
+                }
+            })
+            .collect::<Vec<_>>();
+        if input_argument_results.iter().all(|s| s.is_good()) {
+            Ok(None)
+        } else {
+            Ok(Some(CallMethodResult {
+                status_code: StatusCode::BadInvalidArgument,
+                input_argument_results: Some(input_argument_results),
+                input_argument_diagnostic_infos: None,
+                output_arguments: None,
+            }))
+        }
+    }
+
     /// Recursive function tries to find if a type is a subtype of another type by looking at its
     /// references. Function will positively match a type against itself.
     pub fn is_subtype(&self, subtype_id: &NodeId, base_type_id: &NodeId) -> bool {
@@ -1218,11 +1429,17 @@ impl AddressSpace {
         // Ensure the node type is of the right class
         if let Some(node) = self.node_map.get(&node_type_id) {
             if node.node_class() == node_type_class {
+                // Instances of an object type are objects, of a variable type are variables
+                let instance_class = if node_type_class == NodeClass::VariableType {
+                    NodeClass::Variable
+                } else {
+                    NodeClass::Object
+                };
                 // Find nodes with a matching type definition
                 let nodes = self
                     .node_map
                     .iter()
-                    .filter(|(_, v)| v.node_class() == NodeClass::Object)
+                    .filter(|(_, v)| v.node_class() == instance_class)
                     .filter(move |(k, _)| {
                         // Node has to have a type definition reference to the type
                         if let Some(type_refs) = self
@@ -1230,7 +1447,7 @@ impl AddressSpace {
                         {
                             // Type definition must find the sought after type
                             type_refs.iter().any(|r| {
-                                include_subtypes && self.is_subtype(&node_type_id, &r.target_node)
+                                include_subtypes && self.is_subtype(&r.target_node, &node_type_id)
                                     || r.target_node == node_type_id
                             })
                         } else {
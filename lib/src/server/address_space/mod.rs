@@ -445,6 +445,7 @@ pub mod method;
 pub mod node;
 pub mod object;
 pub mod object_type;
+pub mod provider;
 pub mod reference_type;
 pub mod references;
 pub mod relative_path;
@@ -499,6 +500,7 @@ pub mod types {
     pub use super::node::{NodeBase, NodeType};
     pub use super::object::{Object, ObjectBuilder};
     pub use super::object_type::{ObjectType, ObjectTypeBuilder};
+    pub use super::provider::AddressSpaceProvider;
     pub use super::reference_type::{ReferenceType, ReferenceTypeBuilder};
     pub use super::references::ReferenceDirection;
     pub use super::variable::{Variable, VariableBuilder};
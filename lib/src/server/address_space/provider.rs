@@ -0,0 +1,295 @@
+// OPCUA for Rust
+// SPDX-License-Identifier: MPL-2.0
+// Copyright (C) 2017-2024 Adam Lock
+
+//! Provides the `AddressSpaceProvider` trait through which services find and modify nodes, so the
+//! nodes can be held somewhere other than the in-memory `AddressSpace`.
+
+use std::sync::Arc;
+
+use crate::sync::*;
+use crate::types::{
+    service_types::{CallMethodRequest, CallMethodResult},
+    AttributeId, BrowseDirection, DateTimeUtc, NodeId, NumericRange, StatusCode, Variant,
+};
+
+use crate::server::{session::SessionManager, state::ServerState};
+
+use super::{
+    address_space::AddressSpace,
+    node::NodeType,
+    references::{Reference, ReferenceDirection},
+};
+
+/// A store of nodes and the references between them. The in-memory `AddressSpace` is the default
+/// implementation, but nodes could equally be held in a database or made on demand from a device.
+///
+/// Nodes are lent to a callback rather than returned by reference, so a provider that has no
+/// nodes in memory can make one, pass it to the callback and then drop it. Attributes are written
+/// through the provider so that it can store the new value wherever the node is held.
+pub trait AddressSpaceProvider: Send + Sync {
+    /// Calls the function with the node that has the node id. The function is not called if
+    /// there is no such node.
+    fn with_node(&self, node_id: &NodeId, f: &mut dyn FnMut(&NodeType));
+
+    /// Sets the attribute of the node to the value. An index range may only be supplied for the
+    /// value attribute of a variable.
+    fn set_attribute(
+        &mut self,
+        node_id: &NodeId,
+        attribute_id: AttributeId,
+        index_range: NumericRange,
+        value: Variant,
+    ) -> Result<(), StatusCode>;
+
+    /// Finds the forward references from the node, optionally filtered by a reference type and
+    /// its subtypes
+    fn find_references(
+        &self,
+        node_id: &NodeId,
+        reference_filter: Option<(NodeId, bool)>,
+    ) -> Option<Vec<Reference>>;
+
+    /// Finds the references from the node in the browse direction, optionally filtered by a
+    /// reference type and its subtypes. Returns the references and the index of the first inverse
+    /// reference in them.
+    fn find_references_by_direction(
+        &self,
+        node_id: &NodeId,
+        browse_direction: BrowseDirection,
+        reference_filter: Option<(NodeId, bool)>,
+    ) -> (Vec<Reference>, usize);
+
+    /// Tests if the type is the base type or one of its subtypes
+    fn is_subtype(&self, subtype_id: &NodeId, base_type_id: &NodeId) -> bool;
+
+    /// The last time that nodes or references were added or removed. Browse continuation points
+    /// made before this time are no longer valid.
+    fn last_modified(&self) -> DateTimeUtc;
+
+    /// Tests if the node exists
+    fn node_exists(&self, node_id: &NodeId) -> bool {
+        let mut exists = false;
+        self.with_node(node_id, &mut |_| exists = true);
+        exists
+    }
+
+    /// The namespace that nodes added by clients are created in when they do not supply a node id
+    fn default_namespace(&self) -> u16;
+
+    /// Inserts the node and its references to other nodes. Returns false if the node already
+    /// exists.
+    fn insert(
+        &mut self,
+        node: NodeType,
+        references: Option<&[(&NodeId, &NodeId, ReferenceDirection)]>,
+    ) -> bool;
+
+    /// Deletes the node, its children and optionally the references to it. Returns false if there
+    /// was nothing to delete.
+    fn delete(&mut self, node_id: &NodeId, delete_target_references: bool) -> bool;
+
+    /// Inserts a reference from the node to the target node
+    fn insert_reference(
+        &mut self,
+        node_id: &NodeId,
+        target_node_id: &NodeId,
+        reference_type_id: &NodeId,
+    );
+
+    /// Deletes the reference from the node to the target node. Returns false if there was no such
+    /// reference.
+    fn delete_reference(
+        &mut self,
+        node_id: &NodeId,
+        target_node_id: &NodeId,
+        reference_type_id: &NodeId,
+    ) -> bool;
+
+    /// Tests if there is a reference of the exact type from the node to the target node
+    fn has_reference(
+        &self,
+        node_id: &NodeId,
+        target_node_id: &NodeId,
+        reference_type_id: &NodeId,
+    ) -> bool {
+        self.find_references(node_id, Some((reference_type_id.clone(), false)))
+            .map(|references| references.iter().any(|r| r.target_node == *target_node_id))
+            .unwrap_or(false)
+    }
+
+    /// Finds the objects whose type definition is the object type, or optionally one of its
+    /// subtypes
+    fn find_objects_by_type(
+        &self,
+        object_type_id: &NodeId,
+        include_subtypes: bool,
+    ) -> Option<Vec<NodeId>>;
+
+    /// Finds the variables whose type definition is the variable type, or optionally one of its
+    /// subtypes
+    fn find_variables_by_type(
+        &self,
+        variable_type_id: &NodeId,
+        include_subtypes: bool,
+    ) -> Option<Vec<NodeId>>;
+
+    /// Calls the method on the object in the request
+    fn call_method(
+        &mut self,
+        server_state: &ServerState,
+        session_id: &NodeId,
+        session_manager: Arc<RwLock<SessionManager>>,
+        request: &CallMethodRequest,
+    ) -> Result<CallMethodResult, StatusCode>;
+}
+
+/// Calls the function with the node that has the node id and returns its result, or `None` if
+/// there is no such node.
+pub fn map_node<P, F, R>(provider: &P, node_id: &NodeId, f: F) -> Option<R>
+where
+    P: AddressSpaceProvider + ?Sized,
+    F: FnOnce(&NodeType) -> R,
+{
+    let mut f = Some(f);
+    let mut result = None;
+    provider.with_node(node_id, &mut |node| {
+        if let Some(f) = f.take() {
+            result = Some(f(node));
+        }
+    });
+    result
+}
+
+impl AddressSpaceProvider for AddressSpace {
+    fn with_node(&self, node_id: &NodeId, f: &mut dyn FnMut(&NodeType)) {
+        if let Some(node) = AddressSpace::find_node(self, node_id) {
+            f(node);
+        }
+    }
+
+    fn set_attribute(
+        &mut self,
+        node_id: &NodeId,
+        attribute_id: AttributeId,
+        index_range: NumericRange,
+        value: Variant,
+    ) -> Result<(), StatusCode> {
+        let Some(node) = AddressSpace::find_node_mut(self, node_id) else {
+            return Err(StatusCode::BadNodeIdUnknown);
+        };
+        if attribute_id == AttributeId::Value {
+            match node {
+                NodeType::Variable(ref mut variable) => variable.set_value(index_range, value),
+                _ => Err(StatusCode::BadAttributeIdInvalid),
+            }
+        } else if index_range != NumericRange::None {
+            Err(StatusCode::BadWriteNotSupported)
+        } else {
+            node.as_mut_node().set_attribute(attribute_id, value)
+        }
+    }
+
+    fn find_references(
+        &self,
+        node_id: &NodeId,
+        reference_filter: Option<(NodeId, bool)>,
+    ) -> Option<Vec<Reference>> {
+        AddressSpace::find_references(self, node_id, reference_filter)
+    }
+
+    fn find_references_by_direction(
+        &self,
+        node_id: &NodeId,
+        browse_direction: BrowseDirection,
+        reference_filter: Option<(NodeId, bool)>,
+    ) -> (Vec<Reference>, usize) {
+        AddressSpace::find_references_by_direction(
+            self,
+            node_id,
+            browse_direction,
+            reference_filter,
+        )
+    }
+
+    fn is_subtype(&self, subtype_id: &NodeId, base_type_id: &NodeId) -> bool {
+        AddressSpace::is_subtype(self, subtype_id, base_type_id)
+    }
+
+    fn last_modified(&self) -> DateTimeUtc {
+        AddressSpace::last_modified(self)
+    }
+
+    fn node_exists(&self, node_id: &NodeId) -> bool {
+        AddressSpace::node_exists(self, node_id)
+    }
+
+    fn default_namespace(&self) -> u16 {
+        AddressSpace::default_namespace(self)
+    }
+
+    fn insert(
+        &mut self,
+        node: NodeType,
+        references: Option<&[(&NodeId, &NodeId, ReferenceDirection)]>,
+    ) -> bool {
+        AddressSpace::insert(self, node, references)
+    }
+
+    fn delete(&mut self, node_id: &NodeId, delete_target_references: bool) -> bool {
+        AddressSpace::delete(self, node_id, delete_target_references)
+    }
+
+    fn insert_reference(
+        &mut self,
+        node_id: &NodeId,
+        target_node_id: &NodeId,
+        reference_type_id: &NodeId,
+    ) {
+        AddressSpace::insert_reference(self, node_id, target_node_id, reference_type_id)
+    }
+
+    fn delete_reference(
+        &mut self,
+        node_id: &NodeId,
+        target_node_id: &NodeId,
+        reference_type_id: &NodeId,
+    ) -> bool {
+        AddressSpace::delete_reference(self, node_id, target_node_id, reference_type_id)
+    }
+
+    fn has_reference(
+        &self,
+        node_id: &NodeId,
+        target_node_id: &NodeId,
+        reference_type_id: &NodeId,
+    ) -> bool {
+        AddressSpace::has_reference(self, node_id, target_node_id, reference_type_id)
+    }
+
+    fn find_objects_by_type(
+        &self,
+        object_type_id: &NodeId,
+        include_subtypes: bool,
+    ) -> Option<Vec<NodeId>> {
+        AddressSpace::find_objects_by_type(self, object_type_id, include_subtypes)
+    }
+
+    fn find_variables_by_type(
+        &self,
+        variable_type_id: &NodeId,
+        include_subtypes: bool,
+    ) -> Option<Vec<NodeId>> {
+        AddressSpace::find_variables_by_type(self, variable_type_id, include_subtypes)
+    }
+
+    fn call_method(
+        &mut self,
+        server_state: &ServerState,
+        session_id: &NodeId,
+        session_manager: Arc<RwLock<SessionManager>>,
+        request: &CallMethodRequest,
+    ) -> Result<CallMethodResult, StatusCode> {
+        AddressSpace::call_method(self, server_state, session_id, session_manager, request)
+    }
+}
@@ -6,22 +6,28 @@ use std::collections::HashSet;
 
 use crate::types::{
     node_id::NodeId,
-    service_types::{RelativePath, RelativePathElement},
+    service_types::{BrowseDirection, RelativePath, RelativePathElement},
     status_code::StatusCode,
-    QualifiedName,
+    QualifiedName, ReferenceTypeId,
 };
 
-use super::{node::NodeType, AddressSpace};
+use super::{
+    node::NodeType,
+    provider::{map_node, AddressSpaceProvider},
+};
 
 /// Given a browse path consisting of browse names, walk nodes from the root until we find a single node (or not).
 /// This function is a simplified use case for event filters and such like where a browse path
 /// is defined as an array and doesn't need to be parsed out of a relative path. All nodes in the
-/// path must be objects or variables.
-pub(crate) fn find_node_from_browse_path<'a>(
-    address_space: &'a AddressSpace,
+/// path must be objects or variables. Returns the node id of the node that was found.
+pub(crate) fn find_node_from_browse_path<P>(
+    address_space: &P,
     parent_node_id: &NodeId,
     browse_path: &[QualifiedName],
-) -> Result<&'a NodeType, StatusCode> {
+) -> Result<NodeId, StatusCode>
+where
+    P: AddressSpaceProvider + ?Sized,
+{
     if browse_path.is_empty() {
         Err(StatusCode::BadNotFound)
     } else {
@@ -30,21 +36,20 @@ pub(crate) fn find_node_from_browse_path<'a>(
         // visible in the server's address space
         let mut parent_node_id = parent_node_id.clone();
         for browse_name in browse_path {
-            if let Some(child_nodes) = address_space.find_hierarchical_references(&parent_node_id) {
-                let found_node_id = child_nodes.iter().find(|node_id| {
-                    if let Some(node) = address_space.find_node(node_id) {
-                        if node.as_node().browse_name() == *browse_name {
-                            // Check that the node is an Object or Variable
-                            matches!(node, NodeType::Object(_) | NodeType::Variable(_))
-                        } else {
-                            false
-                        }
-                    } else {
-                        false
-                    }
+            if let Some(child_references) = address_space.find_references(
+                &parent_node_id,
+                Some((ReferenceTypeId::HierarchicalReferences.into(), true)),
+            ) {
+                let found_node_id = child_references.iter().find(|reference| {
+                    map_node(address_space, &reference.target_node, |node| {
+                        // Check that the node is an Object or Variable
+                        node.as_node().browse_name() == *browse_name
+                            && matches!(node, NodeType::Object(_) | NodeType::Variable(_))
+                    })
+                    .unwrap_or(false)
                 });
-                if let Some(found_node_id) = found_node_id {
-                    parent_node_id = found_node_id.clone();
+                if let Some(found_reference) = found_node_id {
+                    parent_node_id = found_reference.target_node.clone();
                 } else {
                     //debug!(
                     //    "Cannot find node under {} with browse_path of {:?}/1",
@@ -60,18 +65,21 @@ pub(crate) fn find_node_from_browse_path<'a>(
                 return Err(StatusCode::BadNotFound);
             }
         }
-        Ok(address_space.find_node(&parent_node_id).unwrap())
+        Ok(parent_node_id)
     }
 }
 
 /// Given a path as a string, find all the nodes that match against it. Note this function
 /// uses a default path resolver based on common browse names. If you need something else use
 /// `find_nodes_relative_path()` after you have created a relative path.
-pub fn find_nodes_relative_path_simple(
-    address_space: &AddressSpace,
+pub fn find_nodes_relative_path_simple<P>(
+    address_space: &P,
     node_id: &NodeId,
     relative_path: &str,
-) -> Result<Vec<NodeId>, StatusCode> {
+) -> Result<Vec<NodeId>, StatusCode>
+where
+    P: AddressSpaceProvider + ?Sized,
+{
     let relative_path =
         RelativePath::from_str(relative_path, &RelativePathElement::default_node_resolver)
             .map_err(|_| StatusCode::BadUnexpectedError)?;
@@ -79,12 +87,15 @@ pub fn find_nodes_relative_path_simple(
 }
 
 /// Given a `RelativePath`, find all the nodes that match against it.
-pub fn find_nodes_relative_path(
-    address_space: &AddressSpace,
+pub fn find_nodes_relative_path<P>(
+    address_space: &P,
     node_id: &NodeId,
     relative_path: &RelativePath,
-) -> Result<Vec<NodeId>, StatusCode> {
-    match address_space.find_node(node_id) {
+) -> Result<Vec<NodeId>, StatusCode>
+where
+    P: AddressSpaceProvider + ?Sized,
+{
+    match map_node(address_space, node_id, |_| ()) {
         None => {
             trace!("find_nodes_relative_path cannot find node {}", node_id);
             Err(StatusCode::BadNodeIdUnknown)
@@ -138,20 +149,31 @@ pub fn find_nodes_relative_path(
     }
 }
 
-fn follow_relative_path(
-    address_space: &AddressSpace,
+fn follow_relative_path<P>(
+    address_space: &P,
     node_id: &NodeId,
     relative_path: &RelativePathElement,
-) -> Option<Vec<NodeId>> {
-    let reference_filter = {
-        if let Ok(reference_type_id) = relative_path.reference_type_id.as_reference_type_id() {
-            Some((reference_type_id, relative_path.include_subtypes))
-        } else {
-            None
-        }
+) -> Option<Vec<NodeId>>
+where
+    P: AddressSpaceProvider + ?Sized,
+{
+    // The reference type may be a server defined type, so it is used as a node id rather than
+    // being coerced into one of the standard reference types
+    let reference_filter = if relative_path.reference_type_id.is_null() {
+        None
+    } else {
+        Some((
+            relative_path.reference_type_id.clone(),
+            relative_path.include_subtypes,
+        ))
     };
     let references = if relative_path.is_inverse {
-        address_space.find_inverse_references(node_id, reference_filter)
+        let (references, _) = address_space.find_references_by_direction(
+            node_id,
+            BrowseDirection::Inverse,
+            reference_filter,
+        );
+        Some(references)
     } else {
         address_space.find_references(node_id, reference_filter)
     };
@@ -159,11 +181,11 @@ fn follow_relative_path(
         let compare_target_name = !relative_path.target_name.is_null();
         let mut result = Vec::with_capacity(references.len());
         for reference in &references {
-            if let Some(node) = address_space.find_node(&reference.target_node) {
-                let node = node.as_node();
-                if !compare_target_name || node.browse_name() == relative_path.target_name {
-                    result.push(reference.target_node.clone());
-                }
+            let matches = map_node(address_space, &reference.target_node, |node| {
+                !compare_target_name || node.as_node().browse_name() == relative_path.target_name
+            });
+            if matches == Some(true) {
+                result.push(reference.target_node.clone());
             }
         }
         // Vector may contain duplicates, so reduce those to a unique set
@@ -199,9 +199,8 @@ impl Node for Variable {
         data_encoding: &QualifiedName,
         max_age: f64,
     ) -> Option<DataValue> {
-        /* TODO for Variables derived from the Structure data type, the AttributeId::Value should check
-        data encoding and return the value encoded according "Default Binary", "Default XML" or "Default JSON" (OPC UA 1.04).
-        */
+        // Structured values are held as binary encoded extension objects, so the data encoding
+        // has already been checked to be "Default Binary" by the read service
         match attribute_id {
             // Mandatory attributes
             AttributeId::Value => {
@@ -697,3 +696,38 @@ impl Variable {
         self.data_type = data_type.into();
     }
 }
+
+/// Tests if an array value fits a value rank, and that each of its dimensions is no larger than
+/// the array dimensions, if there are any. A declared dimension of 0 means the length of that
+/// dimension is unknown. Values which are not arrays never fit.
+pub(crate) fn array_fits_value_rank(
+    value_rank: i32,
+    array_dimensions: Option<&[u32]>,
+    value: &Variant,
+) -> bool {
+    let Variant::Array(array) = value else {
+        return false;
+    };
+    let dimensions = array
+        .dimensions
+        .clone()
+        .unwrap_or_else(|| vec![array.values.len() as u32]);
+    let value_rank_matches = match value_rank {
+        // Any, or one or more dimensions
+        -2 | 0 => true,
+        // Scalar or one dimension
+        -3 => dimensions.len() == 1,
+        value_rank => value_rank > 0 && value_rank as usize == dimensions.len(),
+    };
+    let dimensions_fit = match array_dimensions {
+        Some(array_dimensions) if !array_dimensions.is_empty() => {
+            array_dimensions.len() == dimensions.len()
+                && array_dimensions
+                    .iter()
+                    .zip(dimensions.iter())
+                    .all(|(max, len)| *max == 0 || len <= max)
+        }
+        _ => true,
+    };
+    value_rank_matches && dimensions_fit
+}
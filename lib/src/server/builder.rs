@@ -200,6 +200,16 @@ impl ServerBuilder {
         self
     }
 
+    /// Adds a namespace uri for the server's own nodes. The first namespace added is the one that
+    /// new nodes are created in.
+    pub fn namespace<T>(mut self, namespace: T) -> Self
+    where
+        T: Into<String>,
+    {
+        self.config.namespaces.push(namespace.into());
+        self
+    }
+
     /// Sets whether the server should generate its own key pair if there is none found in the pki
     /// directory.
     pub fn create_sample_keypair(mut self, create_sample_keypair: bool) -> Self {
@@ -307,6 +317,12 @@ impl ServerBuilder {
         self
     }
 
+    /// Set the maximum number of sessions on the server
+    pub fn max_sessions(mut self, max_sessions: usize) -> Self {
+        self.config.limits.max_sessions = max_sessions;
+        self
+    }
+
     /// Set the maximum number of subscriptions in a session
     pub fn max_subscriptions(mut self, max_subscriptions: usize) -> Self {
         self.config.limits.max_subscriptions = max_subscriptions;
@@ -376,6 +392,13 @@ impl ServerBuilder {
         self
     }
 
+    /// Sets whether anonymous access is allowed on the endpoints whose user token ids include
+    /// anonymous. By default, it is.
+    pub fn allow_anonymous(mut self, allow_anonymous: bool) -> Self {
+        self.config.allow_anonymous = allow_anonymous;
+        self
+    }
+
     /// Configures the server to use a single-threaded executor. The default executor uses a
     /// thread pool with a worker thread for each CPU core available on the system.
     pub fn single_threaded_executor(mut self) -> Self {
@@ -388,4 +411,11 @@ impl ServerBuilder {
         self.config.performance.single_threaded_executor = false;
         self
     }
+
+    /// Configures the server to record the count and duration of the calls to each service. By
+    /// default, it does not.
+    pub fn service_metrics(mut self) -> Self {
+        self.config.performance.service_metrics = true;
+        self
+    }
 }
@@ -4,7 +4,7 @@
 
 //! Callbacks that a server implementation may register with the library
 
-use std::sync::Arc;
+use std::{fmt, sync::Arc, time::Duration};
 
 use crate::sync::*;
 use crate::types::{
@@ -13,7 +13,10 @@ use crate::types::{
     AttributeId, DataValue, NodeId, NumericRange, QualifiedName,
 };
 
-use super::session::{Session, SessionManager};
+use super::{
+    config::ServerEndpoint,
+    session::{Session, SessionManager},
+};
 
 /// An attribute getter trait is used to obtain the data value associated with the particular attribute id
 /// This allows server implementations to supply a value on demand, usually in response to a polling action
@@ -87,6 +90,22 @@ pub trait UnregisterNodes {
     ) -> Result<(), StatusCode>;
 }
 
+/// Called by ActivateSession to authenticate a user name and password, e.g. against an external
+/// user directory, in place of the user tokens held in the server configuration. The endpoint must
+/// still be configured to accept user name identity tokens.
+pub trait AuthenticationManager {
+    /// Authenticates the user name and decrypted password supplied to the endpoint, returning the
+    /// user token id that identifies the user to the session. The implementation should return
+    /// `BadIdentityTokenRejected` if the credentials are wrong, and `BadUserAccessDenied` if they
+    /// are right but the user may not log in, e.g. because the account is disabled.
+    fn authenticate_username(
+        &self,
+        endpoint: &ServerEndpoint,
+        user_name: &str,
+        password: &str,
+    ) -> Result<String, StatusCode>;
+}
+
 /// Called by the Method service when it invokes a method
 pub trait Method {
     /// A method is registered via the address space to a method id and optionally an object id.
@@ -99,3 +118,44 @@ pub trait Method {
         request: &CallMethodRequest,
     ) -> Result<CallMethodResult, StatusCode>;
 }
+
+/// Describes a request that the server has handled
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestEvent {
+    /// The kind of request, e.g. `BrowseRequest`
+    pub request_type: &'static str,
+    /// The session the request was made on, if it was made on one
+    pub session_id: Option<NodeId>,
+    /// The request handle the client gave the request
+    pub request_handle: u32,
+    /// How long the server took to service the request
+    pub elapsed: Duration,
+    /// The result of the service, or `None` if the response is sent later, e.g. for a publish
+    /// request
+    pub service_result: Option<StatusCode>,
+}
+
+impl fmt::Display for RequestEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "request_type={} session_id={} request_handle={} elapsed_us={} service_result={}",
+            self.request_type,
+            self.session_id
+                .as_ref()
+                .map_or_else(|| "none".to_string(), |session_id| session_id.to_string()),
+            self.request_handle,
+            self.elapsed.as_micros(),
+            self.service_result.map_or_else(
+                || "pending".to_string(),
+                |service_result| service_result.to_string()
+            ),
+        )
+    }
+}
+
+/// Called by the server after it handles each request, e.g. to gather metrics
+pub trait RequestObserver {
+    /// Called with a description of the request that was handled
+    fn on_request(&self, event: &RequestEvent);
+}
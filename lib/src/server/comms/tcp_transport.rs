@@ -39,12 +39,12 @@ use crate::sync::*;
 use crate::types::status_code::StatusCode;
 
 use crate::server::{
-    address_space::types::AddressSpace,
+    address_space::{provider::AddressSpaceProvider, types::AddressSpace},
     comms::{secure_channel_service::SecureChannelService, transport::*},
     services::message_handler::MessageHandler,
     session::SessionManager,
     state::ServerState,
-    subscriptions::subscription::TickReason,
+    subscriptions::{subscription::TickReason, PublishResponseEntry},
 };
 
 /// Messages that may be sent to the writer.
@@ -69,6 +69,30 @@ impl MessageSender {
     pub fn send_message(&self, request_id: u32, message: SupportedMessage) {
         let _ = self.sender.send(Message::Message(request_id, message));
     }
+
+    /// Creates a sender whose messages can be read back from the returned receiver
+    #[cfg(test)]
+    pub(crate) fn new_test_channel() -> (MessageSender, MessageReceiver) {
+        let (sender, receiver) = unbounded_channel();
+        (MessageSender { sender }, MessageReceiver { receiver })
+    }
+}
+
+/// Receiving end of a test `MessageSender`
+#[cfg(test)]
+pub(crate) struct MessageReceiver {
+    receiver: UnboundedReceiver<Message>,
+}
+
+#[cfg(test)]
+impl MessageReceiver {
+    /// Returns the next message that was sent, if any
+    pub fn try_recv_message(&mut self) -> Option<(u32, SupportedMessage)> {
+        match self.receiver.try_recv() {
+            Ok(Message::Message(request_id, message)) => Some((request_id, message)),
+            _ => None,
+        }
+    }
 }
 
 struct ReadState {
@@ -80,6 +104,8 @@ struct ReadState {
     pub hello_timeout: u32,
     /// Reader from which messages will be decoded
     pub reader: OwnedReadHalf,
+    /// Write buffer, whose chunk size is revised by the HELLO
+    pub send_buffer: Arc<Mutex<MessageWriter>>,
 }
 
 struct WriteState {
@@ -104,6 +130,8 @@ pub struct TcpTransport {
     secure_channel: Arc<RwLock<SecureChannel>>,
     /// Address space
     address_space: Arc<RwLock<AddressSpace>>,
+    /// Provider of the nodes that services and subscriptions operate on
+    address_space_provider: Arc<RwLock<dyn AddressSpaceProvider>>,
     /// The current transport state
     transport_state: TransportState,
     /// Client address
@@ -157,6 +185,7 @@ impl TcpTransport {
         certificate_store: Arc<RwLock<CertificateStore>>,
         server_state: Arc<RwLock<ServerState>>,
         address_space: Arc<RwLock<AddressSpace>>,
+        address_space_provider: Arc<RwLock<dyn AddressSpaceProvider>>,
         session_manager: Arc<RwLock<SessionManager>>,
     ) -> TcpTransport {
         let decoding_options = {
@@ -176,6 +205,7 @@ impl TcpTransport {
             server_state.clone(),
             session_manager.clone(),
             address_space.clone(),
+            address_space_provider.clone(),
         );
 
         let secure_channel_service = SecureChannelService::new();
@@ -185,6 +215,7 @@ impl TcpTransport {
             server_state,
             transport_id,
             address_space,
+            address_space_provider,
             transport_state: TransportState::New,
             client_address: None,
             message_handler,
@@ -218,7 +249,7 @@ impl TcpTransport {
         );
 
         // Store the address of the client
-        let (send_buffer_size, receive_buffer_size) = {
+        let (send_buffer_size, receive_buffer_size, max_message_size) = {
             let mut connection = trace_write_lock!(connection);
             connection.client_address = Some(socket.peer_addr().unwrap());
             connection.transport_state = TransportState::WaitingHello;
@@ -226,6 +257,7 @@ impl TcpTransport {
             (
                 server_state.send_buffer_size,
                 server_state.receive_buffer_size,
+                server_state.max_message_size,
             )
         };
 
@@ -236,6 +268,7 @@ impl TcpTransport {
             looping_interval_ms,
             send_buffer_size,
             receive_buffer_size,
+            max_message_size,
         ));
     }
 
@@ -259,13 +292,13 @@ impl TcpTransport {
         looping_interval_ms: f64,
         send_buffer_size: usize,
         receive_buffer_size: usize,
+        max_message_size: usize,
     ) {
         // The reader task will send responses, the writer task will receive responses
         let (tx, rx) = unbounded_channel();
-        let send_buffer = Arc::new(Mutex::new(MessageWriter::new(send_buffer_size, 0, 0)));
 
         let (reader, writer) = socket.into_split();
-        let (hello_timeout, secure_channel) = {
+        let (hello_timeout, max_chunk_count, secure_channel) = {
             let transport = trace_read_lock!(transport);
             let server_state = trace_read_lock!(transport.server_state);
             let server_config = trace_read_lock!(server_state.config);
@@ -276,15 +309,24 @@ impl TcpTransport {
             );
             (
                 server_config.tcp_config.hello_timeout,
+                server_config.limits.max_chunk_count,
                 transport.secure_channel.clone(),
             )
         };
 
+        // The limits are narrowed to those of the client when its HELLO arrives
+        let send_buffer = Arc::new(Mutex::new(MessageWriter::new(
+            send_buffer_size,
+            max_message_size,
+            max_chunk_count,
+        )));
+
         let read_state = ReadState {
             reader,
             hello_timeout,
             transport: transport.clone(),
             sender: tx.clone(),
+            send_buffer: send_buffer.clone(),
         };
 
         // Spawn all the tasks that monitor the session - the subscriptions, finished state,
@@ -351,8 +393,13 @@ impl TcpTransport {
                     SupportedMessage::AcknowledgeMessage(ack) => {
                         send_buffer.write_ack(&ack)?;
                     }
-                    msg => {
-                        send_buffer.write(request_id, msg, &secure_channel)?;
+                    response => {
+                        Self::write_response(
+                            &mut send_buffer,
+                            request_id,
+                            response,
+                            &secure_channel,
+                        )?;
                     }
                 }
             }
@@ -361,6 +408,30 @@ impl TcpTransport {
         Ok(())
     }
 
+    /// Writes the response to the send buffer. A response too large to send within the negotiated
+    /// limits is replaced by a service fault.
+    pub(crate) fn write_response(
+        send_buffer: &mut MessageWriter,
+        request_id: u32,
+        response: SupportedMessage,
+        secure_channel: &SecureChannel,
+    ) -> Result<(), StatusCode> {
+        let request_handle = response.request_handle();
+        match send_buffer.write(request_id, response, secure_channel) {
+            Err(StatusCode::BadResponseTooLarge) => {
+                let request_header =
+                    RequestHeader::new(&NodeId::null(), &DateTime::now(), request_handle);
+                let service_fault =
+                    ServiceFault::new(&request_header, StatusCode::BadResponseTooLarge);
+                send_buffer.write(request_id, service_fault.into(), secure_channel)?;
+            }
+            result => {
+                result?;
+            }
+        }
+        Ok(())
+    }
+
     async fn wait_for_hello(
         reader: &mut FramedRead<OwnedReadHalf, TcpCodec>,
         hello_timeout: u32,
@@ -411,6 +482,7 @@ impl TcpTransport {
         trace_write_lock!(transport).process_hello(
             hello,
             &mut sender,
+            &read_state.send_buffer,
             &decoding_options,
             send_buffer_size,
             receive_buffer_size,
@@ -455,43 +527,67 @@ impl TcpTransport {
             timer.tick().await;
 
             let transport = trace_read_lock!(transport);
+
             let session_manager = trace_read_lock!(transport.session_manager);
 
             for (_node_id, session) in session_manager.sessions.iter() {
                 let mut session = trace_write_lock!(session);
-                let address_space = trace_read_lock!(transport.address_space);
+                let address_space = trace_read_lock!(transport.address_space_provider);
                 let now = Utc::now();
 
                 // Request queue might contain stale publish requests
                 session.expire_stale_publish_requests(&now);
 
                 // Process subscriptions
-                session.tick_subscriptions(&now, &address_space, TickReason::TickTimerFired)?;
+                session.tick_subscriptions(&now, &*address_space, TickReason::TickTimerFired)?;
 
                 // Check if there are publish responses to send for transmission
                 if let Some(publish_responses) =
                     session.subscriptions_mut().take_publish_responses()
                 {
-                    for publish_response in publish_responses {
-                        trace!(
-                            "<-- Sending a Publish Response{}, {:?}",
-                            publish_response.request_id,
-                            &publish_response.response
-                        );
-                        // Messages will be sent by the writing task
-                        sender
-                            .send(Message::Message(
-                                publish_response.request_id,
-                                publish_response.response,
-                            ))
-                            .map_err(|e| {
-                                error!("Unable to send publish response to writer task: {}", e);
-                                StatusCode::BadUnexpectedError
-                            })?;
-                    }
+                    Self::send_publish_responses(&sender, publish_responses)?;
                 }
             }
+            drop(session_manager);
+
+            // A server that is shutting down closes the sessions on this connection after their
+            // final tick. Sessions on other connections are closed by their own transport.
+            if transport.is_server_abort() {
+                let secure_channel_id = {
+                    let secure_channel = trace_read_lock!(transport.secure_channel);
+                    secure_channel.secure_channel_id()
+                };
+                let mut session_manager = trace_write_lock!(transport.session_manager);
+                let publish_responses = session_manager
+                    .shutdown_sessions(secure_channel_id, transport.address_space.clone());
+                Self::send_publish_responses(&sender, publish_responses)?;
+            }
+        }
+    }
+
+    /// Passes publish responses to the writing task to be sent
+    fn send_publish_responses(
+        sender: &UnboundedSender<Message>,
+        publish_responses: impl IntoIterator<Item = PublishResponseEntry>,
+    ) -> Result<(), StatusCode> {
+        for publish_response in publish_responses {
+            trace!(
+                "<-- Sending a Publish Response{}, {:?}",
+                publish_response.request_id,
+                &publish_response.response
+            );
+            // Messages will be sent by the writing task
+            sender
+                .send(Message::Message(
+                    publish_response.request_id,
+                    publish_response.response,
+                ))
+                .map_err(|e| {
+                    error!("Unable to send publish response to writer task: {}", e);
+                    StatusCode::BadUnexpectedError
+                })?;
         }
+        Ok(())
     }
 
     /// Test if the connection should abort
@@ -504,6 +600,7 @@ impl TcpTransport {
         &mut self,
         hello: HelloMessage,
         sender: &mut UnboundedSender<Message>,
+        send_buffer: &Mutex<MessageWriter>,
         decoding_options: &DecodingOptions,
         send_buffer_size: usize,
         receive_buffer_size: usize,
@@ -532,6 +629,11 @@ impl TcpTransport {
 
         let client_protocol_version = hello.protocol_version;
 
+        let send_buffer_size = {
+            let mut send_buffer = trace_lock!(send_buffer);
+            Self::apply_hello_limits(&hello, &mut send_buffer, decoding_options, send_buffer_size)
+        };
+
         // Send acknowledge
         let mut acknowledge = AcknowledgeMessage {
             message_header: MessageHeader::new(MessageType::Acknowledge),
@@ -553,6 +655,30 @@ impl TcpTransport {
         Ok(())
     }
 
+    /// Narrows the limits of the messages sent to the client to those in its HELLO. Chunks must fit
+    /// in the client's receive buffer, and messages must not exceed the size or number of chunks
+    /// it can receive. Returns the size of the chunks that will be sent.
+    pub(crate) fn apply_hello_limits(
+        hello: &HelloMessage,
+        send_buffer: &mut MessageWriter,
+        decoding_options: &DecodingOptions,
+        send_buffer_size: usize,
+    ) -> usize {
+        // A limit of 0 means there is no limit
+        let min_limit = |server_limit: usize, client_limit: u32| match client_limit as usize {
+            0 => server_limit,
+            client_limit if server_limit == 0 => client_limit,
+            client_limit => server_limit.min(client_limit),
+        };
+        let send_buffer_size = send_buffer_size.min(hello.receive_buffer_size as usize);
+        send_buffer.set_limits(
+            send_buffer_size,
+            min_limit(decoding_options.max_message_size, hello.max_message_size),
+            min_limit(decoding_options.max_chunk_count, hello.max_chunk_count),
+        );
+        send_buffer_size
+    }
+
     fn turn_received_chunks_into_message(
         &mut self,
         chunks: &[MessageChunk],
@@ -675,9 +801,7 @@ impl TcpTransport {
         request: &SupportedMessage,
         sender: &MessageSender,
     ) -> Result<(), StatusCode> {
-        let _ = self
-            .message_handler
-            .handle_message(request_id, request, sender)?;
-        Ok(())
+        self.message_handler
+            .handle_message(request_id, request, sender)
     }
 }
@@ -10,7 +10,10 @@ use std::{
 };
 
 use crate::{
-    core::{comms::url::url_matches_except_host, config::Config},
+    core::{
+        comms::url::{is_opc_ua_binary_url, url_matches_except_host},
+        config::Config,
+    },
     crypto::{CertificateStore, SecurityPolicy, Thumbprint},
     types::{service_types::ApplicationType, DecodingOptions, MessageSecurityMode, UAString},
 };
@@ -23,6 +26,7 @@ const RECEIVE_BUFFER_SIZE: usize = u16::MAX as usize;
 const SEND_BUFFER_SIZE: usize = u16::MAX as usize;
 
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct TcpConfig {
     /// Timeout for hello on a session in seconds
     pub hello_timeout: u32,
@@ -33,6 +37,7 @@ pub struct TcpConfig {
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct ServerUserToken {
     /// User name
     pub user: String,
@@ -90,32 +95,33 @@ impl ServerUserToken {
     /// Test if the token is valid. This does not care for x509 tokens if the cert is present on
     /// the disk or not.
     pub fn is_valid(&self, id: &str) -> bool {
-        let mut valid = true;
+        self.validate(id).is_empty()
+    }
+
+    /// Returns the problems with the user token, or an empty list if it is valid.
+    pub fn validate(&self, id: &str) -> Vec<String> {
+        let mut errors = Vec::new();
         if id == ANONYMOUS_USER_TOKEN_ID {
-            error!(
+            errors.push(format!(
                 "User token {} is invalid because id is a reserved value, use another value.",
                 id
-            );
-            valid = false;
+            ));
         }
         if self.user.is_empty() {
-            error!("User token {} has an empty user name.", id);
-            valid = false;
+            errors.push(format!("User token {} has an empty user name.", id));
         }
         if self.pass.is_some() && self.x509.is_some() {
-            error!(
+            errors.push(format!(
                 "User token {} holds a password and certificate info - it cannot be both.",
                 id
-            );
-            valid = false;
+            ));
         } else if self.pass.is_none() && self.x509.is_none() {
-            error!(
+            errors.push(format!(
                 "User token {} fails to provide a password or certificate info.",
                 id
-            );
-            valid = false;
+            ));
         }
-        valid
+        errors
     }
 
     pub fn is_user_pass(&self) -> bool {
@@ -128,11 +134,15 @@ impl ServerUserToken {
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct Limits {
     /// Indicates if clients are able to modify the address space through the node management service
     /// set. This is a very broad flag and is likely to require more fine grained per user control
     /// in a later revision. By default, this value is `false`
     pub clients_can_modify_address_space: bool,
+    /// Maximum number of sessions on the server, 0 for no limit
+    #[serde(default = "Limits::default_max_sessions")]
+    pub max_sessions: usize,
     /// Maximum number of subscriptions in a session, 0 for no limit
     pub max_subscriptions: usize,
     /// Maximum number of monitored items per subscription, 0 for no limit
@@ -159,6 +169,12 @@ pub struct Limits {
     pub receive_buffer_size: usize,
 }
 
+impl Limits {
+    fn default_max_sessions() -> usize {
+        constants::DEFAULT_MAX_SESSIONS
+    }
+}
+
 impl Default for Limits {
     fn default() -> Self {
         let decoding_options = DecodingOptions::default();
@@ -166,6 +182,7 @@ impl Default for Limits {
             max_array_length: decoding_options.max_array_length,
             max_string_length: decoding_options.max_string_length,
             max_byte_string_length: decoding_options.max_byte_string_length,
+            max_sessions: constants::DEFAULT_MAX_SESSIONS,
             max_subscriptions: constants::DEFAULT_MAX_SUBSCRIPTIONS,
             max_monitored_items_per_sub: constants::DEFAULT_MAX_MONITORED_ITEMS_PER_SUB,
             max_monitored_item_queue_size: constants::MAX_DATA_CHANGE_QUEUE_SIZE,
@@ -181,6 +198,7 @@ impl Default for Limits {
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct CertificateValidation {
     /// Auto trusts client certificates. For testing/samples only unless you're sure what you're
     /// doing.
@@ -199,6 +217,7 @@ impl Default for CertificateValidation {
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct ServerEndpoint {
     /// Endpoint path
     pub path: String,
@@ -398,7 +417,16 @@ impl ServerEndpoint {
     }
 
     pub fn is_valid(&self, id: &str, user_tokens: &BTreeMap<String, ServerUserToken>) -> bool {
-        let mut valid = true;
+        self.validate(id, user_tokens).is_empty()
+    }
+
+    /// Returns the problems with the endpoint, or an empty list if it is valid.
+    pub fn validate(
+        &self,
+        id: &str,
+        user_tokens: &BTreeMap<String, ServerUserToken>,
+    ) -> Vec<String> {
+        let mut errors = Vec::new();
 
         // Validate that the user token ids exist
         for id in &self.user_token_ids {
@@ -407,8 +435,7 @@ impl ServerEndpoint {
                 continue;
             }
             if !user_tokens.contains_key(id) {
-                error!("Cannot find user token with id {}", id);
-                valid = false;
+                errors.push(format!("Cannot find user token with id {}", id));
             }
         }
 
@@ -416,8 +443,7 @@ impl ServerEndpoint {
             let password_security_policy =
                 SecurityPolicy::from_str(password_security_policy).unwrap();
             if password_security_policy == SecurityPolicy::Unknown {
-                error!("Endpoint {} is invalid. Password security policy \"{}\" is invalid. Valid values are None, Basic128Rsa15, Basic256, Basic256Sha256", id, password_security_policy);
-                valid = false;
+                errors.push(format!("Endpoint {} is invalid. Password security policy \"{}\" is invalid. Valid values are None, Basic128Rsa15, Basic256, Basic256Sha256", id, password_security_policy));
             }
         }
 
@@ -425,25 +451,21 @@ impl ServerEndpoint {
         let security_policy = SecurityPolicy::from_str(&self.security_policy).unwrap();
         let security_mode = MessageSecurityMode::from(self.security_mode.as_ref());
         if security_policy == SecurityPolicy::Unknown {
-            error!("Endpoint {} is invalid. Security policy \"{}\" is invalid. Valid values are None, Basic128Rsa15, Basic256, Basic256Sha256, Aes128Sha256RsaOaep, Aes256Sha256RsaPss,", id, self.security_policy);
-            valid = false;
+            errors.push(format!("Endpoint {} is invalid. Security policy \"{}\" is invalid. Valid values are None, Basic128Rsa15, Basic256, Basic256Sha256, Aes128Sha256RsaOaep, Aes256Sha256RsaPss,", id, self.security_policy));
         } else if security_mode == MessageSecurityMode::Invalid {
-            error!("Endpoint {} is invalid. Security mode \"{}\" is invalid. Valid values are None, Sign, SignAndEncrypt", id, self.security_mode);
-            valid = false;
+            errors.push(format!("Endpoint {} is invalid. Security mode \"{}\" is invalid. Valid values are None, Sign, SignAndEncrypt", id, self.security_mode));
         } else if (security_policy == SecurityPolicy::None
             && security_mode != MessageSecurityMode::None)
             || (security_policy != SecurityPolicy::None
                 && security_mode == MessageSecurityMode::None)
         {
-            error!("Endpoint {} is invalid. Security policy and security mode must both contain None or neither of them should (1).", id);
-            valid = false;
+            errors.push(format!("Endpoint {} is invalid. Security policy and security mode must both contain None or neither of them should (1).", id));
         } else if security_policy != SecurityPolicy::None
             && security_mode == MessageSecurityMode::None
         {
-            error!("Endpoint {} is invalid. Security policy and security mode must both contain None or neither of them should (2).", id);
-            valid = false;
+            errors.push(format!("Endpoint {} is invalid. Security policy and security mode must both contain None or neither of them should (2).", id));
         }
-        valid
+        errors
     }
 
     pub fn security_policy(&self) -> SecurityPolicy {
@@ -519,13 +541,19 @@ impl ServerEndpoint {
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct Performance {
     /// Use a single-threaded executor. The default executor uses a thread pool with a worker
     /// thread for each CPU core available on the system.
     pub single_threaded_executor: bool,
+    /// Records the count and duration of the calls to each service, which are then reported in
+    /// the server metrics. Off by default since it adds work to every request.
+    #[serde(default)]
+    pub service_metrics: bool,
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct ServerConfig {
     /// An id for this server
     pub application_name: String,
@@ -533,6 +561,10 @@ pub struct ServerConfig {
     pub application_uri: String,
     /// Product url
     pub product_uri: String,
+    /// Namespace uris of the server's own nodes, registered after the application uri. The first
+    /// is the namespace that new nodes are created in.
+    #[serde(default)]
+    pub namespaces: Vec<String>,
     /// Autocreates public / private keypair if they don't exist. For testing/samples only
     /// since you do not have control of the values
     pub create_sample_keypair: bool,
@@ -557,6 +589,10 @@ pub struct ServerConfig {
     pub locale_ids: Vec<String>,
     /// User tokens
     pub user_tokens: BTreeMap<String, ServerUserToken>,
+    /// Allows anonymous access on the endpoints whose user token ids include anonymous. When
+    /// false, no endpoint allows anonymous access or advertises it. True by default.
+    #[serde(default = "ServerConfig::default_allow_anonymous")]
+    pub allow_anonymous: bool,
     /// discovery endpoint url which may or may not be the same as the service endpoints below.
     pub discovery_urls: Vec<String>,
     /// Default endpoint id
@@ -566,8 +602,8 @@ pub struct ServerConfig {
 }
 
 impl Config for ServerConfig {
-    fn is_valid(&self) -> bool {
-        let mut valid = true;
+    fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
         if self.application_name.is_empty() {
             warn!("No application was set");
         }
@@ -577,42 +613,51 @@ impl Config for ServerConfig {
         if self.product_uri.is_empty() {
             warn!("No product uri was set");
         }
+        if self.namespaces.iter().any(|namespace| namespace.is_empty()) {
+            errors.push("Server configuration is invalid. It has an empty namespace".to_string());
+        }
         if self.endpoints.is_empty() {
-            error!("Server configuration is invalid. It defines no endpoints");
-            valid = false;
+            errors.push("Server configuration is invalid. It defines no endpoints".to_string());
         }
         for (id, endpoint) in &self.endpoints {
-            if !endpoint.is_valid(id, &self.user_tokens) {
-                valid = false;
-            }
+            errors.append(&mut endpoint.validate(id, &self.user_tokens));
         }
         if let Some(ref default_endpoint) = self.default_endpoint {
             if !self.endpoints.contains_key(default_endpoint) {
-                valid = false;
+                errors.push(format!(
+                    "Server configuration is invalid. Default endpoint {} does not exist",
+                    default_endpoint
+                ));
             }
         }
         for (id, user_token) in &self.user_tokens {
-            if !user_token.is_valid(id) {
-                valid = false;
-            }
+            errors.append(&mut user_token.validate(id));
         }
         if self.limits.max_array_length == 0 {
-            error!("Server configuration is invalid. Max array length is invalid");
-            valid = false;
+            errors.push("Server configuration is invalid. Max array length is invalid".to_string());
         }
         if self.limits.max_string_length == 0 {
-            error!("Server configuration is invalid. Max string length is invalid");
-            valid = false;
+            errors
+                .push("Server configuration is invalid. Max string length is invalid".to_string());
         }
         if self.limits.max_byte_string_length == 0 {
-            error!("Server configuration is invalid. Max byte string length is invalid");
-            valid = false;
+            errors.push(
+                "Server configuration is invalid. Max byte string length is invalid".to_string(),
+            );
         }
         if self.discovery_urls.is_empty() {
-            error!("Server configuration is invalid. Discovery urls not set");
-            valid = false;
+            errors.push("Server configuration is invalid. Discovery urls not set".to_string());
         }
-        valid
+        self.discovery_urls
+            .iter()
+            .filter(|discovery_url| !is_opc_ua_binary_url(discovery_url))
+            .for_each(|discovery_url| {
+                errors.push(format!(
+                    "Server configuration is invalid. Discovery url {} is not an opc.tcp url",
+                    discovery_url
+                ));
+            });
+        errors
     }
 
     fn application_name(&self) -> UAString {
@@ -647,6 +692,7 @@ impl Default for ServerConfig {
             application_name: String::new(),
             application_uri: String::new(),
             product_uri: String::new(),
+            namespaces: Vec::new(),
             create_sample_keypair: false,
             certificate_path: None,
             private_key_path: None,
@@ -660,12 +706,14 @@ impl Default for ServerConfig {
             },
             limits: Limits::default(),
             user_tokens: BTreeMap::new(),
+            allow_anonymous: true,
             locale_ids: vec!["en".to_string()],
             discovery_urls: Vec::new(),
             default_endpoint: None,
             endpoints: BTreeMap::new(),
             performance: Performance {
                 single_threaded_executor: false,
+                service_metrics: false,
             },
         }
     }
@@ -675,6 +723,10 @@ impl ServerConfig {
     /// The default PKI directory
     pub const PKI_DIR: &'static str = "pki";
 
+    fn default_allow_anonymous() -> bool {
+        true
+    }
+
     pub fn new<T>(
         application_name: T,
         user_tokens: BTreeMap<String, ServerUserToken>,
@@ -700,6 +752,7 @@ impl ServerConfig {
             application_name,
             application_uri,
             product_uri,
+            namespaces: Vec::new(),
             create_sample_keypair: false,
             certificate_path: None,
             private_key_path: None,
@@ -717,11 +770,13 @@ impl ServerConfig {
             limits: Limits::default(),
             locale_ids,
             user_tokens,
+            allow_anonymous: true,
             discovery_urls,
             default_endpoint: None,
             endpoints,
             performance: Performance {
                 single_threaded_executor: false,
+                service_metrics: false,
             },
         }
     }
@@ -2,14 +2,18 @@
 // SPDX-License-Identifier: MPL-2.0
 // Copyright (C) 2017-2024 Adam Lock
 
-//! Provides a browse continuation point type for tracking a browse operation initiated by a client.
+//! Provides continuation point types for tracking browse and query operations initiated by a
+//! client.
 
 use std::sync::Arc;
 
 use crate::sync::*;
-use crate::types::{service_types::ReferenceDescription, ByteString, DateTimeUtc};
+use crate::types::{
+    service_types::{QueryDataSet, ReferenceDescription},
+    ByteString, DateTimeUtc,
+};
 
-use crate::prelude::AddressSpace;
+use crate::server::address_space::provider::AddressSpaceProvider;
 
 #[derive(Clone, Debug)]
 pub struct BrowseContinuationPoint {
@@ -23,7 +27,30 @@ pub struct BrowseContinuationPoint {
 impl BrowseContinuationPoint {
     /// Test if the continuation point valid which is only true if address space has not been
     /// modified since the point was made.
-    pub fn is_valid_browse_continuation_point(&self, address_space: &AddressSpace) -> bool {
+    pub fn is_valid_browse_continuation_point<P>(&self, address_space: &P) -> bool
+    where
+        P: AddressSpaceProvider + ?Sized,
+    {
+        self.address_space_last_modified >= address_space.last_modified()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct QueryContinuationPoint {
+    pub id: ByteString,
+    pub address_space_last_modified: DateTimeUtc,
+    pub max_data_sets_to_return: usize,
+    /// The data sets that have still to be returned
+    pub query_data_sets: Vec<QueryDataSet>,
+}
+
+impl QueryContinuationPoint {
+    /// Test if the continuation point valid which is only true if address space has not been
+    /// modified since the point was made.
+    pub fn is_valid_query_continuation_point<P>(&self, address_space: &P) -> bool
+    where
+        P: AddressSpaceProvider + ?Sized,
+    {
         self.address_space_last_modified >= address_space.last_modified()
     }
 }
@@ -10,7 +10,11 @@ use crate::types::{
 };
 
 use crate::server::address_space::{
-    object::ObjectBuilder, relative_path::*, variable::VariableBuilder, AddressSpace,
+    object::ObjectBuilder,
+    provider::{map_node, AddressSpaceProvider},
+    relative_path::*,
+    variable::VariableBuilder,
+    AddressSpace,
 };
 
 /// Events can implement this to populate themselves into the address space
@@ -436,16 +440,23 @@ macro_rules! base_event_impl {
     };
 }
 
-fn event_source_node(event_id: &NodeId, address_space: &AddressSpace) -> Option<NodeId> {
+fn event_source_node<P>(event_id: &NodeId, address_space: &P) -> Option<NodeId>
+where
+    P: AddressSpaceProvider + ?Sized,
+{
     if let Ok(event_time_node) =
         find_node_from_browse_path(address_space, event_id, &["SourceNode".into()])
     {
-        if let Some(value) = event_time_node.as_node().get_attribute(
-            TimestampsToReturn::Neither,
-            AttributeId::Value,
-            NumericRange::None,
-            &QualifiedName::null(),
-        ) {
+        if let Some(value) = map_node(address_space, &event_time_node, |node| {
+            node.as_node().get_attribute(
+                TimestampsToReturn::Neither,
+                AttributeId::Value,
+                NumericRange::None,
+                &QualifiedName::null(),
+            )
+        })
+        .flatten()
+        {
             if let Some(value) = value.value {
                 match value {
                     Variant::NodeId(node_id) => Some(*node_id),
@@ -462,17 +473,24 @@ fn event_source_node(event_id: &NodeId, address_space: &AddressSpace) -> Option<
     }
 }
 
-fn event_time(event_id: &NodeId, address_space: &AddressSpace) -> Option<DateTime> {
+fn event_time<P>(event_id: &NodeId, address_space: &P) -> Option<DateTime>
+where
+    P: AddressSpaceProvider + ?Sized,
+{
     // Find the Time variable under the event to return a timestamp.
     if let Ok(event_time_node) =
         find_node_from_browse_path(address_space, event_id, &["Time".into()])
     {
-        if let Some(value) = event_time_node.as_node().get_attribute(
-            TimestampsToReturn::Neither,
-            AttributeId::Value,
-            NumericRange::None,
-            &QualifiedName::null(),
-        ) {
+        if let Some(value) = map_node(address_space, &event_time_node, |node| {
+            node.as_node().get_attribute(
+                TimestampsToReturn::Neither,
+                AttributeId::Value,
+                NumericRange::None,
+                &QualifiedName::null(),
+            )
+        })
+        .flatten()
+        {
             if let Some(value) = value.value {
                 match value {
                     Variant::DateTime(date_time) => Some(*date_time),
@@ -490,21 +508,22 @@ fn event_time(event_id: &NodeId, address_space: &AddressSpace) -> Option<DateTim
 }
 
 /// Attempts to find events that were emitted by the source object based upon a time predicate
-pub fn filter_events<T, R, F>(
+pub fn filter_events<P, T, R, F>(
     source_object_id: T,
     event_type_id: R,
-    address_space: &AddressSpace,
+    address_space: &P,
     time_predicate: F,
 ) -> Option<Vec<NodeId>>
 where
     T: Into<NodeId>,
     R: Into<NodeId>,
     F: Fn(&DateTimeUtc) -> bool,
+    P: AddressSpaceProvider + ?Sized,
 {
     let event_type_id = event_type_id.into();
     let source_object_id = source_object_id.into();
     // Find events of type event_type_id
-    if let Some(events) = address_space.find_objects_by_type(event_type_id, true) {
+    if let Some(events) = address_space.find_objects_by_type(&event_type_id, true) {
         let event_ids = events
             .iter()
             .filter(move |event_id| {
@@ -563,13 +582,14 @@ where
 }
 
 /// Searches for events of the specified event type which reference the source object
-pub fn events_for_object<T>(
+pub fn events_for_object<P, T>(
     source_object_id: T,
-    address_space: &AddressSpace,
+    address_space: &P,
     happened_since: &DateTimeUtc,
 ) -> Option<Vec<NodeId>>
 where
     T: Into<NodeId>,
+    P: AddressSpaceProvider + ?Sized,
 {
     filter_events(
         source_object_id,
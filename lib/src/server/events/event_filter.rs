@@ -15,16 +15,23 @@ use crate::types::{
 };
 
 use crate::server::{
-    address_space::{address_space::AddressSpace, node::NodeType, relative_path::*},
+    address_space::{
+        node::NodeType,
+        provider::{map_node, AddressSpaceProvider},
+        relative_path::*,
+    },
     events::event::events_for_object,
     events::operator,
 };
 
 /// This validates the event filter as best it can to make sure it doesn't contain nonsense.
-pub fn validate(
+pub fn validate<P>(
     event_filter: &EventFilter,
-    address_space: &AddressSpace,
-) -> Result<EventFilterResult, StatusCode> {
+    address_space: &P,
+) -> Result<EventFilterResult, StatusCode>
+where
+    P: AddressSpaceProvider + ?Sized,
+{
     let select_clause_results = event_filter.select_clauses.as_ref().map(|select_clauses| {
         select_clauses
             .iter()
@@ -40,13 +47,16 @@ pub fn validate(
 }
 
 /// Evaluate the event filter and see if it triggers.
-pub fn evaluate(
+pub fn evaluate<P>(
     object_id: &NodeId,
     event_filter: &EventFilter,
-    address_space: &AddressSpace,
+    address_space: &P,
     happened_since: &DateTimeUtc,
     client_handle: u32,
-) -> Option<Vec<EventFieldList>> {
+) -> Option<Vec<EventFieldList>>
+where
+    P: AddressSpaceProvider + ?Sized,
+{
     if let Some(events) = events_for_object(object_id, address_space, happened_since) {
         let event_fields = events
             .iter()
@@ -84,11 +94,14 @@ pub fn evaluate(
 }
 
 /// Evaluates a where clause which is a tree of conditionals
-pub(crate) fn evaluate_where_clause(
+pub(crate) fn evaluate_where_clause<P>(
     object_id: &NodeId,
     where_clause: &ContentFilter,
-    address_space: &AddressSpace,
-) -> Result<Variant, StatusCode> {
+    address_space: &P,
+) -> Result<Variant, StatusCode>
+where
+    P: AddressSpaceProvider + ?Sized,
+{
     // Clause is meant to have been validated before now so this code is not as stringent and makes some expectations.
     if let Some(ref elements) = where_clause.elements {
         if !elements.is_empty() {
@@ -111,10 +124,10 @@ pub(crate) fn evaluate_where_clause(
     }
 }
 
-fn validate_select_clause(
-    clause: &SimpleAttributeOperand,
-    address_space: &AddressSpace,
-) -> StatusCode {
+fn validate_select_clause<P>(clause: &SimpleAttributeOperand, address_space: &P) -> StatusCode
+where
+    P: AddressSpaceProvider + ?Sized,
+{
     // The SimpleAttributeOperand structure is used in the selectClauses to select the value to return
     // if an Event meets the criteria specified by the whereClause. A null value is returned in the corresponding
     // event field in the publish response if the selected field is not part of the event or an
@@ -126,32 +139,35 @@ fn validate_select_clause(
         StatusCode::BadIndexRangeInvalid
     } else if let Some(ref browse_path) = clause.browse_path {
         // Validate that the browse paths seem okay relative to the object type definition in the clause
-        if let Ok(node) =
+        let valid_attribute_id =
             find_node_from_browse_path(address_space, &clause.type_definition_id, browse_path)
-        {
-            // Validate the attribute id. Per spec:
-            //
-            //   The SimpleAttributeOperand allows the client to specify any attribute; however the server
-            //   is only required to support the value attribute for variable nodes and the NodeId attribute
-            //   for object nodes. That said, profiles defined in Part 7 may make support for
-            //   additional attributes mandatory.
-            //
-            // So code will implement the bare minimum for now.
-            let valid_attribute_id = match node {
-                NodeType::Object(_) => {
-                    // Only the node id
-                    clause.attribute_id == AttributeId::NodeId as u32
-                }
-                NodeType::Variable(_) => {
-                    // Only the value
-                    clause.attribute_id == AttributeId::Value as u32
-                }
-                _ => {
-                    // find_node_from_browse_path shouldn't have returned anything except an object
-                    // or variable node.
-                    panic!()
-                }
-            };
+                .ok()
+                .and_then(|node_id| {
+                    // Validate the attribute id. Per spec:
+                    //
+                    //   The SimpleAttributeOperand allows the client to specify any attribute; however the server
+                    //   is only required to support the value attribute for variable nodes and the NodeId attribute
+                    //   for object nodes. That said, profiles defined in Part 7 may make support for
+                    //   additional attributes mandatory.
+                    //
+                    // So code will implement the bare minimum for now.
+                    map_node(address_space, &node_id, |node| match node {
+                        NodeType::Object(_) => {
+                            // Only the node id
+                            clause.attribute_id == AttributeId::NodeId as u32
+                        }
+                        NodeType::Variable(_) => {
+                            // Only the value
+                            clause.attribute_id == AttributeId::Value as u32
+                        }
+                        _ => {
+                            // find_node_from_browse_path shouldn't have returned anything except an object
+                            // or variable node.
+                            panic!()
+                        }
+                    })
+                });
+        if let Some(valid_attribute_id) = valid_attribute_id {
             if !valid_attribute_id {
                 StatusCode::BadAttributeIdInvalid
             } else {
@@ -167,10 +183,13 @@ fn validate_select_clause(
     }
 }
 
-fn validate_where_clause(
+fn validate_where_clause<P>(
     where_clause: &ContentFilter,
-    address_space: &AddressSpace,
-) -> Result<ContentFilterResult, StatusCode> {
+    address_space: &P,
+) -> Result<ContentFilterResult, StatusCode>
+where
+    P: AddressSpaceProvider + ?Sized,
+{
     // The ContentFilter structure defines a collection of elements that define filtering criteria.
     // Each element in the collection describes an operator and an array of operands to be used by
     // the operator. The operators that can be used in a ContentFilter are described in Table 119.
@@ -209,6 +228,7 @@ fn validate_where_clause(
                     FilterOperator::Cast => filter_operands.len() < 2,
                     FilterOperator::BitwiseAnd => filter_operands.len() < 2,
                     FilterOperator::BitwiseOr => filter_operands.len() < 2,
+                    FilterOperator::OfType => filter_operands.is_empty(),
                     _ => true,
                 };
 
@@ -305,6 +325,7 @@ fn validate_where_clause(
 
 #[test]
 fn validate_where_clause_test() {
+    use crate::server::address_space::AddressSpace;
     use crate::types::service_types::ContentFilterElement;
 
     let address_space = AddressSpace::new();
@@ -357,6 +378,7 @@ fn validate_where_clause_test() {
                     vec![Operand::literal(10)],
                 )),
                 ContentFilterElement::from((FilterOperator::BitwiseOr, vec![Operand::literal(10)])),
+                ContentFilterElement::from((FilterOperator::OfType, vec![])),
                 ContentFilterElement::from((FilterOperator::Like, vec![Operand::literal(10)])),
             ]),
         };
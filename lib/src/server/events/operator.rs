@@ -9,17 +9,18 @@ use std::convert::TryFrom;
 use regex::Regex;
 
 use crate::types::{
+    node_ids::ReferenceTypeId,
     operand::Operand,
     service_types::{ContentFilterElement, FilterOperator, SimpleAttributeOperand},
     status_code::StatusCode,
-    AttributeId, ExtensionObject, NodeId, NumericRange, QualifiedName, TimestampsToReturn, Variant,
-    VariantTypeId,
+    AttributeId, ExtensionObject, NodeId, NumericRange, QualifiedName, RelativePath,
+    TimestampsToReturn, UAString, Variant, VariantTypeId,
 };
 
 use crate::server::address_space::{
     node::{NodeBase, NodeType},
-    relative_path::find_node_from_browse_path,
-    AddressSpace,
+    provider::{map_node, AddressSpaceProvider},
+    relative_path::{find_node_from_browse_path, find_nodes_relative_path},
 };
 
 /// Turns a list of operands inside extension objects to their analogous Operand objects
@@ -42,13 +43,16 @@ fn make_filter_operands(filter_operands: &[ExtensionObject]) -> Result<Vec<Opera
 }
 
 /// Evaluates the expression
-pub(crate) fn evaluate(
+pub(crate) fn evaluate<P>(
     object_id: &NodeId,
     element: &ContentFilterElement,
     used_elements: &mut HashSet<u32>,
     elements: &[ContentFilterElement],
-    address_space: &AddressSpace,
-) -> Result<Variant, StatusCode> {
+    address_space: &P,
+) -> Result<Variant, StatusCode>
+where
+    P: AddressSpaceProvider + ?Sized,
+{
     if let Some(ref filter_operands) = element.filter_operands {
         if !filter_operands.is_empty() {
             // Turn ExtensionObjects into Operands here. This should be externalised even further so it
@@ -161,6 +165,13 @@ pub(crate) fn evaluate(
                     elements,
                     address_space,
                 ),
+                FilterOperator::OfType => of_type(
+                    object_id,
+                    &operands[..],
+                    used_elements,
+                    elements,
+                    address_space,
+                ),
                 _ => Err(StatusCode::BadFilterOperatorUnsupported),
             }
         } else {
@@ -176,67 +187,78 @@ pub(crate) fn evaluate(
 }
 
 /// Get the value of something and convert to the expected type.
-fn value_as(
+fn value_as<P>(
     object_id: &NodeId,
     as_type: VariantTypeId,
     operand: &Operand,
     used_elements: &mut HashSet<u32>,
     elements: &[ContentFilterElement],
-    address_space: &AddressSpace,
-) -> Result<Variant, StatusCode> {
+    address_space: &P,
+) -> Result<Variant, StatusCode>
+where
+    P: AddressSpaceProvider + ?Sized,
+{
     let v = value_of(object_id, operand, used_elements, elements, address_space)?;
     Ok(v.convert(as_type))
 }
 
-pub(crate) fn value_of_simple_attribute(
+pub(crate) fn value_of_simple_attribute<P>(
     object_id: &NodeId,
     o: &SimpleAttributeOperand,
-    address_space: &AddressSpace,
-) -> Variant {
+    address_space: &P,
+) -> Variant
+where
+    P: AddressSpaceProvider + ?Sized,
+{
     // Get the Object / Variable by browse path
     if let Some(ref browse_path) = o.browse_path {
         // TODO o.data_type is ignored but be used to restrict the browse
         // path to subtypes of HierarchicalReferences
 
         // Find the actual node via browse path
-        if let Ok(node) = find_node_from_browse_path(address_space, object_id, browse_path) {
-            match node {
-                NodeType::Object(ref node) => {
-                    if o.attribute_id == AttributeId::NodeId as u32 {
-                        node.node_id().into()
-                    } else {
-                        error!(
-                            "value_of, unsupported attribute id {} on object",
-                            o.attribute_id
-                        );
-                        Variant::Empty
+        let value = find_node_from_browse_path(address_space, object_id, browse_path)
+            .ok()
+            .and_then(|node_id| {
+                map_node(address_space, &node_id, |node| match node {
+                    NodeType::Object(ref node) => {
+                        if o.attribute_id == AttributeId::NodeId as u32 {
+                            node.node_id().into()
+                        } else {
+                            error!(
+                                "value_of, unsupported attribute id {} on object",
+                                o.attribute_id
+                            );
+                            Variant::Empty
+                        }
                     }
-                }
-                NodeType::Variable(ref node) => {
-                    if o.attribute_id == AttributeId::Value as u32 {
-                        if let Some(ref value) = node
-                            .value(
-                                TimestampsToReturn::Neither,
-                                NumericRange::None,
-                                &QualifiedName::null(),
-                                0.0,
-                            )
-                            .value
-                        {
-                            value.clone()
+                    NodeType::Variable(ref node) => {
+                        if o.attribute_id == AttributeId::Value as u32 {
+                            if let Some(ref value) = node
+                                .value(
+                                    TimestampsToReturn::Neither,
+                                    NumericRange::None,
+                                    &QualifiedName::null(),
+                                    0.0,
+                                )
+                                .value
+                            {
+                                value.clone()
+                            } else {
+                                Variant::Empty
+                            }
                         } else {
+                            error!(
+                                "value_of, unsupported attribute id {} on Variable",
+                                o.attribute_id
+                            );
                             Variant::Empty
                         }
-                    } else {
-                        error!(
-                            "value_of, unsupported attribute id {} on Variable",
-                            o.attribute_id
-                        );
-                        Variant::Empty
                     }
-                }
-                _ => Variant::Empty,
-            }
+                    _ => Variant::Empty,
+                })
+            });
+        if let Some(value) = value {
+            value
         } else {
             error!(
                 "value_of, cannot find node from browse path {:?}",
@@ -250,14 +272,67 @@ pub(crate) fn value_of_simple_attribute(
     }
 }
 
+/// Get the value of an attribute of the node found by following the relative path from the object.
+/// An empty path refers to the object itself.
+pub(crate) fn value_of_attribute<P>(
+    object_id: &NodeId,
+    browse_path: &RelativePath,
+    attribute_id: u32,
+    index_range: &UAString,
+    address_space: &P,
+) -> Variant
+where
+    P: AddressSpaceProvider + ?Sized,
+{
+    let node_id = match browse_path.elements {
+        Some(ref elements) if !elements.is_empty() => {
+            find_nodes_relative_path(address_space, object_id, browse_path)
+                .ok()
+                .and_then(|node_ids| node_ids.into_iter().next())
+        }
+        _ => Some(object_id.clone()),
+    };
+    let Some(node_id) = node_id.filter(|node_id| address_space.node_exists(node_id)) else {
+        error!(
+            "value_of, cannot find node from browse path {:?}",
+            browse_path
+        );
+        return Variant::Empty;
+    };
+    let (Ok(attribute_id), Ok(index_range)) = (
+        AttributeId::from_u32(attribute_id),
+        index_range.as_ref().parse::<NumericRange>(),
+    ) else {
+        error!(
+            "value_of, invalid attribute id {} or index range",
+            attribute_id
+        );
+        return Variant::Empty;
+    };
+    map_node(address_space, &node_id, |node| {
+        node.as_node().get_attribute(
+            TimestampsToReturn::Neither,
+            attribute_id,
+            index_range,
+            &QualifiedName::null(),
+        )
+    })
+    .flatten()
+    .and_then(|value| value.value)
+    .unwrap_or(Variant::Empty)
+}
+
 // This function fetches the value of the operand.
-pub(crate) fn value_of(
+pub(crate) fn value_of<P>(
     object_id: &NodeId,
     operand: &Operand,
     used_elements: &mut HashSet<u32>,
     elements: &[ContentFilterElement],
-    address_space: &AddressSpace,
-) -> Result<Variant, StatusCode> {
+    address_space: &P,
+) -> Result<Variant, StatusCode>
+where
+    P: AddressSpaceProvider + ?Sized,
+{
     match operand {
         Operand::ElementOperand(ref o) => {
             if used_elements.contains(&o.index) {
@@ -280,9 +355,13 @@ pub(crate) fn value_of(
         Operand::SimpleAttributeOperand(ref o) => {
             Ok(value_of_simple_attribute(object_id, o, address_space))
         }
-        Operand::AttributeOperand(_) => {
-            panic!();
-        }
+        Operand::AttributeOperand(ref o) => Ok(value_of_attribute(
+            object_id,
+            &o.browse_path,
+            o.attribute_id,
+            &o.index_range,
+            address_space,
+        )),
     }
 }
 
@@ -302,13 +381,16 @@ fn convert(v1: Variant, v2: Variant) -> (Variant, Variant) {
 }
 
 // Tests if the operand is null (empty). TRUE if operand[0] is a null value.
-pub(crate) fn is_null(
+pub(crate) fn is_null<P>(
     object_id: &NodeId,
     operands: &[Operand],
     used_elements: &mut HashSet<u32>,
     elements: &[ContentFilterElement],
-    address_space: &AddressSpace,
-) -> Result<Variant, StatusCode> {
+    address_space: &P,
+) -> Result<Variant, StatusCode>
+where
+    P: AddressSpaceProvider + ?Sized,
+{
     let v1 = value_of(
         object_id,
         &operands[0],
@@ -356,14 +438,17 @@ macro_rules! compare_values {
 /// Compares to operands by taking their numeric value, comparing the value and saying
 /// which of the two is less than, greater than or equal. If the values cannot be compared, the
 /// result is an error.
-fn compare_operands(
+fn compare_operands<P>(
     object_id: &NodeId,
     o1: &Operand,
     o2: &Operand,
     used_elements: &mut HashSet<u32>,
     elements: &[ContentFilterElement],
-    address_space: &AddressSpace,
-) -> Result<ComparisonResult, StatusCode> {
+    address_space: &P,
+) -> Result<ComparisonResult, StatusCode>
+where
+    P: AddressSpaceProvider + ?Sized,
+{
     let v1 = value_of(object_id, o1, used_elements, elements, address_space)?;
     let v2 = value_of(object_id, o2, used_elements, elements, address_space)?;
     // Try and convert one value or the other to the same type
@@ -395,13 +480,16 @@ fn compare_operands(
 // the system shall perform any implicit conversion to a common type. This operator resolves to
 // FALSE if no implicit conversion is available and the operands are of different types. This
 // operator returns FALSE if the implicit conversion fails.
-pub(crate) fn eq(
+pub(crate) fn eq<P>(
     object_id: &NodeId,
     operands: &[Operand],
     used_elements: &mut HashSet<u32>,
     elements: &[ContentFilterElement],
-    address_space: &AddressSpace,
-) -> Result<Variant, StatusCode> {
+    address_space: &P,
+) -> Result<Variant, StatusCode>
+where
+    P: AddressSpaceProvider + ?Sized,
+{
     let result = compare_operands(
         object_id,
         &operands[0],
@@ -413,14 +501,55 @@ pub(crate) fn eq(
     Ok((result == ComparisonResult::Equals).into())
 }
 
+// Check if the object's type definition is the type in operand[0], or a subtype of it. The
+// operand is the node id of the type, e.g. of an event type.
+pub(crate) fn of_type<P>(
+    object_id: &NodeId,
+    operands: &[Operand],
+    used_elements: &mut HashSet<u32>,
+    elements: &[ContentFilterElement],
+    address_space: &P,
+) -> Result<Variant, StatusCode>
+where
+    P: AddressSpaceProvider + ?Sized,
+{
+    let type_id = match value_of(
+        object_id,
+        &operands[0],
+        used_elements,
+        elements,
+        address_space,
+    )? {
+        Variant::NodeId(type_id) => *type_id,
+        _ => {
+            error!("OfType operand is not a node id");
+            return Err(StatusCode::BadFilterOperandInvalid);
+        }
+    };
+    let is_of_type = address_space
+        .find_references(
+            object_id,
+            Some((ReferenceTypeId::HasTypeDefinition.into(), false)),
+        )
+        .is_some_and(|references| {
+            references
+                .iter()
+                .any(|r| address_space.is_subtype(&r.target_node, &type_id))
+        });
+    Ok(is_of_type.into())
+}
+
 // Check if operand[0] is greater than operand[1]
-pub(crate) fn gt(
+pub(crate) fn gt<P>(
     object_id: &NodeId,
     operands: &[Operand],
     used_elements: &mut HashSet<u32>,
     elements: &[ContentFilterElement],
-    address_space: &AddressSpace,
-) -> Result<Variant, StatusCode> {
+    address_space: &P,
+) -> Result<Variant, StatusCode>
+where
+    P: AddressSpaceProvider + ?Sized,
+{
     let result = compare_operands(
         object_id,
         &operands[0],
@@ -433,13 +562,16 @@ pub(crate) fn gt(
 }
 
 // Check if operand[0] is less than operand[1]
-pub(crate) fn lt(
+pub(crate) fn lt<P>(
     object_id: &NodeId,
     operands: &[Operand],
     used_elements: &mut HashSet<u32>,
     elements: &[ContentFilterElement],
-    address_space: &AddressSpace,
-) -> Result<Variant, StatusCode> {
+    address_space: &P,
+) -> Result<Variant, StatusCode>
+where
+    P: AddressSpaceProvider + ?Sized,
+{
     let result = compare_operands(
         object_id,
         &operands[0],
@@ -452,13 +584,16 @@ pub(crate) fn lt(
 }
 
 // Check if operand[0] is greater than or equal to operand[1]
-pub(crate) fn gte(
+pub(crate) fn gte<P>(
     object_id: &NodeId,
     operands: &[Operand],
     used_elements: &mut HashSet<u32>,
     elements: &[ContentFilterElement],
-    address_space: &AddressSpace,
-) -> Result<Variant, StatusCode> {
+    address_space: &P,
+) -> Result<Variant, StatusCode>
+where
+    P: AddressSpaceProvider + ?Sized,
+{
     let result = compare_operands(
         object_id,
         &operands[0],
@@ -471,13 +606,16 @@ pub(crate) fn gte(
 }
 
 // Check if operand[0] is less than or equal to operand[1]
-pub(crate) fn lte(
+pub(crate) fn lte<P>(
     object_id: &NodeId,
     operands: &[Operand],
     used_elements: &mut HashSet<u32>,
     elements: &[ContentFilterElement],
-    address_space: &AddressSpace,
-) -> Result<Variant, StatusCode> {
+    address_space: &P,
+) -> Result<Variant, StatusCode>
+where
+    P: AddressSpaceProvider + ?Sized,
+{
     let result = compare_operands(
         object_id,
         &operands[0],
@@ -634,13 +772,16 @@ fn like_to_regex_tests() {
 }
 
 // Check if operand[0] is matches the pattern defined by operand[1].
-pub(crate) fn like(
+pub(crate) fn like<P>(
     object_id: &NodeId,
     operands: &[Operand],
     used_elements: &mut HashSet<u32>,
     elements: &[ContentFilterElement],
-    address_space: &AddressSpace,
-) -> Result<Variant, StatusCode> {
+    address_space: &P,
+) -> Result<Variant, StatusCode>
+where
+    P: AddressSpaceProvider + ?Sized,
+{
     // If 0 matches a pattern in 1. See table 117
     //
     // 0 and 1 are operands that resolve to a string
@@ -688,13 +829,16 @@ pub(crate) fn like(
 }
 
 // TRUE if operand[0] is FALSE.
-pub(crate) fn not(
+pub(crate) fn not<P>(
     object_id: &NodeId,
     operands: &[Operand],
     used_elements: &mut HashSet<u32>,
     elements: &[ContentFilterElement],
-    address_space: &AddressSpace,
-) -> Result<Variant, StatusCode> {
+    address_space: &P,
+) -> Result<Variant, StatusCode>
+where
+    P: AddressSpaceProvider + ?Sized,
+{
     // operand[0] resolves to a boolean
     // TRUE if 0 is FALSE
     // If resolve fails, result is NULL
@@ -715,13 +859,16 @@ pub(crate) fn not(
 }
 
 // TRUE if operand[0] is greater or equal to operand[1] and less than or equal to operand[2].
-pub(crate) fn between(
+pub(crate) fn between<P>(
     object_id: &NodeId,
     operands: &[Operand],
     used_elements: &mut HashSet<u32>,
     elements: &[ContentFilterElement],
-    address_space: &AddressSpace,
-) -> Result<Variant, StatusCode> {
+    address_space: &P,
+) -> Result<Variant, StatusCode>
+where
+    P: AddressSpaceProvider + ?Sized,
+{
     // 0, 1, 2 are ordered values
     // Element 0 must be greater or equal than element 1
     let result = match compare_operands(
@@ -752,13 +899,16 @@ pub(crate) fn between(
 }
 
 // TRUE if operand[0] is equal to one or more of the remaining operands
-pub(crate) fn in_list(
+pub(crate) fn in_list<P>(
     object_id: &NodeId,
     operands: &[Operand],
     used_elements: &mut HashSet<u32>,
     elements: &[ContentFilterElement],
-    address_space: &AddressSpace,
-) -> Result<Variant, StatusCode> {
+    address_space: &P,
+) -> Result<Variant, StatusCode>
+where
+    P: AddressSpaceProvider + ?Sized,
+{
     // TRUE if operand[0] is equal to one or more of the remaining operands.
     // The Equals Operator is evaluated for operand[0] and each remaining operand in the list.
     // If any Equals evaluation is TRUE, InList returns TRUE.
@@ -780,13 +930,16 @@ pub(crate) fn in_list(
 }
 
 // TRUE if operand[0] and operand[1] are TRUE.
-pub(crate) fn and(
+pub(crate) fn and<P>(
     object_id: &NodeId,
     operands: &[Operand],
     used_elements: &mut HashSet<u32>,
     elements: &[ContentFilterElement],
-    address_space: &AddressSpace,
-) -> Result<Variant, StatusCode> {
+    address_space: &P,
+) -> Result<Variant, StatusCode>
+where
+    P: AddressSpaceProvider + ?Sized,
+{
     // The following restrictions apply to the operands:
     //  [0]: Any operand that resolves to a Boolean.
     //  [1]: Any operand that resolves to a Boolean.
@@ -820,13 +973,16 @@ pub(crate) fn and(
 }
 
 // TRUE if operand[0] or operand[1] are TRUE.
-pub(crate) fn or(
+pub(crate) fn or<P>(
     object_id: &NodeId,
     operands: &[Operand],
     used_elements: &mut HashSet<u32>,
     elements: &[ContentFilterElement],
-    address_space: &AddressSpace,
-) -> Result<Variant, StatusCode> {
+    address_space: &P,
+) -> Result<Variant, StatusCode>
+where
+    P: AddressSpaceProvider + ?Sized,
+{
     // The following restrictions apply to the operands:
     //  [0]: Any operand that resolves to a Boolean.
     //  [1]: Any operand that resolves to a Boolean.
@@ -861,13 +1017,16 @@ pub(crate) fn or(
 }
 
 // Converts operand[0] to a value with a data type with a NodeId identified by operand[1].
-pub(crate) fn cast(
+pub(crate) fn cast<P>(
     object_id: &NodeId,
     operands: &[Operand],
     used_elements: &mut HashSet<u32>,
     elements: &[ContentFilterElement],
-    address_space: &AddressSpace,
-) -> Result<Variant, StatusCode> {
+    address_space: &P,
+) -> Result<Variant, StatusCode>
+where
+    P: AddressSpaceProvider + ?Sized,
+{
     // Explicitly casts operand 0 to a value with the data type with a node if identified in node 1
     // [0] Any operand
     // [1] Any operand that resolves to a NodeId or ExpandedNodeId where the node is of type DataType
@@ -933,14 +1092,17 @@ macro_rules! bitwise_operation {
     }};
 }
 
-fn bitwise_operation(
+fn bitwise_operation<P>(
     object_id: &NodeId,
     operation: BitOperation,
     operands: &[Operand],
     used_elements: &mut HashSet<u32>,
     elements: &[ContentFilterElement],
-    address_space: &AddressSpace,
-) -> Result<Variant, StatusCode> {
+    address_space: &P,
+) -> Result<Variant, StatusCode>
+where
+    P: AddressSpaceProvider + ?Sized,
+{
     let v1 = value_of(
         object_id,
         &operands[0],
@@ -974,13 +1136,16 @@ fn bitwise_operation(
 // The result is an integer which matches the size of the largest operand and contains a bitwise
 // And operation of the two operands where both have been converted to the same size (largest of
 // the two operands).
-pub(crate) fn bitwise_and(
+pub(crate) fn bitwise_and<P>(
     object_id: &NodeId,
     operands: &[Operand],
     used_elements: &mut HashSet<u32>,
     elements: &[ContentFilterElement],
-    address_space: &AddressSpace,
-) -> Result<Variant, StatusCode> {
+    address_space: &P,
+) -> Result<Variant, StatusCode>
+where
+    P: AddressSpaceProvider + ?Sized,
+{
     bitwise_operation(
         object_id,
         BitOperation::And,
@@ -994,13 +1159,16 @@ pub(crate) fn bitwise_and(
 // The result is an integer which matches the size of the largest operand and contains a bitwise Or
 // operation of the two operands where both have been converted to the same size (largest of the
 // two operands).
-pub(crate) fn bitwise_or(
+pub(crate) fn bitwise_or<P>(
     object_id: &NodeId,
     operands: &[Operand],
     used_elements: &mut HashSet<u32>,
     elements: &[ContentFilterElement],
-    address_space: &AddressSpace,
-) -> Result<Variant, StatusCode> {
+    address_space: &P,
+) -> Result<Variant, StatusCode>
+where
+    P: AddressSpaceProvider + ?Sized,
+{
     bitwise_operation(
         object_id,
         BitOperation::Or,
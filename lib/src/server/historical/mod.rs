@@ -8,7 +8,7 @@ use crate::sync::*;
 use crate::types::status_code::StatusCode;
 use crate::types::*;
 
-use crate::server::address_space::AddressSpace;
+use crate::server::address_space::provider::AddressSpaceProvider;
 
 /// Values that should be set in the address space via `AddressSpace::set_history_server_capabilities()`
 /// to denote to clients what history capabilities the server has.
@@ -40,7 +40,7 @@ pub struct HistoryServerCapabilities {
 pub trait HistoricalEventProvider {
     fn read_event_details(
         &self,
-        _address_space: Arc<RwLock<AddressSpace>>,
+        _address_space: Arc<RwLock<dyn AddressSpaceProvider>>,
         _request: ReadEventDetails,
         _timestamps_to_return: TimestampsToReturn,
         _release_continuation_points: bool,
@@ -52,7 +52,7 @@ pub trait HistoricalEventProvider {
 
     fn update_event_details(
         &self,
-        _address_space: Arc<RwLock<AddressSpace>>,
+        _address_space: Arc<RwLock<dyn AddressSpaceProvider>>,
         _request: UpdateEventDetails,
     ) -> Result<Vec<StatusCode>, StatusCode> {
         info!("Unimplemented update_event_details");
@@ -61,7 +61,7 @@ pub trait HistoricalEventProvider {
 
     fn delete_event_details(
         &self,
-        _address_space: Arc<RwLock<AddressSpace>>,
+        _address_space: Arc<RwLock<dyn AddressSpaceProvider>>,
         _request: DeleteEventDetails,
     ) -> Result<Vec<StatusCode>, StatusCode> {
         info!("Unimplemented delete_event_details");
@@ -74,6 +74,102 @@ pub enum HistoryRawData {
     HistoryModifiedData(HistoryModifiedData),
 }
 
+/// Finds the values of a variable at the times requested by a `ReadAtTimeDetails` from its raw
+/// samples, which must be in order of source timestamp. This is intended for use by
+/// implementations of `HistoricalDataProvider::read_at_time_details()`.
+///
+/// A sample at exactly the requested time is returned as it is. Otherwise the value is
+/// interpolated between the samples either side of the time, or is the previous value when there
+/// is no later sample or the value is not numeric. With simple bounds the nearest samples are
+/// the bounds whatever their status and a bad bound makes the value uncertain, otherwise bad
+/// samples are skipped. Times before the first sample have no data. A value that is not a sample
+/// has the requested time as its source timestamp and no server timestamp, since the server never
+/// saw it.
+pub fn values_at_times(samples: &[DataValue], request: &ReadAtTimeDetails) -> Vec<DataValue> {
+    let Some(ref req_times) = request.req_times else {
+        return Vec::new();
+    };
+    let is_bound = |v: &&DataValue| {
+        request.use_simple_bounds || !v.status.is_some_and(|status| status.is_bad())
+    };
+    req_times
+        .iter()
+        .map(|req_time| {
+            let timestamp = |v: &DataValue| v.source_timestamp.unwrap_or_else(DateTime::null);
+            if let Some(sample) = samples.iter().find(|v| timestamp(v) == *req_time) {
+                return sample.clone();
+            }
+            let before = samples
+                .iter()
+                .rev()
+                .filter(|v| timestamp(v) < *req_time)
+                .find(is_bound);
+            let after = samples
+                .iter()
+                .filter(|v| timestamp(v) > *req_time)
+                .find(is_bound);
+            let (value, status) = match (before, after) {
+                (None, _) => (None, StatusCode::BadNoData),
+                (Some(before), after) => {
+                    let value = after.and_then(|after| {
+                        interpolate(
+                            (timestamp(before), before.value.as_ref()?),
+                            (timestamp(after), after.value.as_ref()?),
+                            *req_time,
+                        )
+                    });
+                    let bad_bound = [Some(before), after]
+                        .iter()
+                        .flatten()
+                        .any(|v| v.status.is_some_and(|status| !status.is_good()));
+                    let status = if after.is_none() || bad_bound {
+                        StatusCode::UncertainDataSubNormal
+                    } else {
+                        StatusCode::Good
+                    };
+                    (
+                        value.or_else(|| before.value.clone()),
+                        status | StatusCode::HISTORICAL_INTERPOLATED,
+                    )
+                }
+            };
+            DataValue {
+                value,
+                status: Some(status),
+                source_timestamp: Some(*req_time),
+                source_picoseconds: None,
+                server_timestamp: None,
+                server_picoseconds: None,
+            }
+        })
+        .collect()
+}
+
+/// Linearly interpolates between two numeric values at a time between theirs. The value has
+/// the type of the value before it, rounding to the nearest integer for integer types.
+fn interpolate(
+    (before_time, before): (DateTime, &Variant),
+    (after_time, after): (DateTime, &Variant),
+    time: DateTime,
+) -> Option<Variant> {
+    if !before.is_numeric() || !after.is_numeric() {
+        return None;
+    }
+    let variant_type = before.type_id();
+    let (Variant::Double(before), Variant::Double(after)) = (
+        before.cast(VariantTypeId::Double),
+        after.cast(VariantTypeId::Double),
+    ) else {
+        return None;
+    };
+    let span = (after_time.ticks() - before_time.ticks()) as f64;
+    let elapsed = (time.ticks() - before_time.ticks()) as f64;
+    match Variant::Double(before + (after - before) * elapsed / span).cast(variant_type) {
+        Variant::Empty => None,
+        value => Some(value),
+    }
+}
+
 /// The `HistoricalEventProvider` trait provides the function stubs that a server will call
 /// to process historical data operations. The implementor of this trait may provide their
 /// own implementation as many functions as they desire leaving the remainder as stubs.
@@ -87,7 +183,7 @@ pub trait HistoricalDataProvider {
     /// or a `HistoryModifiedData` for a read modified action.
     fn read_raw_modified_details(
         &self,
-        _address_space: Arc<RwLock<AddressSpace>>,
+        _address_space: Arc<RwLock<dyn AddressSpaceProvider>>,
         _request: ReadRawModifiedDetails,
         _timestamps_to_return: TimestampsToReturn,
         _release_continuation_points: bool,
@@ -99,7 +195,7 @@ pub trait HistoricalDataProvider {
 
     fn read_processed_details(
         &self,
-        _address_space: Arc<RwLock<AddressSpace>>,
+        _address_space: Arc<RwLock<dyn AddressSpaceProvider>>,
         _request: ReadProcessedDetails,
         _timestamps_to_return: TimestampsToReturn,
         _release_continuation_points: bool,
@@ -111,7 +207,7 @@ pub trait HistoricalDataProvider {
 
     fn read_at_time_details(
         &self,
-        _address_space: Arc<RwLock<AddressSpace>>,
+        _address_space: Arc<RwLock<dyn AddressSpaceProvider>>,
         _request: ReadAtTimeDetails,
         _timestamps_to_return: TimestampsToReturn,
         _release_continuation_points: bool,
@@ -123,7 +219,7 @@ pub trait HistoricalDataProvider {
 
     fn update_data_details(
         &self,
-        _address_space: Arc<RwLock<AddressSpace>>,
+        _address_space: Arc<RwLock<dyn AddressSpaceProvider>>,
         _request: UpdateDataDetails,
     ) -> Result<Vec<StatusCode>, StatusCode> {
         info!("Unimplemented update_data_details");
@@ -132,7 +228,7 @@ pub trait HistoricalDataProvider {
 
     fn update_structure_data_details(
         &self,
-        _address_space: Arc<RwLock<AddressSpace>>,
+        _address_space: Arc<RwLock<dyn AddressSpaceProvider>>,
         _request: UpdateStructureDataDetails,
     ) -> Result<Vec<StatusCode>, StatusCode> {
         info!("Unimplemented update_structure_data_details");
@@ -141,7 +237,7 @@ pub trait HistoricalDataProvider {
 
     fn delete_raw_modified_details(
         &self,
-        _address_space: Arc<RwLock<AddressSpace>>,
+        _address_space: Arc<RwLock<dyn AddressSpaceProvider>>,
         _request: DeleteRawModifiedDetails,
     ) -> Result<Vec<StatusCode>, StatusCode> {
         info!("Unimplemented delete_raw_modified_details");
@@ -150,7 +246,7 @@ pub trait HistoricalDataProvider {
 
     fn delete_at_time_details(
         &self,
-        _address_space: Arc<RwLock<AddressSpace>>,
+        _address_space: Arc<RwLock<dyn AddressSpaceProvider>>,
         _request: DeleteAtTimeDetails,
     ) -> Result<Vec<StatusCode>, StatusCode> {
         info!("Unimplemented delete_at_time_details");
@@ -6,10 +6,14 @@
 //! to see what is happening in the server. State is updated by the server as sessions are added, removed,
 //! and when subscriptions / monitored items are added, removed.
 
+use std::collections::BTreeMap;
+
 use crate::runtime_components;
+use crate::sync::*;
 use crate::types::DateTime;
 
 use crate::server::{
+    callbacks::RequestEvent,
     comms::transport::{Transport, TransportState},
     config,
     diagnostics::ServerDiagnostics,
@@ -25,6 +29,7 @@ pub struct ServerMetrics {
     pub config: Option<config::ServerConfig>,
     pub connections: Vec<Connection>,
     pub runtime_components: Vec<String>,
+    pub services: ServiceMetrics,
 }
 
 #[derive(Serialize)]
@@ -51,6 +56,107 @@ pub struct Session {
     pub subscriptions: subscriptions::Metrics,
 }
 
+/// Upper bounds in microseconds of the buckets that service call durations are counted in. Calls
+/// slower than the last bound are counted in one more bucket.
+pub const SERVICE_DURATION_BUCKETS_US: [u64; 6] =
+    [100, 1_000, 10_000, 100_000, 1_000_000, 10_000_000];
+
+/// Counts and durations of the calls to one service
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct ServiceMetric {
+    /// Number of calls
+    pub count: u64,
+    /// Number of calls whose result was bad
+    pub bad_count: u64,
+    /// Total time spent servicing the calls in microseconds
+    pub total_elapsed_us: u64,
+    /// Longest time spent servicing a call in microseconds
+    pub max_elapsed_us: u64,
+    /// Number of calls in each of the `SERVICE_DURATION_BUCKETS_US` buckets, followed by the
+    /// number of slower calls
+    pub duration_histogram: Vec<u64>,
+}
+
+impl Default for ServiceMetric {
+    fn default() -> Self {
+        Self {
+            count: 0,
+            bad_count: 0,
+            total_elapsed_us: 0,
+            max_elapsed_us: 0,
+            duration_histogram: vec![0; SERVICE_DURATION_BUCKETS_US.len() + 1],
+        }
+    }
+}
+
+/// Metrics of the calls to each service, keyed by request type, e.g. `BrowseRequest`
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct ServiceMetrics {
+    pub services: BTreeMap<String, ServiceMetric>,
+}
+
+impl ServiceMetric {
+    /// Adds a handled request to the metric
+    fn record(&mut self, event: &RequestEvent) {
+        let elapsed_us = event.elapsed.as_micros() as u64;
+        self.count += 1;
+        if matches!(event.service_result, Some(service_result) if service_result.is_bad()) {
+            self.bad_count += 1;
+        }
+        self.total_elapsed_us += elapsed_us;
+        self.max_elapsed_us = self.max_elapsed_us.max(elapsed_us);
+        let bucket = SERVICE_DURATION_BUCKETS_US
+            .iter()
+            .position(|bound| elapsed_us <= *bound)
+            .unwrap_or(SERVICE_DURATION_BUCKETS_US.len());
+        self.duration_histogram[bucket] += 1;
+    }
+}
+
+impl ServiceMetrics {
+    /// Returns the metrics of a service, if it has been called
+    pub fn service(&self, request_type: &str) -> Option<&ServiceMetric> {
+        self.services.get(request_type)
+    }
+}
+
+/// Accumulates the metrics of each service as requests are handled. Each service has its own lock
+/// so requests to different services are recorded without waiting on each other.
+#[derive(Default)]
+pub(crate) struct ServiceMetricsRecorder {
+    services: RwLock<BTreeMap<&'static str, Mutex<ServiceMetric>>>,
+}
+
+impl ServiceMetricsRecorder {
+    /// Adds a handled request to the metrics of its service
+    pub fn record(&self, event: &RequestEvent) {
+        {
+            let services = trace_read_lock!(self.services);
+            if let Some(metric) = services.get(event.request_type) {
+                trace_lock!(metric).record(event);
+                return;
+            }
+        }
+        // First call of the service
+        let mut services = trace_write_lock!(self.services);
+        let metric = services.entry(event.request_type).or_default();
+        metric.get_mut().record(event);
+    }
+
+    /// Returns a snapshot of the metrics of each service
+    pub fn snapshot(&self) -> ServiceMetrics {
+        let services = trace_read_lock!(self.services);
+        ServiceMetrics {
+            services: services
+                .iter()
+                .map(|(request_type, metric)| {
+                    (request_type.to_string(), trace_lock!(metric).clone())
+                })
+                .collect(),
+        }
+    }
+}
+
 impl Default for ServerMetrics {
     fn default() -> Self {
         // Sample metrics
@@ -63,6 +169,7 @@ impl Default for ServerMetrics {
             config: None,
             connections: Vec::new(),
             runtime_components: Vec::new(),
+            services: ServiceMetrics::default(),
         }
     }
 }
@@ -108,6 +215,7 @@ impl ServerMetrics {
             let diagnostics = trace_read_lock!(server_state.diagnostics);
             self.diagnostics = diagnostics.clone();
         }
+        self.services = server_state.service_metrics();
 
         let elapsed = now
             .as_chrono()
@@ -92,6 +92,8 @@ pub mod constants {
     pub const DEFAULT_HELLO_TIMEOUT_SECONDS: u32 = 5;
     /// Default OPC UA server port for this implementation
     pub const DEFAULT_RUST_OPC_UA_SERVER_PORT: u16 = 4855;
+    /// Default maximum number of sessions on the server
+    pub const DEFAULT_MAX_SESSIONS: usize = 20;
     /// Default maximum number of subscriptions in a session
     pub const DEFAULT_MAX_SUBSCRIPTIONS: usize = 100;
     /// Default maximum number of monitored items per subscription
@@ -118,12 +120,22 @@ pub mod constants {
     pub const HELLO_TIMEOUT_POLL_MS: u64 = 500;
     /// Maximum time in MS that a session can be inactive before a timeout
     pub const MAX_SESSION_TIMEOUT: f64 = 60000f64;
+    /// Interval in millis between checks for sessions that have timed out. A session may outlive
+    /// its timeout by up to this long.
+    pub const SESSION_TIMEOUT_SWEEP_MS: u64 = 1000;
     /// Maximum size in bytes that a request message is allowed to be
     pub const MAX_REQUEST_MESSAGE_SIZE: u32 = 32768;
     /// Default keep alive count
     pub const DEFAULT_KEEP_ALIVE_COUNT: u32 = 10;
     /// Maximum keep alive count
     pub const MAX_KEEP_ALIVE_COUNT: u32 = 30000;
+    /// Maximum number of servers that may register with the server through RegisterServer
+    pub const MAX_REGISTERED_SERVERS: usize = 100;
+    /// Interval in millis between checks that registered servers' semaphore files still exist
+    pub const REGISTERED_SERVERS_SWEEP_MS: u64 = 5000;
+    /// Maximum number of sent notification messages that a session retains for republishing,
+    /// across all of its subscriptions
+    pub const MAX_RETRANSMISSION_QUEUE_SIZE: usize = 1000;
     /// Maximum browse continuation points
     pub const MAX_BROWSE_CONTINUATION_POINTS: usize = 20;
     /// Maximum history continuation points
@@ -145,6 +157,24 @@ pub mod constants {
     pub const MAX_NODES_PER_HISTORY_READ_EVENTS: usize = 10;
     pub const MAX_NODES_PER_HISTORY_UPDATE_DATA: usize = 10;
     pub const MAX_NODES_PER_HISTORY_UPDATE_EVENTS: usize = 10;
+
+    /// Manufacturer name reported in the server's build info
+    pub const BUILD_INFO_MANUFACTURER_NAME: &str = "OPC UA for Rust";
+    /// Software version reported in the server's build info
+    pub const BUILD_INFO_SOFTWARE_VERSION: &str = env!("CARGO_PKG_VERSION");
+    /// Build number reported in the server's build info. It is taken from the `OPCUA_BUILD_NUMBER`
+    /// environment variable when the crate is compiled, and is empty if that is not set.
+    pub const BUILD_INFO_BUILD_NUMBER: &str = match option_env!("OPCUA_BUILD_NUMBER") {
+        Some(build_number) => build_number,
+        None => "",
+    };
+    /// Build date reported in the server's build info, as an RFC 3339 timestamp. It is taken from
+    /// the `OPCUA_BUILD_DATE` environment variable when the crate is compiled, and the build date
+    /// is reported as null if that is not set or cannot be parsed.
+    pub const BUILD_INFO_BUILD_DATE: &str = match option_env!("OPCUA_BUILD_DATE") {
+        Some(build_date) => build_date,
+        None => "",
+    };
 }
 
 #[cfg(test)]
@@ -4,7 +4,7 @@
 
 //! Provides the [`Server`] type and functionality related to it.
 
-use std::{marker::Sync, net::SocketAddr, sync::Arc};
+use std::{collections::BTreeMap, marker::Sync, net::SocketAddr, sync::Arc};
 
 use tokio::{
     self,
@@ -19,14 +19,15 @@ use crate::sync::*;
 use crate::types::service_types::ServerState as ServerStateType;
 
 use crate::server::{
-    address_space::types::AddressSpace,
+    address_space::{provider::AddressSpaceProvider, types::AddressSpace},
     comms::tcp_transport::*,
     comms::transport::Transport,
     config::ServerConfig,
     constants,
     diagnostics::ServerDiagnostics,
     events::audit::AuditLog,
-    metrics::ServerMetrics,
+    metrics::{ServerMetrics, ServiceMetricsRecorder},
+    services::discovery::DiscoveryService,
     session::SessionManager,
     state::{OperationalLimits, ServerState},
     util::PollingAction,
@@ -73,6 +74,9 @@ pub struct Server {
     server_state: Arc<RwLock<ServerState>>,
     /// Address space
     address_space: Arc<RwLock<AddressSpace>>,
+    /// Provider of the nodes that services and subscriptions operate on. The address space by
+    /// default
+    address_space_provider: Arc<RwLock<dyn AddressSpaceProvider>>,
     /// List of open connections
     connections: Arc<RwLock<Connections>>,
     /// Session manager
@@ -99,12 +103,15 @@ impl Server {
         let application_name = config.application_name.clone();
         let application_uri = UAString::from(&config.application_uri);
         let product_uri = UAString::from(&config.product_uri);
+        let namespaces = config.namespaces.clone();
         let start_time = DateTime::now();
         let servers = vec![config.application_uri.clone()];
         let base_endpoint = format!(
             "opc.tcp://{}:{}",
             config.tcp_config.host, config.tcp_config.port
         );
+        let max_sessions = config.limits.max_sessions;
+        let allow_anonymous = config.allow_anonymous;
         let max_subscriptions = config.limits.max_subscriptions;
         let max_monitored_items_per_sub = config.limits.max_monitored_items_per_sub;
         let max_monitored_item_queue_size = config.limits.max_monitored_item_queue_size;
@@ -114,6 +121,12 @@ impl Server {
         let min_sampling_interval_ms = config.limits.min_sampling_interval * 1000.0;
         let send_buffer_size = config.limits.send_buffer_size;
         let receive_buffer_size = config.limits.receive_buffer_size;
+        let max_message_size = config.limits.max_message_size;
+        let service_metrics = if config.performance.service_metrics {
+            Some(Arc::new(ServiceMetricsRecorder::default()))
+        } else {
+            None
+        };
 
         // Security, pki auto create cert
         let application_description = if config.create_sample_keypair {
@@ -158,7 +171,9 @@ impl Server {
                 locale: UAString::null(),
                 text: UAString::from(application_name),
             },
+            namespaces,
             servers,
+            registered_servers: BTreeMap::new(),
             base_endpoint,
             state: ServerStateType::Shutdown,
             start_time,
@@ -166,6 +181,8 @@ impl Server {
             server_certificate,
             server_pkey,
             last_subscription_id: 0,
+            max_sessions,
+            allow_anonymous,
             max_subscriptions,
             max_monitored_items_per_sub,
             max_monitored_item_queue_size,
@@ -175,15 +192,20 @@ impl Server {
             max_keep_alive_count: constants::MAX_KEEP_ALIVE_COUNT,
             max_lifetime_count: constants::MAX_KEEP_ALIVE_COUNT * 3,
             diagnostics,
+            service_metrics,
             abort: false,
             audit_log,
             register_nodes_callback: None,
             unregister_nodes_callback: None,
             historical_data_provider: None,
             historical_event_provider: None,
+            authentication_manager: None,
+            request_observer: None,
             operational_limits: OperationalLimits::default(),
+            substitute_endpoint_hostname: false,
             send_buffer_size,
             receive_buffer_size,
+            max_message_size,
         };
         let server_state = Arc::new(RwLock::new(server_state));
 
@@ -202,6 +224,7 @@ impl Server {
             pending_polling_actions: Vec::new(),
             server_state,
             server_metrics: server_metrics.clone(),
+            address_space_provider: address_space.clone(),
             address_space,
             certificate_store,
             connections: Arc::new(RwLock::new(Vec::new())),
@@ -336,6 +359,12 @@ impl Server {
 
             // Start any pending polling action timers
             server.start_pending_polling_actions();
+
+            // Start a timer that unregisters servers whose semaphore files are gone
+            server.start_registered_servers_sweep();
+
+            // Start a timer that closes sessions which have timed out
+            server.start_session_timeout_sweep();
         }
 
         // Start a server abort task loop
@@ -398,6 +427,29 @@ impl Server {
         self.address_space.clone()
     }
 
+    /// Returns the [`AddressSpaceProvider`] that services and subscriptions find nodes through.
+    ///
+    /// [`AddressSpaceProvider`]: ../address_space/provider/trait.AddressSpaceProvider.html
+    pub fn address_space_provider(&self) -> Arc<RwLock<dyn AddressSpaceProvider>> {
+        self.address_space_provider.clone()
+    }
+
+    /// Sets the [`AddressSpaceProvider`] that services and subscriptions find nodes through, in
+    /// place of the in-memory [`AddressSpace`]. Connections made after this call use the provider.
+    ///
+    /// The server still keeps its own nodes, such as session diagnostics and raised events, in
+    /// the in-memory [`AddressSpace`]. A provider that should serve those nodes to clients must
+    /// find them there.
+    ///
+    /// [`AddressSpaceProvider`]: ../address_space/provider/trait.AddressSpaceProvider.html
+    /// [`AddressSpace`]: ../address_space/address_space/struct.AddressSpace.html
+    pub fn set_address_space_provider(
+        &mut self,
+        address_space_provider: Arc<RwLock<dyn AddressSpaceProvider>>,
+    ) {
+        self.address_space_provider = address_space_provider;
+    }
+
     /// Returns the [`Connections`] for the server.
     ///
     /// [`Connections`]: ./type.Connections.html
@@ -507,6 +559,40 @@ impl Server {
         });
     }
 
+    /// This timer unregisters servers which registered through RegisterServer with a semaphore
+    /// file that has since been removed.
+    fn start_registered_servers_sweep(&self) {
+        let server_state = self.server_state.clone();
+        let _ = PollingAction::spawn(
+            self.server_state.clone(),
+            constants::REGISTERED_SERVERS_SWEEP_MS,
+            move || DiscoveryService::remove_unregistered_servers(server_state.clone()),
+        );
+    }
+
+    /// This timer removes sessions which have been inactive for longer than their timeout. It
+    /// runs once for the server rather than on each connection, and only takes the session
+    /// manager's write lock when there are sessions to remove.
+    fn start_session_timeout_sweep(&self) {
+        let session_manager = self.session_manager.clone();
+        let address_space = self.address_space.clone();
+        let _ = PollingAction::spawn(
+            self.server_state.clone(),
+            constants::SESSION_TIMEOUT_SWEEP_MS,
+            move || {
+                let now = chrono::Utc::now();
+                let has_timed_out_sessions = {
+                    let session_manager = trace_read_lock!(session_manager);
+                    session_manager.has_timed_out_sessions(&now)
+                };
+                if has_timed_out_sessions {
+                    let mut session_manager = trace_write_lock!(session_manager);
+                    let _ = session_manager.remove_timed_out_sessions(&now, address_space.clone());
+                }
+            },
+        );
+    }
+
     /// Discovery registration is disabled.
     #[cfg(not(feature = "discovery-server-registration"))]
     fn start_discovery_server_registration_timer(&self, discovery_server_url: &str) {
@@ -633,6 +719,7 @@ impl Server {
             self.certificate_store.clone(),
             self.server_state.clone(),
             self.address_space.clone(),
+            self.address_space_provider.clone(),
             self.session_manager.clone(),
         )
     }
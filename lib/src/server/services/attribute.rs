@@ -13,8 +13,9 @@ use crate::{
 use crate::server::{
     address_space::{
         node::{HasNodeId, NodeBase, NodeType},
-        variable::Variable,
-        AddressSpace, UserAccessLevel,
+        provider::{map_node, AddressSpaceProvider},
+        variable::{array_fits_value_rank, Variable},
+        UserAccessLevel,
     },
     services::Service,
     session::Session,
@@ -57,13 +58,16 @@ impl AttributeService {
     /// elements or to read ranges of elements of the composite. Servers may make historical
     /// values available to Clients using this Service, although the historical values themselves
     /// are not visible in the AddressSpace.
-    pub fn read(
+    pub fn read<P>(
         &self,
         server_state: Arc<RwLock<ServerState>>,
         session: Arc<RwLock<Session>>,
-        address_space: Arc<RwLock<AddressSpace>>,
+        address_space: Arc<RwLock<P>>,
         request: &ReadRequest,
-    ) -> SupportedMessage {
+    ) -> SupportedMessage
+    where
+        P: AddressSpaceProvider + ?Sized,
+    {
         if is_empty_option_vec!(request.nodes_to_read) {
             self.service_fault(&request.request_header, StatusCode::BadNothingToDo)
         } else if request.max_age < 0f64 {
@@ -77,9 +81,14 @@ impl AttributeService {
                 StatusCode::BadTimestampsToReturnInvalid,
             )
         } else {
-            let server_state = trace_read_lock!(server_state);
+            // The server state lock is released before reading, since the getters of the server
+            // status variables take it themselves
+            let max_nodes_per_read = {
+                let server_state = trace_read_lock!(server_state);
+                server_state.operational_limits.max_nodes_per_read
+            };
             let nodes_to_read = request.nodes_to_read.as_ref().unwrap();
-            if nodes_to_read.len() <= server_state.operational_limits.max_nodes_per_read {
+            if nodes_to_read.len() <= max_nodes_per_read {
                 // Read nodes and their attributes
                 let session = trace_read_lock!(session);
                 let address_space = trace_read_lock!(address_space);
@@ -89,7 +98,7 @@ impl AttributeService {
                     .map(|node_to_read| {
                         Self::read_node_value(
                             &session,
-                            &address_space,
+                            &*address_space,
                             node_to_read,
                             request.max_age,
                             timestamps_to_return,
@@ -116,7 +125,7 @@ impl AttributeService {
         &self,
         server_state: Arc<RwLock<ServerState>>,
         _session: Arc<RwLock<Session>>,
-        address_space: Arc<RwLock<AddressSpace>>,
+        address_space: Arc<RwLock<dyn AddressSpaceProvider>>,
         request: &HistoryReadRequest,
     ) -> SupportedMessage {
         if is_empty_option_vec!(request.nodes_to_read) {
@@ -150,13 +159,16 @@ impl AttributeService {
     /// constructed Attribute values whose elements are indexed, such as an array, this Service
     /// allows Clients to write the entire set of indexed values as a composite, to write individual
     /// elements or to write ranges of elements of the composite.
-    pub fn write(
+    pub fn write<P>(
         &self,
         server_state: Arc<RwLock<ServerState>>,
         session: Arc<RwLock<Session>>,
-        address_space: Arc<RwLock<AddressSpace>>,
+        address_space: Arc<RwLock<P>>,
         request: &WriteRequest,
-    ) -> SupportedMessage {
+    ) -> SupportedMessage
+    where
+        P: AddressSpaceProvider + ?Sized,
+    {
         if is_empty_option_vec!(request.nodes_to_write) {
             debug!("Empty list passed to write {:?}", request);
             self.service_fault(&request.request_header, StatusCode::BadNothingToDo)
@@ -171,7 +183,7 @@ impl AttributeService {
                 let results = nodes_to_write
                     .iter()
                     .map(|node_to_write| {
-                        Self::write_node_value(&session, &mut address_space, node_to_write)
+                        Self::write_node_value(&session, &mut *address_space, node_to_write)
                     })
                     .collect();
 
@@ -197,7 +209,7 @@ impl AttributeService {
         &self,
         server_state: Arc<RwLock<ServerState>>,
         _session: Arc<RwLock<Session>>,
-        address_space: Arc<RwLock<AddressSpace>>,
+        address_space: Arc<RwLock<dyn AddressSpaceProvider>>,
         request: &HistoryUpdateRequest,
     ) -> SupportedMessage {
         if is_empty_option_vec!(request.history_update_details) {
@@ -342,7 +354,7 @@ impl AttributeService {
     fn do_history_update_details(
         decoding_options: &DecodingOptions,
         server_state: Arc<RwLock<ServerState>>,
-        address_space: Arc<RwLock<AddressSpace>>,
+        address_space: Arc<RwLock<dyn AddressSpaceProvider>>,
         u: &ExtensionObject,
     ) -> (StatusCode, Option<Vec<StatusCode>>) {
         match Self::decode_history_update_details(u, decoding_options) {
@@ -419,11 +431,9 @@ impl AttributeService {
     fn do_history_read_details(
         decoding_options: &DecodingOptions,
         server_state: Arc<RwLock<ServerState>>,
-        address_space: Arc<RwLock<AddressSpace>>,
+        address_space: Arc<RwLock<dyn AddressSpaceProvider>>,
         request: &HistoryReadRequest,
     ) -> Result<Vec<HistoryReadResult>, StatusCode> {
-        // TODO enforce operation limits
-
         // Validate the action being performed
         let nodes_to_read = &request.nodes_to_read.as_ref().unwrap();
         let timestamps_to_return = request.timestamps_to_return;
@@ -432,6 +442,27 @@ impl AttributeService {
             Self::decode_history_read_details(&request.history_read_details, decoding_options)?;
 
         let server_state = trace_read_lock!(server_state);
+
+        // Enforce operation limits
+        let max_nodes_per_history_read = match read_details {
+            ReadDetails::ReadEventDetails(_) => {
+                server_state
+                    .operational_limits
+                    .max_nodes_per_history_read_events
+            }
+            _ => {
+                server_state
+                    .operational_limits
+                    .max_nodes_per_history_read_data
+            }
+        };
+        if nodes_to_read.len() > max_nodes_per_history_read {
+            warn!(
+                "HistoryReadRequest too many nodes to read {}",
+                nodes_to_read.len()
+            );
+            return Err(StatusCode::BadTooManyOperations);
+        }
         let results = match read_details {
             ReadDetails::ReadEventDetails(details) => {
                 let historical_event_provider = server_state
@@ -489,25 +520,51 @@ impl AttributeService {
         Ok(results)
     }
 
-    fn is_supported_data_encoding(data_encoding: &QualifiedName) -> bool {
+    /// Checks the data encoding that a value is requested in. An encoding may only be requested
+    /// for the value of a variable whose data type is a structure. Structured values are held as
+    /// binary encoded extension objects, so "Default Binary" is the only supported encoding.
+    fn validate_data_encoding<P>(
+        address_space: &P,
+        node: &NodeType,
+        attribute_id: AttributeId,
+        data_encoding: &QualifiedName,
+    ) -> Result<(), StatusCode>
+    where
+        P: AddressSpaceProvider + ?Sized,
+    {
         if data_encoding.is_null() {
-            true
+            return Ok(());
+        }
+        let is_structure = match node {
+            NodeType::Variable(variable) if attribute_id == AttributeId::Value => {
+                address_space.is_subtype(&variable.data_type(), &DataTypeId::Structure.into())
+            }
+            _ => false,
+        };
+        if !is_structure {
+            Err(StatusCode::BadDataEncodingInvalid)
+        } else if data_encoding.namespace_index == 0 && data_encoding.name.eq("Default Binary") {
+            Ok(())
         } else {
-            data_encoding.namespace_index == 0 && data_encoding.name.eq("Default Binary")
+            Err(StatusCode::BadDataEncodingUnsupported)
         }
     }
 
-    fn read_node_value(
+    fn read_node_value<P>(
         session: &Session,
-        address_space: &AddressSpace,
+        address_space: &P,
         node_to_read: &ReadValueId,
         max_age: f64,
         timestamps_to_return: TimestampsToReturn,
-    ) -> DataValue {
+    ) -> DataValue
+    where
+        P: AddressSpaceProvider + ?Sized,
+    {
         // Node node found
         // debug!("read_node_value asked to read node id {}, attribute {}", node_to_read.node_id, node_to_read.attribute_id);
-        let mut result_value = DataValue::null();
-        if let Some(node) = address_space.find_node(&node_to_read.node_id) {
+        let node_id = session.resolve_registered_node(&node_to_read.node_id);
+        let result_value = map_node(address_space, node_id, |node| {
+            let mut result_value = DataValue::null();
             if let Ok(attribute_id) = AttributeId::from_u32(node_to_read.attribute_id) {
                 let index_range = match node_to_read
                     .index_range
@@ -542,10 +599,15 @@ impl AttributeService {
                         node_to_read.node_id, node_to_read.attribute_id
                     );
                     result_value.status = Some(StatusCode::BadIndexRangeNoData);
-                } else if !Self::is_supported_data_encoding(&node_to_read.data_encoding) {
-                    // Caller must request binary
+                } else if let Err(status_code) = Self::validate_data_encoding(
+                    address_space,
+                    node,
+                    attribute_id,
+                    &node_to_read.data_encoding,
+                ) {
+                    // Caller must request binary, and only of a structured value
                     debug!("read_node_value result for read node id {}, attribute {} is invalid data encoding", node_to_read.node_id, node_to_read.attribute_id);
-                    result_value.status = Some(StatusCode::BadDataEncodingInvalid);
+                    result_value.status = Some(status_code);
                 } else if let Some(attribute) = node.as_node().get_attribute_max_age(
                     timestamps_to_return,
                     attribute_id,
@@ -624,14 +686,18 @@ impl AttributeService {
                 );
                 result_value.status = Some(StatusCode::BadAttributeIdInvalid);
             }
-        } else {
+            result_value
+        });
+        result_value.unwrap_or_else(|| {
             debug!(
                 "read_node_value result for read node id {}, attribute {} cannot find node",
                 node_to_read.node_id, node_to_read.attribute_id
             );
-            result_value.status = Some(StatusCode::BadNodeIdUnknown);
-        }
-        result_value
+            DataValue {
+                status: Some(StatusCode::BadNodeIdUnknown),
+                ..DataValue::null()
+            }
+        })
     }
 
     fn user_access_level(
@@ -721,11 +787,10 @@ impl AttributeService {
     */
 
     /// Determine if the value is writable to a Variable node's data type
-    fn validate_value_to_write(
-        address_space: &AddressSpace,
-        variable: &Variable,
-        value: &Variant,
-    ) -> bool {
+    fn validate_value_to_write<P>(address_space: &P, variable: &Variable, value: &Variant) -> bool
+    where
+        P: AddressSpaceProvider + ?Sized,
+    {
         // Get the value rank and data type of the variable
         let value_rank = variable.value_rank();
         let node_data_type = variable.data_type();
@@ -757,8 +822,9 @@ impl AttributeService {
                 true
             }
         } else if let Some(value_data_type) = value.array_data_type() {
-            // TODO check that value is array of same dimensions
+            // Value is an array. Check the data type matches and the dimensions fit the variable
             address_space.is_subtype(&value_data_type, &node_data_type)
+                && Self::validate_array_dimensions(variable, value)
         } else {
             // Value should have a data type
             false
@@ -769,89 +835,113 @@ impl AttributeService {
         valid
     }
 
-    fn write_node_value(
+    /// Checks an array value against the value rank and array dimensions of the variable
+    fn validate_array_dimensions(variable: &Variable, value: &Variant) -> bool {
+        let array_dimensions = variable.array_dimensions();
+        let valid =
+            array_fits_value_rank(variable.value_rank(), array_dimensions.as_deref(), value);
+        if !valid {
+            debug!(
+                "Array value {:?} does not fit var {} with value rank {} and array dimensions {:?}",
+                value,
+                variable.node_id(),
+                variable.value_rank(),
+                array_dimensions
+            );
+        }
+        valid
+    }
+
+    fn write_node_value<P>(
         session: &Session,
-        address_space: &mut AddressSpace,
+        address_space: &mut P,
         node_to_write: &WriteValue,
-    ) -> StatusCode {
-        if let Some(node) = address_space.find_node(&node_to_write.node_id) {
-            if let Ok(attribute_id) = AttributeId::from_u32(node_to_write.attribute_id) {
-                let index_range = node_to_write.index_range.as_ref().parse::<NumericRange>();
-
-                if !Self::is_writable(session, node, attribute_id) {
-                    StatusCode::BadNotWritable
-                } else if attribute_id != AttributeId::Value && !node_to_write.index_range.is_null()
+    ) -> StatusCode
+    where
+        P: AddressSpaceProvider + ?Sized,
+    {
+        let node_id = session.resolve_registered_node(&node_to_write.node_id);
+        let validated = map_node(&*address_space, node_id, |node| {
+            Self::validate_node_to_write(session, &*address_space, node, node_to_write)
+        });
+        match validated {
+            Some(Ok((attribute_id, index_range, value))) => {
+                if let Err(err) =
+                    address_space.set_attribute(node_id, attribute_id, index_range, value)
                 {
-                    // Index ranges are not supported on anything other than a value attribute
-                    error!("Server does not support indexes for attributes other than Value");
-                    StatusCode::BadWriteNotSupported
-                //                 else if node_to_write.value.server_timestamp.is_some() || node_to_write.value.server_picoseconds.is_some() ||
-                //                    node_to_write.value.source_timestamp.is_some() || node_to_write.value.source_picoseconds.is_some() {
-                //                    error!("Server does not support timestamps in write");
-                //                    StatusCode::BadWriteNotSupported
-                } else if index_range.is_err() {
-                    error!("Index range is invalid");
-                    StatusCode::BadIndexRangeInvalid
-                } else if let Some(ref value) = node_to_write.value.value {
-                    let index_range = index_range.unwrap();
-
-                    // This is a band-aid for Variable::Value which should check if the data type
-                    // matches the written value. Note, that ALL attributes should check for subtypes
-                    // but they don't. There should be a general purpose fn attribute_type(attribute_id) helper
-                    // on the node impl that returns a datatype for the attribute regardless of node.
-                    let data_type_valid = if attribute_id == AttributeId::Value {
-                        match node {
-                            NodeType::Variable(ref variable) => {
-                                Self::validate_value_to_write(address_space, variable, value)
-                            }
-                            _ => true, // Other types don't have this attr but they will reject later during set
-                        }
-                    } else {
-                        true
-                    };
-                    if !data_type_valid {
-                        error!("Data type of value is invalid for writing to attribute");
-                        StatusCode::BadTypeMismatch
-                    } else {
-                        let node = address_space.find_node_mut(&node_to_write.node_id).unwrap();
-                        let result = if attribute_id == AttributeId::Value {
-                            match node {
-                                NodeType::Variable(ref mut variable) => variable
-                                    .set_value(index_range, value.clone())
-                                    .map_err(|err| {
-                                        error!(
-                                            "Value could not be set to node {} Value, error = {}",
-                                            node_to_write.node_id, err
-                                        );
-                                        err
-                                    }),
-                                _ => Err(StatusCode::BadAttributeIdInvalid),
-                            }
-                        } else {
-                            let node = node.as_mut_node();
-                            node.set_attribute(attribute_id, value.clone())
-                                .map_err(|err| {
-                                    error!("Value could not be set to node {} attribute {:?}, error = {}", node_to_write.node_id, attribute_id, err);
-                                    err
-                                })
-                        };
-                        if let Err(err) = result {
-                            err
-                        } else {
-                            StatusCode::Good
+                    error!(
+                        "Value could not be set to node {} attribute {:?}, error = {}",
+                        node_to_write.node_id, attribute_id, err
+                    );
+                    err
+                } else {
+                    StatusCode::Good
+                }
+            }
+            Some(Err(status_code)) => status_code,
+            None => {
+                warn!("Cannot find node id {}", node_to_write.node_id);
+                StatusCode::BadNodeIdUnknown
+            }
+        }
+    }
+
+    /// Checks that the value can be written to the attribute of the node, returning the attribute,
+    /// index range and value to set on it.
+    fn validate_node_to_write<P>(
+        session: &Session,
+        address_space: &P,
+        node: &NodeType,
+        node_to_write: &WriteValue,
+    ) -> Result<(AttributeId, NumericRange, Variant), StatusCode>
+    where
+        P: AddressSpaceProvider + ?Sized,
+    {
+        let Ok(attribute_id) = AttributeId::from_u32(node_to_write.attribute_id) else {
+            warn!("Attribute id {} is invalid", node_to_write.attribute_id);
+            return Err(StatusCode::BadAttributeIdInvalid);
+        };
+        let index_range = node_to_write.index_range.as_ref().parse::<NumericRange>();
+        if !Self::is_writable(session, node, attribute_id) {
+            Err(StatusCode::BadNotWritable)
+        } else if attribute_id != AttributeId::Value && !node_to_write.index_range.is_null() {
+            // Index ranges are not supported on anything other than a value attribute
+            error!("Server does not support indexes for attributes other than Value");
+            Err(StatusCode::BadWriteNotSupported)
+        //                 else if node_to_write.value.server_timestamp.is_some() || node_to_write.value.server_picoseconds.is_some() ||
+        //                    node_to_write.value.source_timestamp.is_some() || node_to_write.value.source_picoseconds.is_some() {
+        //                    error!("Server does not support timestamps in write");
+        //                    StatusCode::BadWriteNotSupported
+        } else {
+            let Ok(index_range) = index_range else {
+                error!("Index range is invalid");
+                return Err(StatusCode::BadIndexRangeInvalid);
+            };
+            if let Some(ref value) = node_to_write.value.value {
+                // This is a band-aid for Variable::Value which should check if the data type
+                // matches the written value. Note, that ALL attributes should check for subtypes
+                // but they don't. There should be a general purpose fn attribute_type(attribute_id) helper
+                // on the node impl that returns a datatype for the attribute regardless of node.
+                let data_type_valid = if attribute_id == AttributeId::Value {
+                    match node {
+                        NodeType::Variable(ref variable) => {
+                            Self::validate_value_to_write(address_space, variable, value)
                         }
+                        _ => true, // Other types don't have this attr but they will reject later during set
                     }
                 } else {
-                    error!("Server does not support missing value in write");
-                    StatusCode::BadTypeMismatch
+                    true
+                };
+                if !data_type_valid {
+                    error!("Data type of value is invalid for writing to attribute");
+                    Err(StatusCode::BadTypeMismatch)
+                } else {
+                    Ok((attribute_id, index_range, value.clone()))
                 }
             } else {
-                warn!("Attribute id {} is invalid", node_to_write.attribute_id);
-                StatusCode::BadAttributeIdInvalid
+                error!("Server does not support missing value in write");
+                Err(StatusCode::BadTypeMismatch)
             }
-        } else {
-            warn!("Cannot find node id {}", node_to_write.node_id);
-            StatusCode::BadNodeIdUnknown
         }
     }
 }
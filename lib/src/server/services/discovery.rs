@@ -2,13 +2,15 @@
 // SPDX-License-Identifier: MPL-2.0
 // Copyright (C) 2017-2024 Adam Lock
 
-use std::sync::Arc;
+use std::{path::Path, sync::Arc};
 
-use crate::core::{config::Config, supported_message::SupportedMessage};
+use crate::core::{
+    comms::secure_channel::SecureChannel, config::Config, supported_message::SupportedMessage,
+};
 use crate::sync::*;
 use crate::types::{status_code::StatusCode, *};
 
-use crate::server::state::ServerState;
+use crate::server::{constants, state::ServerState};
 
 use super::Service;
 
@@ -33,12 +35,12 @@ impl DiscoveryService {
     ) -> SupportedMessage {
         let server_state = trace_read_lock!(server_state);
 
-        // TODO some of the arguments in the request are ignored
-        //  localeIds - list of locales to use for human readable strings (in the endpoint descriptions)
-
         // TODO audit - generate event for failed service invocation
 
+        // The server has a single application name, so the endpoint descriptions are the same
+        // whatever the locale ids requested
         let endpoints = server_state.endpoints(&request.endpoint_url, &request.profile_uris);
+
         GetEndpointsResponse {
             response_header: ResponseHeader::new_good(&request.request_header),
             endpoints,
@@ -46,12 +48,92 @@ impl DiscoveryService {
         .into()
     }
 
+    /// Registers, updates or unregisters (when it is not online) the server described by the
+    /// request so that it is returned by subsequent calls to FindServers. Registration is only
+    /// allowed over a signed or encrypted secure channel, and is limited to
+    /// `constants::MAX_REGISTERED_SERVERS` servers.
     pub fn register_server(
         &self,
-        _server_state: Arc<RwLock<ServerState>>,
+        secure_channel: Arc<RwLock<SecureChannel>>,
+        server_state: Arc<RwLock<ServerState>>,
         request: &RegisterServerRequest,
     ) -> SupportedMessage {
-        self.service_fault(&request.request_header, StatusCode::BadNotSupported)
+        let security_mode = {
+            let secure_channel = trace_read_lock!(secure_channel);
+            secure_channel.security_mode()
+        };
+        let server = &request.server;
+        if !matches!(
+            security_mode,
+            MessageSecurityMode::Sign | MessageSecurityMode::SignAndEncrypt
+        ) {
+            error!(
+                "Server {} cannot be registered over a secure channel with security mode {:?}",
+                server.server_uri, security_mode
+            );
+            return self.service_fault(
+                &request.request_header,
+                StatusCode::BadSecurityModeInsufficient,
+            );
+        }
+
+        let mut server_state = trace_write_lock!(server_state);
+        let server_uri = server.server_uri.as_ref().to_string();
+        let result = Self::validate_registered_server(server).and_then(|_| {
+            if server.is_online
+                && !server_state.registered_servers.contains_key(&server_uri)
+                && server_state.registered_servers.len() >= constants::MAX_REGISTERED_SERVERS
+            {
+                Err(StatusCode::BadTooManyOperations)
+            } else {
+                Ok(())
+            }
+        });
+        if let Err(status_code) = result {
+            error!(
+                "Server {} cannot be registered, {}",
+                server.server_uri, status_code
+            );
+            self.service_fault(&request.request_header, status_code)
+        } else {
+            if server.is_online {
+                debug!("Registering server {}", server_uri);
+                server_state
+                    .registered_servers
+                    .insert(server_uri, server.clone());
+            } else {
+                debug!("Unregistering server {}", server_uri);
+                server_state.registered_servers.remove(&server_uri);
+            }
+            RegisterServerResponse {
+                response_header: ResponseHeader::new_good(&request.request_header),
+            }
+            .into()
+        }
+    }
+
+    /// Unregisters servers which registered with a semaphore file that no longer exists. This is
+    /// called periodically by the server rather than on every FindServers call.
+    pub fn remove_unregistered_servers(server_state: Arc<RwLock<ServerState>>) {
+        let unregistered = {
+            let server_state = trace_read_lock!(server_state);
+            server_state
+                .registered_servers
+                .iter()
+                .filter(|(_, server)| !Self::semaphore_file_exists(server))
+                .map(|(server_uri, _)| server_uri.clone())
+                .collect::<Vec<String>>()
+        };
+        if !unregistered.is_empty() {
+            let mut server_state = trace_write_lock!(server_state);
+            unregistered.iter().for_each(|server_uri| {
+                debug!(
+                    "Semaphore file for server {} is missing so it is unregistered",
+                    server_uri
+                );
+                server_state.registered_servers.remove(server_uri);
+            });
+        }
     }
 
     pub fn register_server2(
@@ -69,6 +151,8 @@ impl DiscoveryService {
     ) -> SupportedMessage {
         let server_state = trace_read_lock!(server_state);
 
+        // The server has a single application name, so it is returned as it is whatever the
+        // locale ids requested
         let application_description = {
             let config = trace_read_lock!(server_state.config);
             config.application_description()
@@ -77,11 +161,15 @@ impl DiscoveryService {
         // Fields within the request
 
         let mut servers = vec![application_description];
+        servers.extend(
+            server_state
+                .registered_servers
+                .values()
+                .map(|server| Self::registered_server_description(server, &request.locale_ids)),
+        );
 
         // TODO endpoint URL
 
-        // TODO localeids, filter out servers that do not support locale ids
-
         // Filter servers that do not have a matching application uri
         if let Some(ref server_uris) = request.server_uris {
             if !server_uris.is_empty() {
@@ -99,4 +187,54 @@ impl DiscoveryService {
         }
         .into()
     }
+
+    /// Checks the registered server has the fields required by the spec for a RegisterServer
+    /// request.
+    fn validate_registered_server(server: &RegisteredServer) -> Result<(), StatusCode> {
+        if server.server_uri.is_empty() {
+            Err(StatusCode::BadServerUriInvalid)
+        } else if is_empty_option_vec!(server.server_names) {
+            Err(StatusCode::BadServerNameMissing)
+        } else if is_empty_option_vec!(server.discovery_urls) {
+            Err(StatusCode::BadDiscoveryUrlMissing)
+        } else if server.server_type == ApplicationType::Client {
+            // The semaphore file isn't checked here, the response would tell the client whether
+            // the path exists on this machine. Servers whose file is missing are dropped by
+            // remove_unregistered_servers instead.
+            Err(StatusCode::BadInvalidArgument)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Tests that the registered server's semaphore file, if it has one, exists.
+    fn semaphore_file_exists(server: &RegisteredServer) -> bool {
+        server.semaphore_file_path.is_empty()
+            || Path::new(server.semaphore_file_path.as_ref()).exists()
+    }
+
+    /// Describes a registered server using the name matching the first of the locale ids that it
+    /// has a name for, or its first name.
+    fn registered_server_description(
+        server: &RegisteredServer,
+        locale_ids: &Option<Vec<UAString>>,
+    ) -> ApplicationDescription {
+        let server_names = server.server_names.as_deref().unwrap_or(&[]);
+        let application_name = locale_ids
+            .iter()
+            .flatten()
+            .find_map(|locale_id| server_names.iter().find(|n| n.locale == *locale_id))
+            .or_else(|| server_names.first())
+            .cloned()
+            .unwrap_or_default();
+        ApplicationDescription {
+            application_uri: server.server_uri.clone(),
+            product_uri: server.product_uri.clone(),
+            application_name,
+            application_type: server.server_type,
+            gateway_server_uri: server.gateway_server_uri.clone(),
+            discovery_profile_uri: UAString::null(),
+            discovery_urls: server.discovery_urls.clone(),
+        }
+    }
 }
@@ -2,7 +2,7 @@
 // SPDX-License-Identifier: MPL-2.0
 // Copyright (C) 2017-2024 Adam Lock
 
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc, time::Instant};
 
 use chrono::Utc;
 
@@ -13,7 +13,8 @@ use crate::sync::*;
 use crate::types::{status_code::StatusCode, *};
 
 use crate::server::{
-    address_space::AddressSpace,
+    address_space::{provider::AddressSpaceProvider, AddressSpace},
+    callbacks::RequestEvent,
     comms::tcp_transport::MessageSender,
     services::{
         attribute::AttributeService, discovery::DiscoveryService, method::MethodService,
@@ -26,6 +27,40 @@ use crate::server::{
     state::ServerState,
 };
 
+/// Handles one kind of request, returning the response to send or `None` if the response will be
+/// sent later, e.g. for a publish request.
+type ServiceHandler =
+    Box<dyn Fn(&MessageHandler, u32, &SupportedMessage) -> Option<SupportedMessage> + Send + Sync>;
+
+/// A service handler and the name of the request type it handles, e.g. `BrowseRequest`
+struct RegisteredService {
+    request_type: &'static str,
+    handler: ServiceHandler,
+}
+
+/// Registers a service handler for a request type. The handler is keyed by the object id of the
+/// request type and is given the message and the request extracted from it.
+macro_rules! register_service {
+    ($handlers: expr, $request_type: ident, |$handler: ident, $request_id: ident, $message: ident, $request: ident| $body: block) => {
+        paste::paste! {
+            $handlers.insert(
+                ObjectId::[<$request_type _Encoding_DefaultBinary>],
+                RegisteredService {
+                    request_type: stringify!($request_type),
+                    handler: Box::new(
+                        |$handler: &MessageHandler, $request_id: u32, $message: &SupportedMessage| {
+                            let SupportedMessage::$request_type($request) = $message else {
+                                unreachable!()
+                            };
+                            $body
+                        },
+                    ),
+                },
+            );
+        }
+    };
+}
+
 /// Processes and dispatches messages for handling
 pub(crate) struct MessageHandler {
     /// Secure channel
@@ -34,8 +69,10 @@ pub(crate) struct MessageHandler {
     certificate_store: Arc<RwLock<CertificateStore>>,
     /// Server state
     server_state: Arc<RwLock<ServerState>>,
-    /// Address space
+    /// Address space, which holds the nodes of the server itself such as session diagnostics
     address_space: Arc<RwLock<AddressSpace>>,
+    /// Provider of the nodes that services and subscriptions operate on
+    address_space_provider: Arc<RwLock<dyn AddressSpaceProvider>>,
     /// Session state
     session_manager: Arc<RwLock<SessionManager>>,
     /// Attribute service
@@ -56,6 +93,8 @@ pub(crate) struct MessageHandler {
     subscription_service: SubscriptionService,
     /// View service
     view_service: ViewService,
+    /// Handlers for each supported request type, keyed by the request's object id
+    service_handlers: HashMap<ObjectId, RegisteredService>,
 }
 
 impl MessageHandler {
@@ -65,6 +104,7 @@ impl MessageHandler {
         server_state: Arc<RwLock<ServerState>>,
         session_manager: Arc<RwLock<SessionManager>>,
         address_space: Arc<RwLock<AddressSpace>>,
+        address_space_provider: Arc<RwLock<dyn AddressSpaceProvider>>,
     ) -> MessageHandler {
         MessageHandler {
             secure_channel,
@@ -72,6 +112,7 @@ impl MessageHandler {
             server_state,
             session_manager,
             address_space,
+            address_space_provider,
             attribute_service: AttributeService::new(),
             discovery_service: DiscoveryService::new(),
             method_service: MethodService::new(),
@@ -81,6 +122,7 @@ impl MessageHandler {
             session_service: SessionService::new(),
             view_service: ViewService::new(),
             subscription_service: SubscriptionService::new(),
+            service_handlers: Self::service_handlers(),
         }
     }
 
@@ -90,51 +132,199 @@ impl MessageHandler {
         message: &SupportedMessage,
         sender: &MessageSender,
     ) -> Result<(), StatusCode> {
-        // Note the order of arguments for all these services is the order that they must be locked in,
-        //
-        // 1. ServerState
-        // 2. Session
-        // 3. AddressSpace
-
-        let server_state = self.server_state.clone();
-        let address_space = self.address_space.clone();
+        let object_id = Self::request_object_id(message);
+        let service = object_id.and_then(|object_id| self.service_handlers.get(&object_id));
+        if let Some(service) = service {
+            let (service_metrics, request_observer) = {
+                let server_state = trace_read_lock!(self.server_state);
+                (
+                    server_state.service_metrics.clone(),
+                    server_state.request_observer.clone(),
+                )
+            };
 
-        let response = match message {
-            // Discovery Service Set, OPC UA Part 4, Section 5.4
-            SupportedMessage::GetEndpointsRequest(request) => {
-                Some(self.discovery_service.get_endpoints(server_state, request))
+            // The request is only described if the description goes somewhere
+            let describe = service_metrics.is_some()
+                || request_observer.is_some()
+                || log_enabled!(log::Level::Debug);
+            if !describe {
+                if let Some(response) = self.service_response(request_id, message, &service.handler)
+                {
+                    sender.send_message(request_id, response);
+                }
+                return Ok(());
             }
 
-            SupportedMessage::RegisterServerRequest(request) => Some(
-                self.discovery_service
-                    .register_server(server_state, request),
-            ),
-
-            SupportedMessage::RegisterServer2Request(request) => Some(
-                self.discovery_service
-                    .register_server2(server_state, request),
-            ),
+            let started = Instant::now();
+            let session_id = {
+                let session_manager = trace_read_lock!(self.session_manager);
+                session_manager
+                    .find_session_by_token(&message.request_header().authentication_token)
+                    .map(|session| trace_read_lock!(session).session_id().clone())
+            };
+            let response = self.service_response(request_id, message, &service.handler);
+
+            // Describe the request to the log, the service metrics and any observer
+            let event = RequestEvent {
+                request_type: service.request_type,
+                session_id,
+                request_handle: message.request_handle(),
+                elapsed: started.elapsed(),
+                service_result: response
+                    .as_ref()
+                    .map(|response| response.response_header().service_result),
+            };
+            debug!("{}", event);
+            if let Some(service_metrics) = service_metrics {
+                service_metrics.record(&event);
+            }
+            // The observer is called without holding the server state lock
+            if let Some(request_observer) = request_observer {
+                request_observer.on_request(&event);
+            }
 
-            SupportedMessage::FindServersRequest(request) => {
-                Some(self.discovery_service.find_servers(server_state, request))
+            if let Some(response) = response {
+                sender.send_message(request_id, response);
             }
+            Ok(())
+        } else {
+            debug!(
+                "Message handler does not handle this kind of message {:?}",
+                message
+            );
+            Err(StatusCode::BadServiceUnsupported)
+        }
+    }
+
+    /// Calls the service handler for the request, returning the response to send or `None` if the
+    /// response will be sent later. Requests larger than the max message size are answered with a
+    /// service fault. Responses are checked against the max message size when they are written.
+    fn service_response(
+        &self,
+        request_id: u32,
+        message: &SupportedMessage,
+        service_handler: &ServiceHandler,
+    ) -> Option<SupportedMessage> {
+        let (max_message_size, is_abort) = {
+            let server_state = trace_read_lock!(self.server_state);
+            (server_state.max_message_size, server_state.is_abort())
+        };
+        if is_abort {
+            error!("Request rejected because the server is shutting down");
+            return Some(
+                ServiceFault::new(message.request_header(), StatusCode::BadServerHalted).into(),
+            );
+        }
+        if max_message_size > 0 && message.byte_len() > max_message_size {
+            error!(
+                "Request of {} bytes exceeds the max message size {}",
+                message.byte_len(),
+                max_message_size
+            );
+            return Some(
+                ServiceFault::new(message.request_header(), StatusCode::BadRequestTooLarge).into(),
+            );
+        }
+        service_handler(self, request_id, message)
+    }
 
-            // Session Service Set, OPC UA Part 4, Section 5.6
-            SupportedMessage::CreateSessionRequest(request) => {
-                let mut session_manager = trace_write_lock!(self.session_manager);
+    /// Returns the object id that service handlers are registered against for this message
+    fn request_object_id(message: &SupportedMessage) -> Option<ObjectId> {
+        match message {
+            SupportedMessage::Invalid(_) | SupportedMessage::AcknowledgeMessage(_) => None,
+            message => message.node_id().as_object_id().ok(),
+        }
+    }
 
-                // TODO this is completely arbitrary - 5 sessions total in a single connection
-                pub(crate) const MAX_SESSIONS_PER_TRANSPORT: usize = 5;
+    /// Returns the object ids of every request type that has a registered service handler
+    #[cfg(test)]
+    pub(crate) fn registered_services(&self) -> Vec<ObjectId> {
+        self.service_handlers.keys().cloned().collect()
+    }
 
-                let response = if session_manager.len() >= MAX_SESSIONS_PER_TRANSPORT {
+    /// Creates the handlers for each request type that the server services.
+    ///
+    /// Note the order of arguments for all these services is the order that they must be locked in,
+    ///
+    /// 1. ServerState
+    /// 2. Session
+    /// 3. AddressSpace
+    fn service_handlers() -> HashMap<ObjectId, RegisteredService> {
+        let mut handlers = HashMap::new();
+
+        // Discovery Service Set, OPC UA Part 4, Section 5.4
+        register_service!(
+            handlers,
+            GetEndpointsRequest,
+            |handler, _request_id, message, request| {
+                Some(
+                    handler
+                        .discovery_service
+                        .get_endpoints(handler.server_state.clone(), request),
+                )
+            }
+        );
+        register_service!(
+            handlers,
+            RegisterServerRequest,
+            |handler, _request_id, message, request| {
+                Some(handler.discovery_service.register_server(
+                    handler.secure_channel.clone(),
+                    handler.server_state.clone(),
+                    request,
+                ))
+            }
+        );
+        register_service!(
+            handlers,
+            RegisterServer2Request,
+            |handler, _request_id, message, request| {
+                Some(
+                    handler
+                        .discovery_service
+                        .register_server2(handler.server_state.clone(), request),
+                )
+            }
+        );
+        register_service!(
+            handlers,
+            FindServersRequest,
+            |handler, _request_id, message, request| {
+                Some(
+                    handler
+                        .discovery_service
+                        .find_servers(handler.server_state.clone(), request),
+                )
+            }
+        );
+
+        // Session Service Set, OPC UA Part 4, Section 5.6
+        register_service!(
+            handlers,
+            CreateSessionRequest,
+            |handler, _request_id, message, request| {
+                let mut session_manager = trace_write_lock!(handler.session_manager);
+
+                let max_sessions = {
+                    let server_state = trace_read_lock!(handler.server_state);
+                    server_state.max_sessions
+                };
+                let response = if max_sessions > 0 && session_manager.len() >= max_sessions {
+                    error!(
+                        "Create session rejected because the server already has {} sessions",
+                        session_manager.len()
+                    );
+                    let server_state = trace_read_lock!(handler.server_state);
+                    let mut diagnostics = trace_write_lock!(server_state.diagnostics);
+                    diagnostics.on_rejected_session();
                     ServiceFault::new(&request.request_header, StatusCode::BadTooManySessions)
                         .into()
                 } else {
-                    let (session, response) = self.session_service.create_session(
-                        self.secure_channel.clone(),
-                        self.certificate_store.clone(),
-                        server_state,
-                        address_space,
+                    let (session, response) = handler.session_service.create_session(
+                        handler.secure_channel.clone(),
+                        handler.certificate_store.clone(),
+                        handler.server_state.clone(),
+                        handler.address_space.clone(),
                         request,
                     );
                     if let Some(session) = session {
@@ -144,328 +334,496 @@ impl MessageHandler {
                 };
                 Some(response)
             }
-            SupportedMessage::CloseSessionRequest(request) => {
-                let secure_channel = self.secure_channel.clone();
-                Some(self.session_service.close_session(
-                    secure_channel,
-                    self.session_manager.clone(),
-                    server_state,
-                    address_space,
+        );
+        register_service!(
+            handlers,
+            CloseSessionRequest,
+            |handler, _request_id, message, request| {
+                Some(handler.session_service.close_session(
+                    handler.secure_channel.clone(),
+                    handler.session_manager.clone(),
+                    handler.server_state.clone(),
+                    handler.address_space.clone(),
                     request,
                 ))
             }
-
-            // NOTE - ALL THE REQUESTS BEYOND THIS POINT MUST BE VALIDATED AGAINST THE SESSION
-            SupportedMessage::ActivateSessionRequest(request) => self
-                .validate_activate_service_request(message, "", |session| {
-                    let secure_channel = self.secure_channel.clone();
-                    self.session_service.activate_session(
-                        secure_channel,
-                        server_state,
+        );
+
+        // NOTE - ALL THE REQUESTS BEYOND THIS POINT MUST BE VALIDATED AGAINST THE SESSION
+        register_service!(
+            handlers,
+            ActivateSessionRequest,
+            |handler, _request_id, message, request| {
+                handler.validate_activate_service_request(message, "", |session| {
+                    handler.session_service.activate_session(
+                        handler.secure_channel.clone(),
+                        handler.certificate_store.clone(),
+                        handler.server_state.clone(),
                         session,
-                        address_space,
+                        handler.address_space.clone(),
                         request,
                     )
-                }),
-
-            // NOTE - ALL THE REQUESTS BEYOND THIS POINT MUST BE VALIDATED AGAINST THE SESSION AND
-            //        HAVE AN ACTIVE SESSION
-            SupportedMessage::CancelRequest(request) => {
-                self.validate_service_request(message, "", |session, _| {
-                    Some(self.session_service.cancel(server_state, session, request))
                 })
             }
-
-            // NodeManagement Service Set, OPC UA Part 4, Section 5.7
-            SupportedMessage::AddNodesRequest(request) => {
-                self.validate_service_request(message, ADD_NODES_COUNT, |session, _| {
-                    Some(self.node_management_service.add_nodes(
-                        server_state,
+        );
+
+        // NOTE - ALL THE REQUESTS BEYOND THIS POINT MUST BE VALIDATED AGAINST THE SESSION AND
+        //        HAVE AN ACTIVE SESSION
+        register_service!(
+            handlers,
+            CancelRequest,
+            |handler, _request_id, message, request| {
+                handler.validate_service_request(message, "", |session, _| {
+                    Some(handler.session_service.cancel(
+                        handler.server_state.clone(),
                         session,
-                        address_space,
                         request,
                     ))
                 })
             }
-
-            SupportedMessage::AddReferencesRequest(request) => {
-                self.validate_service_request(message, ADD_REFERENCES_COUNT, |session, _| {
-                    Some(self.node_management_service.add_references(
-                        server_state,
+        );
+
+        // NodeManagement Service Set, OPC UA Part 4, Section 5.7
+        register_service!(
+            handlers,
+            AddNodesRequest,
+            |handler, _request_id, message, request| {
+                handler.validate_service_request(message, ADD_NODES_COUNT, |session, _| {
+                    Some(handler.node_management_service.add_nodes(
+                        handler.server_state.clone(),
                         session,
-                        address_space,
+                        handler.address_space_provider.clone(),
                         request,
                     ))
                 })
             }
-
-            SupportedMessage::DeleteNodesRequest(request) => {
-                self.validate_service_request(message, DELETE_NODES_COUNT, |session, _| {
-                    Some(self.node_management_service.delete_nodes(
-                        server_state,
+        );
+        register_service!(
+            handlers,
+            AddReferencesRequest,
+            |handler, _request_id, message, request| {
+                handler.validate_service_request(message, ADD_REFERENCES_COUNT, |session, _| {
+                    Some(handler.node_management_service.add_references(
+                        handler.server_state.clone(),
                         session,
-                        address_space,
+                        handler.address_space_provider.clone(),
                         request,
                     ))
                 })
             }
-
-            SupportedMessage::DeleteReferencesRequest(request) => {
-                self.validate_service_request(message, DELETE_REFERENCES_COUNT, |session, _| {
-                    Some(self.node_management_service.delete_references(
-                        server_state,
+        );
+        register_service!(
+            handlers,
+            DeleteNodesRequest,
+            |handler, _request_id, message, request| {
+                handler.validate_service_request(message, DELETE_NODES_COUNT, |session, _| {
+                    Some(handler.node_management_service.delete_nodes(
+                        handler.server_state.clone(),
                         session,
-                        address_space,
+                        handler.address_space_provider.clone(),
                         request,
                     ))
                 })
             }
-
-            // View Service Set, OPC UA Part 4, Section 5.8
-            SupportedMessage::BrowseRequest(request) => {
-                self.validate_service_request(message, BROWSE_COUNT, |session, _| {
-                    Some(
-                        self.view_service
-                            .browse(server_state, session, address_space, request),
-                    )
+        );
+        register_service!(
+            handlers,
+            DeleteReferencesRequest,
+            |handler, _request_id, message, request| {
+                handler.validate_service_request(message, DELETE_REFERENCES_COUNT, |session, _| {
+                    Some(handler.node_management_service.delete_references(
+                        handler.server_state.clone(),
+                        session,
+                        handler.address_space_provider.clone(),
+                        request,
+                    ))
                 })
             }
-            SupportedMessage::BrowseNextRequest(request) => {
-                self.validate_service_request(message, BROWSE_NEXT_COUNT, |session, _| {
-                    Some(
-                        self.view_service
-                            .browse_next(session, address_space, request),
-                    )
+        );
+
+        // View Service Set, OPC UA Part 4, Section 5.8
+        register_service!(
+            handlers,
+            BrowseRequest,
+            |handler, _request_id, message, request| {
+                handler.validate_service_request(message, BROWSE_COUNT, |session, _| {
+                    Some(handler.view_service.browse(
+                        handler.server_state.clone(),
+                        session,
+                        handler.address_space_provider.clone(),
+                        request,
+                    ))
                 })
             }
-            SupportedMessage::TranslateBrowsePathsToNodeIdsRequest(request) => self
-                .validate_service_request(
+        );
+        register_service!(
+            handlers,
+            BrowseNextRequest,
+            |handler, _request_id, message, request| {
+                handler.validate_service_request(message, BROWSE_NEXT_COUNT, |session, _| {
+                    Some(handler.view_service.browse_next(
+                        session,
+                        handler.address_space_provider.clone(),
+                        request,
+                    ))
+                })
+            }
+        );
+        register_service!(
+            handlers,
+            TranslateBrowsePathsToNodeIdsRequest,
+            |handler, _request_id, message, request| {
+                handler.validate_service_request(
                     message,
                     TRANSLATE_BROWSE_PATHS_TO_NODE_IDS_COUNT,
                     |_, _| {
-                        Some(self.view_service.translate_browse_paths_to_node_ids(
-                            server_state,
-                            address_space,
+                        Some(handler.view_service.translate_browse_paths_to_node_ids(
+                            handler.server_state.clone(),
+                            handler.address_space_provider.clone(),
                             request,
                         ))
                     },
-                ),
-            SupportedMessage::RegisterNodesRequest(request) => {
-                self.validate_service_request(message, REGISTER_NODES_COUNT, |session, _| {
-                    Some(
-                        self.view_service
-                            .register_nodes(server_state, session, request),
-                    )
+                )
+            }
+        );
+        register_service!(
+            handlers,
+            RegisterNodesRequest,
+            |handler, _request_id, message, request| {
+                handler.validate_service_request(message, REGISTER_NODES_COUNT, |session, _| {
+                    Some(handler.view_service.register_nodes(
+                        handler.server_state.clone(),
+                        session,
+                        request,
+                    ))
                 })
             }
-            SupportedMessage::UnregisterNodesRequest(request) => {
-                self.validate_service_request(message, UNREGISTER_NODES_COUNT, |session, _| {
-                    Some(
-                        self.view_service
-                            .unregister_nodes(server_state, session, request),
-                    )
+        );
+        register_service!(
+            handlers,
+            UnregisterNodesRequest,
+            |handler, _request_id, message, request| {
+                handler.validate_service_request(message, UNREGISTER_NODES_COUNT, |session, _| {
+                    Some(handler.view_service.unregister_nodes(
+                        handler.server_state.clone(),
+                        session,
+                        request,
+                    ))
                 })
             }
-
-            // Query Service Set, OPC UA Part 4, Section 5.9
-            SupportedMessage::QueryFirstRequest(request) => {
-                self.validate_service_request(message, READ_COUNT, |session, _| {
-                    Some(self.query_service.query_first(
-                        server_state,
+        );
+
+        // Query Service Set, OPC UA Part 4, Section 5.9
+        register_service!(
+            handlers,
+            QueryFirstRequest,
+            |handler, _request_id, message, request| {
+                handler.validate_service_request(message, QUERY_FIRST_COUNT, |session, _| {
+                    Some(handler.query_service.query_first(
+                        handler.server_state.clone(),
                         session,
-                        address_space,
+                        handler.address_space_provider.clone(),
                         request,
                     ))
                 })
             }
-
-            SupportedMessage::QueryNextRequest(request) => {
-                self.validate_service_request(message, READ_COUNT, |session, _| {
-                    Some(self.query_service.query_next(
-                        server_state,
+        );
+        register_service!(
+            handlers,
+            QueryNextRequest,
+            |handler, _request_id, message, request| {
+                handler.validate_service_request(message, QUERY_NEXT_COUNT, |session, _| {
+                    Some(handler.query_service.query_next(
+                        handler.server_state.clone(),
                         session,
-                        address_space,
+                        handler.address_space_provider.clone(),
                         request,
                     ))
                 })
             }
-
-            // Attribute Service Set, OPC UA Part 4, Section 5.10
-            SupportedMessage::ReadRequest(request) => {
-                self.validate_service_request(message, READ_COUNT, |session, _| {
-                    Some(
-                        self.attribute_service
-                            .read(server_state, session, address_space, request),
-                    )
+        );
+
+        // Attribute Service Set, OPC UA Part 4, Section 5.10
+        register_service!(
+            handlers,
+            ReadRequest,
+            |handler, _request_id, message, request| {
+                handler.validate_service_request(message, READ_COUNT, |session, _| {
+                    Some(handler.attribute_service.read(
+                        handler.server_state.clone(),
+                        session,
+                        handler.address_space_provider.clone(),
+                        request,
+                    ))
                 })
             }
-            SupportedMessage::HistoryReadRequest(request) => {
-                self.validate_service_request(message, HISTORY_READ_COUNT, |session, _| {
-                    Some(self.attribute_service.history_read(
-                        server_state,
+        );
+        register_service!(
+            handlers,
+            HistoryReadRequest,
+            |handler, _request_id, message, request| {
+                handler.validate_service_request(message, HISTORY_READ_COUNT, |session, _| {
+                    Some(handler.attribute_service.history_read(
+                        handler.server_state.clone(),
                         session,
-                        address_space,
+                        handler.address_space_provider.clone(),
                         request,
                     ))
                 })
             }
-            SupportedMessage::WriteRequest(request) => {
-                self.validate_service_request(message, WRITE_COUNT, |session, _| {
-                    Some(self.attribute_service.write(
-                        server_state,
+        );
+        register_service!(
+            handlers,
+            WriteRequest,
+            |handler, _request_id, message, request| {
+                handler.validate_service_request(message, WRITE_COUNT, |session, _| {
+                    Some(handler.attribute_service.write(
+                        handler.server_state.clone(),
                         session,
-                        address_space,
+                        handler.address_space_provider.clone(),
                         request,
                     ))
                 })
             }
-            SupportedMessage::HistoryUpdateRequest(request) => {
-                self.validate_service_request(message, HISTORY_UPDATE_COUNT, |session, _| {
-                    Some(self.attribute_service.history_update(
-                        server_state,
+        );
+        register_service!(
+            handlers,
+            HistoryUpdateRequest,
+            |handler, _request_id, message, request| {
+                handler.validate_service_request(message, HISTORY_UPDATE_COUNT, |session, _| {
+                    Some(handler.attribute_service.history_update(
+                        handler.server_state.clone(),
                         session,
-                        address_space,
+                        handler.address_space_provider.clone(),
                         request,
                     ))
                 })
             }
-
-            // Method Service Set, OPC UA Part 4, Section 5.11
-            SupportedMessage::CallRequest(request) => {
-                self.validate_service_request(message, CALL_COUNT, |session, session_manager| {
+        );
+
+        // Method Service Set, OPC UA Part 4, Section 5.11
+        register_service!(
+            handlers,
+            CallRequest,
+            |handler, _request_id, message, request| {
+                handler.validate_service_request(message, CALL_COUNT, |session, session_manager| {
                     let session_id = {
                         let session = trace_read_lock!(session);
                         session.session_id().clone()
                     };
-                    Some(self.method_service.call(
-                        server_state,
+                    Some(handler.method_service.call(
+                        handler.server_state.clone(),
                         &session_id,
                         session_manager,
-                        address_space,
+                        handler.address_space_provider.clone(),
                         request,
                     ))
                 })
             }
-
-            // Monitored Item Service Set, OPC UA Part 4, Section 5.12
-            SupportedMessage::CreateMonitoredItemsRequest(request) => self
-                .validate_service_request(message, CREATE_MONITORED_ITEMS_COUNT, |session, _| {
-                    Some(self.monitored_item_service.create_monitored_items(
-                        server_state,
-                        session,
-                        address_space,
-                        request,
-                    ))
-                }),
-            SupportedMessage::ModifyMonitoredItemsRequest(request) => self
-                .validate_service_request(message, MODIFY_MONITORED_ITEMS_COUNT, |session, _| {
-                    Some(self.monitored_item_service.modify_monitored_items(
-                        server_state,
-                        session,
-                        address_space,
-                        request,
-                    ))
-                }),
-            SupportedMessage::SetMonitoringModeRequest(request) => {
-                self.validate_service_request(message, SET_MONITORING_MODE_COUNT, |session, _| {
-                    Some(
-                        self.monitored_item_service
-                            .set_monitoring_mode(session, request),
-                    )
-                })
+        );
+
+        // Monitored Item Service Set, OPC UA Part 4, Section 5.12
+        register_service!(
+            handlers,
+            CreateMonitoredItemsRequest,
+            |handler, _request_id, message, request| {
+                handler.validate_service_request(
+                    message,
+                    CREATE_MONITORED_ITEMS_COUNT,
+                    |session, _| {
+                        Some(handler.monitored_item_service.create_monitored_items(
+                            handler.server_state.clone(),
+                            session,
+                            handler.address_space_provider.clone(),
+                            request,
+                        ))
+                    },
+                )
             }
-            SupportedMessage::SetTriggeringRequest(request) => {
-                self.validate_service_request(message, SET_TRIGGERING_COUNT, |session, _| {
-                    Some(self.monitored_item_service.set_triggering(session, request))
-                })
+        );
+        register_service!(
+            handlers,
+            ModifyMonitoredItemsRequest,
+            |handler, _request_id, message, request| {
+                handler.validate_service_request(
+                    message,
+                    MODIFY_MONITORED_ITEMS_COUNT,
+                    |session, _| {
+                        Some(handler.monitored_item_service.modify_monitored_items(
+                            handler.server_state.clone(),
+                            session,
+                            handler.address_space_provider.clone(),
+                            request,
+                        ))
+                    },
+                )
             }
-            SupportedMessage::DeleteMonitoredItemsRequest(request) => self
-                .validate_service_request(message, DELETE_MONITORED_ITEMS_COUNT, |session, _| {
+        );
+        register_service!(
+            handlers,
+            SetMonitoringModeRequest,
+            |handler, _request_id, message, request| {
+                handler.validate_service_request(
+                    message,
+                    SET_MONITORING_MODE_COUNT,
+                    |session, _| {
+                        Some(
+                            handler
+                                .monitored_item_service
+                                .set_monitoring_mode(session, request),
+                        )
+                    },
+                )
+            }
+        );
+        register_service!(
+            handlers,
+            SetTriggeringRequest,
+            |handler, _request_id, message, request| {
+                handler.validate_service_request(message, SET_TRIGGERING_COUNT, |session, _| {
                     Some(
-                        self.monitored_item_service
-                            .delete_monitored_items(session, request),
+                        handler
+                            .monitored_item_service
+                            .set_triggering(session, request),
                     )
-                }),
-
-            // Subscription Service Set, OPC UA Part 4, Section 5.13
-            SupportedMessage::CreateSubscriptionRequest(request) => {
-                self.validate_service_request(message, CREATE_SUBSCRIPTION_COUNT, |session, _| {
-                    Some(self.subscription_service.create_subscription(
-                        server_state,
-                        session,
-                        request,
-                    ))
                 })
             }
-            SupportedMessage::ModifySubscriptionRequest(request) => {
-                self.validate_service_request(message, MODIFY_SUBSCRIPTION_COUNT, |session, _| {
-                    Some(self.subscription_service.modify_subscription(
-                        server_state,
-                        session,
-                        request,
-                    ))
-                })
+        );
+        register_service!(
+            handlers,
+            DeleteMonitoredItemsRequest,
+            |handler, _request_id, message, request| {
+                handler.validate_service_request(
+                    message,
+                    DELETE_MONITORED_ITEMS_COUNT,
+                    |session, _| {
+                        Some(
+                            handler
+                                .monitored_item_service
+                                .delete_monitored_items(session, request),
+                        )
+                    },
+                )
             }
-            SupportedMessage::SetPublishingModeRequest(request) => {
-                self.validate_service_request(message, SET_PUBLISHING_MODE_COUNT, |session, _| {
-                    Some(
-                        self.subscription_service
-                            .set_publishing_mode(session, request),
-                    )
-                })
+        );
+
+        // Subscription Service Set, OPC UA Part 4, Section 5.13
+        register_service!(
+            handlers,
+            CreateSubscriptionRequest,
+            |handler, _request_id, message, request| {
+                handler.validate_service_request(
+                    message,
+                    CREATE_SUBSCRIPTION_COUNT,
+                    |session, _| {
+                        Some(handler.subscription_service.create_subscription(
+                            handler.server_state.clone(),
+                            session,
+                            request,
+                        ))
+                    },
+                )
             }
-            SupportedMessage::DeleteSubscriptionsRequest(request) => {
-                self.validate_service_request(message, DELETE_SUBSCRIPTIONS_COUNT, |session, _| {
-                    Some(
-                        self.subscription_service
-                            .delete_subscriptions(session, request),
-                    )
-                })
+        );
+        register_service!(
+            handlers,
+            ModifySubscriptionRequest,
+            |handler, _request_id, message, request| {
+                handler.validate_service_request(
+                    message,
+                    MODIFY_SUBSCRIPTION_COUNT,
+                    |session, _| {
+                        Some(handler.subscription_service.modify_subscription(
+                            handler.server_state.clone(),
+                            session,
+                            request,
+                        ))
+                    },
+                )
             }
-            SupportedMessage::TransferSubscriptionsRequest(request) => self
-                .validate_service_request(message, TRANSFER_SUBSCRIPTIONS_COUNT, |session, _| {
-                    Some(
-                        self.subscription_service
-                            .transfer_subscriptions(session, request),
-                    )
-                }),
-            SupportedMessage::PublishRequest(request) => {
-                self.validate_service_request(message, "", |session, _| {
+        );
+        register_service!(
+            handlers,
+            SetPublishingModeRequest,
+            |handler, _request_id, message, request| {
+                handler.validate_service_request(
+                    message,
+                    SET_PUBLISHING_MODE_COUNT,
+                    |session, _| {
+                        Some(
+                            handler
+                                .subscription_service
+                                .set_publishing_mode(session, request),
+                        )
+                    },
+                )
+            }
+        );
+        register_service!(
+            handlers,
+            DeleteSubscriptionsRequest,
+            |handler, _request_id, message, request| {
+                handler.validate_service_request(
+                    message,
+                    DELETE_SUBSCRIPTIONS_COUNT,
+                    |session, _| {
+                        Some(
+                            handler
+                                .subscription_service
+                                .delete_subscriptions(session, request),
+                        )
+                    },
+                )
+            }
+        );
+        register_service!(
+            handlers,
+            TransferSubscriptionsRequest,
+            |handler, _request_id, message, request| {
+                handler.validate_service_request(
+                    message,
+                    TRANSFER_SUBSCRIPTIONS_COUNT,
+                    |session, _| {
+                        Some(
+                            handler
+                                .subscription_service
+                                .transfer_subscriptions(session, request),
+                        )
+                    },
+                )
+            }
+        );
+        register_service!(
+            handlers,
+            PublishRequest,
+            |handler, request_id, message, request| {
+                handler.validate_service_request(message, "", |session, _| {
                     // TODO publish request diagnostics have to be done asynchronously too
 
                     // Unlike other calls which return immediately, this one is asynchronous - the
                     // request is queued and the response will come back out of sequence some time in
                     // the future.
-                    self.subscription_service.async_publish(
+                    handler.subscription_service.async_publish(
                         &Utc::now(),
                         session,
-                        address_space,
+                        handler.address_space_provider.clone(),
                         request_id,
                         request,
                     )
                 })
             }
-            SupportedMessage::RepublishRequest(request) => {
-                self.validate_service_request(message, REPUBLISH_COUNT, |session, _| {
-                    Some(self.subscription_service.republish(session, request))
+        );
+        register_service!(
+            handlers,
+            RepublishRequest,
+            |handler, _request_id, message, request| {
+                handler.validate_service_request(message, REPUBLISH_COUNT, |session, _| {
+                    Some(handler.subscription_service.republish(session, request))
                 })
             }
+        );
 
-            // Unhandle messages
-            message => {
-                debug!(
-                    "Message handler does not handle this kind of message {:?}",
-                    message
-                );
-                return Err(StatusCode::BadServiceUnsupported);
-            }
-        };
-
-        if let Some(response) = response {
-            let _ = sender.send_message(request_id, response);
-        }
-
-        Ok(())
+        handlers
     }
 
     /// Tests if this request should be rejected because of a session timeout
@@ -475,11 +833,8 @@ impl MessageHandler {
         now: DateTimeUtc,
     ) -> Result<(), SupportedMessage> {
         let mut session = trace_write_lock!(session);
-        let last_service_request_timestamp = session.last_service_request_timestamp();
-        let elapsed = now - last_service_request_timestamp;
-        if elapsed.num_milliseconds() as f64 > session.session_timeout()
-            && session.session_timeout() > 0.0
-        {
+        if session.is_timed_out(&now) {
+            let elapsed = now - session.last_service_request_timestamp();
             session.terminate_session();
             error!("Session has timed out because too much time has elapsed between service calls - elapsed time = {}ms", elapsed.num_milliseconds());
             Err(ServiceFault::new(request_header, StatusCode::BadSessionIdInvalid).into())
@@ -495,7 +850,10 @@ impl MessageHandler {
         request_header: &RequestHeader,
     ) -> Result<(), SupportedMessage> {
         let session = trace_read_lock!(session);
-        if !session.is_activated() {
+        if session.is_terminated() {
+            error!("Session is terminated so request fails");
+            Err(ServiceFault::new(request_header, StatusCode::BadSessionIdInvalid).into())
+        } else if !session.is_activated() {
             error!("Session is not activated so request fails");
             Err(ServiceFault::new(request_header, StatusCode::BadSessionNotActivated).into())
         } else {
@@ -542,6 +900,7 @@ impl MessageHandler {
                 let response = action(session.clone());
                 let mut session = trace_write_lock!(session);
                 session.set_last_service_request_timestamp(now);
+                session.update_session_diagnostics();
                 (response, true)
             };
             Self::diag_service_response(session, authorized, &response, diagnostic_key);
@@ -585,6 +944,7 @@ impl MessageHandler {
                     let response = action(session.clone(), session_manager);
                     let mut session = trace_write_lock!(session);
                     session.set_last_service_request_timestamp(now);
+                    session.update_session_diagnostics();
                     (response, true)
                 };
             // Async calls may not return a response here
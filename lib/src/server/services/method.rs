@@ -9,7 +9,8 @@ use crate::sync::*;
 use crate::types::{status_code::StatusCode, *};
 
 use crate::server::{
-    address_space::AddressSpace, services::Service, session::SessionManager, state::ServerState,
+    address_space::provider::AddressSpaceProvider, services::Service, session::SessionManager,
+    state::ServerState,
 };
 
 /// The method service. Allows a client to call a method on the server.
@@ -26,14 +27,17 @@ impl MethodService {
         MethodService {}
     }
 
-    pub fn call(
+    pub fn call<P>(
         &self,
         server_state: Arc<RwLock<ServerState>>,
         session_id: &NodeId,
         session_manager: Arc<RwLock<SessionManager>>,
-        address_space: Arc<RwLock<AddressSpace>>,
+        address_space: Arc<RwLock<P>>,
         request: &CallRequest,
-    ) -> SupportedMessage {
+    ) -> SupportedMessage
+    where
+        P: AddressSpaceProvider + ?Sized,
+    {
         if let Some(ref calls) = request.methods_to_call {
             let server_state = trace_read_lock!(server_state);
             if calls.len() <= server_state.operational_limits.max_nodes_per_method_call {
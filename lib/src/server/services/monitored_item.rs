@@ -9,7 +9,8 @@ use crate::sync::*;
 use crate::types::{status_code::StatusCode, *};
 
 use crate::server::{
-    address_space::AddressSpace, services::Service, session::Session, state::ServerState,
+    address_space::provider::AddressSpaceProvider, services::Service, session::Session,
+    state::ServerState,
 };
 
 /// The monitored item service. Allows client to create, modify and delete monitored items on a subscription.
@@ -27,13 +28,16 @@ impl MonitoredItemService {
     }
 
     /// Implementation of CreateMonitoredItems service. See OPC Unified Architecture, Part 4 5.12.2
-    pub fn create_monitored_items(
+    pub fn create_monitored_items<P>(
         &self,
         server_state: Arc<RwLock<ServerState>>,
         session: Arc<RwLock<Session>>,
-        address_space: Arc<RwLock<AddressSpace>>,
+        address_space: Arc<RwLock<P>>,
         request: &CreateMonitoredItemsRequest,
-    ) -> SupportedMessage {
+    ) -> SupportedMessage
+    where
+        P: AddressSpaceProvider + ?Sized,
+    {
         if is_empty_option_vec!(request.items_to_create) {
             self.service_fault(&request.request_header, StatusCode::BadNothingToDo)
         } else {
@@ -48,7 +52,7 @@ impl MonitoredItemService {
                 let now = chrono::Utc::now();
                 let results = Some(subscription.create_monitored_items(
                     &server_state,
-                    &address_space,
+                    &*address_space,
                     &now,
                     request.timestamps_to_return,
                     items_to_create,
@@ -70,13 +74,16 @@ impl MonitoredItemService {
     }
 
     /// Implementation of ModifyMonitoredItems service. See OPC Unified Architecture, Part 4 5.12.3
-    pub fn modify_monitored_items(
+    pub fn modify_monitored_items<P>(
         &self,
         server_state: Arc<RwLock<ServerState>>,
         session: Arc<RwLock<Session>>,
-        address_space: Arc<RwLock<AddressSpace>>,
+        address_space: Arc<RwLock<P>>,
         request: &ModifyMonitoredItemsRequest,
-    ) -> SupportedMessage {
+    ) -> SupportedMessage
+    where
+        P: AddressSpaceProvider + ?Sized,
+    {
         if is_empty_option_vec!(request.items_to_modify) {
             self.service_fault(&request.request_header, StatusCode::BadNothingToDo)
         } else {
@@ -89,7 +96,7 @@ impl MonitoredItemService {
             if let Some(subscription) = session.subscriptions_mut().get_mut(subscription_id) {
                 let results = Some(subscription.modify_monitored_items(
                     &server_state,
-                    &address_space,
+                    &*address_space,
                     request.timestamps_to_return,
                     items_to_modify,
                 ));
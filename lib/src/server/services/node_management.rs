@@ -9,7 +9,11 @@ use crate::sync::*;
 use crate::types::{node_ids::ObjectId, status_code::StatusCode, *};
 
 use crate::server::{
-    address_space::{relative_path, types::*, AddressSpace},
+    address_space::{
+        provider::{map_node, AddressSpaceProvider},
+        relative_path,
+        types::*,
+    },
     services::Service,
     session::Session,
     state::ServerState,
@@ -29,13 +33,16 @@ impl NodeManagementService {
     }
 
     /// Implements the AddNodes service
-    pub fn add_nodes(
+    pub fn add_nodes<P>(
         &self,
         server_state: Arc<RwLock<ServerState>>,
         session: Arc<RwLock<Session>>,
-        address_space: Arc<RwLock<AddressSpace>>,
+        address_space: Arc<RwLock<P>>,
         request: &AddNodesRequest,
-    ) -> SupportedMessage {
+    ) -> SupportedMessage
+    where
+        P: AddressSpaceProvider + ?Sized,
+    {
         // TODO audit - generate AuditAddNodesEventType event
         if let Some(ref nodes_to_add) = request.nodes_to_add {
             if !nodes_to_add.is_empty() {
@@ -54,7 +61,7 @@ impl NodeManagementService {
                         .map(|node_to_add| {
                             let (status_code, added_node_id) = Self::add_node(
                                 &session,
-                                &mut address_space,
+                                &mut *address_space,
                                 node_to_add,
                                 &decoding_options,
                             );
@@ -82,13 +89,16 @@ impl NodeManagementService {
     }
 
     /// Implements the AddReferences service
-    pub fn add_references(
+    pub fn add_references<P>(
         &self,
         server_state: Arc<RwLock<ServerState>>,
         session: Arc<RwLock<Session>>,
-        address_space: Arc<RwLock<AddressSpace>>,
+        address_space: Arc<RwLock<P>>,
         request: &AddReferencesRequest,
-    ) -> SupportedMessage {
+    ) -> SupportedMessage
+    where
+        P: AddressSpaceProvider + ?Sized,
+    {
         // TODO audit - generate AuditAddReferencesEventType event
         if let Some(ref references_to_add) = request.references_to_add {
             if !references_to_add.is_empty() {
@@ -102,7 +112,7 @@ impl NodeManagementService {
                     let mut address_space = trace_write_lock!(address_space);
                     let results = references_to_add
                         .iter()
-                        .map(|r| Self::add_reference(&session, &mut address_space, r))
+                        .map(|r| Self::add_reference(&session, &mut *address_space, r))
                         .collect();
                     AddReferencesResponse {
                         response_header: ResponseHeader::new_good(&request.request_header),
@@ -122,13 +132,16 @@ impl NodeManagementService {
     }
 
     /// Implements the DeleteNodes service
-    pub fn delete_nodes(
+    pub fn delete_nodes<P>(
         &self,
         server_state: Arc<RwLock<ServerState>>,
         session: Arc<RwLock<Session>>,
-        address_space: Arc<RwLock<AddressSpace>>,
+        address_space: Arc<RwLock<P>>,
         request: &DeleteNodesRequest,
-    ) -> SupportedMessage {
+    ) -> SupportedMessage
+    where
+        P: AddressSpaceProvider + ?Sized,
+    {
         // TODO audit - generate AuditDeleteNodesEventType event
         if let Some(ref nodes_to_delete) = request.nodes_to_delete {
             if !nodes_to_delete.is_empty() {
@@ -143,7 +156,7 @@ impl NodeManagementService {
                     let results = nodes_to_delete
                         .iter()
                         .map(|node_to_delete| {
-                            Self::delete_node(&session, &mut address_space, node_to_delete)
+                            Self::delete_node(&session, &mut *address_space, node_to_delete)
                         })
                         .collect();
                     let response = DeleteNodesResponse {
@@ -164,13 +177,16 @@ impl NodeManagementService {
     }
 
     /// Implements the DeleteReferences service
-    pub fn delete_references(
+    pub fn delete_references<P>(
         &self,
         server_state: Arc<RwLock<ServerState>>,
         session: Arc<RwLock<Session>>,
-        address_space: Arc<RwLock<AddressSpace>>,
+        address_space: Arc<RwLock<P>>,
         request: &DeleteReferencesRequest,
-    ) -> SupportedMessage {
+    ) -> SupportedMessage
+    where
+        P: AddressSpaceProvider + ?Sized,
+    {
         // TODO audit - generate AuditDeleteReferencesEventType event
         if let Some(ref references_to_delete) = request.references_to_delete {
             if !references_to_delete.is_empty() {
@@ -184,7 +200,7 @@ impl NodeManagementService {
                     let mut address_space = trace_write_lock!(address_space);
                     let results = references_to_delete
                         .iter()
-                        .map(|r| Self::delete_reference(&session, &mut address_space, r))
+                        .map(|r| Self::delete_reference(&session, &mut *address_space, r))
                         .collect();
                     DeleteReferencesResponse {
                         response_header: ResponseHeader::new_good(&request.request_header),
@@ -297,12 +313,15 @@ impl NodeManagementService {
         }.map_err(|_| StatusCode::BadNodeAttributesInvalid)
     }
 
-    fn add_node(
+    fn add_node<P>(
         session: &Session,
-        address_space: &mut AddressSpace,
+        address_space: &mut P,
         item: &AddNodesItem,
         decoding_options: &DecodingOptions,
-    ) -> (StatusCode, NodeId) {
+    ) -> (StatusCode, NodeId)
+    where
+        P: AddressSpaceProvider + ?Sized,
+    {
         if !session.can_modify_address_space() {
             // No permission to modify address space
             return (StatusCode::BadUserAccessDenied, NodeId::null());
@@ -348,7 +367,7 @@ impl NodeManagementService {
             RelativePath::from_str(&browse_name, &RelativePathElement::default_node_resolver)
                 .unwrap();
         if let Ok(nodes) = relative_path::find_nodes_relative_path(
-            address_space,
+            &*address_space,
             &item.parent_node_id.node_id,
             &relative_path,
         ) {
@@ -359,9 +378,10 @@ impl NodeManagementService {
         }
 
         if let Ok(reference_type_id) = item.reference_type_id.as_reference_type_id() {
+            let reference_type_id: NodeId = reference_type_id.into();
             // Node Id was either supplied or will be generated
             let new_node_id = if requested_new_node_id.is_null() {
-                NodeId::next_numeric(address_space.internal_namespace())
+                NodeId::next_numeric(address_space.default_namespace())
             } else {
                 requested_new_node_id.node_id.clone()
             };
@@ -370,9 +390,11 @@ impl NodeManagementService {
             // BadReferenceNotAllowed
 
             // Check the type definition is valid
-            if !address_space
-                .is_valid_type_definition(item.node_class, &item.type_definition.node_id)
-            {
+            if !Self::is_valid_type_definition(
+                &*address_space,
+                item.node_class,
+                &item.type_definition.node_id,
+            ) {
                 // Type definition was either invalid or supplied when it should not have been supplied
                 error!("node cannot be created because type definition is not valid");
                 return (StatusCode::BadTypeDefinitionInvalid, NodeId::null());
@@ -396,18 +418,22 @@ impl NodeManagementService {
                 &item.node_attributes,
                 decoding_options,
             ) {
-                // Add the node to the address space
+                // Add the node to the address space, referenced from its parent
                 address_space.insert(
                     node,
                     Some(&[(
                         &item.parent_node_id.node_id,
                         &reference_type_id,
-                        ReferenceDirection::Forward,
+                        ReferenceDirection::Inverse,
                     )]),
                 );
                 // Object / Variable types must add a reference to the type
                 if item.node_class == NodeClass::Object || item.node_class == NodeClass::Variable {
-                    address_space.set_node_type(&new_node_id, item.type_definition.node_id.clone());
+                    address_space.insert_reference(
+                        &new_node_id,
+                        &item.type_definition.node_id,
+                        &ReferenceTypeId::HasTypeDefinition.into(),
+                    );
                 }
                 (StatusCode::Good, new_node_id)
             } else {
@@ -421,11 +447,40 @@ impl NodeManagementService {
         }
     }
 
-    fn add_reference(
+    /// Tests if the type definition is valid for a node of the class. Objects and variables must
+    /// have an object type and a variable type respectively, and other nodes must not have one.
+    fn is_valid_type_definition<P>(
+        address_space: &P,
+        node_class: NodeClass,
+        type_definition: &NodeId,
+    ) -> bool
+    where
+        P: AddressSpaceProvider + ?Sized,
+    {
+        let type_definition_class = || map_node(address_space, type_definition, |n| n.node_class());
+        match node_class {
+            NodeClass::Object => {
+                !type_definition.is_null() && type_definition_class() == Some(NodeClass::ObjectType)
+            }
+            NodeClass::Variable => {
+                !type_definition.is_null()
+                    && type_definition_class() == Some(NodeClass::VariableType)
+            }
+            _ => {
+                // Other node classes must NOT supply a type definition
+                type_definition.is_null()
+            }
+        }
+    }
+
+    fn add_reference<P>(
         session: &Session,
-        address_space: &mut AddressSpace,
+        address_space: &mut P,
         item: &AddReferencesItem,
-    ) -> StatusCode {
+    ) -> StatusCode
+    where
+        P: AddressSpaceProvider + ?Sized,
+    {
         if !session.can_modify_address_space() {
             // No permission to modify address space
             StatusCode::BadUserAccessDenied
@@ -440,33 +495,31 @@ impl NodeManagementService {
         } else if item.target_node_class == NodeClass::Unspecified {
             StatusCode::BadNodeClassInvalid
         } else {
-            if let Some(node_type) = address_space.find_node(&item.target_node_id.node_id) {
+            let target_node_class = map_node(&*address_space, &item.target_node_id.node_id, |n| {
+                n.node_class()
+            });
+            if let Some(target_node_class) = target_node_class {
                 // If the target node exists the class can be compared to the one supplied
-                if item.target_node_class != node_type.node_class() {
+                if item.target_node_class != target_node_class {
                     return StatusCode::BadNodeClassInvalid;
                 }
             }
             if let Ok(reference_type_id) = item.reference_type_id.as_reference_type_id() {
-                if !address_space.has_reference(
-                    &item.source_node_id,
-                    &item.target_node_id.node_id,
-                    reference_type_id,
-                ) {
+                let reference_type_id: &NodeId = &reference_type_id.into();
+                // An inverse reference is held as a forward reference from the target
+                let (source_node_id, target_node_id) = if item.is_forward {
+                    (&item.source_node_id, &item.target_node_id.node_id)
+                } else {
+                    (&item.target_node_id.node_id, &item.source_node_id)
+                };
+                if !address_space.has_reference(source_node_id, target_node_id, reference_type_id) {
                     // TODO test data model constraint
                     // BadReferenceNotAllowed
-                    if item.is_forward {
-                        address_space.insert_reference(
-                            &item.source_node_id,
-                            &item.target_node_id.node_id,
-                            reference_type_id,
-                        );
-                    } else {
-                        address_space.insert_reference(
-                            &item.target_node_id.node_id,
-                            &item.source_node_id,
-                            reference_type_id,
-                        );
-                    }
+                    address_space.insert_reference(
+                        source_node_id,
+                        target_node_id,
+                        reference_type_id,
+                    );
                     StatusCode::Good
                 } else {
                     error!("reference cannot be added because reference is a duplicate");
@@ -479,11 +532,14 @@ impl NodeManagementService {
         }
     }
 
-    fn delete_node(
+    fn delete_node<P>(
         session: &Session,
-        address_space: &mut AddressSpace,
+        address_space: &mut P,
         item: &DeleteNodesItem,
-    ) -> StatusCode {
+    ) -> StatusCode
+    where
+        P: AddressSpaceProvider + ?Sized,
+    {
         if !session.can_modify_address_space() {
             // No permission to modify address space
             StatusCode::BadUserAccessDenied
@@ -495,11 +551,14 @@ impl NodeManagementService {
         }
     }
 
-    fn delete_reference(
+    fn delete_reference<P>(
         session: &Session,
-        address_space: &mut AddressSpace,
+        address_space: &mut P,
         item: &DeleteReferencesItem,
-    ) -> StatusCode {
+    ) -> StatusCode
+    where
+        P: AddressSpaceProvider + ?Sized,
+    {
         let node_id = &item.source_node_id;
         let target_node_id = &item.target_node_id.node_id;
 
@@ -516,6 +575,7 @@ impl NodeManagementService {
             error!("reference cannot be added because target node id is invalid");
             StatusCode::BadTargetNodeIdInvalid
         } else if let Ok(reference_type_id) = item.reference_type_id.as_reference_type_id() {
+            let reference_type_id: &NodeId = &reference_type_id.into();
             if item.delete_bidirectional {
                 address_space.delete_reference(node_id, target_node_id, reference_type_id);
                 address_space.delete_reference(target_node_id, node_id, reference_type_id);
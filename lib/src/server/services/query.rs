@@ -2,17 +2,25 @@
 // SPDX-License-Identifier: MPL-2.0
 // Copyright (C) 2017-2024 Adam Lock
 
+use std::convert::TryFrom;
 use std::sync::Arc;
 
 use crate::core::supported_message::SupportedMessage;
+use crate::crypto::random;
 use crate::sync::*;
-use crate::types::{status_code::StatusCode, *};
+use crate::types::{node_ids::ReferenceTypeId, operand::Operand, status_code::StatusCode, *};
 
 use crate::server::{
-    address_space::AddressSpace, services::Service, session::Session, state::ServerState,
+    address_space::provider::{map_node, AddressSpaceProvider},
+    continuation_point::QueryContinuationPoint,
+    events::{event_filter, operator},
+    services::Service,
+    session::Session,
+    state::ServerState,
 };
 
-/// The view service. Allows the client to browse the address space of the server.
+/// The query service. Allows the client to find the nodes of a type whose attributes match a
+/// filter.
 pub(crate) struct QueryService;
 
 impl Service for QueryService {
@@ -26,23 +34,310 @@ impl QueryService {
         QueryService {}
     }
 
-    pub fn query_first(
+    pub fn query_first<P>(
         &self,
         _server_state: Arc<RwLock<ServerState>>,
-        _session: Arc<RwLock<Session>>,
-        _address_space: Arc<RwLock<AddressSpace>>,
+        session: Arc<RwLock<Session>>,
+        address_space: Arc<RwLock<P>>,
         request: &QueryFirstRequest,
-    ) -> SupportedMessage {
-        self.service_fault(&request.request_header, StatusCode::BadNotSupported)
+    ) -> SupportedMessage
+    where
+        P: AddressSpaceProvider + ?Sized,
+    {
+        if is_empty_option_vec!(request.node_types) {
+            return self.service_fault(&request.request_header, StatusCode::BadNothingToDo);
+        }
+        let view = &request.view;
+        if !view.view_id.is_null() || !view.timestamp.is_null() {
+            // Views are not supported
+            info!("Query request ignored because view was specified (views not supported)");
+            return self.service_fault(&request.request_header, StatusCode::BadViewIdUnknown);
+        }
+
+        let mut session = trace_write_lock!(session);
+        let address_space = trace_read_lock!(address_space);
+
+        let (filter_valid, filter_result) = Self::validate_filter(&request.filter);
+        if !filter_valid {
+            return QueryFirstResponse {
+                response_header: ResponseHeader::new_service_result(
+                    &request.request_header,
+                    StatusCode::BadContentFilterInvalid,
+                ),
+                query_data_sets: None,
+                continuation_point: ByteString::null(),
+                parsing_results: None,
+                diagnostic_infos: None,
+                filter_result,
+            }
+            .into();
+        }
+
+        // Every node type produces a parsing result. The data sets are made from the instances of
+        // the types that were parsed successfully and which pass the filter.
+        let node_types = request.node_types.as_ref().unwrap();
+        let mut query_data_sets = Vec::new();
+        let parsing_results = node_types
+            .iter()
+            .map(|node_type| {
+                let status_code = Self::instances_of_type(&*address_space, node_type).map_or_else(
+                    |status_code| status_code,
+                    |node_ids| {
+                        query_data_sets.extend(
+                            node_ids
+                                .iter()
+                                .filter(|node_id| {
+                                    matches!(
+                                        event_filter::evaluate_where_clause(
+                                            node_id,
+                                            &request.filter,
+                                            &*address_space
+                                        ),
+                                        Ok(Variant::Boolean(true))
+                                    )
+                                })
+                                .map(|node_id| {
+                                    Self::query_data_set(&*address_space, node_id, node_type)
+                                }),
+                        );
+                        StatusCode::Good
+                    },
+                );
+                ParsingResult {
+                    status_code,
+                    data_status_codes: None,
+                    data_diagnostic_infos: None,
+                }
+            })
+            .collect::<Vec<ParsingResult>>();
+
+        // Trim the data sets to the maximum and hold the rest on a continuation point
+        let max_data_sets_to_return = request.max_data_sets_to_return as usize;
+        let (query_data_sets, continuation_point) = Self::split_at_continuation_point(
+            &mut session,
+            &*address_space,
+            max_data_sets_to_return,
+            query_data_sets,
+        );
+
+        QueryFirstResponse {
+            response_header: ResponseHeader::new_good(&request.request_header),
+            query_data_sets: Some(query_data_sets),
+            continuation_point,
+            parsing_results: Some(parsing_results),
+            diagnostic_infos: None,
+            filter_result,
+        }
+        .into()
     }
 
-    pub fn query_next(
+    pub fn query_next<P>(
         &self,
         _server_state: Arc<RwLock<ServerState>>,
-        _session: Arc<RwLock<Session>>,
-        _address_space: Arc<RwLock<AddressSpace>>,
+        session: Arc<RwLock<Session>>,
+        address_space: Arc<RwLock<P>>,
         request: &QueryNextRequest,
-    ) -> SupportedMessage {
-        self.service_fault(&request.request_header, StatusCode::BadNotSupported)
+    ) -> SupportedMessage
+    where
+        P: AddressSpaceProvider + ?Sized,
+    {
+        let mut session = trace_write_lock!(session);
+        let address_space = trace_read_lock!(address_space);
+
+        let continuation_point = session
+            .find_query_continuation_point(&request.continuation_point)
+            .filter(|continuation_point| {
+                continuation_point.is_valid_query_continuation_point(&*address_space)
+            });
+        let Some(continuation_point) = continuation_point else {
+            return self.service_fault(
+                &request.request_header,
+                StatusCode::BadContinuationPointInvalid,
+            );
+        };
+
+        // Releasing the continuation point returns nothing else
+        let (query_data_sets, revised_continuation_point) = if request.release_continuation_point {
+            (None, ByteString::null())
+        } else {
+            let (query_data_sets, revised_continuation_point) = Self::split_at_continuation_point(
+                &mut session,
+                &*address_space,
+                continuation_point.max_data_sets_to_return,
+                continuation_point.query_data_sets,
+            );
+            (Some(query_data_sets), revised_continuation_point)
+        };
+
+        QueryNextResponse {
+            response_header: ResponseHeader::new_good(&request.request_header),
+            query_data_sets,
+            revised_continuation_point,
+        }
+        .into()
+    }
+
+    /// Checks that every element of the filter uses a supported operator and operands that can be
+    /// parsed. Returns whether the filter is valid and the result of each element.
+    fn validate_filter(filter: &ContentFilter) -> (bool, ContentFilterResult) {
+        let Some(ref elements) = filter.elements else {
+            return (
+                true,
+                ContentFilterResult {
+                    element_results: None,
+                    element_diagnostic_infos: None,
+                },
+            );
+        };
+        let element_results = elements
+            .iter()
+            .map(|element| {
+                let operand_status_codes = element
+                    .filter_operands
+                    .iter()
+                    .flatten()
+                    .map(|operand| match Operand::try_from(operand) {
+                        Ok(Operand::ElementOperand(o)) if o.index as usize >= elements.len() => {
+                            StatusCode::BadFilterElementInvalid
+                        }
+                        Ok(_) => StatusCode::Good,
+                        Err(_) => StatusCode::BadFilterOperandInvalid,
+                    })
+                    .collect::<Vec<StatusCode>>();
+                // Only the operators that compare or combine attribute values are supported
+                let status_code = match element.filter_operator {
+                    FilterOperator::Equals
+                    | FilterOperator::IsNull
+                    | FilterOperator::GreaterThan
+                    | FilterOperator::LessThan
+                    | FilterOperator::GreaterThanOrEqual
+                    | FilterOperator::LessThanOrEqual
+                    | FilterOperator::Like
+                    | FilterOperator::Not
+                    | FilterOperator::Between
+                    | FilterOperator::InList
+                    | FilterOperator::OfType
+                    | FilterOperator::And
+                    | FilterOperator::Or => {
+                        if operand_status_codes.is_empty() {
+                            StatusCode::BadFilterOperandCountMismatch
+                        } else if operand_status_codes.iter().all(|s| s.is_good()) {
+                            StatusCode::Good
+                        } else {
+                            StatusCode::BadFilterOperandInvalid
+                        }
+                    }
+                    _ => StatusCode::BadFilterOperatorUnsupported,
+                };
+                ContentFilterElementResult {
+                    status_code,
+                    operand_status_codes: Some(operand_status_codes),
+                    operand_diagnostic_infos: None,
+                }
+            })
+            .collect::<Vec<ContentFilterElementResult>>();
+        let valid = element_results.iter().all(|r| r.status_code.is_good());
+        (
+            valid,
+            ContentFilterResult {
+                element_results: Some(element_results),
+                element_diagnostic_infos: None,
+            },
+        )
+    }
+
+    /// Finds the nodes whose type definition is the described type, or optionally a subtype of it.
+    fn instances_of_type<P>(
+        address_space: &P,
+        node_type: &NodeTypeDescription,
+    ) -> Result<Vec<NodeId>, StatusCode>
+    where
+        P: AddressSpaceProvider + ?Sized,
+    {
+        let type_definition = &node_type.type_definition_node;
+        if type_definition.server_index != 0 {
+            return Err(StatusCode::BadNotSupported);
+        }
+        let type_id = &type_definition.node_id;
+        let instances = match map_node(address_space, type_id, |n| n.node_class()) {
+            Some(NodeClass::ObjectType) => {
+                address_space.find_objects_by_type(type_id, node_type.include_sub_types)
+            }
+            Some(NodeClass::VariableType) => {
+                address_space.find_variables_by_type(type_id, node_type.include_sub_types)
+            }
+            Some(_) => return Err(StatusCode::BadTypeDefinitionInvalid),
+            None => return Err(StatusCode::BadNodeIdUnknown),
+        };
+        Ok(instances.unwrap_or_default())
+    }
+
+    /// Makes the data set for a node, holding the values of the data the client asked for.
+    fn query_data_set<P>(
+        address_space: &P,
+        node_id: &NodeId,
+        node_type: &NodeTypeDescription,
+    ) -> QueryDataSet
+    where
+        P: AddressSpaceProvider + ?Sized,
+    {
+        let type_definition_node = address_space
+            .find_references(
+                node_id,
+                Some((ReferenceTypeId::HasTypeDefinition.into(), false)),
+            )
+            .and_then(|references| references.first().map(|r| r.target_node.clone()))
+            .unwrap_or_else(NodeId::null);
+        let values = node_type.data_to_return.as_ref().map(|data_to_return| {
+            data_to_return
+                .iter()
+                .map(|data| {
+                    operator::value_of_attribute(
+                        node_id,
+                        &data.relative_path,
+                        data.attribute_id,
+                        &data.index_range,
+                        address_space,
+                    )
+                })
+                .collect()
+        });
+        QueryDataSet {
+            node_id: node_id.into(),
+            type_definition_node: type_definition_node.into(),
+            values,
+        }
+    }
+
+    /// Splits off the data sets beyond the maximum to return, holding them on a new continuation
+    /// point. A maximum of 0 returns everything.
+    fn split_at_continuation_point<P>(
+        session: &mut Session,
+        address_space: &P,
+        max_data_sets_to_return: usize,
+        mut query_data_sets: Vec<QueryDataSet>,
+    ) -> (Vec<QueryDataSet>, ByteString)
+    where
+        P: AddressSpaceProvider + ?Sized,
+    {
+        if max_data_sets_to_return == 0 || query_data_sets.len() <= max_data_sets_to_return {
+            (query_data_sets, ByteString::null())
+        } else {
+            let remaining = query_data_sets.split_off(max_data_sets_to_return);
+            let continuation_point = random::byte_string(6);
+            debug!(
+                "Query data sets remaining {} exceeds max data sets {}, creating new continuation point {}",
+                remaining.len(),
+                max_data_sets_to_return,
+                continuation_point.as_base64()
+            );
+            session.add_query_continuation_point(QueryContinuationPoint {
+                id: continuation_point.clone(),
+                address_space_last_modified: address_space.last_modified(),
+                max_data_sets_to_return,
+                query_data_sets: remaining,
+            });
+            (query_data_sets, continuation_point)
+        }
     }
 }
@@ -48,6 +48,14 @@ impl SessionService {
 
         let endpoints = server_state.new_endpoint_descriptions(request.endpoint_url.as_ref());
 
+        let (security_policy, security_mode) = {
+            let secure_channel = trace_read_lock!(secure_channel);
+            (
+                secure_channel.security_policy(),
+                secure_channel.security_mode(),
+            )
+        };
+
         // Check the args
         let service_result = {
             // Validate the endpoint url
@@ -60,6 +68,17 @@ impl SessionService {
                 if endpoints.is_none() {
                     error!("Create session cannot find matching endpoints");
                     StatusCode::BadTcpEndpointUrlInvalid
+                } else if let Err(err) = server_state.validate_endpoint_security(
+                    request.endpoint_url.as_ref(),
+                    security_policy,
+                    security_mode,
+                ) {
+                    // The channel's security must be offered by one of the endpoints
+                    error!(
+                        "Create session has no endpoint for security policy {:?} / mode {:?}",
+                        security_policy, security_mode
+                    );
+                    err
                 } else {
                     StatusCode::Good
                 }
@@ -81,10 +100,6 @@ impl SessionService {
                 crypto::X509::from_byte_string(&request.client_certificate).ok();
 
             // Check the client's certificate for validity and acceptance
-            let security_policy = {
-                let secure_channel = trace_read_lock!(secure_channel);
-                secure_channel.security_policy()
-            };
             let service_result = if security_policy != SecurityPolicy::None {
                 let certificate_store = trace_read_lock!(certificate_store);
                 let result = if let Some(ref client_certificate) = client_certificate {
@@ -168,6 +183,7 @@ impl SessionService {
                 session.set_client_certificate(client_certificate);
                 session.set_session_nonce(server_nonce.clone());
                 session.set_session_name(request.session_name.clone());
+                session.set_client_description(request.client_description.clone());
 
                 audit::log_create_session(
                     &server_state,
@@ -204,6 +220,7 @@ impl SessionService {
     pub fn activate_session(
         &self,
         secure_channel: Arc<RwLock<SecureChannel>>,
+        certificate_store: Arc<RwLock<CertificateStore>>,
         server_state: Arc<RwLock<ServerState>>,
         session: Arc<RwLock<Session>>,
         address_space: Arc<RwLock<AddressSpace>>,
@@ -223,17 +240,15 @@ impl SessionService {
         };
 
         let server_nonce = security_policy.random_nonce();
-        let mut service_result = if !server_state.endpoint_exists(
-            endpoint_url,
-            security_policy,
-            security_mode,
-        ) {
+        let mut service_result = if let Err(err) =
+            server_state.validate_endpoint_security(endpoint_url, security_policy, security_mode)
+        {
             // Need an endpoint
             error!(
                     "activate_session, Endpoint does not exist for requested url & mode {}, {:?} / {:?}",
                     endpoint_url, security_policy, security_mode
                 );
-            StatusCode::BadTcpEndpointUrlInvalid
+            err
         } else if security_policy != SecurityPolicy::None {
             // Crypto see 5.6.3.1 verify the caller is the same caller as create_session by validating
             // signature supplied by the client during the create.
@@ -248,8 +263,11 @@ impl SessionService {
             StatusCode::Good
         };
 
+        let mut user_token_id = None;
         if service_result.is_good() {
-            if let Err(err) = server_state.authenticate_endpoint(
+            let certificate_store = trace_read_lock!(certificate_store);
+            match server_state.authenticate_endpoint(
+                &certificate_store,
                 request,
                 endpoint_url,
                 security_policy,
@@ -257,8 +275,11 @@ impl SessionService {
                 &request.user_identity_token,
                 session.session_nonce(),
             ) {
-                error!("activate_session, invalid endpoint");
-                service_result = err;
+                Ok(id) => user_token_id = Some(id),
+                Err(err) => {
+                    error!("activate_session, invalid endpoint");
+                    service_result = err;
+                }
             }
         }
 
@@ -296,6 +317,7 @@ impl SessionService {
                 &request.user_identity_token,
                 &server_state.decoding_options(),
             ));
+            session.set_user_token_id(user_token_id);
             session.set_locale_ids(request.locale_ids.clone());
 
             let diagnostic_infos = None;
@@ -393,19 +415,21 @@ impl SessionService {
     pub fn cancel(
         &self,
         _server_state: Arc<RwLock<ServerState>>,
-        _session: Arc<RwLock<Session>>,
+        session: Arc<RwLock<Session>>,
         request: &CancelRequest,
     ) -> SupportedMessage {
-        // This service call currently does nothing
+        let mut session = trace_write_lock!(session);
+        let cancel_count = session.cancel_requests(request.request_handle);
         CancelResponse {
             response_header: ResponseHeader::new_good(&request.request_header),
-            cancel_count: 0,
+            cancel_count,
         }
         .into()
     }
 
     /// Verifies that the supplied client signature was produced by the session's client certificate
-    /// from the server's certificate and nonce.
+    /// from the server's certificate and the nonce most recently issued to the session. A signature
+    /// that does not verify is `BadApplicationSignatureInvalid`.
     fn verify_client_signature(
         security_policy: SecurityPolicy,
         server_state: &ServerState,
@@ -414,13 +438,18 @@ impl SessionService {
     ) -> StatusCode {
         if let Some(ref client_certificate) = session.client_certificate() {
             if let Some(ref server_certificate) = server_state.server_certificate {
-                crypto::verify_signature_data(
+                let result = crypto::verify_signature_data(
                     client_signature,
                     security_policy,
                     client_certificate,
                     server_certificate,
                     session.session_nonce().as_ref(),
-                )
+                );
+                if result == StatusCode::BadSecurityChecksFailed {
+                    StatusCode::BadApplicationSignatureInvalid
+                } else {
+                    result
+                }
             } else {
                 error!("Client signature verification failed, server has no server certificate");
                 StatusCode::BadUnexpectedError
@@ -9,8 +9,8 @@ use crate::sync::*;
 use crate::types::{status_code::StatusCode, *};
 
 use crate::server::{
-    address_space::AddressSpace, services::Service, session::Session, state::ServerState,
-    subscriptions::subscription::Subscription,
+    address_space::provider::AddressSpaceProvider, services::Service, session::Session,
+    state::ServerState, subscriptions::subscription::Subscription,
 };
 
 /// The subscription service. Allows the client to create, modify and delete subscriptions of monitored items
@@ -58,7 +58,7 @@ impl SubscriptionService {
 
             // Create a new subscription
             let publishing_enabled = request.publishing_enabled;
-            let subscription = Subscription::new(
+            let mut subscription = Subscription::new(
                 server_state.diagnostics.clone(),
                 subscription_id,
                 publishing_enabled,
@@ -67,6 +67,7 @@ impl SubscriptionService {
                 revised_max_keep_alive_count,
                 request.priority,
             );
+            subscription.set_max_notifications_per_publish(request.max_notifications_per_publish);
             subscriptions.insert(subscription_id, subscription);
 
             // Create the response
@@ -114,9 +115,9 @@ impl SubscriptionService {
             subscription.set_max_keep_alive_count(revised_max_keep_alive_count);
             subscription.set_max_lifetime_count(revised_lifetime_count);
             subscription.set_priority(request.priority);
+            subscription.set_max_notifications_per_publish(request.max_notifications_per_publish);
             subscription.reset_lifetime_counter();
             subscription.reset_keep_alive_counter();
-            // ...max_notifications_per_publish??
 
             ModifySubscriptionResponse {
                 response_header: ResponseHeader::new_good(&request.request_header),
@@ -234,14 +235,17 @@ impl SubscriptionService {
     }
 
     /// Handles a PublishRequest. This is asynchronous, so the response will be sent later on.
-    pub fn async_publish(
+    pub fn async_publish<P>(
         &self,
         now: &DateTimeUtc,
         session: Arc<RwLock<Session>>,
-        address_space: Arc<RwLock<AddressSpace>>,
+        address_space: Arc<RwLock<P>>,
         request_id: u32,
         request: &PublishRequest,
-    ) -> Option<SupportedMessage> {
+    ) -> Option<SupportedMessage>
+    where
+        P: AddressSpaceProvider + ?Sized,
+    {
         trace!("--> Receive a PublishRequest {:?}", request);
         let mut session = trace_write_lock!(session);
         if session.subscriptions().is_empty() {
@@ -250,7 +254,7 @@ impl SubscriptionService {
             let address_space = trace_read_lock!(address_space);
             let request_header = request.request_header.clone();
             let result =
-                session.enqueue_publish_request(now, request_id, request.clone(), &address_space);
+                session.enqueue_publish_request(now, request_id, request.clone(), &*address_space);
             if let Err(error) = result {
                 Some(self.service_fault(&request_header, error))
             } else {
@@ -11,7 +11,10 @@ use crate::sync::*;
 use crate::types::{node_ids::ReferenceTypeId, status_code::StatusCode, *};
 
 use crate::server::{
-    address_space::{relative_path, AddressSpace},
+    address_space::{
+        provider::{map_node, AddressSpaceProvider},
+        relative_path,
+    },
     continuation_point::BrowseContinuationPoint,
     services::Service,
     session::Session,
@@ -31,13 +34,16 @@ impl ViewService {
         ViewService {}
     }
 
-    pub fn browse(
+    pub fn browse<P>(
         &self,
         server_state: Arc<RwLock<ServerState>>,
         session: Arc<RwLock<Session>>,
-        address_space: Arc<RwLock<AddressSpace>>,
+        address_space: Arc<RwLock<P>>,
         request: &BrowseRequest,
-    ) -> SupportedMessage {
+    ) -> SupportedMessage
+    where
+        P: AddressSpaceProvider + ?Sized,
+    {
         if is_empty_option_vec!(request.nodes_to_browse) {
             self.service_fault(&request.request_header, StatusCode::BadNothingToDo)
         } else {
@@ -72,7 +78,7 @@ impl ViewService {
                     // Browse the nodes
                     let results = Some(Self::browse_nodes(
                         &mut session,
-                        &address_space,
+                        &*address_space,
                         nodes_to_browse,
                         max_references_per_node as usize,
                     ));
@@ -94,12 +100,15 @@ impl ViewService {
         }
     }
 
-    pub fn browse_next(
+    pub fn browse_next<P>(
         &self,
         session: Arc<RwLock<Session>>,
-        address_space: Arc<RwLock<AddressSpace>>,
+        address_space: Arc<RwLock<P>>,
         request: &BrowseNextRequest,
-    ) -> SupportedMessage {
+    ) -> SupportedMessage
+    where
+        P: AddressSpaceProvider + ?Sized,
+    {
         if is_empty_option_vec!(request.continuation_points) {
             self.service_fault(&request.request_header, StatusCode::BadNothingToDo)
         } else {
@@ -108,40 +117,59 @@ impl ViewService {
 
             let continuation_points = request.continuation_points.as_ref().unwrap();
             let results = if request.release_continuation_points {
-                session.remove_browse_continuation_points(continuation_points);
-                None
+                // Release the continuation points, reporting any that were unknown
+                continuation_points
+                    .iter()
+                    .map(|continuation_point| {
+                        let status_code = if session
+                            .find_browse_continuation_point(continuation_point)
+                            .is_some()
+                        {
+                            StatusCode::Good
+                        } else {
+                            StatusCode::BadContinuationPointInvalid
+                        };
+                        BrowseResult {
+                            status_code,
+                            continuation_point: ByteString::null(),
+                            references: None,
+                        }
+                    })
+                    .collect()
             } else {
                 // Iterate from the continuation point, assuming it is valid
-                session.remove_expired_browse_continuation_points(&address_space);
-                let results = continuation_points
+                session.remove_expired_browse_continuation_points(&*address_space);
+                continuation_points
                     .iter()
                     .map(|continuation_point| {
                         Self::browse_from_continuation_point(
                             &mut session,
-                            &address_space,
+                            &*address_space,
                             continuation_point,
                         )
                     })
-                    .collect();
-                Some(results)
+                    .collect()
             };
 
             let diagnostic_infos = None;
             BrowseNextResponse {
                 response_header: ResponseHeader::new_good(&request.request_header),
-                results,
+                results: Some(results),
                 diagnostic_infos,
             }
             .into()
         }
     }
 
-    pub fn translate_browse_paths_to_node_ids(
+    pub fn translate_browse_paths_to_node_ids<P>(
         &self,
         server_state: Arc<RwLock<ServerState>>,
-        address_space: Arc<RwLock<AddressSpace>>,
+        address_space: Arc<RwLock<P>>,
         request: &TranslateBrowsePathsToNodeIdsRequest,
-    ) -> SupportedMessage {
+    ) -> SupportedMessage
+    where
+        P: AddressSpaceProvider + ?Sized,
+    {
         if is_empty_option_vec!(request.browse_paths) {
             self.service_fault(&request.request_header, StatusCode::BadNothingToDo)
         } else {
@@ -166,7 +194,7 @@ impl ViewService {
                         } else {
                             // Starting from the node_id, find paths
                             match relative_path::find_nodes_relative_path(
-                                &address_space,
+                                &*address_space,
                                 &node_id,
                                 &browse_path.relative_path,
                             ) {
@@ -234,12 +262,23 @@ impl ViewService {
                 <= server_state.operational_limits.max_nodes_per_register_nodes
             {
                 if let Some(ref mut callback) = server_state.register_nodes_callback {
-                    match callback.register_nodes(session, &nodes_to_register[..]) {
-                        Ok(registered_node_ids) => RegisterNodesResponse {
-                            response_header: ResponseHeader::new_good(&request.request_header),
-                            registered_node_ids: Some(registered_node_ids),
+                    match callback.register_nodes(session.clone(), &nodes_to_register[..]) {
+                        Ok(registered_node_ids) => {
+                            // Remember aliases so services can resolve them back to their nodes
+                            let mut session = trace_write_lock!(session);
+                            nodes_to_register
+                                .iter()
+                                .zip(registered_node_ids.iter())
+                                .for_each(|(node_id, registered_node_id)| {
+                                    session
+                                        .register_node(registered_node_id.clone(), node_id.clone());
+                                });
+                            RegisterNodesResponse {
+                                response_header: ResponseHeader::new_good(&request.request_header),
+                                registered_node_ids: Some(registered_node_ids),
+                            }
+                            .into()
                         }
-                        .into(),
                         Err(err) => self.service_fault(&request.request_header, err),
                     }
                 } else {
@@ -275,20 +314,24 @@ impl ViewService {
             if nodes_to_unregister.len()
                 <= server_state.operational_limits.max_nodes_per_register_nodes
             {
-                if let Some(ref mut callback) = server_state.unregister_nodes_callback {
-                    match callback.unregister_nodes(session, &nodes_to_unregister[..]) {
-                        Ok(_) => UnregisterNodesResponse {
+                let result = if let Some(ref mut callback) = server_state.unregister_nodes_callback
+                {
+                    callback.unregister_nodes(session.clone(), &nodes_to_unregister[..])
+                } else {
+                    Ok(())
+                };
+                match result {
+                    Ok(_) => {
+                        let mut session = trace_write_lock!(session);
+                        nodes_to_unregister.iter().for_each(|registered_node_id| {
+                            session.unregister_node(registered_node_id);
+                        });
+                        UnregisterNodesResponse {
                             response_header: ResponseHeader::new_good(&request.request_header),
                         }
-                        .into(),
-                        Err(err) => self.service_fault(&request.request_header, err),
-                    }
-                } else {
-                    // There is no callback so just succeed
-                    UnregisterNodesResponse {
-                        response_header: ResponseHeader::new_good(&request.request_header),
+                        .into()
                     }
-                    .into()
+                    Err(err) => self.service_fault(&request.request_header, err),
                 }
             } else {
                 error!(
@@ -300,12 +343,15 @@ impl ViewService {
         }
     }
 
-    fn browse_nodes(
+    fn browse_nodes<P>(
         session: &mut Session,
-        address_space: &AddressSpace,
+        address_space: &P,
         nodes_to_browse: &[BrowseDescription],
         max_references_per_node: usize,
-    ) -> Vec<BrowseResult> {
+    ) -> Vec<BrowseResult>
+    where
+        P: AddressSpaceProvider + ?Sized,
+    {
         nodes_to_browse
             .iter()
             .map(|node_to_browse| {
@@ -327,13 +373,16 @@ impl ViewService {
             .collect()
     }
 
-    fn browse_node(
+    fn browse_node<P>(
         session: &mut Session,
-        address_space: &AddressSpace,
+        address_space: &P,
         starting_index: usize,
         node_to_browse: &BrowseDescription,
         max_references_per_node: usize,
-    ) -> Result<BrowseResult, StatusCode> {
+    ) -> Result<BrowseResult, StatusCode>
+    where
+        P: AddressSpaceProvider + ?Sized,
+    {
         // Node must exist or there will be no references
         if node_to_browse.node_id.is_null() || !address_space.node_exists(&node_to_browse.node_id) {
             return Err(StatusCode::BadNodeIdUnknown);
@@ -341,15 +390,23 @@ impl ViewService {
 
         //debug!("Node to browse = {:?}", node_to_browse);
 
-        // Request may wish to filter by a kind of reference
+        if node_to_browse.browse_direction == BrowseDirection::Invalid {
+            return Err(StatusCode::BadBrowseDirectionInvalid);
+        }
+
+        // Request may wish to filter by a kind of reference, which must be a reference type node
         let reference_type_id = if node_to_browse.reference_type_id.is_null() {
             None
-        } else if let Ok(reference_type_id) =
-            node_to_browse.reference_type_id.as_reference_type_id()
-        {
-            Some((reference_type_id, node_to_browse.include_subtypes))
         } else {
-            None
+            match map_node(address_space, &node_to_browse.reference_type_id, |node| {
+                node.node_class()
+            }) {
+                Some(NodeClass::ReferenceType) => Some((
+                    node_to_browse.reference_type_id.clone(),
+                    node_to_browse.include_subtypes,
+                )),
+                _ => return Err(StatusCode::BadReferenceTypeIdInvalid),
+            }
         };
 
         // Fetch the references to / from the given node to browse
@@ -375,13 +432,18 @@ impl ViewService {
             if target_node_id.is_null() {
                 continue;
             }
-            let target_node = address_space.find_node(&target_node_id);
-            if target_node.is_none() {
+            let Some((target_node_class, target_browse_name, target_display_name)) =
+                map_node(address_space, &target_node_id, |target_node| {
+                    let target_node = target_node.as_node();
+                    (
+                        target_node.node_class(),
+                        target_node.browse_name(),
+                        target_node.display_name(),
+                    )
+                })
+            else {
                 continue;
-            }
-
-            let target_node = target_node.unwrap().as_node();
-            let target_node_class = target_node.node_class();
+            };
 
             // Skip target nodes not required by the mask
             if target_node_class != NodeClass::Unspecified && !node_class_mask.is_empty() {
@@ -413,13 +475,13 @@ impl ViewService {
                 };
             let browse_name =
                 if result_mask.contains(BrowseDescriptionResultMask::RESULT_MASK_BROWSE_NAME) {
-                    target_node.browse_name().clone()
+                    target_browse_name
                 } else {
                     QualifiedName::null()
                 };
             let display_name =
                 if result_mask.contains(BrowseDescriptionResultMask::RESULT_MASK_DISPLAY_NAME) {
-                    target_node.display_name().clone()
+                    target_display_name
                 } else {
                     LocalizedText::null()
                 };
@@ -431,8 +493,8 @@ impl ViewService {
                     match target_node_class {
                         NodeClass::Object | NodeClass::Variable => {
                             let type_defs = address_space.find_references(
-                                &target_node.node_id(),
-                                Some((ReferenceTypeId::HasTypeDefinition, false)),
+                                &target_node_id,
+                                Some((ReferenceTypeId::HasTypeDefinition.into(), false)),
                             );
                             if let Some(type_defs) = type_defs {
                                 ExpandedNodeId::new(type_defs[0].target_node.clone())
@@ -467,11 +529,14 @@ impl ViewService {
         ))
     }
 
-    fn browse_from_continuation_point(
+    fn browse_from_continuation_point<P>(
         session: &mut Session,
-        address_space: &AddressSpace,
+        address_space: &P,
         continuation_point: &ByteString,
-    ) -> BrowseResult {
+    ) -> BrowseResult
+    where
+        P: AddressSpaceProvider + ?Sized,
+    {
         // Find the continuation point in the session
         if let Some(continuation_point) = session.find_browse_continuation_point(continuation_point)
         {
@@ -502,13 +567,16 @@ impl ViewService {
         }
     }
 
-    fn reference_description_to_browse_result(
+    fn reference_description_to_browse_result<P>(
         session: &mut Session,
-        address_space: &AddressSpace,
+        address_space: &P,
         reference_descriptions: &[ReferenceDescription],
         starting_index: usize,
         max_references_per_node: usize,
-    ) -> BrowseResult {
+    ) -> BrowseResult
+    where
+        P: AddressSpaceProvider + ?Sized,
+    {
         let references_remaining = reference_descriptions.len() - starting_index;
         let (reference_descriptions, continuation_point) = if max_references_per_node > 0
             && references_remaining > max_references_per_node
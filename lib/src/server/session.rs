@@ -2,7 +2,7 @@
 // SPDX-License-Identifier: MPL-2.0
 // Copyright (C) 2017-2024 Adam Lock
 use std::{
-    collections::{HashMap, HashSet, VecDeque},
+    collections::{HashMap, VecDeque},
     sync::{
         atomic::{AtomicI32, Ordering},
         Arc,
@@ -16,14 +16,15 @@ use crate::sync::*;
 use crate::types::{service_types::PublishRequest, status_code::StatusCode, *};
 
 use crate::server::{
-    address_space::{AddressSpace, UserAccessLevel},
-    continuation_point::BrowseContinuationPoint,
+    address_space::{provider::AddressSpaceProvider, AddressSpace, UserAccessLevel},
+    continuation_point::{BrowseContinuationPoint, QueryContinuationPoint},
     diagnostics::ServerDiagnostics,
     identity_token::IdentityToken,
     session_diagnostics::SessionDiagnostics,
     state::ServerState,
     subscriptions::subscription::TickReason,
     subscriptions::subscriptions::Subscriptions,
+    subscriptions::PublishResponseEntry,
 };
 
 /// Session info holds information about a session created by CreateSession service
@@ -81,6 +82,88 @@ impl SessionManager {
         }
     }
 
+    /// Tests if any sessions have timed out
+    pub fn has_timed_out_sessions(&self, now: &DateTimeUtc) -> bool {
+        self.sessions.values().any(|session| {
+            let session = trace_read_lock!(session);
+            session.is_timed_out(now)
+        })
+    }
+
+    /// Removes sessions which have timed out, terminating them and deleting their subscriptions
+    /// along with the monitored items that they hold. Returns the ids of the removed sessions.
+    pub fn remove_timed_out_sessions(
+        &mut self,
+        now: &DateTimeUtc,
+        address_space: Arc<RwLock<AddressSpace>>,
+    ) -> Vec<NodeId> {
+        let timed_out_session_ids: Vec<NodeId> = self
+            .sessions
+            .iter()
+            .filter(|(_, session)| {
+                let session = trace_read_lock!(session);
+                session.is_timed_out(now)
+            })
+            .map(|(session_id, _)| session_id.clone())
+            .collect();
+        for session_id in &timed_out_session_ids {
+            if let Some(session) = self.sessions.remove(session_id) {
+                info!("Session {} has timed out and is being removed", session_id);
+                let mut session = trace_write_lock!(session);
+                session.subscriptions_mut().clear();
+                session.set_terminated();
+                session.deregister_session(address_space.clone());
+            }
+        }
+        if !timed_out_session_ids.is_empty() {
+            self.sessions_terminated = self.sessions.is_empty();
+        }
+        timed_out_session_ids
+    }
+
+    /// Closes the sessions on a secure channel because the server is shutting down. Each
+    /// subscription of the sessions is sent a final status change, the other publish requests
+    /// they hold are answered with `BadServerHalted` and their subscriptions are deleted. Returns
+    /// the publish responses that remain to be sent on the secure channel.
+    pub fn shutdown_sessions(
+        &mut self,
+        secure_channel_id: u32,
+        address_space: Arc<RwLock<AddressSpace>>,
+    ) -> Vec<PublishResponseEntry> {
+        let session_ids = self
+            .sessions
+            .iter()
+            .filter(|(_, session)| {
+                let session = trace_read_lock!(session);
+                session.secure_channel_id() == secure_channel_id
+            })
+            .map(|(session_id, _)| session_id.clone())
+            .collect::<Vec<NodeId>>();
+        let now = Utc::now();
+        let mut publish_responses = Vec::new();
+        for session_id in &session_ids {
+            if let Some(session) = self.sessions.remove(session_id) {
+                info!(
+                    "Session {} is being closed because the server is shutting down",
+                    session_id
+                );
+                let mut session = trace_write_lock!(session);
+                let subscriptions = session.subscriptions_mut();
+                subscriptions.shutdown(&now);
+                if let Some(responses) = subscriptions.take_publish_responses() {
+                    publish_responses.extend(responses);
+                }
+                subscriptions.clear();
+                session.set_terminated();
+                session.deregister_session(address_space.clone());
+            }
+        }
+        if !session_ids.is_empty() {
+            self.sessions_terminated = self.sessions.is_empty();
+        }
+        publish_responses
+    }
+
     /// Find a session by its session id and return it.
     pub fn find_session_by_id(&self, session_id: &NodeId) -> Option<Arc<RwLock<Session>>> {
         self.sessions
@@ -156,10 +239,14 @@ pub struct Session {
     session_nonce: ByteString,
     /// Session name (supplied by client)
     session_name: UAString,
+    /// Description of the client application (supplied by client)
+    client_description: ApplicationDescription,
     /// Session timeout
     session_timeout: f64,
     /// User identity token
     user_identity: IdentityToken,
+    /// Id of the server user token that the identity was authenticated as
+    user_token_id: Option<String>,
     /// Session's preferred locale ids
     locale_ids: Option<Vec<UAString>>,
     /// Negotiated max request message size
@@ -172,6 +259,11 @@ pub struct Session {
     max_browse_continuation_points: usize,
     /// Browse continuation points (oldest to newest)
     browse_continuation_points: VecDeque<BrowseContinuationPoint>,
+    /// Query continuation points (oldest to newest)
+    query_continuation_points: VecDeque<QueryContinuationPoint>,
+    /// Nodes registered through RegisterNodes, keyed by the registered node id with the value
+    /// being the node id it stands in for
+    registered_nodes: HashMap<NodeId, NodeId>,
     /// Diagnostics associated with the server
     diagnostics: Arc<RwLock<ServerDiagnostics>>,
     /// Diagnostics associated with the session
@@ -218,14 +310,18 @@ impl Session {
             authentication_token: NodeId::null(),
             session_nonce: ByteString::null(),
             session_name: UAString::null(),
+            client_description: ApplicationDescription::default(),
             session_timeout: 0f64,
             user_identity: IdentityToken::None,
+            user_token_id: None,
             locale_ids: None,
             max_request_message_size: 0,
             max_response_message_size: 0,
             endpoint_url: UAString::null(),
             max_browse_continuation_points,
             browse_continuation_points: VecDeque::with_capacity(max_browse_continuation_points),
+            query_continuation_points: VecDeque::new(),
+            registered_nodes: HashMap::new(),
             can_modify_address_space: true,
             diagnostics: Arc::new(RwLock::new(ServerDiagnostics::default())),
             session_diagnostics: Arc::new(RwLock::new(SessionDiagnostics::default())),
@@ -264,14 +360,18 @@ impl Session {
             authentication_token: NodeId::null(),
             session_nonce: ByteString::null(),
             session_name: UAString::null(),
+            client_description: ApplicationDescription::default(),
             session_timeout: 0f64,
             user_identity: IdentityToken::None,
+            user_token_id: None,
             locale_ids: None,
             max_request_message_size: 0,
             max_response_message_size: 0,
             endpoint_url: UAString::null(),
             max_browse_continuation_points,
             browse_continuation_points: VecDeque::with_capacity(max_browse_continuation_points),
+            query_continuation_points: VecDeque::new(),
+            registered_nodes: HashMap::new(),
             can_modify_address_space,
             diagnostics,
             session_diagnostics: Arc::new(RwLock::new(SessionDiagnostics::default())),
@@ -334,10 +434,21 @@ impl Session {
         self.session_timeout = session_timeout;
     }
 
+    /// Tests if more time than the session timeout has elapsed since the last service request. A
+    /// session timeout of zero means the session never times out.
+    pub fn is_timed_out(&self, now: &DateTimeUtc) -> bool {
+        let elapsed = *now - self.last_service_request_timestamp;
+        self.session_timeout > 0.0 && elapsed.num_milliseconds() as f64 > self.session_timeout
+    }
+
     pub fn set_max_request_message_size(&mut self, max_request_message_size: u32) {
         self.max_request_message_size = max_request_message_size;
     }
 
+    pub fn max_response_message_size(&self) -> u32 {
+        self.max_response_message_size
+    }
+
     pub fn set_max_response_message_size(&mut self, max_response_message_size: u32) {
         self.max_response_message_size = max_response_message_size;
     }
@@ -358,6 +469,16 @@ impl Session {
         self.user_identity = user_identity;
     }
 
+    /// Id of the server user token that the session's identity was authenticated as, e.g. the
+    /// anonymous user or a configured user name / X509 user, or `None` before activation.
+    pub fn user_token_id(&self) -> Option<&str> {
+        self.user_token_id.as_deref()
+    }
+
+    pub fn set_user_token_id(&mut self, user_token_id: Option<String>) {
+        self.user_token_id = user_token_id;
+    }
+
     pub fn last_service_request_timestamp(&self) -> DateTimeUtc {
         self.last_service_request_timestamp
     }
@@ -401,6 +522,14 @@ impl Session {
         self.session_name = session_name;
     }
 
+    pub fn client_description(&self) -> &ApplicationDescription {
+        &self.client_description
+    }
+
+    pub fn set_client_description(&mut self, client_description: ApplicationDescription) {
+        self.client_description = client_description;
+    }
+
     pub(crate) fn session_diagnostics(&self) -> Arc<RwLock<SessionDiagnostics>> {
         self.session_diagnostics.clone()
     }
@@ -413,23 +542,36 @@ impl Session {
         &mut self.subscriptions
     }
 
-    pub(crate) fn enqueue_publish_request(
+    /// Cancels outstanding requests with the supplied request handle, returning how many were
+    /// cancelled. Publish requests are the only requests which are held by the session while they
+    /// wait for a response, so they are the only ones that can be cancelled.
+    pub(crate) fn cancel_requests(&mut self, request_handle: u32) -> u32 {
+        self.subscriptions.cancel_publish_requests(request_handle)
+    }
+
+    pub(crate) fn enqueue_publish_request<P>(
         &mut self,
         now: &DateTimeUtc,
         request_id: u32,
         request: PublishRequest,
-        address_space: &AddressSpace,
-    ) -> Result<(), StatusCode> {
+        address_space: &P,
+    ) -> Result<(), StatusCode>
+    where
+        P: AddressSpaceProvider + ?Sized,
+    {
         self.subscriptions
             .enqueue_publish_request(now, request_id, request, address_space)
     }
 
-    pub(crate) fn tick_subscriptions(
+    pub(crate) fn tick_subscriptions<P>(
         &mut self,
         now: &DateTimeUtc,
-        address_space: &AddressSpace,
+        address_space: &P,
         reason: TickReason,
-    ) -> Result<(), StatusCode> {
+    ) -> Result<(), StatusCode>
+    where
+        P: AddressSpaceProvider + ?Sized,
+    {
         self.subscriptions.tick(now, address_space, reason)
     }
 
@@ -479,10 +621,62 @@ impl Session {
         }
     }
 
-    pub(crate) fn remove_expired_browse_continuation_points(
+    pub(crate) fn add_query_continuation_point(
         &mut self,
-        address_space: &AddressSpace,
+        continuation_point: QueryContinuationPoint,
     ) {
+        // Remove excess query continuation points
+        while self.query_continuation_points.len()
+            >= super::constants::MAX_QUERY_CONTINUATION_POINTS
+        {
+            let continuation_point = self.query_continuation_points.pop_front();
+            debug!(
+                "Removing old query continuation point {} to make way for new one",
+                continuation_point.unwrap().id.as_base64()
+            );
+        }
+        self.query_continuation_points.push_back(continuation_point);
+    }
+
+    /// Finds and REMOVES a query continuation point by id.
+    pub(crate) fn find_query_continuation_point(
+        &mut self,
+        id: &ByteString,
+    ) -> Option<QueryContinuationPoint> {
+        if let Some(idx) = self
+            .query_continuation_points
+            .iter()
+            .position(|continuation_point| continuation_point.id == *id)
+        {
+            self.query_continuation_points.remove(idx)
+        } else {
+            None
+        }
+    }
+
+    /// Records that the registered node id is an alias for the node id. Nodes that are registered
+    /// under their own node id need no alias.
+    pub(crate) fn register_node(&mut self, registered_node_id: NodeId, node_id: NodeId) {
+        if registered_node_id != node_id {
+            self.registered_nodes.insert(registered_node_id, node_id);
+        }
+    }
+
+    /// Removes the alias for a registered node id. Node ids which are not registered are ignored.
+    pub(crate) fn unregister_node(&mut self, registered_node_id: &NodeId) {
+        self.registered_nodes.remove(registered_node_id);
+    }
+
+    /// Returns the node id that a registered node id stands for, or the supplied node id if it
+    /// is not registered.
+    pub fn resolve_registered_node<'a>(&'a self, node_id: &'a NodeId) -> &'a NodeId {
+        self.registered_nodes.get(node_id).unwrap_or(node_id)
+    }
+
+    pub(crate) fn remove_expired_browse_continuation_points<P>(&mut self, address_space: &P)
+    where
+        P: AddressSpaceProvider + ?Sized,
+    {
         self.browse_continuation_points.retain(|continuation_point| {
             let valid = continuation_point.is_valid_browse_continuation_point(address_space);
             if !valid {
@@ -492,16 +686,6 @@ impl Session {
         });
     }
 
-    /// Remove all the specified continuation points by id
-    pub(crate) fn remove_browse_continuation_points(&mut self, continuation_points: &[ByteString]) {
-        // Turn the supplied slice into a set
-        let continuation_points_set: HashSet<ByteString> =
-            continuation_points.iter().cloned().collect();
-        // Now remove any continuation points that are part of that set
-        self.browse_continuation_points
-            .retain(|continuation_point| !continuation_points_set.contains(&continuation_point.id));
-    }
-
     pub(crate) fn can_modify_address_space(&self) -> bool {
         self.can_modify_address_space
     }
@@ -548,9 +732,18 @@ impl Session {
     }
 
     pub(crate) fn register_session(&self, address_space: Arc<RwLock<AddressSpace>>) {
-        let session_diagnostics = trace_read_lock!(self.session_diagnostics);
         let mut address_space = trace_write_lock!(address_space);
-        session_diagnostics.register_session(self, &mut address_space);
+        SessionDiagnostics::register_session(
+            self.session_diagnostics.clone(),
+            self,
+            &mut address_space,
+        );
+    }
+
+    /// Updates the session's diagnostics with its last contact time and subscription counts
+    pub(crate) fn update_session_diagnostics(&self) {
+        let mut session_diagnostics = trace_write_lock!(self.session_diagnostics);
+        session_diagnostics.update_from_session(self);
     }
 
     pub(crate) fn deregister_session(&self, address_space: Arc<RwLock<AddressSpace>>) {
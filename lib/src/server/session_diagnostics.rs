@@ -1,9 +1,16 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::Arc};
 
-use crate::types::{node_ids::ObjectTypeId, service_types::ServiceCounterDataType};
+use crate::sync::*;
+use crate::types::{
+    node_ids::{DataTypeId, ObjectId, ObjectTypeId, VariableTypeId},
+    service_types::{ApplicationDescription, ServiceCounterDataType, SessionDiagnosticsDataType},
+    *,
+};
 
 use super::{
-    address_space::{address_space::AddressSpace, object::ObjectBuilder},
+    address_space::{
+        address_space::AddressSpace, object::ObjectBuilder, variable::VariableBuilder, AttrFnGetter,
+    },
     session::Session,
 };
 
@@ -11,103 +18,226 @@ use super::{
 
 #[derive(Default)]
 pub(crate) struct SessionDiagnostics {
-    total_request_count: u32,
+    session_id: NodeId,
+    session_name: UAString,
+    client_description: ApplicationDescription,
+    endpoint_url: UAString,
+    actual_session_timeout: f64,
+    max_response_message_size: u32,
+    client_connection_time: DateTime,
+    client_last_contact_time: DateTime,
+    current_subscriptions_count: u32,
+    current_monitored_items_count: u32,
+    current_publish_requests_in_queue: u32,
+    total_request_count: ServiceCounterDataType,
     unauthorized_request_count: u32,
     service_counters: HashMap<&'static str, ServiceCounterDataType>,
 }
 
 impl SessionDiagnostics {
-    /// Registers a session object
-    pub(crate) fn register_session(&self, session: &Session, address_space: &mut AddressSpace) {
-        // TODO SessionDiagnosticsObjectType
-
+    /// Registers a session object under the server's sessions diagnostics summary. The object
+    /// holds a SessionDiagnostics variable whose value and components are read from the
+    /// diagnostics as they are at the time of the read.
+    pub(crate) fn register_session(
+        session_diagnostics: Arc<RwLock<SessionDiagnostics>>,
+        session: &Session,
+        address_space: &mut AddressSpace,
+    ) {
         let session_id = session.session_id();
         debug!("register_session for session id {}", session_id);
 
+        {
+            let mut diagnostics = trace_write_lock!(session_diagnostics);
+            diagnostics.session_id = session_id.clone();
+            diagnostics.session_name = session.session_name().clone();
+            diagnostics.client_description = session.client_description().clone();
+            diagnostics.endpoint_url = session.endpoint_url().clone();
+            diagnostics.actual_session_timeout = session.session_timeout();
+            diagnostics.max_response_message_size = session.max_response_message_size();
+            diagnostics.client_connection_time = DateTime::now();
+            diagnostics.update_from_session(session);
+        }
+
+        // Browse name shall be session name, session id is the node id
+        let session_name = if session.session_name().is_empty() {
+            format!("{}", session_id)
+        } else {
+            session.session_name().to_string()
+        };
         debug!("Adding an object node for the session id {}", session_id);
-        let _ = ObjectBuilder::new(
-            session_id,
-            format!("{}", session_id),
-            format!("{}", session_id),
-        )
-        .has_type_definition(ObjectTypeId::SessionDiagnosticsObjectType)
-        .insert(address_space);
-
-        // Now add variables
-        /*
-             12816 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics),
-           12817 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_SessionId),
-           12818 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_SessionName),
-           12819 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_ClientDescription),
-           12820 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_ServerUri),
-           12821 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_EndpointUrl),
-           12822 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_LocaleIds),
-           12823 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_ActualSessionTimeout),
-           12824 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_MaxResponseMessageSize),
-           12825 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_ClientConnectionTime),
-           12826 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_ClientLastContactTime),
-           12827 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_CurrentSubscriptionsCount),
-           12828 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_CurrentMonitoredItemsCount),
-           12829 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_CurrentPublishRequestsInQueue),
-
-           12830 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_TotalRequestCount),
-           12831 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_UnauthorizedRequestCount),
-
-           12832 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_ReadCount),
-           12833 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_HistoryReadCount),
-           12834 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_WriteCount),
-           12835 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_HistoryUpdateCount),
-           12836 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_CallCount),
-           12837 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_CreateMonitoredItemsCount),
-           12838 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_ModifyMonitoredItemsCount),
-           12839 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_SetMonitoringModeCount),
-           12840 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_SetTriggeringCount),
-           12841 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_DeleteMonitoredItemsCount),
-           12842 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_CreateSubscriptionCount),
-           12843 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_ModifySubscriptionCount),
-           12844 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_SetPublishingModeCount),
-           12845 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_PublishCount),
-           12846 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_RepublishCount),
-           12847 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_TransferSubscriptionsCount),
-           12848 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_DeleteSubscriptionsCount),
-           12849 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_AddNodesCount),
-           12850 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_AddReferencesCount),
-           12851 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_DeleteNodesCount),
-           12852 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_DeleteReferencesCount),
-           12853 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_BrowseCount),
-           12854 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_BrowseNextCount),
-           12855 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_TranslateBrowsePathsToNodeIdsCount),
-           12856 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_QueryFirstCount),
-           12857 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_QueryNextCount),
-           12858 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_RegisterNodesCount),
-           12859 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_UnregisterNodesCount),
-        */
-
-        // Browse name shall be session name
-        // session id is the nodeid
-
-        // SessionDiagnostics - SessionDiagnosticsDataType
-        //   SessionId - NodeId
-        //   SessionName - String
-        //   ClientDescription - Application Description
-        //   ServerUri - String
-        //   EndpointUrl - String
-        //   LocaleId - LocaleId[]
-        //   MaxResponseMessageSize - UInt32
-        //   ActualSessionTimeout - Duration
-        //   ClientConnectionTime - UtcTime
-        //   ClientLastContactTime - UtcTime
-        //   CurrentSubscriptionsCount - UInt32
-        //   CurrentMonitoredItemsCount - UInt32
-        //   CurrentPublishRequestsInQueue - UInt32
-        //   TotalRequestCount - ServiceCounterData
-        //   UnauthorizedRequestCount - UInt32
-        //   ReadCount - ServiceCounterData
-        //   HistoryReadCount - ServiceCounterData
-        //   WriteCount - ServiceCounterData
-        //   HistoryUpdateCount
-        // SessionSecurityDiagnostics - SessionSecurityDiagnosticDataType
-        // SeubscriptionDiagnosticsArray - SubscriptionDiagnosticsArray
+        let _ = ObjectBuilder::new(session_id, session_name.as_str(), session_name.as_str())
+            .component_of(ObjectId::Server_ServerDiagnostics_SessionsDiagnosticsSummary)
+            .has_type_definition(ObjectTypeId::SessionDiagnosticsObjectType)
+            .insert(address_space);
+
+        let diagnostics_id = Self::variable_id(session_id, "SessionDiagnostics");
+        {
+            let session_diagnostics = session_diagnostics.clone();
+            let _ =
+                VariableBuilder::new(&diagnostics_id, "SessionDiagnostics", "SessionDiagnostics")
+                    .component_of(session_id.clone())
+                    .has_type_definition(VariableTypeId::SessionDiagnosticsVariableType)
+                    .data_type(DataTypeId::SessionDiagnosticsDataType)
+                    .value_getter(AttrFnGetter::new_boxed(
+                        move |_, timestamps_to_return, _, _, _, _| {
+                            let session_diagnostics = trace_read_lock!(session_diagnostics);
+                            let value = ExtensionObject::from_encodable(
+                                ObjectId::SessionDiagnosticsDataType_Encoding_DefaultBinary,
+                                &session_diagnostics.session_diagnostics_data(),
+                            );
+                            let mut value = DataValue::from(Variant::from(value));
+                            let now = DateTime::now();
+                            value.set_timestamps(timestamps_to_return, now, now);
+                            Ok(Some(value))
+                        },
+                    ))
+                    .insert(address_space);
+        }
+
+        // Now add the components of the SessionDiagnostics variable
+        let components = [
+            ("SessionId", DataTypeId::NodeId),
+            ("SessionName", DataTypeId::String),
+            ("ClientDescription", DataTypeId::ApplicationDescription),
+            ("EndpointUrl", DataTypeId::String),
+            ("ActualSessionTimeout", DataTypeId::Duration),
+            ("MaxResponseMessageSize", DataTypeId::UInt32),
+            ("ClientConnectionTime", DataTypeId::UtcTime),
+            ("ClientLastContactTime", DataTypeId::UtcTime),
+            ("CurrentSubscriptionsCount", DataTypeId::UInt32),
+            ("CurrentMonitoredItemsCount", DataTypeId::UInt32),
+            ("CurrentPublishRequestsInQueue", DataTypeId::UInt32),
+            ("TotalRequestCount", DataTypeId::ServiceCounterDataType),
+            ("UnauthorizedRequestCount", DataTypeId::UInt32),
+        ]
+        .into_iter()
+        .chain(
+            SERVICE_COUNTERS
+                .iter()
+                .map(|name| (*name, DataTypeId::ServiceCounterDataType)),
+        );
+        for (name, data_type) in components {
+            let session_diagnostics = session_diagnostics.clone();
+            let _ = VariableBuilder::new(&Self::variable_id(session_id, name), name, name)
+                .component_of(diagnostics_id.clone())
+                .has_type_definition(VariableTypeId::BaseDataVariableType)
+                .data_type(data_type)
+                .value_getter(AttrFnGetter::new_boxed(
+                    move |_, timestamps_to_return, _, _, _, _| {
+                        let session_diagnostics = trace_read_lock!(session_diagnostics);
+                        let mut value = DataValue::from(session_diagnostics.value(name));
+                        let now = DateTime::now();
+                        value.set_timestamps(timestamps_to_return, now, now);
+                        Ok(Some(value))
+                    },
+                ))
+                .insert(address_space);
+        }
+    }
+
+    /// Makes the node id of a diagnostics variable belonging to the session
+    fn variable_id(session_id: &NodeId, name: &str) -> NodeId {
+        let identifier = match session_id.identifier {
+            Identifier::String(ref v) => v.to_string(),
+            ref identifier => identifier.to_string(),
+        };
+        NodeId::new(session_id.namespace, format!("{}.{}", identifier, name))
+    }
+
+    /// Returns the value of a named component of the SessionDiagnostics variable
+    fn value(&self, name: &str) -> Variant {
+        match name {
+            "SessionId" => Variant::from(self.session_id.clone()),
+            "SessionName" => Variant::from(self.session_name.clone()),
+            "ClientDescription" => Variant::from(ExtensionObject::from_encodable(
+                ObjectId::ApplicationDescription_Encoding_DefaultBinary,
+                &self.client_description,
+            )),
+            "EndpointUrl" => Variant::from(self.endpoint_url.clone()),
+            "ActualSessionTimeout" => Variant::from(self.actual_session_timeout),
+            "MaxResponseMessageSize" => Variant::from(self.max_response_message_size),
+            "ClientConnectionTime" => Variant::from(self.client_connection_time),
+            "ClientLastContactTime" => Variant::from(self.client_last_contact_time),
+            "CurrentSubscriptionsCount" => Variant::from(self.current_subscriptions_count),
+            "CurrentMonitoredItemsCount" => Variant::from(self.current_monitored_items_count),
+            "CurrentPublishRequestsInQueue" => {
+                Variant::from(self.current_publish_requests_in_queue)
+            }
+            "TotalRequestCount" => Self::counter_value(&self.total_request_count),
+            "UnauthorizedRequestCount" => Variant::from(self.unauthorized_request_count),
+            name => Self::counter_value(&self.service_counter(name)),
+        }
+    }
+
+    fn counter_value(counter: &ServiceCounterDataType) -> Variant {
+        Variant::from(ExtensionObject::from_encodable(
+            ObjectId::ServiceCounterDataType_Encoding_DefaultBinary,
+            counter,
+        ))
+    }
+
+    /// Returns a snapshot of the session's diagnostics
+    pub(crate) fn session_diagnostics_data(&self) -> SessionDiagnosticsDataType {
+        SessionDiagnosticsDataType {
+            session_id: self.session_id.clone(),
+            session_name: self.session_name.clone(),
+            client_description: self.client_description.clone(),
+            server_uri: UAString::null(),
+            endpoint_url: self.endpoint_url.clone(),
+            locale_ids: None,
+            actual_session_timeout: self.actual_session_timeout,
+            max_response_message_size: self.max_response_message_size,
+            client_connection_time: self.client_connection_time,
+            client_last_contact_time: self.client_last_contact_time,
+            current_subscriptions_count: self.current_subscriptions_count,
+            current_monitored_items_count: self.current_monitored_items_count,
+            current_publish_requests_in_queue: self.current_publish_requests_in_queue,
+            total_request_count: self.total_request_count.clone(),
+            unauthorized_request_count: self.unauthorized_request_count,
+            read_count: self.service_counter(READ_COUNT),
+            history_read_count: self.service_counter(HISTORY_READ_COUNT),
+            write_count: self.service_counter(WRITE_COUNT),
+            history_update_count: self.service_counter(HISTORY_UPDATE_COUNT),
+            call_count: self.service_counter(CALL_COUNT),
+            create_monitored_items_count: self.service_counter(CREATE_MONITORED_ITEMS_COUNT),
+            modify_monitored_items_count: self.service_counter(MODIFY_MONITORED_ITEMS_COUNT),
+            set_monitoring_mode_count: self.service_counter(SET_MONITORING_MODE_COUNT),
+            set_triggering_count: self.service_counter(SET_TRIGGERING_COUNT),
+            delete_monitored_items_count: self.service_counter(DELETE_MONITORED_ITEMS_COUNT),
+            create_subscription_count: self.service_counter(CREATE_SUBSCRIPTION_COUNT),
+            modify_subscription_count: self.service_counter(MODIFY_SUBSCRIPTION_COUNT),
+            set_publishing_mode_count: self.service_counter(SET_PUBLISHING_MODE_COUNT),
+            publish_count: self.service_counter(PUBLISH_COUNT),
+            republish_count: self.service_counter(REPUBLISH_COUNT),
+            transfer_subscriptions_count: self.service_counter(TRANSFER_SUBSCRIPTIONS_COUNT),
+            delete_subscriptions_count: self.service_counter(DELETE_SUBSCRIPTIONS_COUNT),
+            add_nodes_count: self.service_counter(ADD_NODES_COUNT),
+            add_references_count: self.service_counter(ADD_REFERENCES_COUNT),
+            delete_nodes_count: self.service_counter(DELETE_NODES_COUNT),
+            delete_references_count: self.service_counter(DELETE_REFERENCES_COUNT),
+            browse_count: self.service_counter(BROWSE_COUNT),
+            browse_next_count: self.service_counter(BROWSE_NEXT_COUNT),
+            translate_browse_paths_to_node_ids_count: self
+                .service_counter(TRANSLATE_BROWSE_PATHS_TO_NODE_IDS_COUNT),
+            query_first_count: self.service_counter(QUERY_FIRST_COUNT),
+            query_next_count: self.service_counter(QUERY_NEXT_COUNT),
+            register_nodes_count: self.service_counter(REGISTER_NODES_COUNT),
+            unregister_nodes_count: self.service_counter(UNREGISTER_NODES_COUNT),
+        }
+    }
+
+    /// Updates the last contact time and the subscription counts from the session. It is called
+    /// as the session services each request, so the values are as they were after the last one.
+    pub(crate) fn update_from_session(&mut self, session: &Session) {
+        let subscriptions = session.subscriptions();
+        self.client_last_contact_time = session.last_service_request_timestamp().into();
+        self.current_subscriptions_count = subscriptions.len() as u32;
+        self.current_monitored_items_count = subscriptions
+            .subscriptions()
+            .values()
+            .map(|subscription| subscription.monitored_items_len() as u32)
+            .sum();
+        self.current_publish_requests_in_queue = subscriptions.publish_request_queue_len() as u32;
     }
 
     /// Deregisters a session object
@@ -117,21 +247,18 @@ impl SessionDiagnostics {
 
     /// Called on every request
     pub(crate) fn request(&mut self) {
-        self.total_request_count += 1;
+        self.total_request_count.success();
     }
 
     /// Called on an authorized request
     pub(crate) fn unauthorized_request(&mut self) {
         self.unauthorized_request_count += 1;
-        self.total_request_count += 1;
+        self.total_request_count.error();
     }
 
     /// Fetches a snapshot of the current service counter value
-    pub(crate) fn service_counter(
-        &mut self,
-        diagnostic_key: &'static str,
-    ) -> ServiceCounterDataType {
-        if let Some(counter) = self.service_counters.get_mut(diagnostic_key) {
+    pub(crate) fn service_counter(&self, diagnostic_key: &str) -> ServiceCounterDataType {
+        if let Some(counter) = self.service_counters.get(diagnostic_key) {
             counter.clone()
         } else {
             ServiceCounterDataType::default()
@@ -174,7 +301,7 @@ pub(crate) const DELETE_MONITORED_ITEMS_COUNT: &str = "DeleteMonitoredItemsCount
 pub(crate) const CREATE_SUBSCRIPTION_COUNT: &str = "CreateSubscriptionCount";
 pub(crate) const MODIFY_SUBSCRIPTION_COUNT: &str = "ModifySubscriptionCount";
 pub(crate) const SET_PUBLISHING_MODE_COUNT: &str = "SetPublishingModeCount";
-//pub(crate) const PUBLISH_COUNT: &str = "PublishCount";
+pub(crate) const PUBLISH_COUNT: &str = "PublishCount";
 pub(crate) const REPUBLISH_COUNT: &str = "RepublishCount";
 pub(crate) const TRANSFER_SUBSCRIPTIONS_COUNT: &str = "TransferSubscriptionsCount";
 pub(crate) const DELETE_SUBSCRIPTIONS_COUNT: &str = "DeleteSubscriptionsCount";
@@ -186,7 +313,39 @@ pub(crate) const BROWSE_COUNT: &str = "BrowseCount";
 pub(crate) const BROWSE_NEXT_COUNT: &str = "BrowseNextCount";
 pub(crate) const TRANSLATE_BROWSE_PATHS_TO_NODE_IDS_COUNT: &str =
     "TranslateBrowsePathsToNodeIdsCount";
-//pub(crate) const QUERY_FIRST_COUNT: &str = "QueryFirstCount";
-//pub(crate) const QUERY_NEXT_COUNT: &str = "QueryNextCount";
+pub(crate) const QUERY_FIRST_COUNT: &str = "QueryFirstCount";
+pub(crate) const QUERY_NEXT_COUNT: &str = "QueryNextCount";
 pub(crate) const REGISTER_NODES_COUNT: &str = "RegisterNodesCount";
 pub(crate) const UNREGISTER_NODES_COUNT: &str = "UnregisterNodesCount";
+
+/// The service counters exposed as components of a session's SessionDiagnostics variable
+const SERVICE_COUNTERS: [&str; 28] = [
+    READ_COUNT,
+    HISTORY_READ_COUNT,
+    WRITE_COUNT,
+    HISTORY_UPDATE_COUNT,
+    CALL_COUNT,
+    CREATE_MONITORED_ITEMS_COUNT,
+    MODIFY_MONITORED_ITEMS_COUNT,
+    SET_MONITORING_MODE_COUNT,
+    SET_TRIGGERING_COUNT,
+    DELETE_MONITORED_ITEMS_COUNT,
+    CREATE_SUBSCRIPTION_COUNT,
+    MODIFY_SUBSCRIPTION_COUNT,
+    SET_PUBLISHING_MODE_COUNT,
+    PUBLISH_COUNT,
+    REPUBLISH_COUNT,
+    TRANSFER_SUBSCRIPTIONS_COUNT,
+    DELETE_SUBSCRIPTIONS_COUNT,
+    ADD_NODES_COUNT,
+    ADD_REFERENCES_COUNT,
+    DELETE_NODES_COUNT,
+    DELETE_REFERENCES_COUNT,
+    BROWSE_COUNT,
+    BROWSE_NEXT_COUNT,
+    TRANSLATE_BROWSE_PATHS_TO_NODE_IDS_COUNT,
+    QUERY_FIRST_COUNT,
+    QUERY_NEXT_COUNT,
+    REGISTER_NODES_COUNT,
+    UNREGISTER_NODES_COUNT,
+];
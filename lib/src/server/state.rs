@@ -4,10 +4,10 @@
 
 //! Provides server state information, such as status, configuration, running servers and so on.
 
-use std::sync::Arc;
+use std::{collections::BTreeMap, sync::Arc};
 
 use crate::core::prelude::*;
-use crate::crypto::{user_identity, PrivateKey, SecurityPolicy, X509};
+use crate::crypto::{user_identity, CertificateStore, PrivateKey, SecurityPolicy, X509};
 use crate::sync::*;
 use crate::types::{
     profiles,
@@ -20,7 +20,7 @@ use crate::types::{
 };
 
 use crate::server::{
-    callbacks::{RegisterNodes, UnregisterNodes},
+    callbacks::{AuthenticationManager, RegisterNodes, RequestObserver, UnregisterNodes},
     config::{ServerConfig, ServerEndpoint},
     constants,
     diagnostics::ServerDiagnostics,
@@ -33,6 +33,7 @@ use crate::server::{
         IdentityToken, POLICY_ID_ANONYMOUS, POLICY_ID_USER_PASS_NONE, POLICY_ID_USER_PASS_RSA_15,
         POLICY_ID_USER_PASS_RSA_OAEP, POLICY_ID_X509,
     },
+    metrics::{ServiceMetrics, ServiceMetricsRecorder},
 };
 
 pub(crate) struct OperationalLimits {
@@ -79,12 +80,17 @@ pub struct ServerState {
     pub product_uri: UAString,
     /// The application name
     pub application_name: LocalizedText,
+    /// The namespace uris of the server's own nodes
+    pub namespaces: Vec<String>,
     /// The protocol, hostname and port formatted as a url, but less the path
     pub base_endpoint: String,
     /// The time the server started
     pub start_time: DateTime,
     /// The list of servers (by urn)
     pub servers: Vec<String>,
+    /// Servers which have registered themselves with this server through RegisterServer, keyed
+    /// by their server uri
+    pub(crate) registered_servers: BTreeMap<String, RegisteredServer>,
     /// Server configuration
     pub config: Arc<RwLock<ServerConfig>>,
     /// Server public certificate read from config location or null if there is none
@@ -94,6 +100,12 @@ pub struct ServerState {
     /// The next subscription id - subscriptions are shared across the whole server. Initial value
     /// is a random u32.
     pub last_subscription_id: u32,
+    /// Maximum number of sessions, 0 means no limit (danger)
+    pub max_sessions: usize,
+    /// Allows anonymous access on the endpoints whose user token ids permit it. When false,
+    /// anonymous identity tokens are rejected and not advertised on any endpoint. Set from the
+    /// `allow_anonymous` setting of the server config.
+    pub allow_anonymous: bool,
     /// Maximum number of subscriptions per session, 0 means no limit (danger)
     pub max_subscriptions: usize,
     /// Maximum number of monitored items per subscription, 0 means no limit (danger)
@@ -120,6 +132,8 @@ pub struct ServerState {
     pub(crate) audit_log: Arc<RwLock<AuditLog>>,
     /// Diagnostic information
     pub(crate) diagnostics: Arc<RwLock<ServerDiagnostics>>,
+    /// Counts and durations of the calls to each service, if they are recorded
+    pub(crate) service_metrics: Option<Arc<ServiceMetricsRecorder>>,
     /// Callback for register nodes
     pub(crate) register_nodes_callback: Option<Box<dyn RegisterNodes + Send + Sync>>,
     /// Callback for unregister nodes
@@ -128,10 +142,19 @@ pub struct ServerState {
     pub(crate) historical_data_provider: Option<Box<dyn HistoricalDataProvider + Send + Sync>>,
     /// Callback for historical events
     pub(crate) historical_event_provider: Option<Box<dyn HistoricalEventProvider + Send + Sync>>,
+    /// Authenticates user name identity tokens instead of the configured user tokens
+    pub(crate) authentication_manager: Option<Box<dyn AuthenticationManager + Send + Sync>>,
+    /// Callback for each request the server handles
+    pub(crate) request_observer: Option<Arc<dyn RequestObserver + Send + Sync>>,
+    /// Replaces the hostname of endpoint urls returned by GetEndpoints with the hostname the
+    /// client asked for, e.g. for clients that reach the server through NAT under another name
+    pub substitute_endpoint_hostname: bool,
     /// Size of the send buffer in bytes
     pub send_buffer_size: usize,
     /// Size of the receive buffer in bytes
     pub receive_buffer_size: usize,
+    /// Maximum size of a request or response message in bytes, 0 means no limit
+    pub max_message_size: usize,
 }
 
 impl ServerState {
@@ -140,50 +163,61 @@ impl ServerState {
         endpoint_url: &UAString,
         transport_profile_uris: &Option<Vec<UAString>>,
     ) -> Option<Vec<EndpointDescription>> {
-        // Filter endpoints based on profile_uris
         debug!(
             "Endpoints requested, transport profile uris {:?}",
             transport_profile_uris
         );
-        if let Some(ref transport_profile_uris) = *transport_profile_uris {
-            // Note - some clients pass an empty array
-            if !transport_profile_uris.is_empty() {
-                // As we only support binary transport, the result is None if the supplied profile_uris does not contain that profile
-                let found_binary_transport = transport_profile_uris.iter().any(|profile_uri| {
-                    profile_uri.as_ref() == profiles::TRANSPORT_PROFILE_URI_BINARY
-                });
-                if !found_binary_transport {
-                    error!(
-                        "Client wants to connect with a non binary transport {:#?}",
-                        transport_profile_uris
-                    );
-                    return None;
-                }
-            }
-        }
 
         let config = trace_read_lock!(self.config);
-        if let Ok(hostname) = hostname_from_url(endpoint_url.as_ref()) {
+        let mut endpoints = if let Ok(hostname) = hostname_from_url(endpoint_url.as_ref()) {
             if !hostname.eq_ignore_ascii_case(&config.tcp_config.host) {
                 debug!("Endpoint url \"{}\" hostname supplied by caller does not match server's hostname \"{}\"", endpoint_url, &config.tcp_config.host);
             }
             let endpoints = config
                 .endpoints
-                .iter()
-                .map(|(_, e)| self.new_endpoint_description(&config, e, true))
-                .collect();
-            Some(endpoints)
+                .values()
+                .map(|e| self.new_endpoint_description(&config, e, true));
+            if self.substitute_endpoint_hostname {
+                endpoints
+                    .map(|mut e| {
+                        if let Ok(endpoint_url) =
+                            url_with_replaced_hostname(e.endpoint_url.as_ref(), &hostname)
+                        {
+                            e.endpoint_url = endpoint_url.into();
+                        }
+                        e
+                    })
+                    .collect()
+            } else {
+                endpoints.collect()
+            }
         } else {
             warn!(
                 "Endpoint url \"{}\" is unrecognized, using default",
                 endpoint_url
             );
             if let Some(e) = config.default_endpoint() {
-                Some(vec![self.new_endpoint_description(&config, e, true)])
+                vec![self.new_endpoint_description(&config, e, true)]
             } else {
-                Some(vec![])
+                vec![]
+            }
+        };
+
+        // Filter endpoints based on profile_uris. Note - some clients pass an empty array
+        if let Some(ref transport_profile_uris) = *transport_profile_uris {
+            if !transport_profile_uris.is_empty() {
+                endpoints.retain(|e| transport_profile_uris.contains(&e.transport_profile_uri));
+                if endpoints.is_empty() {
+                    error!(
+                        "Client wants to connect with an unsupported transport {:#?}",
+                        transport_profile_uris
+                    );
+                    return None;
+                }
             }
         }
+
+        Some(endpoints)
     }
 
     pub fn endpoint_exists(
@@ -198,6 +232,24 @@ impl ServerState {
             .is_some()
     }
 
+    /// Checks that a secure channel with the security policy and mode may use the endpoint url.
+    /// Fails with `BadTcpEndpointUrlInvalid` if no endpoint has the url, or with
+    /// `BadSecurityPolicyRejected` if endpoints have the url but none of them offer the security.
+    pub fn validate_endpoint_security(
+        &self,
+        endpoint_url: &str,
+        security_policy: SecurityPolicy,
+        security_mode: MessageSecurityMode,
+    ) -> Result<(), StatusCode> {
+        if self.endpoint_exists(endpoint_url, security_policy, security_mode) {
+            Ok(())
+        } else if self.new_endpoint_descriptions(endpoint_url).is_some() {
+            Err(StatusCode::BadSecurityPolicyRejected)
+        } else {
+            Err(StatusCode::BadTcpEndpointUrlInvalid)
+        }
+    }
+
     /// Make matching endpoint descriptions for the specified url.
     /// If none match then None will be passed, therefore if Some is returned it will be guaranteed
     /// to contain at least one result.
@@ -257,7 +309,7 @@ impl ServerState {
         let mut user_identity_tokens = Vec::with_capacity(3);
 
         // Anonymous policy
-        if endpoint.supports_anonymous() {
+        if self.endpoint_allows_anonymous(endpoint) {
             user_identity_tokens.push(UserTokenPolicy {
                 policy_id: UAString::from(POLICY_ID_ANONYMOUS),
                 token_type: UserTokenType::Anonymous,
@@ -371,6 +423,15 @@ impl ServerState {
 
     pub fn abort(&mut self) {
         info!("Server has been told to abort");
+        self.shutdown();
+    }
+
+    /// Puts the server into the shutdown state and sets the abort flag. The server status reports
+    /// the new state, each connection answers the publish requests it holds with
+    /// `BadServerHalted` before deleting its subscriptions and closing its sessions, and new
+    /// requests are rejected with `BadServerHalted`.
+    pub fn shutdown(&mut self) {
+        info!("Server is shutting down");
         self.abort = true;
         self.state = ServerStateType::Shutdown;
     }
@@ -430,9 +491,12 @@ impl ServerState {
     ///
     /// It is possible that the endpoint does not exist, or that the token is invalid / unsupported
     /// or that the token cannot be used with the end point. The return codes reflect the responses
-    /// that ActivateSession would expect from a service call.
+    /// that ActivateSession would expect from a service call. The certificate of an X509 token
+    /// must be trusted by the certificate store.
+    #[allow(clippy::too_many_arguments)]
     pub fn authenticate_endpoint(
         &self,
+        certificate_store: &CertificateStore,
         request: &ActivateSessionRequest,
         endpoint_url: &str,
         security_policy: SecurityPolicy,
@@ -451,7 +515,7 @@ impl ServerState {
                     Err(StatusCode::BadIdentityTokenInvalid)
                 }
                 IdentityToken::AnonymousIdentityToken(token) => {
-                    Self::authenticate_anonymous_token(endpoint, &token)
+                    self.authenticate_anonymous_token(endpoint, &token)
                 }
                 IdentityToken::UserNameIdentityToken(token) => self
                     .authenticate_username_identity_token(
@@ -463,10 +527,10 @@ impl ServerState {
                     ),
                 IdentityToken::X509IdentityToken(token) => self.authenticate_x509_identity_token(
                     &config,
+                    certificate_store,
                     endpoint,
                     &token,
                     &request.user_token_signature,
-                    &self.server_certificate,
                     server_nonce,
                 ),
                 IdentityToken::Invalid(o) => {
@@ -495,17 +559,24 @@ impl ServerState {
         config.decoding_options()
     }
 
+    /// Tests if anonymous access is allowed on the endpoint, i.e. the server allows it and the
+    /// endpoint supports it
+    fn endpoint_allows_anonymous(&self, endpoint: &ServerEndpoint) -> bool {
+        self.allow_anonymous && endpoint.supports_anonymous()
+    }
+
     /// Authenticates an anonymous token, i.e. does the endpoint support anonymous access or not
     fn authenticate_anonymous_token(
+        &self,
         endpoint: &ServerEndpoint,
         token: &AnonymousIdentityToken,
     ) -> Result<String, StatusCode> {
         if token.policy_id.as_ref() != POLICY_ID_ANONYMOUS {
             error!("Token doesn't possess the correct policy id");
             Err(StatusCode::BadIdentityTokenInvalid)
-        } else if !endpoint.supports_anonymous() {
+        } else if !self.endpoint_allows_anonymous(endpoint) {
             error!(
-                "Endpoint \"{}\" does not support anonymous authentication",
+                "Endpoint \"{}\" does not allow anonymous authentication",
                 endpoint.path
             );
             Err(StatusCode::BadIdentityTokenRejected)
@@ -555,6 +626,14 @@ impl ServerState {
                 token.plaintext_password()?
             };
 
+            if let Some(ref authentication_manager) = self.authentication_manager {
+                return authentication_manager.authenticate_username(
+                    endpoint,
+                    token.user_name.as_ref(),
+                    &token_password,
+                );
+            }
+
             // Iterate ids in endpoint
             for user_token_id in &endpoint.user_token_ids {
                 if let Some(server_user_token) = config.user_tokens.get(user_token_id) {
@@ -596,10 +675,10 @@ impl ServerState {
     fn authenticate_x509_identity_token(
         &self,
         config: &ServerConfig,
+        certificate_store: &CertificateStore,
         endpoint: &ServerEndpoint,
         token: &X509IdentityToken,
         user_token_signature: &SignatureData,
-        server_certificate: &Option<X509>,
         server_nonce: &ByteString,
     ) -> Result<String, StatusCode> {
         if !endpoint.supports_x509(&config.user_tokens) {
@@ -609,7 +688,7 @@ impl ServerState {
             error!("Token doesn't possess the correct policy id");
             Err(StatusCode::BadIdentityTokenRejected)
         } else {
-            let result = match server_certificate {
+            let result = match self.server_certificate {
                 Some(ref server_certificate) => {
                     // Find the security policy used for verifying tokens
                     let user_identity_tokens = self.user_identity_tokens(config, endpoint);
@@ -633,14 +712,30 @@ impl ServerState {
                                 server_certificate,
                                 server_nonce.as_ref(),
                             )
+                            .map_err(|err| {
+                                error!("X509 identity token signature is invalid, {}", err);
+                                StatusCode::BadIdentityTokenInvalid
+                            })
                         }
                     }
                 }
                 None => Err(StatusCode::BadIdentityTokenInvalid),
             };
             result.and_then(|_| {
-                // Check the endpoint to see if this token is supported
                 let signing_cert = X509::from_byte_string(&token.certificate_data)?;
+
+                // The certificate must be trusted and in date before its thumbprint is any use
+                let status_code =
+                    certificate_store.validate_or_reject_user_token_cert(&signing_cert);
+                if status_code.is_bad() {
+                    error!(
+                        "X509 identity token certificate failed validation, {}",
+                        status_code
+                    );
+                    return Err(StatusCode::BadIdentityTokenRejected);
+                }
+
+                // Check the endpoint to see if this token is supported
                 let signing_thumbprint = signing_cert.thumbprint();
                 for user_token_id in &endpoint.user_token_ids {
                     if let Some(server_user_token) = config.user_tokens.get(user_token_id) {
@@ -652,7 +747,8 @@ impl ServerState {
                         }
                     }
                 }
-                Err(StatusCode::BadIdentityTokenInvalid)
+                error!("X509 identity token certificate is not trusted by the endpoint");
+                Err(StatusCode::BadIdentityTokenRejected)
             })
         }
     }
@@ -671,6 +767,32 @@ impl ServerState {
         self.historical_event_provider = Some(historical_event_provider);
     }
 
+    /// Sets the authentication manager that checks user names and passwords in place of the
+    /// user tokens in the server configuration.
+    pub fn set_authentication_manager(
+        &mut self,
+        authentication_manager: Box<dyn AuthenticationManager + Send + Sync>,
+    ) {
+        self.authentication_manager = Some(authentication_manager);
+    }
+
+    /// Returns a snapshot of the counts and durations of the calls to each service. It is empty
+    /// unless the server is configured to record service metrics.
+    pub fn service_metrics(&self) -> ServiceMetrics {
+        self.service_metrics
+            .as_ref()
+            .map(|service_metrics| service_metrics.snapshot())
+            .unwrap_or_default()
+    }
+
+    /// Sets the observer that is told about each request the server handles.
+    pub fn set_request_observer(
+        &mut self,
+        request_observer: Box<dyn RequestObserver + Send + Sync>,
+    ) {
+        self.request_observer = Some(Arc::from(request_observer));
+    }
+
     pub(crate) fn raise_and_log<T>(&self, event: T) -> Result<NodeId, ()>
     where
         T: AuditEvent + Event,
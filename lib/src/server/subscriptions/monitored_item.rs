@@ -16,7 +16,12 @@ use crate::types::{
 };
 
 use crate::server::{
-    address_space::{node::Node, AddressSpace, EventNotifier},
+    address_space::{
+        node::{Node, NodeType},
+        provider::{map_node, AddressSpaceProvider},
+        relative_path::find_node_from_browse_path,
+        EventNotifier,
+    },
     events::event_filter,
     state::ServerState,
 };
@@ -39,6 +44,14 @@ impl From<EventFieldList> for Notification {
     }
 }
 
+/// What a sample of a monitored item takes from the node that it monitors
+enum Sample {
+    EventNotifier(EventNotifier),
+    DataValue(Option<DataValue>),
+    Nothing,
+    InvalidAttributeId,
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize)]
 pub(crate) enum FilterType {
     None,
@@ -104,6 +117,8 @@ pub(crate) struct MonitoredItem {
     timestamps_to_return: TimestampsToReturn,
     last_sample_time: DateTimeUtc,
     last_data_value: Option<DataValue>,
+    /// The engineering unit range of the monitored node, used by percent deadbands
+    eu_range: Option<(f64, f64)>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -148,6 +163,7 @@ impl MonitoredItem {
             timestamps_to_return,
             last_sample_time: *now,
             last_data_value: None,
+            eu_range: None,
             queue_size,
             notification_queue: VecDeque::with_capacity(queue_size),
             queue_overflow: false,
@@ -156,13 +172,16 @@ impl MonitoredItem {
 
     /// Modifies the existing item with the values of the modify request. On success, the result
     /// holds the filter result.
-    pub fn modify(
+    pub fn modify<P>(
         &mut self,
         server_state: &ServerState,
-        address_space: &AddressSpace,
+        address_space: &P,
         timestamps_to_return: TimestampsToReturn,
         request: &MonitoredItemModifyRequest,
-    ) -> Result<ExtensionObject, StatusCode> {
+    ) -> Result<ExtensionObject, StatusCode>
+    where
+        P: AddressSpaceProvider + ?Sized,
+    {
         self.timestamps_to_return = timestamps_to_return;
         self.filter = FilterType::from_filter(
             &request.requested_parameters.filter,
@@ -181,10 +200,13 @@ impl MonitoredItem {
 
         // Shrink / grow the notification queue to the new threshold
         if self.notification_queue.len() > self.queue_size {
-            // Discard old notifications
-            let discard = self.queue_size - self.notification_queue.len();
-            let _ = self.notification_queue.drain(0..discard);
-            // TODO potential edge case with discard oldest behaviour
+            // Discard notifications from whichever end the discard policy says to
+            if self.discard_oldest {
+                let discard = self.notification_queue.len() - self.queue_size;
+                let _ = self.notification_queue.drain(0..discard);
+            } else {
+                self.notification_queue.truncate(self.queue_size);
+            }
             // Shrink the queue
             self.notification_queue.shrink_to_fit();
         } else if self.notification_queue.capacity() < self.queue_size {
@@ -209,24 +231,88 @@ impl MonitoredItem {
 
     /// Validates the filter associated with the monitored item and returns the filter result
     /// encoded in an extension object.
-    pub fn validate_filter(
-        &self,
-        address_space: &AddressSpace,
-    ) -> Result<ExtensionObject, StatusCode> {
+    pub fn validate_filter<P>(&mut self, address_space: &P) -> Result<ExtensionObject, StatusCode>
+    where
+        P: AddressSpaceProvider + ?Sized,
+    {
         // Event filter must be validated
-        let filter_result = if let FilterType::EventFilter(ref event_filter) = self.filter {
-            let filter_result = event_filter::validate(event_filter, address_space)?;
-            ExtensionObject::from_encodable(
-                ObjectId::EventFilterResult_Encoding_DefaultBinary,
-                &filter_result,
-            )
-        } else {
-            // DataChangeFilter has no result
-            ExtensionObject::null()
+        let filter_result = match self.filter {
+            FilterType::EventFilter(ref event_filter) => {
+                let filter_result = event_filter::validate(event_filter, address_space)?;
+                ExtensionObject::from_encodable(
+                    ObjectId::EventFilterResult_Encoding_DefaultBinary,
+                    &filter_result,
+                )
+            }
+            FilterType::DataChangeFilter(ref data_change_filter) => {
+                // DataChangeFilter has no result but the deadband must make sense
+                let deadband_type = data_change_filter.deadband_type;
+                if data_change_filter.deadband_value < 0f64
+                    || (deadband_type != DeadbandType::None as u32
+                        && deadband_type != DeadbandType::Absolute as u32
+                        && deadband_type != DeadbandType::Percent as u32)
+                {
+                    error!(
+                        "Data change filter has an invalid deadband, {:?}",
+                        data_change_filter
+                    );
+                    return Err(StatusCode::BadDeadbandFilterInvalid);
+                }
+                if deadband_type == DeadbandType::Percent as u32 {
+                    // A percent deadband is a percentage of the node's EURange
+                    if data_change_filter.deadband_value > 100f64 {
+                        error!(
+                            "Data change filter has a percent deadband over 100, {:?}",
+                            data_change_filter
+                        );
+                        return Err(StatusCode::BadDeadbandFilterInvalid);
+                    }
+                    let eu_range =
+                        Self::find_eu_range(address_space, &self.item_to_monitor.node_id);
+                    if eu_range.is_none() {
+                        error!(
+                            "Percent deadband requested on node {:?} which has no EURange",
+                            self.item_to_monitor.node_id
+                        );
+                        return Err(StatusCode::BadMonitoredItemFilterUnsupported);
+                    }
+                    self.eu_range = eu_range;
+                }
+                ExtensionObject::null()
+            }
+            FilterType::None => ExtensionObject::null(),
         };
         Ok(filter_result)
     }
 
+    /// Finds the EURange property of the node and returns it as a (low, high) pair.
+    fn find_eu_range<P>(address_space: &P, node_id: &NodeId) -> Option<(f64, f64)>
+    where
+        P: AddressSpaceProvider + ?Sized,
+    {
+        let eu_range_id =
+            find_node_from_browse_path(address_space, node_id, &["EURange".into()]).ok()?;
+        let value = map_node(address_space, &eu_range_id, |node| match node {
+            NodeType::Variable(ref node) => {
+                node.value(
+                    TimestampsToReturn::Neither,
+                    NumericRange::None,
+                    &QualifiedName::null(),
+                    0.0,
+                )
+                .value
+            }
+            _ => None,
+        })??;
+        if let Variant::ExtensionObject(ref o) = value {
+            o.decode_inner::<Range>(&DecodingOptions::default())
+                .ok()
+                .map(|range| (range.low, range.high))
+        } else {
+            None
+        }
+    }
+
     /// Called repeatedly on the monitored item.
     ///
     /// If the monitored item has a negative interval and subscription interval has elapsed,
@@ -235,13 +321,16 @@ impl MonitoredItem {
     ///
     /// Function returns a `TickResult` denoting if the value changed or not, and whether it should
     /// be reported.
-    pub fn tick(
+    pub fn tick<P>(
         &mut self,
         now: &DateTimeUtc,
-        address_space: &AddressSpace,
+        address_space: &P,
         publishing_interval_elapsed: bool,
         resend_data: bool,
-    ) -> TickResult {
+    ) -> TickResult
+    where
+        P: AddressSpaceProvider + ?Sized,
+    {
         if self.monitoring_mode == MonitoringMode::Disabled {
             TickResult::NoChange
         } else {
@@ -305,19 +394,21 @@ impl MonitoredItem {
     }
 
     /// Check for
-    fn check_for_events(
+    fn check_for_events<P>(
         &mut self,
-        address_space: &AddressSpace,
+        address_space: &P,
         happened_since: &DateTimeUtc,
-        node: &dyn Node,
-    ) -> bool {
+        event_notifier: EventNotifier,
+    ) -> bool
+    where
+        P: AddressSpaceProvider + ?Sized,
+    {
         match self.filter {
             FilterType::EventFilter(ref filter) => {
                 // Node has to allow subscribe to events
-                if Self::get_event_notifier(node).contains(EventNotifier::SUBSCRIBE_TO_EVENTS) {
-                    let object_id = node.node_id();
+                if event_notifier.contains(EventNotifier::SUBSCRIBE_TO_EVENTS) {
                     if let Some(events) = event_filter::evaluate(
-                        &object_id,
+                        &self.item_to_monitor.node_id,
                         filter,
                         address_space,
                         happened_since,
@@ -338,19 +429,7 @@ impl MonitoredItem {
         }
     }
 
-    fn check_for_data_change(
-        &mut self,
-        _address_space: &AddressSpace,
-        resend_data: bool,
-        attribute_id: AttributeId,
-        node: &dyn Node,
-    ) -> bool {
-        let data_value = node.get_attribute(
-            TimestampsToReturn::Neither,
-            attribute_id,
-            NumericRange::None,
-            &QualifiedName::null(),
-        );
+    fn check_for_data_change(&mut self, resend_data: bool, data_value: Option<DataValue>) -> bool {
         if let Some(mut data_value) = data_value {
             // Test for data change
             let data_change = if resend_data {
@@ -362,7 +441,7 @@ impl MonitoredItem {
                 match self.filter {
                     FilterType::None => data_value.value != last_data_value.value,
                     FilterType::DataChangeFilter(ref filter) => {
-                        !filter.compare(&data_value, last_data_value, None)
+                        !filter.compare(&data_value, last_data_value, self.eu_range)
                     }
                     _ => {
                         // Unrecognized filter
@@ -437,64 +516,76 @@ impl MonitoredItem {
     /// check, the latest value and its timestamps will be stored in the monitored item.
     ///
     /// The function will return true if the value was changed, false otherwise.
-    pub fn check_value(
+    pub fn check_value<P>(
         &mut self,
-        address_space: &AddressSpace,
+        address_space: &P,
         now: &DateTimeUtc,
         resend_data: bool,
-    ) -> bool {
+    ) -> bool
+    where
+        P: AddressSpaceProvider + ?Sized,
+    {
         if self.monitoring_mode == MonitoringMode::Disabled {
             panic!("Should not check value while monitoring mode is disabled");
         }
-        let changed = if let Some(node) = address_space.find_node(&self.item_to_monitor.node_id) {
+        // Only what the sample needs is taken from the node, so that the provider lends the node
+        // for no longer than it takes to read it
+        let is_event_filter = self.is_event_filter();
+        let sample = map_node(address_space, &self.item_to_monitor.node_id, |node| {
+            let node = node.as_node();
             match AttributeId::from_u32(self.item_to_monitor.attribute_id) {
-                Ok(attribute_id) => {
-                    let node = node.as_node();
-                    match self.filter {
-                        FilterType::EventFilter(_) => {
-                            // EventFilter is only relevant on the EventNotifier attribute
-                            if attribute_id == AttributeId::EventNotifier {
-                                let happened_since = self.last_sample_time;
-                                self.check_for_events(address_space, &happened_since, node)
-                            } else {
-                                false
-                            }
-                        }
-                        _ => self.check_for_data_change(
-                            address_space,
-                            resend_data,
-                            attribute_id,
-                            node,
-                        ),
-                    }
-                }
-                Err(_) => {
-                    trace!(
-                        "Item has no attribute_id {} so it hasn't changed, node {:?}",
-                        self.item_to_monitor.attribute_id,
-                        self.item_to_monitor.node_id
-                    );
-                    false
+                // EventFilter is only relevant on the EventNotifier attribute
+                Ok(AttributeId::EventNotifier) if is_event_filter => {
+                    Sample::EventNotifier(Self::get_event_notifier(node))
                 }
+                Ok(_) if is_event_filter => Sample::Nothing,
+                Ok(attribute_id) => Sample::DataValue(node.get_attribute(
+                    TimestampsToReturn::Neither,
+                    attribute_id,
+                    NumericRange::None,
+                    &QualifiedName::null(),
+                )),
+                Err(_) => Sample::InvalidAttributeId,
+            }
+        });
+        let changed = match sample {
+            Some(Sample::EventNotifier(event_notifier)) => {
+                let happened_since = self.last_sample_time;
+                self.check_for_events(address_space, &happened_since, event_notifier)
+            }
+            Some(Sample::DataValue(data_value)) => {
+                self.check_for_data_change(resend_data, data_value)
+            }
+            Some(Sample::Nothing) => false,
+            Some(Sample::InvalidAttributeId) => {
+                trace!(
+                    "Item has no attribute_id {} so it hasn't changed, node {:?}",
+                    self.item_to_monitor.attribute_id,
+                    self.item_to_monitor.node_id
+                );
+                false
+            }
+            None => {
+                trace!(
+                    "Cannot find item to monitor, node {:?}",
+                    self.item_to_monitor.node_id
+                );
+                false
             }
-        } else {
-            trace!(
-                "Cannot find item to monitor, node {:?}",
-                self.item_to_monitor.node_id
-            );
-            false
         };
         self.last_sample_time = *now;
         changed
     }
 
-    /// Enqueues a notification message for the monitored item
+    /// Enqueues a notification message for the monitored item. When the queue is full, the
+    /// discard policy removes the oldest or the newest notification and the overflow bit is set on
+    /// the notification that follows the discarded one, per OPC UA Part 4 5.12.1.5.
     pub fn enqueue_notification_message<T>(&mut self, notification: T)
     where
         T: Into<Notification>,
     {
-        // test for overflow
-        let overflow = if self.notification_queue.len() == self.queue_size {
+        let mut notification = notification.into();
+        if self.notification_queue.len() == self.queue_size {
             trace!(
                 "Data change overflow, node {:?}",
                 self.item_to_monitor.node_id
@@ -508,18 +599,21 @@ impl MonitoredItem {
                 self.notification_queue.pop_back();
             }
             // Overflow only affects queues > 1 element
-            self.queue_size > 1
-        } else {
-            false
-        };
-        let mut notification = notification.into();
-        if overflow {
-            if let Notification::MonitoredItemNotification(ref mut notification) = notification {
-                // Set the overflow bit on the data value's status
-                notification.value.status =
-                    Some(notification.value.status() | StatusCode::OVERFLOW);
+            if self.queue_size > 1 {
+                let overflowed = if self.discard_oldest {
+                    self.notification_queue.front_mut()
+                } else {
+                    Some(&mut notification)
+                };
+                if let Some(Notification::MonitoredItemNotification(ref mut notification)) =
+                    overflowed
+                {
+                    // Set the overflow bit on the data value's status
+                    notification.value.status =
+                        Some(notification.value.status() | StatusCode::OVERFLOW);
+                }
+                self.queue_overflow = true;
             }
-            self.queue_overflow = true;
         }
         self.notification_queue.push_back(notification);
     }
@@ -598,6 +692,13 @@ impl MonitoredItem {
     }
 
     pub fn set_monitoring_mode(&mut self, monitoring_mode: MonitoringMode) {
+        if monitoring_mode == MonitoringMode::Disabled {
+            // A disabled item discards anything it has queued. Forgetting the last value means the
+            // current value is reported as soon as the item is enabled again.
+            self.notification_queue.clear();
+            self.queue_overflow = false;
+            self.last_data_value = None;
+        }
         self.monitoring_mode = monitoring_mode;
     }
 
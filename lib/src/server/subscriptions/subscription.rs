@@ -18,7 +18,7 @@ use crate::types::{
 use crate::core::handle::Handle;
 
 use crate::server::{
-    address_space::AddressSpace,
+    address_space::provider::AddressSpaceProvider,
     constants,
     diagnostics::ServerDiagnostics,
     state::ServerState,
@@ -120,6 +120,9 @@ pub struct Subscription {
     ///  needs to send notifications the highest priority subscription should
     /// be sent first.
     priority: u8,
+    /// The maximum number of notifications the client wishes to receive in a single publish
+    /// response. A value of 0 means there is no limit.
+    max_notifications_per_publish: u32,
     /// Map of monitored items
     monitored_items: HashMap<u32, MonitoredItem>,
     /// State of the subscription
@@ -184,6 +187,7 @@ impl Subscription {
             subscription_id,
             publishing_interval,
             priority,
+            max_notifications_per_publish: 0,
             monitored_items: HashMap::with_capacity(constants::DEFAULT_MONITORED_ITEM_CAPACITY),
             max_lifetime_counter: lifetime_counter,
             max_keep_alive_counter: keep_alive_counter,
@@ -230,14 +234,17 @@ impl Subscription {
     }
 
     /// Creates monitored items on the specified subscription, returning the creation results
-    pub fn create_monitored_items(
+    pub fn create_monitored_items<P>(
         &mut self,
         server_state: &ServerState,
-        address_space: &AddressSpace,
+        address_space: &P,
         now: &DateTimeUtc,
         timestamps_to_return: TimestampsToReturn,
         items_to_create: &[MonitoredItemCreateRequest],
-    ) -> Vec<MonitoredItemCreateResult> {
+    ) -> Vec<MonitoredItemCreateResult>
+    where
+        P: AddressSpaceProvider + ?Sized,
+    {
         self.reset_lifetime_counter();
 
         // Add items to the subscription if they're not already in its
@@ -259,10 +266,10 @@ impl Subscription {
                         server_state,
                         item_to_create,
                     ) {
-                        Ok(monitored_item) => {
+                        Ok(mut monitored_item) => {
                             if server_state.max_monitored_items_per_sub == 0
                                 || self.monitored_items.len()
-                                    <= server_state.max_monitored_items_per_sub
+                                    < server_state.max_monitored_items_per_sub
                             {
                                 let revised_sampling_interval = monitored_item.sampling_interval();
                                 let revised_queue_size = monitored_item.queue_size() as u32;
@@ -300,13 +307,16 @@ impl Subscription {
     }
 
     /// Modify the specified monitored items, returning a result for each
-    pub fn modify_monitored_items(
+    pub fn modify_monitored_items<P>(
         &mut self,
         server_state: &ServerState,
-        address_space: &AddressSpace,
+        address_space: &P,
         timestamps_to_return: TimestampsToReturn,
         items_to_modify: &[MonitoredItemModifyRequest],
-    ) -> Vec<MonitoredItemModifyResult> {
+    ) -> Vec<MonitoredItemModifyResult>
+    where
+        P: AddressSpaceProvider + ?Sized,
+    {
         self.reset_lifetime_counter();
         items_to_modify
             .iter()
@@ -367,7 +377,7 @@ impl Subscription {
     /// Delete the specified monitored items (by item id), returning a status code for each
     pub fn delete_monitored_items(&mut self, items_to_delete: &[u32]) -> Vec<StatusCode> {
         self.reset_lifetime_counter();
-        items_to_delete
+        let results = items_to_delete
             .iter()
             .map(
                 |item_to_delete| match self.monitored_items.remove(item_to_delete) {
@@ -375,7 +385,14 @@ impl Subscription {
                     None => StatusCode::BadMonitoredItemIdInvalid,
                 },
             )
-            .collect()
+            .collect();
+        // Deleted items can no longer be triggered by the items that remain
+        self.monitored_items
+            .values_mut()
+            .for_each(|monitored_item| {
+                monitored_item.set_triggering(&[], items_to_delete);
+            });
+        results
     }
 
     // Returns two vecs representing the server and client handles for each monitored item.
@@ -421,13 +438,15 @@ impl Subscription {
 
     /// Checks the subscription and monitored items for state change, messages. Returns `true`
     /// if there are zero or more notifications waiting to be processed.
-    pub(crate) fn tick(
+    pub(crate) fn tick<P>(
         &mut self,
         now: &DateTimeUtc,
-        address_space: &AddressSpace,
+        address_space: &P,
         tick_reason: TickReason,
         publishing_req_queued: bool,
-    ) {
+    ) where
+        P: AddressSpaceProvider + ?Sized,
+    {
         // Check if the publishing interval has elapsed. Only checks on the tick timer.
         let publishing_interval_elapsed = match tick_reason {
             TickReason::ReceivePublishRequest => false,
@@ -446,8 +465,8 @@ impl Subscription {
         // elapses but they don't have to. So this is called every tick just to catch items with their
         // own intervals.
 
-        let notification = match self.state {
-            SubscriptionState::Closed | SubscriptionState::Creating => None,
+        let notifications = match self.state {
+            SubscriptionState::Closed | SubscriptionState::Creating => Vec::new(),
             _ => {
                 let resend_data = self.resend_data;
                 self.tick_monitored_items(
@@ -460,7 +479,7 @@ impl Subscription {
         };
         self.resend_data = false;
 
-        let notifications_available = !self.notifications.is_empty() || notification.is_some();
+        let notifications_available = !self.notifications.is_empty() || !notifications.is_empty();
         let more_notifications = self.notifications.len() > 1;
 
         // If items have changed or subscription interval elapsed then we may have notifications
@@ -480,7 +499,7 @@ impl Subscription {
                 "subscription tick - update_state_result = {:?}",
                 update_state_result
             );
-            self.handle_state_result(now, update_state_result, notification);
+            self.handle_state_result(now, update_state_result, notifications);
         }
     }
 
@@ -506,12 +525,12 @@ impl Subscription {
         &mut self,
         now: &DateTimeUtc,
         update_state_result: UpdateStateResult,
-        notification: Option<NotificationMessage>,
+        notifications: Vec<NotificationMessage>,
     ) {
         // Now act on the state's action
         match update_state_result.update_state_action {
             UpdateStateAction::None => {
-                if let Some(ref notification) = notification {
+                if let Some(notification) = notifications.first() {
                     // Reset the next sequence number to the discarded notification
                     let notification_sequence_number = notification.sequence_number;
                     self.sequence_number.set_next(notification_sequence_number);
@@ -520,7 +539,7 @@ impl Subscription {
                 // Send nothing
             }
             UpdateStateAction::ReturnKeepAlive => {
-                if let Some(ref notification) = notification {
+                if let Some(notification) = notifications.first() {
                     // Reset the next sequence number to the discarded notification
                     let notification_sequence_number = notification.sequence_number;
                     self.sequence_number.set_next(notification_sequence_number);
@@ -535,13 +554,13 @@ impl Subscription {
                 self.enqueue_notification(notification);
             }
             UpdateStateAction::ReturnNotifications => {
-                // Add the notification message to the queue
-                if let Some(notification) = notification {
-                    self.enqueue_notification(notification);
-                }
+                // Add the notification messages to the queue
+                notifications
+                    .into_iter()
+                    .for_each(|notification| self.enqueue_notification(notification));
             }
             UpdateStateAction::SubscriptionCreated => {
-                if notification.is_some() {
+                if !notifications.is_empty() {
                     panic!("SubscriptionCreated got a notification");
                 }
                 // Subscription was created successfully
@@ -549,7 +568,7 @@ impl Subscription {
                 //                self.enqueue_notification(notification);
             }
             UpdateStateAction::SubscriptionExpired => {
-                if notification.is_some() {
+                if !notifications.is_empty() {
                     panic!("SubscriptionExpired got a notification");
                 }
                 // Delete the monitored items, issue a status change for the subscription
@@ -565,6 +584,28 @@ impl Subscription {
         }
     }
 
+    /// Makes the final status change notification of a subscription that is closed because the
+    /// server is shutting down.
+    pub(crate) fn shutdown_notification(&mut self, now: &DateTimeUtc) -> NotificationMessage {
+        NotificationMessage::status_change(
+            self.sequence_number.next(),
+            DateTime::from(*now),
+            StatusCode::BadShutdown,
+        )
+    }
+
+    /// The publish time of the oldest notification waiting to be sent, if there is one.
+    pub(crate) fn oldest_notification_time(&self) -> Option<DateTimeUtc> {
+        self.notifications
+            .front()
+            .map(|notification| notification.publish_time.as_chrono())
+    }
+
+    /// Tests if there are notifications waiting for a publish request.
+    pub(crate) fn has_notifications(&self) -> bool {
+        !self.notifications.is_empty()
+    }
+
     pub(crate) fn take_notification(&mut self) -> Option<NotificationMessage> {
         self.notifications.pop_front()
     }
@@ -841,15 +882,19 @@ impl Subscription {
     /// Items that are in a reporting state, or triggered to report will be have their pending notifications
     /// collected together when the publish interval elapsed flag is `true`.
     ///
-    /// The function returns a `notifications` and a `more_notifications` boolean to indicate if the notifications
-    /// are available.
-    fn tick_monitored_items(
+    /// The function returns the notification messages to send, which is empty if there is nothing
+    /// to report. The notifications are split across as many messages as it takes to keep each
+    /// one within the maximum notifications per publish.
+    fn tick_monitored_items<P>(
         &mut self,
         now: &DateTimeUtc,
-        address_space: &AddressSpace,
+        address_space: &P,
         publishing_interval_elapsed: bool,
         resend_data: bool,
-    ) -> Option<NotificationMessage> {
+    ) -> Vec<NotificationMessage>
+    where
+        P: AddressSpaceProvider + ?Sized,
+    {
         let mut triggered_items: BTreeSet<u32> = BTreeSet::new();
         let mut monitored_item_notifications = Vec::with_capacity(self.monitored_items.len() * 2);
 
@@ -940,53 +985,50 @@ impl Subscription {
             }
         });
 
-        // Produce a data change notification
-        if !monitored_item_notifications.is_empty() {
-            let next_sequence_number = self.sequence_number.next();
+        // Produce data change notifications, no more than the maximum in each
+        let max_notifications_per_publish = if self.max_notifications_per_publish == 0 {
+            monitored_item_notifications.len().max(1)
+        } else {
+            self.max_notifications_per_publish as usize
+        };
+        monitored_item_notifications
+            .chunks(max_notifications_per_publish)
+            .map(|monitored_item_notifications| {
+                let next_sequence_number = self.sequence_number.next();
 
-            trace!(
-                "Create notification for subscription {}, sequence number {}",
-                self.subscription_id,
-                next_sequence_number
-            );
+                trace!(
+                    "Create notification for subscription {}, sequence number {}",
+                    self.subscription_id,
+                    next_sequence_number
+                );
 
-            // Collect all datachange notifications
-            let data_change_notifications = monitored_item_notifications
-                .iter()
-                .filter(|v| matches!(v, Notification::MonitoredItemNotification(_)))
-                .map(|v| {
-                    if let Notification::MonitoredItemNotification(v) = v {
-                        v.clone()
-                    } else {
-                        panic!()
-                    }
-                })
-                .collect();
-
-            // Collect event notifications
-            let event_notifications = monitored_item_notifications
-                .iter()
-                .filter(|v| matches!(v, Notification::Event(_)))
-                .map(|v| {
-                    if let Notification::Event(v) = v {
-                        v.clone()
-                    } else {
-                        panic!()
-                    }
-                })
-                .collect();
-
-            // Make a notification
-            let notification = NotificationMessage::data_change(
-                next_sequence_number,
-                DateTime::from(*now),
-                data_change_notifications,
-                event_notifications,
-            );
-            Some(notification)
-        } else {
-            None
-        }
+                // Collect all datachange notifications
+                let data_change_notifications = monitored_item_notifications
+                    .iter()
+                    .filter_map(|v| match v {
+                        Notification::MonitoredItemNotification(v) => Some(v.clone()),
+                        _ => None,
+                    })
+                    .collect();
+
+                // Collect event notifications
+                let event_notifications = monitored_item_notifications
+                    .iter()
+                    .filter_map(|v| match v {
+                        Notification::Event(v) => Some(v.clone()),
+                        _ => None,
+                    })
+                    .collect();
+
+                // Make a notification
+                NotificationMessage::data_change(
+                    next_sequence_number,
+                    DateTime::from(*now),
+                    data_change_notifications,
+                    event_notifications,
+                )
+            })
+            .collect()
     }
 
     /// Reset the keep-alive counter to the maximum keep-alive count of the Subscription.
@@ -1030,6 +1072,11 @@ impl Subscription {
         self.keep_alive_counter = keep_alive_counter;
     }
 
+    #[cfg(test)]
+    pub(crate) fn monitored_item(&self, monitored_item_id: u32) -> Option<&MonitoredItem> {
+        self.monitored_items.get(&monitored_item_id)
+    }
+
     #[cfg(test)]
     pub(crate) fn state(&self) -> SubscriptionState {
         self.state
@@ -1082,6 +1129,18 @@ impl Subscription {
         self.priority = priority;
     }
 
+    pub fn max_notifications_per_publish(&self) -> u32 {
+        self.max_notifications_per_publish
+    }
+
+    pub(crate) fn set_max_notifications_per_publish(&mut self, max_notifications_per_publish: u32) {
+        self.max_notifications_per_publish = max_notifications_per_publish;
+    }
+
+    pub fn publishing_enabled(&self) -> bool {
+        self.publishing_enabled
+    }
+
     pub(crate) fn set_publishing_enabled(&mut self, publishing_enabled: bool) {
         self.publishing_enabled = publishing_enabled;
         self.reset_lifetime_counter();
@@ -1118,20 +1177,46 @@ impl Subscription {
         (results, items)
     }
 
+    /// Finds the links to remove that the triggering item actually has
+    fn validate_triggering_links(
+        &self,
+        monitored_item_id: u32,
+        items: &[u32],
+    ) -> (Vec<StatusCode>, Vec<u32>) {
+        let triggered_items = self
+            .monitored_items
+            .get(&monitored_item_id)
+            .map(|monitored_item| monitored_item.triggered_items());
+        let is_link = |i: &u32| triggered_items.is_some_and(|t| t.contains(i));
+        let results: Vec<StatusCode> = items
+            .iter()
+            .map(|i| {
+                if is_link(i) {
+                    StatusCode::Good
+                } else {
+                    StatusCode::BadMonitoredItemIdInvalid
+                }
+            })
+            .collect();
+        let items: Vec<u32> = items.iter().filter(|i| is_link(i)).copied().collect();
+
+        (results, items)
+    }
+
     /// Sets the triggering monitored items on a subscription. This function will validate that
-    /// the items to add / remove actually exist and will only pass through existing monitored items
-    /// onto the monitored item itself.
+    /// the items to add actually exist and that the links to remove are held by the triggering
+    /// item, and will only pass through those onto the monitored item itself.
     pub(crate) fn set_triggering(
         &mut self,
         monitored_item_id: u32,
         items_to_add: &[u32],
         items_to_remove: &[u32],
     ) -> Result<(Vec<StatusCode>, Vec<StatusCode>), StatusCode> {
-        // Find monitored items that do or do not exist
+        // Find monitored items that do or do not exist, and links that do or do not exist
         let (add_results, items_to_add) =
             self.validate_triggered_items(monitored_item_id, items_to_add);
         let (remove_results, items_to_remove) =
-            self.validate_triggered_items(monitored_item_id, items_to_remove);
+            self.validate_triggering_links(monitored_item_id, items_to_remove);
 
         if let Some(ref mut monitored_item) = self.monitored_items.get_mut(&monitored_item_id) {
             // Set the triggering monitored items
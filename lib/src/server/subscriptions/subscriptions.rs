@@ -3,7 +3,8 @@
 // Copyright (C) 2017-2024 Adam Lock
 
 use std::{
-    collections::{BTreeMap, VecDeque},
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet, VecDeque},
     time::Duration,
 };
 
@@ -14,7 +15,8 @@ use crate::types::{
 };
 
 use crate::server::{
-    address_space::types::AddressSpace,
+    address_space::provider::AddressSpaceProvider,
+    constants,
     subscriptions::{
         subscription::{Subscription, TickReason},
         PublishRequestEntry, PublishResponseEntry,
@@ -92,6 +94,11 @@ impl Subscriptions {
         }
     }
 
+    /// Returns the number of publish requests waiting for a notification to send
+    pub(crate) fn publish_request_queue_len(&self) -> usize {
+        self.publish_request_queue.len()
+    }
+
     #[cfg(test)]
     pub(crate) fn publish_request_queue(&mut self) -> &mut VecDeque<PublishRequestEntry> {
         &mut self.publish_request_queue
@@ -132,13 +139,16 @@ impl Subscriptions {
     ///
     /// If the queue is full this call will pop the oldest and generate a service fault
     /// for that before pushing the new one.
-    pub(crate) fn enqueue_publish_request(
+    pub(crate) fn enqueue_publish_request<P>(
         &mut self,
         now: &DateTimeUtc,
         request_id: u32,
         request: PublishRequest,
-        address_space: &AddressSpace,
-    ) -> Result<(), StatusCode> {
+        address_space: &P,
+    ) -> Result<(), StatusCode>
+    where
+        P: AddressSpaceProvider + ?Sized,
+    {
         // Check if we have too  requests waiting already
         let max_publish_requests = self.max_publish_requests();
         if self.publish_request_queue.len() >= max_publish_requests {
@@ -167,6 +177,91 @@ impl Subscriptions {
         }
     }
 
+    /// Cancels the queued publish requests which have the supplied request handle, answering each
+    /// with `BadRequestCancelledByClient`. Returns the number of requests that were cancelled.
+    pub(crate) fn cancel_publish_requests(&mut self, request_handle: u32) -> u32 {
+        let now = DateTime::now();
+        let mut cancelled_publish_responses = VecDeque::new();
+        self.publish_request_queue.retain(|request| {
+            let request_header = &request.request.request_header;
+            if request_header.request_handle == request_handle {
+                debug!("Publish request {} was cancelled", request_handle);
+                cancelled_publish_responses.push_front(PublishResponseEntry {
+                    request_id: request.request_id,
+                    response: ServiceFault {
+                        response_header: ResponseHeader::new_timestamped_service_result(
+                            now,
+                            request_header,
+                            StatusCode::BadRequestCancelledByClient,
+                        ),
+                    }
+                    .into(),
+                });
+                false
+            } else {
+                true
+            }
+        });
+        let cancel_count = cancelled_publish_responses.len() as u32;
+        self.publish_response_queue
+            .append(&mut cancelled_publish_responses);
+        cancel_count
+    }
+
+    /// Sends each subscription a final status change notification on one of the queued publish
+    /// requests and answers the remaining publish requests with `BadServerHalted`, because the
+    /// server is shutting down. The responses are queued to be sent with any other publish
+    /// responses.
+    pub(crate) fn shutdown(&mut self, now: &DateTimeUtc) {
+        let subscription_ids = self.subscriptions.keys().cloned().collect::<Vec<u32>>();
+        for subscription_id in subscription_ids {
+            let Some(publish_request) = self.publish_request_queue.pop_back() else {
+                break;
+            };
+            let notification_message = self
+                .subscriptions
+                .get_mut(&subscription_id)
+                .unwrap()
+                .shutdown_notification(now);
+            let response = self.make_publish_response(
+                publish_request,
+                subscription_id,
+                now,
+                notification_message,
+                false,
+                None,
+            );
+            self.publish_response_queue.push_back(response);
+        }
+
+        let now = DateTime::from(*now);
+        self.publish_request_queue
+            .drain(..)
+            .rev()
+            .for_each(|request| {
+                self.publish_response_queue.push_back(PublishResponseEntry {
+                    request_id: request.request_id,
+                    response: ServiceFault {
+                        response_header: ResponseHeader::new_timestamped_service_result(
+                            now,
+                            &request.request.request_header,
+                            StatusCode::BadServerHalted,
+                        ),
+                    }
+                    .into(),
+                });
+            });
+    }
+
+    /// Deletes every subscription and discards any queued publish requests and notifications.
+    pub(crate) fn clear(&mut self) {
+        self.subscriptions.clear();
+        self.publish_request_queue.clear();
+        self.publish_response_queue.clear();
+        self.transmission_queue.clear();
+        self.retransmission_queue.clear();
+    }
+
     /// Tests if there are no subscriptions/
     pub fn is_empty(&self) -> bool {
         self.subscriptions.is_empty()
@@ -191,8 +286,30 @@ impl Subscriptions {
         self.subscriptions.insert(subscription_id, subscription);
     }
 
+    /// Removes the subscription along with its monitored items and any of its notifications that
+    /// are waiting to be acknowledged. If it was the last subscription, any queued publish requests
+    /// are answered with `BadNoSubscription` since there is nothing left to publish for them.
     pub fn remove(&mut self, subscription_id: u32) -> Option<Subscription> {
-        self.subscriptions.remove(&subscription_id)
+        let subscription = self.subscriptions.remove(&subscription_id)?;
+        self.retransmission_queue
+            .retain(|&(id, _), _| id != subscription_id);
+        if self.subscriptions.is_empty() {
+            let now = DateTime::now();
+            while let Some(request) = self.publish_request_queue.pop_back() {
+                self.publish_response_queue.push_back(PublishResponseEntry {
+                    request_id: request.request_id,
+                    response: ServiceFault {
+                        response_header: ResponseHeader::new_timestamped_service_result(
+                            now,
+                            &request.request.request_header,
+                            StatusCode::BadNoSubscription,
+                        ),
+                    }
+                    .into(),
+                });
+            }
+        }
+        Some(subscription)
     }
 
     pub fn get_mut(&mut self, subscription_id: u32) -> Option<&mut Subscription> {
@@ -203,20 +320,30 @@ impl Subscriptions {
     /// on each in order of priority. In each case this could generate data change notifications. Data change
     /// notifications will be attached to the next available publish response and queued for sending
     /// to the client.
-    pub(crate) fn tick(
+    pub(crate) fn tick<P>(
         &mut self,
         now: &DateTimeUtc,
-        address_space: &AddressSpace,
+        address_space: &P,
         tick_reason: TickReason,
-    ) -> Result<(), StatusCode> {
+    ) -> Result<(), StatusCode>
+    where
+        P: AddressSpaceProvider + ?Sized,
+    {
         let subscription_ids = {
-            // Sort subscriptions by priority
-            let mut subscription_priority: Vec<(u32, u8)> = self
+            // Sort subscriptions so the highest priority is served first. Between equal
+            // priorities, one whose oldest notification has waited longest goes first.
+            let mut subscription_priority: Vec<(u32, u8, Option<DateTimeUtc>)> = self
                 .subscriptions
                 .values()
-                .map(|v| (v.subscription_id(), v.priority()))
+                .map(|v| {
+                    (
+                        v.subscription_id(),
+                        v.priority(),
+                        v.oldest_notification_time(),
+                    )
+                })
                 .collect();
-            subscription_priority.sort_by(|s1, s2| s1.1.cmp(&s2.1));
+            subscription_priority.sort_by_key(|s| (Reverse(s.1), s.2.is_none(), s.2, s.0));
             subscription_priority
                 .iter()
                 .map(|s| s.0)
@@ -255,14 +382,18 @@ impl Subscriptions {
                 }
             }
 
-            // Remove the subscription if it is done
+            // Remove the subscription if it is done, along with its notifications that are
+            // waiting to be acknowledged
             if subscription.ready_to_remove() {
                 self.subscriptions.remove(&subscription_id);
+                self.retransmission_queue
+                    .retain(|&(id, _), _| id != subscription_id);
             }
         }
 
         // Iterate through notifications from oldest to latest in the transmission making publish
         // responses.
+        let mut sent_subscription_ids = BTreeSet::new();
         while !self.transmission_queue.is_empty() {
             // Get the oldest notification to send
             let (subscription_id, publish_request, notification_message) =
@@ -275,6 +406,7 @@ impl Subscriptions {
             let available_sequence_numbers = self.available_sequence_numbers(subscription_id);
 
             // The notification to be sent is now put into the retransmission queue
+            sent_subscription_ids.insert(subscription_id);
             self.retransmission_queue.insert(
                 (subscription_id, notification_message.sequence_number),
                 notification_message.clone(),
@@ -293,7 +425,7 @@ impl Subscriptions {
         }
 
         // Clean up the retransmission queue
-        self.remove_old_unacknowledged_notifications();
+        self.remove_old_unacknowledged_notifications(&sent_subscription_ids);
 
         Ok(())
     }
@@ -380,13 +512,17 @@ impl Subscriptions {
         }
     }
 
-    /// Searches the transmission queue to see if there are more notifications for the specified
-    /// subscription id
+    /// Searches the transmission queue and the subscription to see if there are more notifications
+    /// for the specified subscription id
     fn more_notifications(&self, subscription_id: u32) -> bool {
         // At least one match means more notifications
         self.transmission_queue
             .iter()
             .any(|v| v.0 == subscription_id)
+            || self
+                .subscriptions
+                .get(&subscription_id)
+                .is_some_and(|subscription| subscription.has_notifications())
     }
 
     /// Returns the array of available sequence numbers in the retransmission queue for the specified subscription
@@ -473,28 +609,43 @@ impl Subscriptions {
         });
     }
 
-    /// Purges notifications waiting for acknowledgement if they are stale or the max permissible
-    /// is exceeded.
-    fn remove_old_unacknowledged_notifications(&mut self) {
-        // Strip out notifications for subscriptions that no longer exist
-        let sequence_nrs_to_remove = self
-            .retransmission_queue
+    /// Purges notifications waiting for acknowledgement if the max permissible is exceeded. Each
+    /// subscription retains up to its lifetime count of sent notifications so a client can ask
+    /// for them to be republished, and the session retains no more than
+    /// `MAX_RETRANSMISSION_QUEUE_SIZE` in total. Only the subscriptions which have just sent
+    /// notifications can be over their own limit, so only they are checked.
+    fn remove_old_unacknowledged_notifications(&mut self, sent_subscription_ids: &BTreeSet<u32>) {
+        let sequence_nrs_to_remove = sent_subscription_ids
             .iter()
-            .filter(|(k, _)| !self.subscriptions.contains_key(&k.0))
-            .map(|(k, _)| *k)
+            .filter_map(|subscription_id| {
+                self.subscriptions
+                    .get(subscription_id)
+                    .map(|subscription| (*subscription_id, subscription))
+            })
+            .flat_map(|(subscription_id, subscription)| {
+                let retained = (subscription_id, 0)..=(subscription_id, u32::MAX);
+                let queued = self.retransmission_queue.range(retained.clone()).count();
+                let max_retained = subscription.max_lifetime_count().max(1) as usize;
+                self.retransmission_queue
+                    .range(retained)
+                    .take(queued.saturating_sub(max_retained))
+                    .map(|(k, _)| *k)
+            })
             .collect::<Vec<_>>();
         self.remove_notifications(&sequence_nrs_to_remove);
 
-        // Compare number of items in retransmission queue to max permissible and remove the older
-        // notifications.
-        let max_retransmission_queue = self.max_publish_requests() * 2;
-        if self.retransmission_queue.len() > max_retransmission_queue {
-            let remove_count = self.retransmission_queue.len() - max_retransmission_queue;
-            let sequence_nrs_to_remove = self
+        // The oldest notifications of any subscription go first when the session is over its limit
+        if self.retransmission_queue.len() > constants::MAX_RETRANSMISSION_QUEUE_SIZE {
+            let mut queued = self
                 .retransmission_queue
                 .iter()
-                .take(remove_count)
-                .map(|(k, _)| *k)
+                .map(|(k, notification)| (notification.publish_time.ticks(), *k))
+                .collect::<Vec<_>>();
+            queued.sort_unstable();
+            let sequence_nrs_to_remove = queued
+                .iter()
+                .take(self.retransmission_queue.len() - constants::MAX_RETRANSMISSION_QUEUE_SIZE)
+                .map(|(_, k)| *k)
                 .collect::<Vec<_>>();
             self.remove_notifications(&sequence_nrs_to_remove);
         }
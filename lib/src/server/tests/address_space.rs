@@ -494,16 +494,17 @@ fn browse_nodes() {
     // Test that a node can be found
     let object_id = ObjectId::RootFolder.into();
     let result = find_node_from_browse_path(
-        &address_space,
+        &*address_space,
         &object_id,
         &vec!["Objects".into(), "Sample".into(), "v1".into()],
     );
-    let node = result.unwrap();
+    let node_id = result.unwrap();
+    let node = address_space.find_node(&node_id).unwrap();
     assert_eq!(node.as_node().browse_name(), QualifiedName::from("v1"));
 
     // Test that a non existent node cannot be found
     let result = find_node_from_browse_path(
-        &address_space,
+        &*address_space,
         &object_id,
         &vec!["Objects".into(), "Sample".into(), "vxxx".into()],
     );
@@ -522,7 +523,7 @@ fn find_nodes_relative_path() {
     let relative_path = "/Objects/Server.ServerStatus.BuildInfo.ProductName";
 
     let results =
-        find_nodes_relative_path_simple(&address_space, &parent_node, relative_path).unwrap();
+        find_nodes_relative_path_simple(&*address_space, &parent_node, relative_path).unwrap();
     assert_eq!(results.len(), 1);
     assert_eq!(
         results[0],
@@ -3,16 +3,24 @@ use std::collections::HashSet;
 use crate::types::{
     node_ids::ReferenceTypeId,
     operand::{ContentFilterBuilder, Operand},
-    service_types::ContentFilterElement,
-    AttributeId, DataTypeId, LocalizedText, NodeId, ObjectId, ObjectTypeId, QualifiedName,
-    UAString, VariableTypeId, Variant,
+    service_types::{
+        ContentFilterElement, EventFilter, MonitoredItemCreateRequest, MonitoringParameters,
+        ReadValueId, SimpleAttributeOperand,
+    },
+    status_code::StatusCode,
+    AttributeId, DataTypeId, ExtensionObject, LocalizedText, MonitoringMode, NodeId, ObjectId,
+    ObjectTypeId, QualifiedName, TimestampsToReturn, UAString, VariableTypeId, Variant,
 };
 
 use crate::server::{
-    address_space::{object_type::ObjectTypeBuilder, variable::VariableBuilder, AddressSpace},
+    address_space::{
+        object::ObjectBuilder, object_type::ObjectTypeBuilder, variable::VariableBuilder,
+        AddressSpace, EventNotifier,
+    },
     events::event::{BaseEventType, Event},
     events::event_filter,
     events::operator,
+    subscriptions::monitored_item::{MonitoredItem, Notification, TickResult},
     tests::*,
 };
 
@@ -788,6 +796,43 @@ fn test_bitwise_and() {
     })
 }
 
+#[test]
+fn test_of_type() {
+    do_operator_test(|address_space, object_id, used_elements, elements| {
+        // The event's own type and the types it is a subtype of match
+        [
+            (TestEventType::event_type_id(), true),
+            (ObjectTypeId::BaseEventType.into(), true),
+            (ObjectTypeId::AuditEventType.into(), false),
+            (ObjectTypeId::FolderType.into(), false),
+        ]
+        .into_iter()
+        .for_each(|(type_id, expected): (NodeId, bool)| {
+            let operands = &[Operand::literal(type_id)];
+            let result = operator::of_type(
+                object_id,
+                &operands[..],
+                used_elements,
+                elements,
+                address_space,
+            )
+            .unwrap();
+            assert_eq!(result, Variant::Boolean(expected));
+        });
+
+        // The operand must be a node id
+        let operands = &[Operand::literal(10)];
+        let result = operator::of_type(
+            object_id,
+            &operands[..],
+            used_elements,
+            elements,
+            address_space,
+        );
+        assert_eq!(result, Err(StatusCode::BadFilterOperandInvalid));
+    })
+}
+
 #[test]
 fn test_where_clause() {
     crate::console_logging::init();
@@ -830,6 +875,32 @@ fn test_where_clause() {
     let result = event_filter::evaluate_where_clause(&object_id, &f, &address_space);
     assert_eq!(result.unwrap(), true.into());
 
+    // OfType(BaseEventType) && Foo > 50 passes the event, but not with a different type or value
+    [
+        (ObjectTypeId::BaseEventType, 50, true),
+        (ObjectTypeId::AuditEventType, 50, false),
+        (ObjectTypeId::BaseEventType, 100, false),
+    ]
+    .into_iter()
+    .for_each(|(type_id, value_to_compare, expected)| {
+        let type_id: NodeId = type_id.into();
+        let f = ContentFilterBuilder::new()
+            .and(Operand::element(1), Operand::element(2))
+            .of_type(Operand::literal(type_id))
+            .gt(
+                Operand::simple_attribute(
+                    ReferenceTypeId::Organizes,
+                    "Foo",
+                    AttributeId::Value,
+                    UAString::null(),
+                ),
+                Operand::literal(value_to_compare),
+            )
+            .build();
+        let result = event_filter::evaluate_where_clause(&event_id(), &f, &address_space);
+        assert_eq!(result.unwrap(), expected.into());
+    });
+
     // Do some relative path comparisons against the event to ensure content filters appear to work
     let expected = vec![
         // Valid
@@ -863,3 +934,111 @@ fn test_where_clause() {
             assert_eq!(result.unwrap(), expected.into());
         });
 }
+
+#[test]
+fn test_monitored_item_where_clause() {
+    crate::console_logging::init();
+
+    let mut address_space = address_space();
+
+    // An object that the events are raised on
+    let machine_id = NodeId::new(2, "Machine");
+    ObjectBuilder::new(&machine_id, "Machine", "Machine")
+        .organized_by(ObjectId::ObjectsFolder)
+        .event_notifier(EventNotifier::SUBSCRIBE_TO_EVENTS)
+        .insert(&mut address_space);
+
+    // Monitor the object for events whose Foo is greater than 50, selecting their Foo
+    let foo = || {
+        SimpleAttributeOperand::new(
+            TestEventType::event_type_id(),
+            "Foo",
+            AttributeId::Value,
+            UAString::null(),
+        )
+    };
+    let filter = EventFilter {
+        select_clauses: Some(vec![foo()]),
+        where_clause: ContentFilterBuilder::new()
+            .gt(Operand::from(foo()), Operand::literal(50))
+            .build(),
+    };
+    let request = MonitoredItemCreateRequest {
+        item_to_monitor: ReadValueId {
+            node_id: machine_id.clone(),
+            attribute_id: AttributeId::EventNotifier as u32,
+            index_range: UAString::null(),
+            data_encoding: QualifiedName::null(),
+        },
+        monitoring_mode: MonitoringMode::Reporting,
+        requested_parameters: MonitoringParameters {
+            client_handle: 999,
+            sampling_interval: -1f64,
+            filter: ExtensionObject::from_encodable(
+                ObjectId::EventFilter_Encoding_DefaultBinary,
+                &filter,
+            ),
+            queue_size: 10,
+            discard_oldest: true,
+        },
+    };
+    let server = ServerBuilder::new_sample().server().unwrap();
+    let server_state = server.server_state();
+    let mut monitored_item = {
+        let server_state = trace_read_lock!(server_state);
+        MonitoredItem::new(
+            &(chrono::Utc::now() - chrono::Duration::seconds(1)),
+            1,
+            TimestampsToReturn::Both,
+            &server_state,
+            &request,
+        )
+        .unwrap()
+    };
+    assert!(monitored_item.validate_filter(&address_space).is_ok());
+
+    // Fire events on the object, and a matching one on another object
+    [(1, 10), (2, 60), (3, 40), (4, 90)]
+        .into_iter()
+        .for_each(|(i, foo)| {
+            create_event(
+                &mut address_space,
+                NodeId::new(2, i.to_string()),
+                &machine_id,
+                foo,
+            )
+        });
+    create_event(
+        &mut address_space,
+        NodeId::new(2, "5"),
+        &ObjectId::Server.into(),
+        70,
+    );
+
+    // Only the events on the object that pass the where clause are delivered
+    assert_eq!(
+        monitored_item.tick(&chrono::Utc::now(), &address_space, true, false),
+        TickResult::ReportValueChanged
+    );
+    let mut delivered = Vec::new();
+    while let Some(notification) = monitored_item.oldest_notification_message() {
+        let Notification::Event(event) = notification else {
+            panic!("Expected an event notification");
+        };
+        assert_eq!(event.client_handle, 999);
+        delivered.extend(event.event_fields.unwrap());
+    }
+    delivered.sort_by_key(|foo| match foo {
+        Variant::Int32(foo) => *foo,
+        _ => panic!("Expected Foo to be an Int32, got {:?}", foo),
+    });
+    assert_eq!(delivered, vec![Variant::Int32(60), Variant::Int32(90)]);
+
+    // An event that does not pass is not delivered
+    create_event(&mut address_space, NodeId::new(2, "6"), &machine_id, 20);
+    assert_eq!(
+        monitored_item.tick(&chrono::Utc::now(), &address_space, true, false),
+        TickResult::NoChange
+    );
+    assert!(monitored_item.notification_queue().is_empty());
+}
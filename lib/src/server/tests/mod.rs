@@ -3,7 +3,10 @@ use std::{path::PathBuf, sync::Arc};
 use chrono;
 
 use crate::{
-    core::{config::Config, supported_message::SupportedMessage},
+    core::{
+        config::{Config, ConfigError},
+        supported_message::SupportedMessage,
+    },
     server::{
         address_space::{address_space::*, variable::*},
         builder::ServerBuilder,
@@ -74,13 +77,81 @@ pub fn server_config_save() {
     let path = make_test_file("server_config.yaml");
     let config = ServerBuilder::new_anonymous("foo").config();
     assert!(config.save(&path).is_ok());
-    if let Ok(config2) = ServerConfig::load(&path) {
+    if let Ok(config2) = ServerConfig::try_load(&path) {
         assert_eq!(config, config2);
     } else {
         panic!("Cannot load config from file");
     }
 }
 
+#[test]
+pub fn server_config_load_errors() {
+    // Missing file
+    let path = make_test_file("server_config_missing.yaml");
+    let _ = std::fs::remove_file(&path);
+    assert!(matches!(
+        ServerConfig::try_load::<ServerConfig>(&path),
+        Err(ConfigError::Io(_))
+    ));
+    #[allow(deprecated)]
+    let result = ServerConfig::load::<ServerConfig>(&path);
+    assert_eq!(result, Err(()));
+
+    // Malformed YAML
+    let path = make_test_file("server_config_malformed.yaml");
+    std::fs::write(&path, "application_name: [foo").unwrap();
+    assert!(matches!(
+        ServerConfig::try_load::<ServerConfig>(&path),
+        Err(ConfigError::Parse(_))
+    ));
+
+    // A misspelled field
+    let path = make_test_file("server_config_unknown_field.yaml");
+    let config = ServerBuilder::new_anonymous("foo").config();
+    let yaml = serde_yaml::to_string(&config)
+        .unwrap()
+        .replace("hello_timeout:", "hello_timout:");
+    std::fs::write(&path, yaml).unwrap();
+    let err = ServerConfig::try_load::<ServerConfig>(&path).unwrap_err();
+    assert!(matches!(err, ConfigError::Parse(_)));
+    let message = err.to_string();
+    assert!(message.contains("tcp_config: unknown field `hello_timout`"));
+    assert!(message.contains(" at line "));
+
+    // Well formed but invalid config, written directly since save() refuses to write it
+    let path = make_test_file("server_config_invalid.yaml");
+    let mut config = ServerBuilder::new_anonymous("foo").config();
+    config.endpoints.clear();
+    std::fs::write(&path, serde_yaml::to_string(&config).unwrap()).unwrap();
+    match ServerConfig::try_load::<ServerConfig>(&path) {
+        Err(ConfigError::Invalid(errors)) => assert_eq!(
+            errors,
+            vec!["Server configuration is invalid. It defines no endpoints".to_string()]
+        ),
+        result => panic!("Expected an invalid config error, got {:?}", result.err()),
+    }
+}
+
+#[test]
+pub fn server_config_load_without_max_sessions() {
+    // Configs written before max_sessions existed still load, with the default limit
+    let path = make_test_file("server_config_no_max_sessions.yaml");
+    let mut config = ServerBuilder::new_anonymous("foo").config();
+    config.limits.max_sessions = 5;
+    let yaml = serde_yaml::to_string(&config)
+        .unwrap()
+        .lines()
+        .filter(|line| !line.contains("max_sessions:"))
+        .collect::<Vec<&str>>()
+        .join("\n");
+    std::fs::write(&path, yaml).unwrap();
+    let config = ServerConfig::try_load::<ServerConfig>(&path).unwrap();
+    assert_eq!(
+        config.limits.max_sessions,
+        crate::server::constants::DEFAULT_MAX_SESSIONS
+    );
+}
+
 #[test]
 pub fn server_config_invalid() {
     // Remove the endpoint
@@ -98,6 +169,74 @@ pub fn server_config_invalid() {
         .user_token_ids
         .insert("hello".to_string());
     assert_eq!(config.is_valid(), false);
+    assert_eq!(
+        config.validate(),
+        vec!["Cannot find user token with id hello".to_string()]
+    );
+
+    // Every problem is reported, not just the first
+    config.limits.max_array_length = 0;
+    config.discovery_urls.clear();
+    assert_eq!(
+        config.validate(),
+        vec![
+            "Cannot find user token with id hello".to_string(),
+            "Server configuration is invalid. Max array length is invalid".to_string(),
+            "Server configuration is invalid. Discovery urls not set".to_string(),
+        ]
+    );
+
+    // Discovery urls must be opc.tcp urls
+    config = ServerBuilder::new_anonymous("foo").config();
+    config.discovery_urls = vec![
+        "opc.tcp://localhost:4855/".to_string(),
+        "http://localhost:4855/".to_string(),
+        "localhost".to_string(),
+    ];
+    assert_eq!(
+        config.validate(),
+        vec![
+            "Server configuration is invalid. Discovery url http://localhost:4855/ is not an opc.tcp url".to_string(),
+            "Server configuration is invalid. Discovery url localhost is not an opc.tcp url".to_string(),
+        ]
+    );
+}
+
+#[test]
+pub fn config_only_implementing_is_valid() {
+    // An implementation written before validate() existed still works
+    #[derive(Serialize)]
+    struct LegacyConfig {
+        valid: bool,
+    }
+
+    impl Config for LegacyConfig {
+        fn is_valid(&self) -> bool {
+            self.valid
+        }
+
+        fn application_name(&self) -> UAString {
+            UAString::from("legacy")
+        }
+
+        fn application_uri(&self) -> UAString {
+            UAString::from("urn:legacy")
+        }
+
+        fn product_uri(&self) -> UAString {
+            UAString::null()
+        }
+
+        fn application_type(&self) -> ApplicationType {
+            ApplicationType::Server
+        }
+    }
+
+    assert!(LegacyConfig { valid: true }.validate().is_empty());
+    assert_eq!(
+        LegacyConfig { valid: false }.validate(),
+        vec!["Configuration is not valid".to_string()]
+    );
 }
 
 #[test]
@@ -1,8 +1,16 @@
+use std::sync::atomic::Ordering;
+
 use chrono::Duration;
 
-use crate::server::{address_space::AccessLevel, services::attribute::AttributeService};
+use crate::server::{
+    address_space::{AccessLevel, UserAccessLevel},
+    constants,
+    historical::values_at_times,
+    services::attribute::AttributeService,
+};
 use crate::supported_message_as;
 use crate::sync::*;
+use crate::types::service_types::ServerState as ServerStateType;
 use crate::types::{Variant, WriteMask};
 
 use super::*;
@@ -162,7 +170,299 @@ fn read() {
 
         // distinguish between read and user read
         // test max_age
-        // test timestamps to return Server, Source, None, Both
+    });
+}
+
+#[test]
+fn read_index_range() {
+    do_attribute_service_test(|server_state, session, address_space, ats| {
+        // A variable holding a 5 element array
+        let node_id = NodeId::next_numeric(2);
+        {
+            let mut address_space = trace_write_lock!(address_space);
+            let _ = VariableBuilder::new(&node_id, "array", "")
+                .data_type(DataTypeId::Int32)
+                .value_rank(1)
+                .value(vec![10i32, 11, 12, 13, 14])
+                .organized_by(ObjectId::RootFolder)
+                .insert(&mut address_space);
+        }
+
+        let nodes_to_read = vec![
+            // 1. a slice of the array
+            read_value_range(&node_id, AttributeId::Value, UAString::from("1:3")),
+            // 2. a malformed range
+            read_value_range(&node_id, AttributeId::Value, UAString::from("3:1")),
+            // 3. a range entirely out of bounds
+            read_value_range(&node_id, AttributeId::Value, UAString::from("5:8")),
+        ];
+        let request = ReadRequest {
+            request_header: make_request_header(),
+            max_age: 0f64,
+            timestamps_to_return: TimestampsToReturn::Both,
+            nodes_to_read: Some(nodes_to_read),
+        };
+
+        let response = ats.read(server_state, session, address_space, &request);
+        let response: ReadResponse = supported_message_as!(response, ReadResponse);
+        let results = response.results.unwrap();
+
+        // 1. Slice
+        assert!(results[0].status.is_none() || results[0].status.unwrap().is_good());
+        assert_eq!(
+            results[0].value.as_ref().unwrap(),
+            &Variant::from(vec![11i32, 12, 13])
+        );
+
+        // 2. Malformed
+        assert_eq!(
+            results[1].status.as_ref().unwrap(),
+            &StatusCode::BadIndexRangeInvalid
+        );
+        assert!(results[1].value.is_none());
+
+        // 3. Out of bounds
+        assert_eq!(
+            results[2].status.as_ref().unwrap(),
+            &StatusCode::BadIndexRangeNoData
+        );
+        assert!(results[2].value.is_none());
+    });
+}
+
+#[test]
+fn read_data_encoding() {
+    do_attribute_service_test(|server_state, session, address_space, ats| {
+        let build_info: NodeId = VariableId::Server_ServerStatus_BuildInfo.into();
+        let start_time: NodeId = VariableId::Server_ServerStatus_StartTime.into();
+        let nodes_to_read = vec![
+            // 1. a structured value in binary
+            read_value_encoding(
+                &build_info,
+                AttributeId::Value,
+                QualifiedName::new(0, "Default Binary"),
+            ),
+            // 2. a structured value in encodings the server doesn't support
+            read_value_encoding(
+                &build_info,
+                AttributeId::Value,
+                QualifiedName::new(0, "Default XML"),
+            ),
+            read_value_encoding(
+                &build_info,
+                AttributeId::Value,
+                QualifiedName::new(0, "Default JSON"),
+            ),
+            // 3. a value that isn't structured
+            read_value_encoding(
+                &start_time,
+                AttributeId::Value,
+                QualifiedName::new(0, "Default Binary"),
+            ),
+            // 4. an attribute other than the value
+            read_value_encoding(
+                &build_info,
+                AttributeId::DataType,
+                QualifiedName::new(0, "Default Binary"),
+            ),
+        ];
+        let request = ReadRequest {
+            request_header: make_request_header(),
+            max_age: 0f64,
+            timestamps_to_return: TimestampsToReturn::Both,
+            nodes_to_read: Some(nodes_to_read),
+        };
+        let response = ats.read(server_state, session, address_space, &request);
+        let results = supported_message_as!(response, ReadResponse)
+            .results
+            .unwrap();
+
+        // 1. The build info is returned as a binary encoded extension object
+        assert_eq!(results[0].status.as_ref().unwrap(), &StatusCode::Good);
+        match results[0].value.as_ref().unwrap() {
+            Variant::ExtensionObject(extension_object) => {
+                let build_info = extension_object
+                    .decode_inner::<BuildInfo>(&DecodingOptions::test())
+                    .unwrap();
+                assert_eq!(
+                    build_info.software_version.as_ref(),
+                    constants::BUILD_INFO_SOFTWARE_VERSION
+                );
+                assert_eq!(
+                    build_info.build_number.as_ref(),
+                    constants::BUILD_INFO_BUILD_NUMBER
+                );
+            }
+            value => panic!("Expected an extension object, got {:?}", value),
+        }
+
+        // 2. XML and JSON are not supported
+        assert_eq!(
+            results[1].status.as_ref().unwrap(),
+            &StatusCode::BadDataEncodingUnsupported
+        );
+        assert_eq!(
+            results[2].status.as_ref().unwrap(),
+            &StatusCode::BadDataEncodingUnsupported
+        );
+        assert!(results[1].value.is_none());
+
+        // 3. and 4. An encoding can't be asked for
+        assert_eq!(
+            results[3].status.as_ref().unwrap(),
+            &StatusCode::BadDataEncodingInvalid
+        );
+        assert_eq!(
+            results[4].status.as_ref().unwrap(),
+            &StatusCode::BadDataEncodingInvalid
+        );
+    });
+}
+
+#[test]
+fn read_server_status() {
+    do_attribute_service_test(|server_state, session, address_space, ats| {
+        let read_values = |server_state: Arc<RwLock<ServerState>>, node_ids: &[VariableId]| {
+            let request = ReadRequest {
+                request_header: make_request_header(),
+                max_age: 0f64,
+                timestamps_to_return: TimestampsToReturn::Both,
+                nodes_to_read: Some(
+                    node_ids
+                        .iter()
+                        .map(|node_id| read_value(&(*node_id).into(), AttributeId::Value))
+                        .collect(),
+                ),
+            };
+            let response = ats.read(
+                server_state,
+                session.clone(),
+                address_space.clone(),
+                &request,
+            );
+            let response: ReadResponse = supported_message_as!(response, ReadResponse);
+            response
+                .results
+                .unwrap()
+                .into_iter()
+                .map(|v| v.value.unwrap())
+                .collect::<Vec<Variant>>()
+        };
+        let node_ids = [
+            VariableId::Server_ServerStatus_State,
+            VariableId::Server_ServerDiagnostics_ServerDiagnosticsSummary_CurrentSessionCount,
+            VariableId::Server_ServerStatus_StartTime,
+            VariableId::Server_ServerStatus_BuildInfo_SoftwareVersion,
+        ];
+
+        // The state follows the server
+        {
+            let mut server_state = trace_write_lock!(server_state);
+            server_state.set_state(ServerStateType::Running);
+        }
+        let values = read_values(server_state.clone(), &node_ids);
+        assert_eq!(values[0], Variant::Int32(ServerStateType::Running as i32));
+        // The test session is open
+        assert_eq!(values[1], Variant::UInt32(1));
+        assert_eq!(
+            values[2],
+            Variant::from(trace_read_lock!(server_state).start_time)
+        );
+        assert_eq!(
+            values[3],
+            Variant::from(constants::BUILD_INFO_SOFTWARE_VERSION)
+        );
+
+        // Opening another session is counted, and the state changes are seen
+        let _session = Session::new(server_state.clone());
+        {
+            let mut server_state = trace_write_lock!(server_state);
+            server_state.set_state(ServerStateType::Suspended);
+        }
+        let values = read_values(server_state.clone(), &node_ids);
+        assert_eq!(values[0], Variant::Int32(ServerStateType::Suspended as i32));
+        assert_eq!(values[1], Variant::UInt32(2));
+    });
+}
+
+#[test]
+fn read_value_and_display_name() {
+    do_attribute_service_test(|server_state, session, address_space, ats| {
+        let node_ids = node_ids(address_space.clone());
+        let server_node_id: NodeId = ObjectId::Server.into();
+
+        let nodes_to_read = vec![
+            read_value(&node_ids[5], AttributeId::Value),
+            read_value(&node_ids[5], AttributeId::DisplayName),
+            read_value(&server_node_id, AttributeId::DisplayName),
+        ];
+        let request = ReadRequest {
+            request_header: make_request_header(),
+            max_age: 0f64,
+            timestamps_to_return: TimestampsToReturn::Both,
+            nodes_to_read: Some(nodes_to_read),
+        };
+
+        let response = ats.read(server_state, session, address_space, &request);
+        let response: ReadResponse = supported_message_as!(response, ReadResponse);
+        let results = response.results.unwrap();
+        assert_eq!(results.len(), 3);
+
+        assert_eq!(results[0].status.as_ref().unwrap(), &StatusCode::Good);
+        assert_eq!(results[0].value.as_ref().unwrap(), &Variant::Int32(5));
+
+        assert_eq!(
+            results[1].value.as_ref().unwrap(),
+            &Variant::from(LocalizedText::new("", ""))
+        );
+
+        assert_eq!(
+            results[2].value.as_ref().unwrap(),
+            &Variant::from(LocalizedText::new("", "Server"))
+        );
+        // Timestamps are only returned for the Value attribute
+        assert!(results[2].source_timestamp.is_none());
+        assert!(results[2].server_timestamp.is_none());
+    });
+}
+
+#[test]
+fn read_timestamps_to_return() {
+    do_attribute_service_test(|server_state, session, address_space, ats| {
+        let node_ids = node_ids(address_space.clone());
+
+        let read = |timestamps_to_return| {
+            let request = ReadRequest {
+                request_header: make_request_header(),
+                max_age: 0f64,
+                timestamps_to_return,
+                nodes_to_read: Some(vec![read_value(&node_ids[0], AttributeId::Value)]),
+            };
+            let response = ats.read(
+                server_state.clone(),
+                session.clone(),
+                address_space.clone(),
+                &request,
+            );
+            let response: ReadResponse = supported_message_as!(response, ReadResponse);
+            response.results.unwrap().remove(0)
+        };
+
+        let result = read(TimestampsToReturn::Source);
+        assert!(result.source_timestamp.is_some());
+        assert!(result.server_timestamp.is_none());
+
+        let result = read(TimestampsToReturn::Server);
+        assert!(result.source_timestamp.is_none());
+        assert!(result.server_timestamp.is_some());
+
+        let result = read(TimestampsToReturn::Neither);
+        assert!(result.source_timestamp.is_none());
+        assert!(result.server_timestamp.is_none());
+
+        let result = read(TimestampsToReturn::Both);
+        assert!(result.source_timestamp.is_some());
+        assert!(result.server_timestamp.is_some());
     });
 }
 
@@ -400,6 +700,52 @@ fn write() {
     });
 }
 
+#[test]
+fn write_then_read() {
+    // Test that a scalar write is committed to the address space and is visible to a read
+    do_attribute_service_test(|server_state, session, address_space, ats| {
+        let node_id = NodeId::next_numeric(2);
+        {
+            let mut address_space = trace_write_lock!(address_space);
+            let _ = VariableBuilder::new(&node_id, var_name(0), "")
+                .data_type(DataTypeId::Int32)
+                .value(0i32)
+                .organized_by(ObjectId::RootFolder)
+                .writable()
+                .insert(&mut address_space);
+        }
+
+        let nodes_to_write = vec![
+            write_value(&node_id, AttributeId::Value, DataValue::new_now(1234i32)),
+            // A value of the wrong type
+            write_value(&node_id, AttributeId::Value, DataValue::new_now("xyz")),
+        ];
+        let response = write_request(
+            server_state.clone(),
+            session.clone(),
+            address_space.clone(),
+            ats,
+            nodes_to_write,
+        );
+        let results = response.results.unwrap();
+        assert_eq!(results[0], StatusCode::Good);
+        assert_eq!(results[1], StatusCode::BadTypeMismatch);
+
+        // Read the value back
+        let request = ReadRequest {
+            request_header: make_request_header(),
+            max_age: 0f64,
+            timestamps_to_return: TimestampsToReturn::Both,
+            nodes_to_read: Some(vec![read_value(&node_id, AttributeId::Value)]),
+        };
+        let response = ats.read(server_state, session, address_space, &request);
+        let response: ReadResponse = supported_message_as!(response, ReadResponse);
+        let results = response.results.unwrap();
+        assert_eq!(results[0].status.as_ref().unwrap(), &StatusCode::Good);
+        assert_eq!(results[0].value.as_ref().unwrap(), &Variant::Int32(1234));
+    });
+}
+
 #[test]
 fn write_bytestring_to_byte_array() {
     // This test checks that writing a byte string to a byte array variable works
@@ -553,6 +899,113 @@ fn write_index_range() {
     });
 }
 
+#[test]
+fn write_array_and_matrix() {
+    // Test that arrays and matrices are written when they fit the variable's dimensions
+    do_attribute_service_test(|server_state, session, address_space, ats| {
+        let matrix = |values: Vec<i32>, dimensions: Vec<u32>| {
+            let values = values.into_iter().map(Variant::from).collect::<Vec<_>>();
+            Variant::from(Array::new_multi(VariantTypeId::Int32, values, dimensions).unwrap())
+        };
+
+        // An array of 4, a 2x2 matrix and a scalar
+        let array_node_id = NodeId::next_numeric(2);
+        let matrix_node_id = NodeId::next_numeric(2);
+        let scalar_node_id = NodeId::next_numeric(2);
+        {
+            let mut address_space = trace_write_lock!(address_space);
+            let _ = VariableBuilder::new(&array_node_id, var_name(0), "")
+                .data_type(DataTypeId::Int32)
+                .value_rank(1)
+                .array_dimensions(&[4])
+                .value(vec![0i32; 4])
+                .organized_by(ObjectId::RootFolder)
+                .writable()
+                .insert(&mut address_space);
+            let _ = VariableBuilder::new(&matrix_node_id, var_name(1), "")
+                .data_type(DataTypeId::Int32)
+                .value_rank(2)
+                .array_dimensions(&[2, 2])
+                .value(matrix(vec![0; 4], vec![2, 2]))
+                .organized_by(ObjectId::RootFolder)
+                .writable()
+                .insert(&mut address_space);
+            let _ = VariableBuilder::new(&scalar_node_id, var_name(2), "")
+                .data_type(DataTypeId::Int32)
+                .value(0i32)
+                .organized_by(ObjectId::RootFolder)
+                .writable()
+                .insert(&mut address_space);
+        }
+
+        let write = |node_id: &NodeId, index_range: &str, value: Variant| {
+            let response = write_request(
+                server_state.clone(),
+                session.clone(),
+                address_space.clone(),
+                ats,
+                vec![write_value_index_range(
+                    node_id,
+                    AttributeId::Value,
+                    index_range,
+                    DataValue::new_now(value),
+                )],
+            );
+            response.results.unwrap()[0]
+        };
+
+        // A full array, then a partial write into indices 1:2
+        assert_eq!(
+            write(&array_node_id, "", Variant::from(vec![1i32, 2, 3, 4])),
+            StatusCode::Good
+        );
+        assert_eq!(
+            write(&array_node_id, "1:2", Variant::from(vec![20i32, 30])),
+            StatusCode::Good
+        );
+        validate_variable_value(address_space.clone(), &array_node_id, |value| {
+            assert_eq!(*value, Variant::from(vec![1i32, 20, 30, 4]));
+        });
+
+        // A partial write beyond the end of the array, and an array longer than the variable's
+        assert_eq!(
+            write(&array_node_id, "4:5", Variant::from(vec![50i32, 60])),
+            StatusCode::BadIndexRangeNoData
+        );
+        assert_eq!(
+            write(&array_node_id, "", Variant::from(vec![1i32, 2, 3, 4, 5])),
+            StatusCode::BadTypeMismatch
+        );
+
+        // A matrix of the same dimensions is written, but not one that is larger or of another rank
+        assert_eq!(
+            write(&matrix_node_id, "", matrix(vec![1, 2, 3, 4], vec![2, 2])),
+            StatusCode::Good
+        );
+        validate_variable_value(address_space.clone(), &matrix_node_id, |value| {
+            assert_eq!(*value, matrix(vec![1, 2, 3, 4], vec![2, 2]));
+        });
+        assert_eq!(
+            write(
+                &matrix_node_id,
+                "",
+                matrix(vec![1, 2, 3, 4, 5, 6], vec![3, 2])
+            ),
+            StatusCode::BadTypeMismatch
+        );
+        assert_eq!(
+            write(&matrix_node_id, "", Variant::from(vec![1i32, 2, 3, 4])),
+            StatusCode::BadTypeMismatch
+        );
+
+        // A scalar variable does not take an array
+        assert_eq!(
+            write(&scalar_node_id, "", Variant::from(vec![1i32])),
+            StatusCode::BadTypeMismatch
+        );
+    });
+}
+
 // #[test] fn write_null_value() { /* Write an empty variant to a value and see that it is allowed */}
 
 struct DataProvider;
@@ -560,7 +1013,7 @@ struct DataProvider;
 impl HistoricalDataProvider for DataProvider {
     fn read_raw_modified_details(
         &self,
-        _address_space: Arc<RwLock<AddressSpace>>,
+        _address_space: Arc<RwLock<dyn AddressSpaceProvider>>,
         _request: ReadRawModifiedDetails,
         _timestamps_to_return: TimestampsToReturn,
         _release_continuation_points: bool,
@@ -572,7 +1025,7 @@ impl HistoricalDataProvider for DataProvider {
 
     fn delete_raw_modified_details(
         &self,
-        _address_space: Arc<RwLock<AddressSpace>>,
+        _address_space: Arc<RwLock<dyn AddressSpaceProvider>>,
         _request: DeleteRawModifiedDetails,
     ) -> Result<Vec<StatusCode>, StatusCode> {
         info!("DataProvider's delete_raw_modified_details");
@@ -695,6 +1148,382 @@ fn history_read_nothing_data_provider() {
     });
 }
 
+/// A data provider holding an in-memory series of values for a single node, one per minute
+/// from its start time. Continuation points hold the index of the next value to return.
+struct SeriesProvider {
+    node_id: NodeId,
+    start_time: chrono::DateTime<chrono::Utc>,
+    len: usize,
+}
+
+impl SeriesProvider {
+    fn value(&self, i: usize) -> DataValue {
+        let timestamp = DateTime::from(self.start_time + Duration::minutes(i as i64));
+        DataValue {
+            value: Some(Variant::Int32(i as i32)),
+            status: Some(StatusCode::Good),
+            source_timestamp: Some(timestamp),
+            source_picoseconds: None,
+            server_timestamp: Some(timestamp),
+            server_picoseconds: None,
+        }
+    }
+}
+
+impl HistoricalDataProvider for SeriesProvider {
+    fn read_raw_modified_details(
+        &self,
+        _address_space: Arc<RwLock<dyn AddressSpaceProvider>>,
+        request: ReadRawModifiedDetails,
+        _timestamps_to_return: TimestampsToReturn,
+        release_continuation_points: bool,
+        nodes_to_read: &[HistoryReadValueId],
+    ) -> Result<Vec<HistoryReadResult>, StatusCode> {
+        let results = nodes_to_read
+            .iter()
+            .map(|node_to_read| {
+                if node_to_read.node_id != self.node_id {
+                    return HistoryReadResult {
+                        status_code: StatusCode::BadNodeIdUnknown,
+                        continuation_point: ByteString::null(),
+                        history_data: ExtensionObject::null(),
+                    };
+                }
+                let first = if node_to_read.continuation_point.is_null() {
+                    0
+                } else {
+                    let continuation_point = node_to_read.continuation_point.value.as_ref();
+                    String::from_utf8(continuation_point.unwrap().clone())
+                        .unwrap()
+                        .parse::<usize>()
+                        .unwrap()
+                };
+                if release_continuation_points {
+                    return HistoryReadResult {
+                        status_code: StatusCode::Good,
+                        continuation_point: ByteString::null(),
+                        history_data: ExtensionObject::null(),
+                    };
+                }
+                let max_values = if request.num_values_per_node == 0 {
+                    usize::MAX
+                } else {
+                    request.num_values_per_node as usize
+                };
+                let mut matching = (first..self.len).filter(|i| {
+                    let timestamp = self.value(*i).source_timestamp.unwrap();
+                    timestamp >= request.start_time && timestamp < request.end_time
+                });
+                let data_values: Vec<DataValue> = matching
+                    .by_ref()
+                    .take(max_values)
+                    .map(|i| self.value(i))
+                    .collect();
+                let continuation_point = if let Some(next) = matching.next() {
+                    ByteString::from(next.to_string().as_bytes())
+                } else {
+                    ByteString::null()
+                };
+                HistoryReadResult {
+                    status_code: StatusCode::Good,
+                    continuation_point,
+                    history_data: ExtensionObject::from_encodable(
+                        ObjectId::HistoryData_Encoding_DefaultBinary,
+                        &HistoryData {
+                            data_values: Some(data_values),
+                        },
+                    ),
+                }
+            })
+            .collect();
+        Ok(results)
+    }
+
+    fn read_at_time_details(
+        &self,
+        _address_space: Arc<RwLock<dyn AddressSpaceProvider>>,
+        request: ReadAtTimeDetails,
+        _timestamps_to_return: TimestampsToReturn,
+        _release_continuation_points: bool,
+        nodes_to_read: &[HistoryReadValueId],
+    ) -> Result<Vec<HistoryReadResult>, StatusCode> {
+        let samples = (0..self.len).map(|i| self.value(i)).collect::<Vec<_>>();
+        let results = nodes_to_read
+            .iter()
+            .map(|node_to_read| {
+                if node_to_read.node_id != self.node_id {
+                    return HistoryReadResult {
+                        status_code: StatusCode::BadNodeIdUnknown,
+                        continuation_point: ByteString::null(),
+                        history_data: ExtensionObject::null(),
+                    };
+                }
+                HistoryReadResult {
+                    status_code: StatusCode::Good,
+                    continuation_point: ByteString::null(),
+                    history_data: ExtensionObject::from_encodable(
+                        ObjectId::HistoryData_Encoding_DefaultBinary,
+                        &HistoryData {
+                            data_values: Some(values_at_times(&samples, &request)),
+                        },
+                    ),
+                }
+            })
+            .collect();
+        Ok(results)
+    }
+}
+
+#[test]
+fn history_read_raw_series() {
+    do_attribute_service_test(|server_state, session, address_space, ats| {
+        let node_id = NodeId::new(2, "series");
+        let start_time = chrono::Utc::now() - Duration::hours(1);
+        {
+            let mut server_state = server_state.write();
+            server_state.set_historical_data_provider(Box::new(SeriesProvider {
+                node_id: node_id.clone(),
+                start_time,
+                len: 10,
+            }));
+        }
+
+        // Read values 2 to 7 inclusive, 4 at a time
+        let history_read_details = ExtensionObject::from_encodable(
+            ObjectId::ReadRawModifiedDetails_Encoding_DefaultBinary,
+            &ReadRawModifiedDetails {
+                is_read_modified: false,
+                start_time: (start_time + Duration::minutes(2)).into(),
+                end_time: (start_time + Duration::minutes(8)).into(),
+                num_values_per_node: 4,
+                return_bounds: false,
+            },
+        );
+        let history_read = |continuation_point: ByteString| {
+            let request = HistoryReadRequest {
+                request_header: make_request_header(),
+                history_read_details: history_read_details.clone(),
+                timestamps_to_return: TimestampsToReturn::Both,
+                release_continuation_points: false,
+                nodes_to_read: Some(vec![HistoryReadValueId {
+                    node_id: node_id.clone(),
+                    index_range: UAString::null(),
+                    data_encoding: QualifiedName::null(),
+                    continuation_point,
+                }]),
+            };
+            let response: HistoryReadResponse = supported_message_as!(
+                ats.history_read(
+                    server_state.clone(),
+                    session.clone(),
+                    address_space.clone(),
+                    &request
+                ),
+                HistoryReadResponse
+            );
+            let result = response.results.unwrap().remove(0);
+            assert_eq!(result.status_code, StatusCode::Good);
+            let history_data = result
+                .history_data
+                .decode_inner::<HistoryData>(&DecodingOptions::test())
+                .unwrap();
+            let values: Vec<Variant> = history_data
+                .data_values
+                .unwrap()
+                .into_iter()
+                .map(|v| v.value.unwrap())
+                .collect();
+            (values, result.continuation_point)
+        };
+
+        // The first page is truncated so it returns a continuation point
+        let (values, continuation_point) = history_read(ByteString::null());
+        assert_eq!(values, (2..6).map(Variant::Int32).collect::<Vec<Variant>>());
+        assert!(!continuation_point.is_null());
+
+        // The second page completes the series
+        let (values, continuation_point) = history_read(continuation_point);
+        assert_eq!(values, (6..8).map(Variant::Int32).collect::<Vec<Variant>>());
+        assert!(continuation_point.is_null());
+    });
+}
+
+#[test]
+fn history_read_at_time() {
+    do_attribute_service_test(|server_state, session, address_space, ats| {
+        let node_id = NodeId::new(2, "series");
+        let start_time = chrono::Utc::now() - Duration::hours(1);
+        {
+            let mut server_state = server_state.write();
+            server_state.set_historical_data_provider(Box::new(SeriesProvider {
+                node_id: node_id.clone(),
+                start_time,
+                len: 10,
+            }));
+        }
+
+        // Samples are a minute apart, so these fall a quarter, a half and three quarters of the
+        // way between samples
+        let req_times = [(2, 15), (4, 30), (6, 45)]
+            .iter()
+            .map(|(m, s)| {
+                DateTime::from(start_time + Duration::minutes(*m) + Duration::seconds(*s))
+            })
+            .collect::<Vec<DateTime>>();
+        let request = HistoryReadRequest {
+            request_header: make_request_header(),
+            history_read_details: ExtensionObject::from_encodable(
+                ObjectId::ReadAtTimeDetails_Encoding_DefaultBinary,
+                &ReadAtTimeDetails {
+                    req_times: Some(req_times.clone()),
+                    use_simple_bounds: true,
+                },
+            ),
+            timestamps_to_return: TimestampsToReturn::Both,
+            release_continuation_points: false,
+            nodes_to_read: Some(vec![HistoryReadValueId {
+                node_id: node_id.clone(),
+                index_range: UAString::null(),
+                data_encoding: QualifiedName::null(),
+                continuation_point: ByteString::null(),
+            }]),
+        };
+        let response: HistoryReadResponse = supported_message_as!(
+            ats.history_read(server_state, session, address_space, &request),
+            HistoryReadResponse
+        );
+        let result = response.results.unwrap().remove(0);
+        assert_eq!(result.status_code, StatusCode::Good);
+        let data_values = result
+            .history_data
+            .decode_inner::<HistoryData>(&DecodingOptions::test())
+            .unwrap()
+            .data_values
+            .unwrap();
+
+        // Each value is interpolated between the samples either side of its time and rounded
+        // back to the samples' type
+        assert_eq!(data_values.len(), 3);
+        for ((data_value, req_time), expected) in
+            data_values.iter().zip(req_times.iter()).zip([2i32, 5, 7])
+        {
+            assert_eq!(data_value.value, Some(Variant::Int32(expected)));
+            assert_eq!(
+                data_value.status,
+                Some(StatusCode::Good | StatusCode::HISTORICAL_INTERPOLATED)
+            );
+            assert_eq!(data_value.source_timestamp, Some(*req_time));
+            assert!(data_value.server_timestamp.is_none());
+        }
+    });
+}
+
+#[test]
+fn values_at_times_bounds() {
+    let start_time = DateTime::now().as_chrono();
+    let sample = |minutes: i64, value: Variant, status: StatusCode| DataValue {
+        value: Some(value),
+        status: Some(status),
+        source_timestamp: Some(DateTime::from(start_time + Duration::minutes(minutes))),
+        source_picoseconds: None,
+        server_timestamp: None,
+        server_picoseconds: None,
+    };
+    let at = |minutes: i64, seconds: i64| {
+        DateTime::from(start_time + Duration::minutes(minutes) + Duration::seconds(seconds))
+    };
+    let samples = vec![
+        sample(0, Variant::Int32(0), StatusCode::Good),
+        sample(1, Variant::Int32(10), StatusCode::Good),
+        sample(2, Variant::Int32(99), StatusCode::BadSensorFailure),
+        sample(3, Variant::Int32(30), StatusCode::Good),
+        sample(4, Variant::from("on"), StatusCode::Good),
+        sample(5, Variant::from("off"), StatusCode::Good),
+    ];
+    let values_at = |use_simple_bounds: bool, req_times: Vec<DateTime>| {
+        values_at_times(
+            &samples,
+            &ReadAtTimeDetails {
+                req_times: Some(req_times),
+                use_simple_bounds,
+            },
+        )
+        .into_iter()
+        .map(|v| {
+            // The samples have no server timestamp and the values made from them do not get one
+            assert!(v.server_timestamp.is_none());
+            (v.value, v.status.unwrap())
+        })
+        .collect::<Vec<_>>()
+    };
+    let interpolated = StatusCode::HISTORICAL_INTERPOLATED;
+
+    // An exact sample is returned as it is, a non numeric value is the previous value, the
+    // last value is held after the last sample and there is no data before the first
+    assert_eq!(
+        values_at(true, vec![at(1, 0), at(4, 30), at(6, 0), at(-1, 0)]),
+        vec![
+            (Some(Variant::Int32(10)), StatusCode::Good),
+            (Some(Variant::from("on")), StatusCode::Good | interpolated),
+            (
+                Some(Variant::from("off")),
+                StatusCode::UncertainDataSubNormal | interpolated
+            ),
+            (None, StatusCode::BadNoData),
+        ]
+    );
+
+    // Simple bounds use the bad sample as a bound, which makes the value uncertain
+    assert_eq!(
+        values_at(true, vec![at(1, 30)]),
+        vec![(
+            Some(Variant::Int32(55)),
+            StatusCode::UncertainDataSubNormal | interpolated
+        )]
+    );
+
+    // Otherwise the bad sample is skipped and the bounds are the good samples either side
+    assert_eq!(
+        values_at(false, vec![at(1, 30)]),
+        vec![(Some(Variant::Int32(15)), StatusCode::Good | interpolated)]
+    );
+}
+
+#[test]
+fn history_read_too_many_operations() {
+    do_attribute_service_test(|server_state, session, address_space, ats| {
+        {
+            let mut server_state = server_state.write();
+            server_state.set_historical_data_provider(Box::new(DataProvider));
+            server_state
+                .operational_limits
+                .max_nodes_per_history_read_data = 1;
+        }
+
+        let history_read_details = ExtensionObject::from_encodable(
+            ObjectId::ReadRawModifiedDetails_Encoding_DefaultBinary,
+            &read_raw_modified_details(),
+        );
+        let mut nodes_to_read = nodes_to_read();
+        nodes_to_read.push(nodes_to_read[0].clone());
+        let request = HistoryReadRequest {
+            request_header: make_request_header(),
+            history_read_details,
+            timestamps_to_return: TimestampsToReturn::Both,
+            release_continuation_points: true,
+            nodes_to_read: Some(nodes_to_read),
+        };
+        let response: ServiceFault = supported_message_as!(
+            ats.history_read(server_state, session, address_space, &request),
+            ServiceFault
+        );
+        assert_eq!(
+            response.response_header.service_result,
+            StatusCode::BadTooManyOperations
+        );
+    });
+}
+
 fn delete_raw_modified_details() -> DeleteRawModifiedDetails {
     let now = chrono::Utc::now();
     let start_time = (now - Duration::days(5)).into();
@@ -827,3 +1656,63 @@ fn history_update_data_provider() {
         assert_eq!(result1.status_code, StatusCode::Good);
     });
 }
+
+#[test]
+fn read_and_write_through_provider() {
+    do_attribute_service_test(|server_state, session, _address_space, ats| {
+        let node_id = NodeId::new(2, "mock");
+        let provider = Arc::new(RwLock::new(MockAddressSpaceProvider::new(vec![(
+            node_id.clone(),
+            Variant::Int32(42),
+        )])));
+
+        let read = |node_id: &NodeId| {
+            let request = ReadRequest {
+                request_header: make_request_header(),
+                max_age: 0f64,
+                timestamps_to_return: TimestampsToReturn::Both,
+                nodes_to_read: Some(vec![read_value(node_id, AttributeId::Value)]),
+            };
+            let response = ats.read(
+                server_state.clone(),
+                session.clone(),
+                provider.clone(),
+                &request,
+            );
+            let response = supported_message_as!(response, ReadResponse);
+            response.results.unwrap().remove(0)
+        };
+
+        // The value comes from the provider's node
+        let value = read(&node_id);
+        assert_eq!(value.value, Some(Variant::Int32(42)));
+        assert_eq!(
+            trace_read_lock!(provider).lookups.load(Ordering::Relaxed),
+            1
+        );
+
+        // A node the provider does not have, even one in the server's address space
+        let value = read(&ObjectId::Server.into());
+        assert_eq!(value.status, Some(StatusCode::BadNodeIdUnknown));
+
+        // Writes go to the provider's node
+        let request = WriteRequest {
+            request_header: make_request_header(),
+            nodes_to_write: Some(vec![write_value(
+                &node_id,
+                AttributeId::Value,
+                DataValue::new_now(99i32),
+            )]),
+        };
+        let response = ats.write(
+            server_state.clone(),
+            session.clone(),
+            provider.clone(),
+            &request,
+        );
+        let response = supported_message_as!(response, WriteResponse);
+        assert_eq!(response.results.unwrap(), vec![StatusCode::Good]);
+        let value = read(&node_id);
+        assert_eq!(value.value, Some(Variant::Int32(99)));
+    });
+}
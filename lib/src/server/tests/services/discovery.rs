@@ -1,3 +1,5 @@
+use crate::core::comms::secure_channel::SecureChannel;
+use crate::server::constants;
 use crate::server::services::discovery::DiscoveryService;
 use crate::supported_message_as;
 use crate::sync::*;
@@ -63,8 +65,70 @@ fn find_servers() {
             assert_eq!(s.application_uri.as_ref(), "urn:OPC UA Sample Server");
             assert_eq!(s.product_uri.as_ref(), "urn:OPC UA Sample Server Testkit");
         });
+    });
+}
+
+#[test]
+fn find_servers_filters() {
+    do_discovery_service_test(|server_state, _session, ds| {
+        let find_servers = |server_uris: Option<Vec<UAString>>,
+                            locale_ids: Option<Vec<UAString>>| {
+            let request = FindServersRequest {
+                request_header: make_request_header(),
+                endpoint_url: Default::default(),
+                locale_ids,
+                server_uris,
+            };
+            let result = ds.find_servers(server_state.clone(), &request);
+            supported_message_as!(result, FindServersResponse)
+                .servers
+                .unwrap()
+        };
+
+        let application_name = {
+            let server_state = trace_read_lock!(server_state);
+            let config = trace_read_lock!(server_state.config);
+            config.application_description().application_name
+        };
+
+        // An empty filter returns this server
+        let servers = find_servers(Some(vec![]), None);
+        assert_eq!(servers.len(), 1);
+        assert_eq!(
+            servers[0].application_uri.as_ref(),
+            "urn:OPC UA Sample Server"
+        );
+        assert_eq!(servers[0].application_name, application_name);
+
+        // Matching server uri
+        let servers = find_servers(
+            Some(vec![
+                UAString::from("urn:xxxx"),
+                UAString::from("urn:OPC UA Sample Server"),
+            ]),
+            None,
+        );
+        assert_eq!(servers.len(), 1);
+        assert_eq!(
+            servers[0].application_uri.as_ref(),
+            "urn:OPC UA Sample Server"
+        );
 
-        // TODO other requests should exercise those filters
+        // No matching server uri
+        let servers = find_servers(Some(vec![UAString::from("urn:xxxx")]), None);
+        assert!(servers.is_empty());
+
+        // The name has no translations, so it is not relabelled with a requested locale even when
+        // the server supports that locale
+        let servers = find_servers(None, Some(vec![UAString::from("de")]));
+        assert_eq!(servers[0].application_name, application_name);
+        {
+            let server_state = trace_read_lock!(server_state);
+            let mut config = trace_write_lock!(server_state.config);
+            config.locale_ids = vec!["en".to_string(), "de".to_string()];
+        }
+        let servers = find_servers(None, Some(vec![UAString::from("fr"), UAString::from("de")]));
+        assert_eq!(servers[0].application_name, application_name);
     });
 }
 
@@ -120,3 +184,408 @@ fn discovery_test() {
         }
     });
 }
+
+#[test]
+fn get_endpoints_filters() {
+    do_discovery_service_test(|server_state, _session, ds| {
+        let get_endpoints = |profile_uris: Option<Vec<UAString>>,
+                             locale_ids: Option<Vec<UAString>>| {
+            let request = GetEndpointsRequest {
+                request_header: make_request_header(),
+                endpoint_url: UAString::from("opc.tcp://localhost:4855/"),
+                locale_ids,
+                profile_uris,
+            };
+            let result = ds.get_endpoints(server_state.clone(), &request);
+            supported_message_as!(result, GetEndpointsResponse).endpoints
+        };
+
+        // Only the binary tcp profile
+        let endpoints = get_endpoints(
+            Some(vec![UAString::from(profiles::TRANSPORT_PROFILE_URI_BINARY)]),
+            None,
+        )
+        .unwrap();
+        assert_eq!(endpoints.len(), 12);
+        assert!(endpoints
+            .iter()
+            .all(|e| e.transport_profile_uri.as_ref() == profiles::TRANSPORT_PROFILE_URI_BINARY));
+
+        // Https endpoints are excluded, and the server has no others
+        let endpoints = get_endpoints(
+            Some(vec![UAString::from(
+                "http://opcfoundation.org/UA-Profile/Transport/https-uabinary",
+            )]),
+            None,
+        );
+        assert!(endpoints.is_none());
+
+        // An empty filter is the same as no filter
+        assert_eq!(get_endpoints(Some(vec![]), None).unwrap().len(), 12);
+
+        // The application name has no translations, so it keeps its original locale whatever the
+        // locale requested
+        let application_name = {
+            let server_state = trace_read_lock!(server_state);
+            let mut config = trace_write_lock!(server_state.config);
+            config.locale_ids = vec!["en".to_string(), "de".to_string()];
+            server_state.application_name.clone()
+        };
+        for locale_id in ["de", "fr"] {
+            let endpoints = get_endpoints(None, Some(vec![UAString::from(locale_id)])).unwrap();
+            assert!(endpoints
+                .iter()
+                .all(|e| e.server.application_name == application_name));
+        }
+    });
+}
+
+#[test]
+fn get_endpoints_substitute_hostname() {
+    do_discovery_service_test(|server_state, _session, ds| {
+        let endpoint_urls = |endpoint_url: &str| {
+            let request = GetEndpointsRequest {
+                request_header: make_request_header(),
+                endpoint_url: UAString::from(endpoint_url),
+                locale_ids: None,
+                profile_uris: None,
+            };
+            let result = ds.get_endpoints(server_state.clone(), &request);
+            let mut endpoint_urls = supported_message_as!(result, GetEndpointsResponse)
+                .endpoints
+                .unwrap()
+                .into_iter()
+                .map(|e| e.endpoint_url.as_ref().to_string())
+                .collect::<Vec<String>>();
+            endpoint_urls.sort();
+            endpoint_urls.dedup();
+            endpoint_urls
+        };
+
+        let (base_endpoint_url, host) = {
+            let server_state = trace_read_lock!(server_state);
+            let config = trace_read_lock!(server_state.config);
+            (config.base_endpoint_url(), config.tcp_config.host.clone())
+        };
+
+        let original = endpoint_urls("opc.tcp://nat.example.com:4855/");
+        assert!(original
+            .iter()
+            .all(|url| url.starts_with(&base_endpoint_url)));
+
+        // With substitution on, only the host of each url changes
+        {
+            let mut server_state = trace_write_lock!(server_state);
+            server_state.substitute_endpoint_hostname = true;
+        }
+        let substituted = endpoint_urls("opc.tcp://nat.example.com:9999/other/path");
+        assert_eq!(substituted.len(), original.len());
+        original.iter().zip(substituted.iter()).for_each(|(o, s)| {
+            assert_eq!(
+                s,
+                &o.replacen(
+                    &format!("opc.tcp://{}:", host),
+                    "opc.tcp://nat.example.com:",
+                    1
+                )
+            );
+        });
+
+        // An unparseable url falls back to the default endpoint without substitution
+        let fallback = endpoint_urls("xyz");
+        assert!(fallback
+            .iter()
+            .all(|url| url.starts_with(&base_endpoint_url)));
+    });
+}
+
+#[test]
+fn get_endpoints_discovery_urls() {
+    // A server reachable on several urls lists all of them on every endpoint
+    let discovery_urls = vec![
+        "opc.tcp://localhost:4855/".to_string(),
+        "opc.tcp://192.168.1.10:4855/".to_string(),
+        "opc.tcp://server.example.com:4855/".to_string(),
+    ];
+    let st = ServiceTest::new_with_server(
+        ServerBuilder::new_sample().discovery_urls(discovery_urls.clone()),
+    );
+    let request = GetEndpointsRequest {
+        request_header: make_request_header(),
+        endpoint_url: UAString::from("opc.tcp://localhost:4855/"),
+        locale_ids: None,
+        profile_uris: None,
+    };
+    let result = DiscoveryService::new().get_endpoints(st.server_state.clone(), &request);
+    let endpoints = supported_message_as!(result, GetEndpointsResponse)
+        .endpoints
+        .unwrap();
+    assert!(!endpoints.is_empty());
+    let expected = Some(
+        discovery_urls
+            .iter()
+            .map(UAString::from)
+            .collect::<Vec<UAString>>(),
+    );
+    endpoints
+        .iter()
+        .for_each(|endpoint| assert_eq!(endpoint.server.discovery_urls, expected));
+
+    // The server's own description holds them too
+    let server_state = trace_read_lock!(st.server_state);
+    assert_eq!(server_state.registered_server().discovery_urls, expected);
+}
+
+fn registered_server(server_uri: &str) -> RegisteredServer {
+    RegisteredServer {
+        server_uri: UAString::from(server_uri),
+        product_uri: UAString::from("urn:product"),
+        server_names: Some(vec![
+            LocalizedText::new("en", "Registered Server"),
+            LocalizedText::new("de", "Registrierter Server"),
+        ]),
+        server_type: ApplicationType::Server,
+        gateway_server_uri: UAString::null(),
+        discovery_urls: Some(vec![UAString::from("opc.tcp://localhost:4856/")]),
+        semaphore_file_path: UAString::null(),
+        is_online: true,
+    }
+}
+
+fn register_server(
+    ds: &DiscoveryService,
+    server_state: Arc<RwLock<ServerState>>,
+    server: RegisteredServer,
+) -> StatusCode {
+    let request = RegisterServerRequest {
+        request_header: make_request_header(),
+        server,
+    };
+    let mut secure_channel = SecureChannel::new_no_certificate_store();
+    secure_channel.set_security_mode(MessageSecurityMode::Sign);
+    let response = ds.register_server(
+        Arc::new(RwLock::new(secure_channel)),
+        server_state,
+        &request,
+    );
+    if let SupportedMessage::ServiceFault(fault) = response {
+        fault.response_header.service_result
+    } else {
+        let response = supported_message_as!(response, RegisterServerResponse);
+        response.response_header.service_result
+    }
+}
+
+fn find_server(
+    ds: &DiscoveryService,
+    server_state: Arc<RwLock<ServerState>>,
+    server_uri: &str,
+    locale_ids: Option<Vec<UAString>>,
+) -> Option<ApplicationDescription> {
+    let request = FindServersRequest {
+        request_header: make_request_header(),
+        endpoint_url: Default::default(),
+        locale_ids,
+        server_uris: Some(vec![UAString::from(server_uri)]),
+    };
+    let response = ds.find_servers(server_state, &request);
+    let response = supported_message_as!(response, FindServersResponse);
+    response.servers.unwrap().pop()
+}
+
+#[test]
+fn register_server_then_find() {
+    do_discovery_service_test(|server_state, _session, ds| {
+        let server_uri = "urn:registered";
+        assert!(find_server(ds, server_state.clone(), server_uri, None).is_none());
+
+        // Register and find the server
+        assert_eq!(
+            register_server(ds, server_state.clone(), registered_server(server_uri)),
+            StatusCode::Good
+        );
+        let server = find_server(ds, server_state.clone(), server_uri, None).unwrap();
+        assert_eq!(server.application_uri.as_ref(), server_uri);
+        assert_eq!(server.product_uri.as_ref(), "urn:product");
+        assert_eq!(server.application_type, ApplicationType::Server);
+        assert_eq!(server.application_name.text.as_ref(), "Registered Server");
+        assert_eq!(
+            server.discovery_urls.unwrap(),
+            vec![UAString::from("opc.tcp://localhost:4856/")]
+        );
+
+        // The name follows the requested locale
+        let server = find_server(
+            ds,
+            server_state.clone(),
+            server_uri,
+            Some(vec![UAString::from("fr"), UAString::from("de")]),
+        )
+        .unwrap();
+        assert_eq!(
+            server.application_name.text.as_ref(),
+            "Registrierter Server"
+        );
+
+        // Registering again updates the registration
+        let mut server = registered_server(server_uri);
+        server.product_uri = UAString::from("urn:product2");
+        assert_eq!(
+            register_server(ds, server_state.clone(), server),
+            StatusCode::Good
+        );
+        let server = find_server(ds, server_state.clone(), server_uri, None).unwrap();
+        assert_eq!(server.product_uri.as_ref(), "urn:product2");
+        {
+            let server_state = trace_read_lock!(server_state);
+            assert_eq!(server_state.registered_servers.len(), 1);
+        }
+
+        // Going offline unregisters the server
+        let mut server = registered_server(server_uri);
+        server.is_online = false;
+        assert_eq!(
+            register_server(ds, server_state.clone(), server),
+            StatusCode::Good
+        );
+        assert!(find_server(ds, server_state.clone(), server_uri, None).is_none());
+    });
+}
+
+#[test]
+fn register_server_invalid() {
+    do_discovery_service_test(|server_state, _session, ds| {
+        let mut server = registered_server("");
+        assert_eq!(
+            register_server(ds, server_state.clone(), server.clone()),
+            StatusCode::BadServerUriInvalid
+        );
+
+        server.server_uri = UAString::from("urn:registered");
+        server.server_names = None;
+        assert_eq!(
+            register_server(ds, server_state.clone(), server.clone()),
+            StatusCode::BadServerNameMissing
+        );
+
+        let mut server = registered_server("urn:registered");
+        server.discovery_urls = Some(vec![]);
+        assert_eq!(
+            register_server(ds, server_state.clone(), server),
+            StatusCode::BadDiscoveryUrlMissing
+        );
+
+        let mut server = registered_server("urn:registered");
+        server.server_type = ApplicationType::Client;
+        assert_eq!(
+            register_server(ds, server_state.clone(), server),
+            StatusCode::BadInvalidArgument
+        );
+
+        let server_state = trace_read_lock!(server_state);
+        assert!(server_state.registered_servers.is_empty());
+    });
+}
+
+#[test]
+fn register_server_semaphore_file() {
+    do_discovery_service_test(|server_state, _session, ds| {
+        let server_uri = "urn:registered_semaphore";
+        let semaphore_file_path =
+            std::env::temp_dir().join(format!("opcua_semaphore_{}", std::process::id()));
+        let _ = std::fs::remove_file(&semaphore_file_path);
+
+        // Registering doesn't reveal whether the semaphore file exists, the server is accepted
+        // and dropped at the next sweep
+        let mut server = registered_server(server_uri);
+        server.semaphore_file_path = UAString::from(semaphore_file_path.to_str().unwrap());
+        assert_eq!(
+            register_server(ds, server_state.clone(), server.clone()),
+            StatusCode::Good
+        );
+        DiscoveryService::remove_unregistered_servers(server_state.clone());
+        assert!(find_server(ds, server_state.clone(), server_uri, None).is_none());
+
+        // The server stays registered while the semaphore file exists
+        std::fs::write(&semaphore_file_path, "").unwrap();
+        assert_eq!(
+            register_server(ds, server_state.clone(), server),
+            StatusCode::Good
+        );
+        assert!(find_server(ds, server_state.clone(), server_uri, None).is_some());
+
+        // Removing the semaphore file unregisters the server at the next sweep
+        std::fs::remove_file(&semaphore_file_path).unwrap();
+        assert!(find_server(ds, server_state.clone(), server_uri, None).is_some());
+        DiscoveryService::remove_unregistered_servers(server_state.clone());
+        assert!(find_server(ds, server_state.clone(), server_uri, None).is_none());
+    });
+}
+
+#[test]
+fn register_server_insecure_channel() {
+    do_discovery_service_test(|server_state, _session, ds| {
+        let request = RegisterServerRequest {
+            request_header: make_request_header(),
+            server: registered_server("urn:registered"),
+        };
+        let secure_channel = Arc::new(RwLock::new(SecureChannel::new_no_certificate_store()));
+        let response = ds.register_server(secure_channel, server_state.clone(), &request);
+        let fault = supported_message_as!(response, ServiceFault);
+        assert_eq!(
+            fault.response_header.service_result,
+            StatusCode::BadSecurityModeInsufficient
+        );
+
+        let server_state = trace_read_lock!(server_state);
+        assert!(server_state.registered_servers.is_empty());
+    });
+}
+
+#[test]
+fn register_server_limit() {
+    do_discovery_service_test(|server_state, _session, ds| {
+        for i in 0..constants::MAX_REGISTERED_SERVERS {
+            assert_eq!(
+                register_server(
+                    ds,
+                    server_state.clone(),
+                    registered_server(&format!("urn:registered{}", i))
+                ),
+                StatusCode::Good
+            );
+        }
+
+        // No more servers can register, but a registered server can still update or unregister
+        assert_eq!(
+            register_server(
+                ds,
+                server_state.clone(),
+                registered_server("urn:one_too_many")
+            ),
+            StatusCode::BadTooManyOperations
+        );
+        assert_eq!(
+            register_server(
+                ds,
+                server_state.clone(),
+                registered_server("urn:registered0")
+            ),
+            StatusCode::Good
+        );
+        let mut server = registered_server("urn:registered0");
+        server.is_online = false;
+        assert_eq!(
+            register_server(ds, server_state.clone(), server),
+            StatusCode::Good
+        );
+        assert_eq!(
+            register_server(
+                ds,
+                server_state.clone(),
+                registered_server("urn:one_too_many")
+            ),
+            StatusCode::Good
+        );
+    });
+}
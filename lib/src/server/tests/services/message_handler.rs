@@ -0,0 +1,1181 @@
+use std::io::Cursor;
+
+use crate::core::comms::{
+    chunker::Chunker,
+    message_chunk::MessageChunk,
+    message_writer::MessageWriter,
+    secure_channel::SecureChannel,
+    tcp_types::{HelloMessage, MIN_CHUNK_SIZE},
+};
+use crate::server::{
+    address_space::relative_path::find_nodes_relative_path_simple,
+    comms::tcp_transport::{MessageSender, TcpTransport},
+    services::message_handler::MessageHandler,
+};
+use crate::supported_message_as;
+use crate::sync::*;
+use crate::types::service_types::ServerState as ServerStateType;
+
+use super::*;
+
+// Message handler tests
+
+struct MessageHandlerTest {
+    server_state: Arc<RwLock<ServerState>>,
+    address_space: Arc<RwLock<AddressSpace>>,
+    session: Arc<RwLock<Session>>,
+    session_manager: Arc<RwLock<SessionManager>>,
+    message_handler: MessageHandler,
+}
+
+impl MessageHandlerTest {
+    fn new() -> MessageHandlerTest {
+        Self::new_with_server(ServerBuilder::new_sample().server().unwrap())
+    }
+
+    fn new_with_server(server: Server) -> MessageHandlerTest {
+        let server_state = server.server_state();
+        let session = Arc::new(RwLock::new(Session::new(server_state.clone())));
+        let session_manager = Arc::new(RwLock::new(SessionManager::default()));
+        {
+            let mut session = trace_write_lock!(session);
+            session.set_authentication_token(make_request_header().authentication_token);
+        }
+        {
+            let mut session_manager = trace_write_lock!(session_manager);
+            session_manager.register_session(session.clone());
+        }
+        let message_handler = MessageHandler::new(
+            Arc::new(RwLock::new(SecureChannel::new_no_certificate_store())),
+            server.certificate_store(),
+            server_state.clone(),
+            session_manager.clone(),
+            server.address_space(),
+            server.address_space_provider(),
+        );
+        MessageHandlerTest {
+            server_state,
+            address_space: server.address_space(),
+            session,
+            session_manager,
+            message_handler,
+        }
+    }
+
+    /// Sends the request through the handler and returns the response it produced
+    fn handle_message(&mut self, request: SupportedMessage) -> SupportedMessage {
+        let (sender, mut receiver) = MessageSender::new_test_channel();
+        self.message_handler
+            .handle_message(1, &request, &sender)
+            .unwrap();
+        let (request_id, response) = receiver.try_recv_message().unwrap();
+        assert_eq!(request_id, 1);
+        response
+    }
+}
+
+fn make_browse_request(request_header: RequestHeader) -> SupportedMessage {
+    BrowseRequest {
+        request_header,
+        view: ViewDescription {
+            view_id: NodeId::null(),
+            timestamp: DateTime::null(),
+            view_version: 0,
+        },
+        requested_max_references_per_node: 0,
+        nodes_to_browse: Some(vec![BrowseDescription {
+            node_id: ObjectId::RootFolder.into(),
+            browse_direction: BrowseDirection::Forward,
+            reference_type_id: ReferenceTypeId::Organizes.into(),
+            include_subtypes: true,
+            node_class_mask: NodeClassMask::all().bits(),
+            result_mask: BrowseDescriptionResultMask::all().bits(),
+        }]),
+    }
+    .into()
+}
+
+fn service_result(response: SupportedMessage) -> StatusCode {
+    let response = supported_message_as!(response, ServiceFault);
+    response.response_header.service_result
+}
+
+#[test]
+fn browse_before_activate_session() {
+    let mut mht = MessageHandlerTest::new();
+
+    // Session exists but has not been activated
+    let response = mht.handle_message(make_browse_request(make_request_header()));
+    assert_eq!(service_result(response), StatusCode::BadSessionNotActivated);
+
+    // Once activated, the same request is serviced
+    {
+        let mut session = trace_write_lock!(mht.session);
+        session.set_activated(true);
+    }
+    let response = mht.handle_message(make_browse_request(make_request_header()));
+    let response = supported_message_as!(response, BrowseResponse);
+    assert!(response.response_header.service_result.is_good());
+    assert_eq!(response.results.as_ref().unwrap().len(), 1);
+}
+
+#[test]
+fn browse_on_unknown_or_closed_session() {
+    let mut mht = MessageHandlerTest::new();
+    {
+        let mut session = trace_write_lock!(mht.session);
+        session.set_activated(true);
+    }
+
+    // A token that matches no session
+    let mut request_header = make_request_header();
+    request_header.authentication_token = NodeId::new(0, 1000);
+    let response = mht.handle_message(make_browse_request(request_header));
+    assert_eq!(service_result(response), StatusCode::BadSessionIdInvalid);
+
+    // A session that has been terminated but is still registered
+    {
+        let mut session = trace_write_lock!(mht.session);
+        session.set_terminated();
+    }
+    let response = mht.handle_message(make_browse_request(make_request_header()));
+    assert_eq!(service_result(response), StatusCode::BadSessionIdInvalid);
+
+    // A session that has been removed from the manager
+    {
+        let mut session_manager = trace_write_lock!(mht.session_manager);
+        session_manager.deregister_session(mht.session.clone());
+    }
+    let response = mht.handle_message(make_browse_request(make_request_header()));
+    assert_eq!(service_result(response), StatusCode::BadSessionIdInvalid);
+}
+
+#[test]
+fn discovery_without_session() {
+    let mut mht = MessageHandlerTest::new();
+
+    // No session is associated with this token
+    let mut request_header = make_request_header();
+    request_header.authentication_token = NodeId::null();
+
+    let response = mht.handle_message(
+        GetEndpointsRequest {
+            request_header: request_header.clone(),
+            endpoint_url: UAString::from("opc.tcp://localhost:4855/"),
+            locale_ids: None,
+            profile_uris: None,
+        }
+        .into(),
+    );
+    let response = supported_message_as!(response, GetEndpointsResponse);
+    assert!(response.response_header.service_result.is_good());
+    assert!(response.endpoints.is_some());
+
+    let response = mht.handle_message(
+        FindServersRequest {
+            request_header,
+            endpoint_url: UAString::null(),
+            locale_ids: None,
+            server_uris: None,
+        }
+        .into(),
+    );
+    let response = supported_message_as!(response, FindServersResponse);
+    assert!(response.response_header.service_result.is_good());
+    assert!(response.servers.is_some());
+}
+
+#[test]
+fn requests_after_shutdown() {
+    let mut mht = MessageHandlerTest::new();
+    {
+        let mut session = trace_write_lock!(mht.session);
+        session.set_activated(true);
+    }
+    let response = mht.handle_message(make_browse_request(make_request_header()));
+    let response = supported_message_as!(response, BrowseResponse);
+    assert!(response.response_header.service_result.is_good());
+
+    {
+        let mut server_state = trace_write_lock!(mht.server_state);
+        server_state.shutdown();
+        assert_eq!(server_state.state(), ServerStateType::Shutdown);
+    }
+
+    // Session and discovery requests are both rejected
+    let response = mht.handle_message(make_browse_request(make_request_header()));
+    assert_eq!(service_result(response), StatusCode::BadServerHalted);
+    let response = mht.handle_message(
+        GetEndpointsRequest {
+            request_header: make_request_header(),
+            endpoint_url: UAString::from("opc.tcp://localhost:4855/"),
+            locale_ids: None,
+            profile_uris: None,
+        }
+        .into(),
+    );
+    assert_eq!(service_result(response), StatusCode::BadServerHalted);
+}
+
+#[test]
+fn unhandled_message_returns_status_code() {
+    let mut mht = MessageHandlerTest::new();
+
+    // A response is not something the server handles, so the status code comes back as an error
+    // and nothing is sent to the client
+    let message: SupportedMessage = ReadResponse {
+        response_header: ResponseHeader::new_good(&make_request_header()),
+        results: None,
+        diagnostic_infos: None,
+    }
+    .into();
+    let (sender, mut receiver) = MessageSender::new_test_channel();
+    let result = mht.message_handler.handle_message(1, &message, &sender);
+    assert_eq!(result, Err(StatusCode::BadServiceUnsupported));
+    assert!(receiver.try_recv_message().is_none());
+}
+
+#[test]
+fn registered_services() {
+    let mht = MessageHandlerTest::new();
+
+    let mut registered = mht.message_handler.registered_services();
+    registered.sort_by_key(|object_id| *object_id as u32);
+
+    let mut expected = vec![
+        ObjectId::GetEndpointsRequest_Encoding_DefaultBinary,
+        ObjectId::RegisterServerRequest_Encoding_DefaultBinary,
+        ObjectId::RegisterServer2Request_Encoding_DefaultBinary,
+        ObjectId::FindServersRequest_Encoding_DefaultBinary,
+        ObjectId::CreateSessionRequest_Encoding_DefaultBinary,
+        ObjectId::CloseSessionRequest_Encoding_DefaultBinary,
+        ObjectId::ActivateSessionRequest_Encoding_DefaultBinary,
+        ObjectId::CancelRequest_Encoding_DefaultBinary,
+        ObjectId::AddNodesRequest_Encoding_DefaultBinary,
+        ObjectId::AddReferencesRequest_Encoding_DefaultBinary,
+        ObjectId::DeleteNodesRequest_Encoding_DefaultBinary,
+        ObjectId::DeleteReferencesRequest_Encoding_DefaultBinary,
+        ObjectId::BrowseRequest_Encoding_DefaultBinary,
+        ObjectId::BrowseNextRequest_Encoding_DefaultBinary,
+        ObjectId::TranslateBrowsePathsToNodeIdsRequest_Encoding_DefaultBinary,
+        ObjectId::RegisterNodesRequest_Encoding_DefaultBinary,
+        ObjectId::UnregisterNodesRequest_Encoding_DefaultBinary,
+        ObjectId::QueryFirstRequest_Encoding_DefaultBinary,
+        ObjectId::QueryNextRequest_Encoding_DefaultBinary,
+        ObjectId::ReadRequest_Encoding_DefaultBinary,
+        ObjectId::HistoryReadRequest_Encoding_DefaultBinary,
+        ObjectId::WriteRequest_Encoding_DefaultBinary,
+        ObjectId::HistoryUpdateRequest_Encoding_DefaultBinary,
+        ObjectId::CallRequest_Encoding_DefaultBinary,
+        ObjectId::CreateMonitoredItemsRequest_Encoding_DefaultBinary,
+        ObjectId::ModifyMonitoredItemsRequest_Encoding_DefaultBinary,
+        ObjectId::SetMonitoringModeRequest_Encoding_DefaultBinary,
+        ObjectId::SetTriggeringRequest_Encoding_DefaultBinary,
+        ObjectId::DeleteMonitoredItemsRequest_Encoding_DefaultBinary,
+        ObjectId::CreateSubscriptionRequest_Encoding_DefaultBinary,
+        ObjectId::ModifySubscriptionRequest_Encoding_DefaultBinary,
+        ObjectId::SetPublishingModeRequest_Encoding_DefaultBinary,
+        ObjectId::DeleteSubscriptionsRequest_Encoding_DefaultBinary,
+        ObjectId::TransferSubscriptionsRequest_Encoding_DefaultBinary,
+        ObjectId::PublishRequest_Encoding_DefaultBinary,
+        ObjectId::RepublishRequest_Encoding_DefaultBinary,
+    ];
+    expected.sort_by_key(|object_id| *object_id as u32);
+
+    assert_eq!(registered, expected);
+}
+
+#[test]
+fn requests_route_to_service() {
+    let mut mht = MessageHandlerTest::new();
+    {
+        let mut session = trace_write_lock!(mht.session);
+        session.set_activated(true);
+    }
+
+    let mut request = create_subscription_request(0, 0);
+    request.request_header = make_request_header();
+    let response = mht.handle_message(request.into());
+    let subscription_id =
+        supported_message_as!(response, CreateSubscriptionResponse).subscription_id;
+
+    let node_id: NodeId = VariableId::Server_ServerStatus_CurrentTime.into();
+    let unknown_node_id = NodeId::new(1, "unknown");
+    let view = || ViewDescription {
+        view_id: NodeId::null(),
+        timestamp: DateTime::null(),
+        view_version: 0,
+    };
+    let read_raw_modified_details = ReadRawModifiedDetails {
+        is_read_modified: false,
+        start_time: DateTime::now(),
+        end_time: DateTime::now(),
+        num_values_per_node: 1,
+        return_bounds: false,
+    };
+
+    // Each request with the response its service returns, or the fault it raises for this
+    // request. A failed activation invalidates the session, so it and closing come last.
+    let requests: Vec<(SupportedMessage, Result<ObjectId, StatusCode>)> = vec![
+        (
+            GetEndpointsRequest {
+                request_header: make_request_header(),
+                endpoint_url: UAString::from("opc.tcp://localhost:4855/"),
+                locale_ids: None,
+                profile_uris: None,
+            }
+            .into(),
+            Ok(ObjectId::GetEndpointsResponse_Encoding_DefaultBinary),
+        ),
+        (
+            RegisterServerRequest {
+                request_header: make_request_header(),
+                server: RegisteredServer {
+                    server_uri: UAString::from("urn:routing"),
+                    product_uri: UAString::null(),
+                    server_names: Some(vec![LocalizedText::new("", "routing")]),
+                    server_type: ApplicationType::Server,
+                    gateway_server_uri: UAString::null(),
+                    discovery_urls: Some(vec![UAString::from("opc.tcp://localhost:4999/")]),
+                    semaphore_file_path: UAString::null(),
+                    is_online: true,
+                },
+            }
+            .into(),
+            Err(StatusCode::BadSecurityModeInsufficient),
+        ),
+        (
+            RegisterServer2Request {
+                request_header: make_request_header(),
+                server: RegisteredServer {
+                    server_uri: UAString::from("urn:routing2"),
+                    product_uri: UAString::null(),
+                    server_names: Some(vec![LocalizedText::new("", "routing2")]),
+                    server_type: ApplicationType::Server,
+                    gateway_server_uri: UAString::null(),
+                    discovery_urls: Some(vec![UAString::from("opc.tcp://localhost:4998/")]),
+                    semaphore_file_path: UAString::null(),
+                    is_online: true,
+                },
+                discovery_configuration: None,
+            }
+            .into(),
+            Err(StatusCode::BadNotSupported),
+        ),
+        (
+            FindServersRequest {
+                request_header: make_request_header(),
+                endpoint_url: UAString::null(),
+                locale_ids: None,
+                server_uris: None,
+            }
+            .into(),
+            Ok(ObjectId::FindServersResponse_Encoding_DefaultBinary),
+        ),
+        (
+            make_create_session_request(),
+            Ok(ObjectId::CreateSessionResponse_Encoding_DefaultBinary),
+        ),
+        (
+            CancelRequest {
+                request_header: make_request_header(),
+                request_handle: 1000,
+            }
+            .into(),
+            Ok(ObjectId::CancelResponse_Encoding_DefaultBinary),
+        ),
+        (
+            AddNodesRequest {
+                request_header: make_request_header(),
+                nodes_to_add: Some(vec![AddNodesItem {
+                    parent_node_id: unknown_node_id.clone().into(),
+                    reference_type_id: ReferenceTypeId::Organizes.into(),
+                    requested_new_node_id: ExpandedNodeId::null(),
+                    browse_name: QualifiedName::from("routing"),
+                    node_class: NodeClass::Object,
+                    node_attributes: ExtensionObject::null(),
+                    type_definition: ObjectTypeId::BaseObjectType.into(),
+                }]),
+            }
+            .into(),
+            Ok(ObjectId::AddNodesResponse_Encoding_DefaultBinary),
+        ),
+        (
+            AddReferencesRequest {
+                request_header: make_request_header(),
+                references_to_add: Some(vec![AddReferencesItem {
+                    source_node_id: unknown_node_id.clone(),
+                    reference_type_id: ReferenceTypeId::Organizes.into(),
+                    is_forward: true,
+                    target_server_uri: UAString::null(),
+                    target_node_id: node_id.clone().into(),
+                    target_node_class: NodeClass::Variable,
+                }]),
+            }
+            .into(),
+            Ok(ObjectId::AddReferencesResponse_Encoding_DefaultBinary),
+        ),
+        (
+            DeleteNodesRequest {
+                request_header: make_request_header(),
+                nodes_to_delete: Some(vec![DeleteNodesItem {
+                    node_id: unknown_node_id.clone(),
+                    delete_target_references: false,
+                }]),
+            }
+            .into(),
+            Ok(ObjectId::DeleteNodesResponse_Encoding_DefaultBinary),
+        ),
+        (
+            DeleteReferencesRequest {
+                request_header: make_request_header(),
+                references_to_delete: Some(vec![DeleteReferencesItem {
+                    source_node_id: unknown_node_id.clone(),
+                    reference_type_id: ReferenceTypeId::Organizes.into(),
+                    is_forward: true,
+                    target_node_id: node_id.clone().into(),
+                    delete_bidirectional: false,
+                }]),
+            }
+            .into(),
+            Ok(ObjectId::DeleteReferencesResponse_Encoding_DefaultBinary),
+        ),
+        (
+            make_browse_request(make_request_header()),
+            Ok(ObjectId::BrowseResponse_Encoding_DefaultBinary),
+        ),
+        (
+            BrowseNextRequest {
+                request_header: make_request_header(),
+                release_continuation_points: true,
+                continuation_points: Some(vec![ByteString::from(vec![1u8])]),
+            }
+            .into(),
+            Ok(ObjectId::BrowseNextResponse_Encoding_DefaultBinary),
+        ),
+        (
+            TranslateBrowsePathsToNodeIdsRequest {
+                request_header: make_request_header(),
+                browse_paths: Some(vec![BrowsePath {
+                    starting_node: ObjectId::RootFolder.into(),
+                    relative_path: RelativePath {
+                        elements: Some(vec![RelativePathElement {
+                            reference_type_id: ReferenceTypeId::Organizes.into(),
+                            is_inverse: false,
+                            include_subtypes: true,
+                            target_name: QualifiedName::from("Objects"),
+                        }]),
+                    },
+                }]),
+            }
+            .into(),
+            Ok(ObjectId::TranslateBrowsePathsToNodeIdsResponse_Encoding_DefaultBinary),
+        ),
+        (
+            RegisterNodesRequest {
+                request_header: make_request_header(),
+                nodes_to_register: Some(vec![node_id.clone()]),
+            }
+            .into(),
+            Ok(ObjectId::RegisterNodesResponse_Encoding_DefaultBinary),
+        ),
+        (
+            UnregisterNodesRequest {
+                request_header: make_request_header(),
+                nodes_to_unregister: Some(vec![node_id.clone()]),
+            }
+            .into(),
+            Ok(ObjectId::UnregisterNodesResponse_Encoding_DefaultBinary),
+        ),
+        (
+            QueryFirstRequest {
+                request_header: make_request_header(),
+                view: view(),
+                node_types: Some(vec![NodeTypeDescription {
+                    type_definition_node: ObjectTypeId::FolderType.into(),
+                    include_sub_types: false,
+                    data_to_return: None,
+                }]),
+                filter: ContentFilter { elements: None },
+                max_data_sets_to_return: 0,
+                max_references_to_return: 0,
+            }
+            .into(),
+            Ok(ObjectId::QueryFirstResponse_Encoding_DefaultBinary),
+        ),
+        (
+            QueryNextRequest {
+                request_header: make_request_header(),
+                release_continuation_point: true,
+                continuation_point: ByteString::from(vec![1u8]),
+            }
+            .into(),
+            Err(StatusCode::BadContinuationPointInvalid),
+        ),
+        (
+            make_read_request(&node_id, 1),
+            Ok(ObjectId::ReadResponse_Encoding_DefaultBinary),
+        ),
+        (
+            HistoryReadRequest {
+                request_header: make_request_header(),
+                history_read_details: ExtensionObject::from_encodable(
+                    ObjectId::ReadRawModifiedDetails_Encoding_DefaultBinary,
+                    &read_raw_modified_details,
+                ),
+                timestamps_to_return: TimestampsToReturn::Both,
+                release_continuation_points: false,
+                nodes_to_read: Some(vec![HistoryReadValueId {
+                    node_id: node_id.clone(),
+                    index_range: UAString::null(),
+                    data_encoding: QualifiedName::null(),
+                    continuation_point: ByteString::null(),
+                }]),
+            }
+            .into(),
+            Err(StatusCode::BadHistoryOperationUnsupported),
+        ),
+        (
+            WriteRequest {
+                request_header: make_request_header(),
+                nodes_to_write: Some(vec![WriteValue {
+                    node_id: unknown_node_id.clone(),
+                    attribute_id: AttributeId::Value as u32,
+                    index_range: UAString::null(),
+                    value: DataValue::new_now(1i32),
+                }]),
+            }
+            .into(),
+            Ok(ObjectId::WriteResponse_Encoding_DefaultBinary),
+        ),
+        (
+            HistoryUpdateRequest {
+                request_header: make_request_header(),
+                history_update_details: Some(vec![ExtensionObject::null()]),
+            }
+            .into(),
+            Ok(ObjectId::HistoryUpdateResponse_Encoding_DefaultBinary),
+        ),
+        (
+            CallRequest {
+                request_header: make_request_header(),
+                methods_to_call: Some(vec![CallMethodRequest {
+                    object_id: ObjectId::Server.into(),
+                    method_id: unknown_node_id.clone(),
+                    input_arguments: None,
+                }]),
+            }
+            .into(),
+            Ok(ObjectId::CallResponse_Encoding_DefaultBinary),
+        ),
+        (
+            CreateMonitoredItemsRequest {
+                request_header: make_request_header(),
+                subscription_id,
+                timestamps_to_return: TimestampsToReturn::Both,
+                items_to_create: Some(vec![MonitoredItemCreateRequest::new(
+                    node_id.clone().into(),
+                    MonitoringMode::Reporting,
+                    MonitoringParameters::default(),
+                )]),
+            }
+            .into(),
+            Ok(ObjectId::CreateMonitoredItemsResponse_Encoding_DefaultBinary),
+        ),
+        (
+            ModifyMonitoredItemsRequest {
+                request_header: make_request_header(),
+                subscription_id,
+                timestamps_to_return: TimestampsToReturn::Both,
+                items_to_modify: Some(vec![MonitoredItemModifyRequest {
+                    monitored_item_id: 1000,
+                    requested_parameters: MonitoringParameters::default(),
+                }]),
+            }
+            .into(),
+            Ok(ObjectId::ModifyMonitoredItemsResponse_Encoding_DefaultBinary),
+        ),
+        (
+            SetMonitoringModeRequest {
+                request_header: make_request_header(),
+                subscription_id,
+                monitoring_mode: MonitoringMode::Disabled,
+                monitored_item_ids: Some(vec![1000]),
+            }
+            .into(),
+            Ok(ObjectId::SetMonitoringModeResponse_Encoding_DefaultBinary),
+        ),
+        (
+            SetTriggeringRequest {
+                request_header: make_request_header(),
+                subscription_id,
+                triggering_item_id: 1000,
+                links_to_add: Some(vec![1001]),
+                links_to_remove: None,
+            }
+            .into(),
+            Err(StatusCode::BadMonitoredItemIdInvalid),
+        ),
+        (
+            DeleteMonitoredItemsRequest {
+                request_header: make_request_header(),
+                subscription_id,
+                monitored_item_ids: Some(vec![1000]),
+            }
+            .into(),
+            Ok(ObjectId::DeleteMonitoredItemsResponse_Encoding_DefaultBinary),
+        ),
+        (
+            ModifySubscriptionRequest {
+                request_header: make_request_header(),
+                subscription_id,
+                requested_publishing_interval: 100f64,
+                requested_lifetime_count: 30,
+                requested_max_keep_alive_count: 10,
+                max_notifications_per_publish: 0,
+                priority: 0,
+            }
+            .into(),
+            Ok(ObjectId::ModifySubscriptionResponse_Encoding_DefaultBinary),
+        ),
+        (
+            SetPublishingModeRequest {
+                request_header: make_request_header(),
+                publishing_enabled: true,
+                subscription_ids: Some(vec![subscription_id]),
+            }
+            .into(),
+            Ok(ObjectId::SetPublishingModeResponse_Encoding_DefaultBinary),
+        ),
+        (
+            TransferSubscriptionsRequest {
+                request_header: make_request_header(),
+                subscription_ids: Some(vec![subscription_id + 1]),
+                send_initial_values: false,
+            }
+            .into(),
+            Ok(ObjectId::TransferSubscriptionsResponse_Encoding_DefaultBinary),
+        ),
+        (
+            RepublishRequest {
+                request_header: make_request_header(),
+                subscription_id,
+                retransmit_sequence_number: 1000,
+            }
+            .into(),
+            Err(StatusCode::BadMessageNotAvailable),
+        ),
+        (
+            DeleteSubscriptionsRequest {
+                request_header: make_request_header(),
+                subscription_ids: Some(vec![subscription_id]),
+            }
+            .into(),
+            Ok(ObjectId::DeleteSubscriptionsResponse_Encoding_DefaultBinary),
+        ),
+        (
+            PublishRequest {
+                request_header: make_request_header(),
+                subscription_acknowledgements: None,
+            }
+            .into(),
+            Err(StatusCode::BadNoSubscription),
+        ),
+        (
+            ActivateSessionRequest {
+                request_header: make_request_header(),
+                client_signature: SignatureData::null(),
+                client_software_certificates: None,
+                locale_ids: None,
+                user_identity_token: ExtensionObject::null(),
+                user_token_signature: SignatureData::null(),
+            }
+            .into(),
+            Err(StatusCode::BadTcpEndpointUrlInvalid),
+        ),
+        (
+            CloseSessionRequest {
+                request_header: make_request_header(),
+                delete_subscriptions: true,
+            }
+            .into(),
+            Ok(ObjectId::CloseSessionResponse_Encoding_DefaultBinary),
+        ),
+    ];
+
+    let mut routed = vec![ObjectId::CreateSubscriptionRequest_Encoding_DefaultBinary];
+    for (request, expected_response) in requests {
+        let request_object_id = request.node_id().as_object_id().unwrap();
+        let response = mht.handle_message(request);
+        let response_object_id = response.node_id().as_object_id().unwrap();
+        let response = if response_object_id == ObjectId::ServiceFault_Encoding_DefaultBinary {
+            Err(service_result(response))
+        } else {
+            Ok(response_object_id)
+        };
+        assert_eq!(
+            response, expected_response,
+            "{:?} was not answered by its service",
+            request_object_id
+        );
+        routed.push(request_object_id);
+    }
+
+    // Every registered request type was sent
+    let mut registered = mht.message_handler.registered_services();
+    registered.sort_by_key(|object_id| *object_id as u32);
+    routed.sort_by_key(|object_id| *object_id as u32);
+    assert_eq!(routed, registered);
+}
+
+fn make_read_request(node_id: &NodeId, count: usize) -> SupportedMessage {
+    ReadRequest {
+        request_header: make_request_header(),
+        max_age: 0f64,
+        timestamps_to_return: TimestampsToReturn::Both,
+        nodes_to_read: Some(
+            (0..count)
+                .map(|_| ReadValueId {
+                    node_id: node_id.clone(),
+                    attribute_id: AttributeId::Value as u32,
+                    index_range: UAString::null(),
+                    data_encoding: QualifiedName::null(),
+                })
+                .collect(),
+        ),
+    }
+    .into()
+}
+
+/// Writes the response as the transport does once the client has sent the HELLO, returning the
+/// message that the client decodes from what was written
+fn write_response_to_client(
+    response: SupportedMessage,
+    hello: &HelloMessage,
+    decoding_options: &DecodingOptions,
+) -> SupportedMessage {
+    let secure_channel = SecureChannel::new_no_certificate_store();
+    let mut send_buffer = MessageWriter::new(65535, 0, 0);
+    TcpTransport::apply_hello_limits(hello, &mut send_buffer, decoding_options, 65535);
+    TcpTransport::write_response(&mut send_buffer, 1, response, &secure_channel).unwrap();
+
+    let bytes = send_buffer.bytes_to_write();
+    let decoding_options = secure_channel.decoding_options();
+    let mut stream = Cursor::new(bytes);
+    let mut chunks = Vec::new();
+    while (stream.position() as usize) < stream.get_ref().len() {
+        chunks.push(MessageChunk::decode(&mut stream, &decoding_options).unwrap());
+    }
+    Chunker::decode(&chunks, &secure_channel, None).unwrap()
+}
+
+#[test]
+fn message_too_large() {
+    let mut mht = MessageHandlerTest::new();
+    {
+        let mut session = trace_write_lock!(mht.session);
+        session.set_activated(true);
+    }
+    {
+        let mut server_state = trace_write_lock!(mht.server_state);
+        server_state.max_message_size = 8192;
+    }
+
+    // A variable holding a large value
+    let node_id = NodeId::new(1, "big");
+    {
+        let mut address_space = trace_write_lock!(mht.address_space);
+        VariableBuilder::new(&node_id, "big", "big")
+            .data_type(DataTypeId::String)
+            .value("x".repeat(1000))
+            .organized_by(ObjectId::ObjectsFolder)
+            .insert(&mut address_space);
+    }
+
+    // The response is serviced, its size is checked when it is written to chunks
+    let response = mht.handle_message(make_read_request(&node_id, 20));
+    let response = supported_message_as!(response, ReadResponse);
+    assert_eq!(response.results.as_ref().unwrap().len(), 20);
+
+    // The response exceeds the max message size it is written with, so the client is sent a
+    // fault instead, whichever of the server and client has the smaller limit
+    let write_response = |response: &ReadResponse, server_max: usize, client_max: usize| {
+        let hello = HelloMessage::new("", 65535, 65535, client_max, 0);
+        let decoding_options = DecodingOptions {
+            max_message_size: server_max,
+            ..Default::default()
+        };
+        write_response_to_client(response.clone().into(), &hello, &decoding_options)
+    };
+    for (server_max, client_max) in [(8192, 0), (0, 8192), (65535, 8192)] {
+        let written = write_response(&response, server_max, client_max);
+        assert_eq!(service_result(written), StatusCode::BadResponseTooLarge);
+    }
+    let written = write_response(&response, 65535, 0);
+    assert_eq!(supported_message_as!(written, ReadResponse), response);
+
+    // The client's max chunk count is honoured too
+    let hello = HelloMessage::new("", 65535, MIN_CHUNK_SIZE, 0, 1);
+    let written = write_response_to_client(response.into(), &hello, &DecodingOptions::default());
+    assert_eq!(service_result(written), StatusCode::BadResponseTooLarge);
+
+    // A request which is itself too large is not serviced
+    let response = mht.handle_message(make_read_request(&node_id, 1000));
+    assert_eq!(service_result(response), StatusCode::BadRequestTooLarge);
+
+    // No limit
+    {
+        let mut server_state = trace_write_lock!(mht.server_state);
+        server_state.max_message_size = 0;
+    }
+    let response = mht.handle_message(make_read_request(&node_id, 20));
+    let response = supported_message_as!(response, ReadResponse);
+    assert_eq!(response.results.as_ref().unwrap().len(), 20);
+}
+
+fn make_create_session_request() -> SupportedMessage {
+    CreateSessionRequest {
+        request_header: make_request_header(),
+        client_description: ApplicationDescription::default(),
+        server_uri: UAString::null(),
+        endpoint_url: UAString::from("opc.tcp://localhost:4855/"),
+        session_name: UAString::from("test"),
+        client_nonce: ByteString::null(),
+        client_certificate: ByteString::null(),
+        requested_session_timeout: 60000f64,
+        max_response_message_size: 0,
+    }
+    .into()
+}
+
+#[test]
+fn too_many_sessions() {
+    let mut mht = MessageHandlerTest::new();
+    {
+        let mut server_state = trace_write_lock!(mht.server_state);
+        server_state.max_sessions = 2;
+    }
+
+    // One session is already registered, so there is room for one more
+    let response = mht.handle_message(make_create_session_request());
+    let response = supported_message_as!(response, CreateSessionResponse);
+    assert!(response.response_header.service_result.is_good());
+    let authentication_token = response.authentication_token;
+    assert_eq!(trace_read_lock!(mht.session_manager).len(), 2);
+
+    // The next is rejected and counted in the diagnostics
+    let rejected_session_count = |mht: &MessageHandlerTest| {
+        let server_state = trace_read_lock!(mht.server_state);
+        let diagnostics = trace_read_lock!(server_state.diagnostics);
+        diagnostics
+            .server_diagnostics_summary()
+            .rejected_session_count
+    };
+    let rejected_before = rejected_session_count(&mht);
+    let response = mht.handle_message(make_create_session_request());
+    assert_eq!(service_result(response), StatusCode::BadTooManySessions);
+    assert_eq!(rejected_session_count(&mht), rejected_before + 1);
+    assert_eq!(trace_read_lock!(mht.session_manager).len(), 2);
+
+    // Closing the new session frees its slot
+    let mut request_header = make_request_header();
+    request_header.authentication_token = authentication_token;
+    let response = mht.handle_message(
+        CloseSessionRequest {
+            request_header,
+            delete_subscriptions: true,
+        }
+        .into(),
+    );
+    let response = supported_message_as!(response, CloseSessionResponse);
+    assert!(response.response_header.service_result.is_good());
+    assert_eq!(trace_read_lock!(mht.session_manager).len(), 1);
+
+    let response = mht.handle_message(make_create_session_request());
+    let response = supported_message_as!(response, CreateSessionResponse);
+    assert!(response.response_header.service_result.is_good());
+}
+
+/// Holds on to every request event it is told about
+struct CapturingRequestObserver {
+    events: Arc<Mutex<Vec<RequestEvent>>>,
+}
+
+impl RequestObserver for CapturingRequestObserver {
+    fn on_request(&self, event: &RequestEvent) {
+        trace_lock!(self.events).push(event.clone());
+    }
+}
+
+#[test]
+fn request_events() {
+    let mut mht = MessageHandlerTest::new();
+    let events = Arc::new(Mutex::new(Vec::new()));
+    {
+        let mut server_state = trace_write_lock!(mht.server_state);
+        server_state.set_request_observer(Box::new(CapturingRequestObserver {
+            events: events.clone(),
+        }));
+    }
+    {
+        let mut session = trace_write_lock!(mht.session);
+        session.set_activated(true);
+    }
+    let session_id = trace_read_lock!(mht.session).session_id().clone();
+
+    let logged = crate::console_logging::capture::capture(|| {
+        let mut request_header = make_request_header();
+        request_header.request_handle = 1234;
+        let _ = mht.handle_message(make_browse_request(request_header));
+
+        // A request on no session
+        let mut request_header = make_request_header();
+        request_header.authentication_token = NodeId::new(0, 1000);
+        let _ = mht.handle_message(make_browse_request(request_header));
+    });
+
+    let events = trace_lock!(events);
+    assert_eq!(events.len(), 2);
+
+    // Each event is also logged at debug level
+    events.iter().for_each(|event| {
+        assert!(logged.contains(&(log::Level::Debug, event.to_string())));
+    });
+
+    let event = &events[0];
+    assert_eq!(event.request_type, "BrowseRequest");
+    assert_eq!(event.session_id, Some(session_id.clone()));
+    assert_eq!(event.request_handle, 1234);
+    assert_eq!(event.service_result, Some(StatusCode::Good));
+    assert_eq!(
+        event.to_string(),
+        format!(
+            "request_type=BrowseRequest session_id={} request_handle=1234 elapsed_us={} service_result=Good",
+            session_id,
+            event.elapsed.as_micros()
+        )
+    );
+
+    let event = &events[1];
+    assert_eq!(event.request_type, "BrowseRequest");
+    assert_eq!(event.session_id, None);
+    assert_eq!(event.service_result, Some(StatusCode::BadSessionIdInvalid));
+    assert!(event.to_string().contains(" session_id=none "));
+}
+
+/// Takes the server state lock when it is told about a request
+struct LockingRequestObserver {
+    server_state: Arc<RwLock<ServerState>>,
+    calls: Arc<Mutex<usize>>,
+}
+
+impl RequestObserver for LockingRequestObserver {
+    fn on_request(&self, _event: &RequestEvent) {
+        let _server_state = trace_write_lock!(self.server_state);
+        *trace_lock!(self.calls) += 1;
+    }
+}
+
+#[test]
+fn request_observer_called_without_server_state_lock() {
+    let mut mht = MessageHandlerTest::new();
+    let calls = Arc::new(Mutex::new(0));
+    {
+        let mut server_state = trace_write_lock!(mht.server_state);
+        server_state.set_request_observer(Box::new(LockingRequestObserver {
+            server_state: mht.server_state.clone(),
+            calls: calls.clone(),
+        }));
+    }
+    let _ = mht.handle_message(make_browse_request(make_request_header()));
+    assert_eq!(*trace_lock!(calls), 1);
+}
+
+#[test]
+fn service_metrics() {
+    let mut mht = MessageHandlerTest::new_with_server(
+        ServerBuilder::new_sample()
+            .service_metrics()
+            .server()
+            .unwrap(),
+    );
+    {
+        let mut session = trace_write_lock!(mht.session);
+        session.set_activated(true);
+    }
+
+    let _ = mht.handle_message(make_browse_request(make_request_header()));
+    let _ = mht.handle_message(make_browse_request(make_request_header()));
+    let _ = mht.handle_message(make_read_request(&ObjectId::Server.into(), 1));
+    // A browse on no session has a bad result
+    let mut request_header = make_request_header();
+    request_header.authentication_token = NodeId::new(0, 1000);
+    let _ = mht.handle_message(make_browse_request(request_header));
+
+    let service_metrics = trace_read_lock!(mht.server_state).service_metrics();
+
+    let browse = service_metrics.service("BrowseRequest").unwrap();
+    assert_eq!(browse.count, 3);
+    assert_eq!(browse.bad_count, 1);
+    assert_eq!(browse.duration_histogram.iter().sum::<u64>(), 3);
+    assert!(browse.max_elapsed_us <= browse.total_elapsed_us);
+
+    let read = service_metrics.service("ReadRequest").unwrap();
+    assert_eq!(read.count, 1);
+    assert_eq!(read.bad_count, 0);
+    assert_eq!(read.duration_histogram.iter().sum::<u64>(), 1);
+
+    assert!(service_metrics.service("WriteRequest").is_none());
+}
+
+#[test]
+fn service_metrics_disabled() {
+    let mut mht = MessageHandlerTest::new();
+    {
+        let mut session = trace_write_lock!(mht.session);
+        session.set_activated(true);
+    }
+
+    // Nothing is recorded unless the server is configured to record service metrics
+    let _ = mht.handle_message(make_browse_request(make_request_header()));
+    let service_metrics = trace_read_lock!(mht.server_state).service_metrics();
+    assert!(service_metrics.services.is_empty());
+}
+
+#[test]
+fn session_diagnostics() {
+    let mut mht = MessageHandlerTest::new();
+    {
+        let mut session = trace_write_lock!(mht.session);
+        session.set_activated(true);
+        session.set_session_name(UAString::from("TestSession"));
+        session.set_client_description(ApplicationDescription {
+            application_uri: UAString::from("urn:testclient"),
+            application_name: LocalizedText::from("Test Client"),
+            ..Default::default()
+        });
+        session.register_session(mht.address_space.clone());
+    }
+
+    // Reads a value found by a path relative to the session's diagnostics object
+    let address_space = mht.address_space.clone();
+    let session_diagnostics_value = |path: &str| -> ExtensionObject {
+        let address_space = trace_read_lock!(address_space);
+        let relative_path = format!(
+            ".ServerDiagnostics.SessionsDiagnosticsSummary.TestSession.SessionDiagnostics{}",
+            path
+        );
+        let node_ids = find_nodes_relative_path_simple(
+            &*address_space,
+            &ObjectId::Server.into(),
+            &relative_path,
+        )
+        .unwrap();
+        assert_eq!(node_ids.len(), 1);
+        let value = address_space
+            .find_variable_by_ref(&node_ids[0])
+            .unwrap()
+            .value(
+                TimestampsToReturn::Neither,
+                NumericRange::None,
+                &QualifiedName::null(),
+                0.0,
+            )
+            .value
+            .unwrap();
+        if let Variant::ExtensionObject(value) = value {
+            *value
+        } else {
+            panic!("Expected an extension object, got {:?}", value);
+        }
+    };
+    let request_counts = |name: &str| -> (u32, u32) {
+        let counter = session_diagnostics_value(&format!(".{}", name))
+            .decode_inner::<ServiceCounterDataType>(&DecodingOptions::test())
+            .unwrap();
+        (counter.total_count, counter.error_count)
+    };
+
+    let client_description = session_diagnostics_value(".ClientDescription")
+        .decode_inner::<ApplicationDescription>(&DecodingOptions::test())
+        .unwrap();
+    assert_eq!(
+        client_description.application_uri.as_ref(),
+        "urn:testclient"
+    );
+    assert_eq!(request_counts("TotalRequestCount"), (0, 0));
+    assert_eq!(request_counts("BrowseCount"), (0, 0));
+
+    let _ = mht.handle_message(make_browse_request(make_request_header()));
+    assert_eq!(request_counts("TotalRequestCount"), (1, 0));
+    assert_eq!(request_counts("BrowseCount"), (1, 0));
+    assert_eq!(request_counts("ReadCount"), (0, 0));
+
+    // The whole structure reflects the same counts
+    let session_diagnostics = session_diagnostics_value("")
+        .decode_inner::<SessionDiagnosticsDataType>(&DecodingOptions::test())
+        .unwrap();
+    assert_eq!(
+        &session_diagnostics.session_id,
+        trace_read_lock!(mht.session).session_id()
+    );
+    assert_eq!(session_diagnostics.session_name.as_ref(), "TestSession");
+    assert_eq!(session_diagnostics.total_request_count.total_count, 1);
+    assert_eq!(session_diagnostics.browse_count.total_count, 1);
+    assert!(!session_diagnostics.client_connection_time.is_null());
+    assert_eq!(
+        session_diagnostics.client_last_contact_time,
+        trace_read_lock!(mht.session)
+            .last_service_request_timestamp()
+            .into()
+    );
+    assert_eq!(session_diagnostics.current_subscriptions_count, 0);
+
+    // The subscription counts are as they were after the last request
+    let mut request = create_subscription_request(0, 0);
+    request.request_header = make_request_header();
+    let _ = mht.handle_message(request.into());
+    let session_diagnostics = session_diagnostics_value("")
+        .decode_inner::<SessionDiagnosticsDataType>(&DecodingOptions::test())
+        .unwrap();
+    assert_eq!(session_diagnostics.current_subscriptions_count, 1);
+    assert_eq!(session_diagnostics.current_monitored_items_count, 0);
+    assert_eq!(session_diagnostics.current_publish_requests_in_queue, 0);
+}
+
+#[test]
+fn read_through_address_space_provider() {
+    let node_id = NodeId::new(2, "mock");
+    let provider = Arc::new(RwLock::new(MockAddressSpaceProvider::new(vec![(
+        node_id.clone(),
+        Variant::Int32(42),
+    )])));
+    let mut server = ServerBuilder::new_sample().server().unwrap();
+    server.set_address_space_provider(provider.clone());
+    let mut mht = MessageHandlerTest::new_with_server(server);
+    {
+        let mut session = trace_write_lock!(mht.session);
+        session.set_activated(true);
+    }
+
+    let request = ReadRequest {
+        request_header: make_request_header(),
+        max_age: 0f64,
+        timestamps_to_return: TimestampsToReturn::Neither,
+        nodes_to_read: Some(vec![
+            ReadValueId::from(node_id.clone()),
+            ReadValueId::from(&ObjectId::Server.into()),
+        ]),
+    };
+    let response = mht.handle_message(request.into());
+    let response = supported_message_as!(response, ReadResponse);
+    let results = response.results.unwrap();
+
+    // The value comes from the provider, which knows nothing of the server's own nodes
+    assert_eq!(results[0].value, Some(Variant::Int32(42)));
+    assert_eq!(results[1].status, Some(StatusCode::BadNodeIdUnknown));
+    assert_eq!(
+        trace_read_lock!(provider).lookups.load(Ordering::Relaxed),
+        2
+    );
+}
@@ -2,10 +2,11 @@ use crate::supported_message_as;
 use crate::sync::*;
 use crate::types::{
     node_ids::{MethodId, ObjectId},
-    service_types::{CallMethodRequest, CallMethodResult, CallRequest, CallResponse},
+    service_types::{Argument, CallMethodRequest, CallMethodResult, CallRequest, CallResponse},
     status_code::StatusCode,
 };
 
+use crate::server::callbacks;
 use crate::server::services::{
     method::MethodService, monitored_item::MonitoredItemService, subscription::SubscriptionService,
 };
@@ -390,3 +391,196 @@ fn call_resend_data() {
         }
     });
 }
+
+struct AddNumbers;
+
+impl callbacks::Method for AddNumbers {
+    fn call(
+        &mut self,
+        _session_id: &NodeId,
+        _session_manager: Arc<RwLock<SessionManager>>,
+        request: &CallMethodRequest,
+    ) -> Result<CallMethodResult, StatusCode> {
+        // Arguments have already been validated against the InputArguments of the method
+        let args = request.input_arguments.as_ref().unwrap();
+        let (a, b) = match (&args[0], &args[1]) {
+            (Variant::Int32(a), Variant::Int32(b)) => (*a, *b),
+            _ => panic!("Arguments should have been validated"),
+        };
+        Ok(CallMethodResult {
+            status_code: StatusCode::Good,
+            input_argument_results: Some(vec![StatusCode::Good, StatusCode::Good]),
+            input_argument_diagnostic_infos: None,
+            output_arguments: Some(vec![Variant::from(a + b)]),
+        })
+    }
+}
+
+fn add_numbers_method(address_space: Arc<RwLock<AddressSpace>>) -> (NodeId, NodeId) {
+    let object_id = NodeId::new(1, "Calculator");
+    let method_id = NodeId::new(1, "Add");
+    let mut address_space = trace_write_lock!(address_space);
+    ObjectBuilder::new(&object_id, "Calculator", "Calculator")
+        .organized_by(ObjectId::ObjectsFolder)
+        .insert(&mut address_space);
+    let argument = |name: &str| Argument {
+        name: UAString::from(name),
+        data_type: DataTypeId::Int32.into(),
+        value_rank: -1,
+        array_dimensions: None,
+        description: LocalizedText::null(),
+    };
+    MethodBuilder::new(&method_id, "Add", "Add")
+        .component_of(object_id.clone())
+        .input_args(&mut address_space, &[argument("A"), argument("B")])
+        .output_args(&mut address_space, &[argument("Sum")])
+        .callback(Box::new(AddNumbers))
+        .insert(&mut address_space);
+    (object_id, method_id)
+}
+
+#[test]
+fn call_method_with_input_arguments() {
+    do_method_service_test(|server_state, session_manager, session, address_space, s| {
+        let (object_id, method_id) = add_numbers_method(address_space.clone());
+
+        let call = |args: Vec<Variant>| {
+            let request = new_call_method_request(object_id.clone(), method_id.clone(), Some(args));
+            call_single(
+                s,
+                server_state.clone(),
+                session_manager.clone(),
+                session.clone(),
+                address_space.clone(),
+                request,
+            )
+            .unwrap()
+        };
+
+        // Valid arguments
+        let response = call(vec![Variant::from(2i32), Variant::from(3i32)]);
+        assert_eq!(response.status_code, StatusCode::Good);
+        assert_eq!(response.output_arguments, Some(vec![Variant::from(5i32)]));
+
+        // Too few and too many arguments
+        let response = call(vec![Variant::from(2i32)]);
+        assert_eq!(response.status_code, StatusCode::BadArgumentsMissing);
+        let response = call(vec![2i32.into(), 3i32.into(), 4i32.into()]);
+        assert_eq!(response.status_code, StatusCode::BadTooManyArguments);
+
+        // Wrong type for the second argument
+        let response = call(vec![Variant::from(2i32), Variant::from("3")]);
+        assert_eq!(response.status_code, StatusCode::BadInvalidArgument);
+        assert_eq!(
+            response.input_argument_results,
+            Some(vec![StatusCode::Good, StatusCode::BadTypeMismatch])
+        );
+        assert!(response.output_arguments.is_none());
+    });
+}
+
+struct AcceptArguments;
+
+impl callbacks::Method for AcceptArguments {
+    fn call(
+        &mut self,
+        _session_id: &NodeId,
+        _session_manager: Arc<RwLock<SessionManager>>,
+        _request: &CallMethodRequest,
+    ) -> Result<CallMethodResult, StatusCode> {
+        Ok(CallMethodResult {
+            status_code: StatusCode::Good,
+            input_argument_results: None,
+            input_argument_diagnostic_infos: None,
+            output_arguments: None,
+        })
+    }
+}
+
+#[test]
+fn call_method_argument_value_ranks() {
+    do_method_service_test(|server_state, session_manager, session, address_space, s| {
+        let object_id = NodeId::new(1, "Arrays");
+        let method_id = NodeId::new(1, "TakeArrays");
+        {
+            let mut address_space = trace_write_lock!(address_space);
+            ObjectBuilder::new(&object_id, "Arrays", "Arrays")
+                .organized_by(ObjectId::ObjectsFolder)
+                .insert(&mut address_space);
+            let argument =
+                |name: &str, value_rank: i32, array_dimensions: Option<Vec<u32>>| Argument {
+                    name: UAString::from(name),
+                    data_type: DataTypeId::Int32.into(),
+                    value_rank,
+                    array_dimensions,
+                    description: LocalizedText::null(),
+                };
+            MethodBuilder::new(&method_id, "TakeArrays", "TakeArrays")
+                .component_of(object_id.clone())
+                .input_args(
+                    &mut address_space,
+                    &[
+                        argument("Any", -2, None),
+                        argument("OneOrMoreDimensions", 0, None),
+                        argument("ScalarOrOneDimension", -3, None),
+                        argument("Pair", 1, Some(vec![2])),
+                    ],
+                )
+                .callback(Box::new(AcceptArguments))
+                .insert(&mut address_space);
+        }
+
+        let call = |args: Vec<Variant>| {
+            let request = new_call_method_request(object_id.clone(), method_id.clone(), Some(args));
+            call_single(
+                s,
+                server_state.clone(),
+                session_manager.clone(),
+                session.clone(),
+                address_space.clone(),
+                request,
+            )
+            .unwrap()
+        };
+        let matrix = || -> Variant {
+            let values = (0..4).map(Variant::from).collect::<Vec<_>>();
+            Array::new_multi(VariantTypeId::Int32, values, vec![2u32, 2])
+                .unwrap()
+                .into()
+        };
+
+        // Values that fit each value rank
+        let response = call(vec![
+            Variant::from(1i32),
+            Variant::from(vec![1i32, 2, 3]),
+            Variant::from(1i32),
+            Variant::from(vec![1i32, 2]),
+        ]);
+        assert_eq!(response.status_code, StatusCode::Good);
+        let response = call(vec![
+            matrix(),
+            matrix(),
+            Variant::from(vec![1i32, 2, 3]),
+            Variant::from(vec![1i32, 2]),
+        ]);
+        assert_eq!(response.status_code, StatusCode::Good);
+
+        // A scalar where an array is needed, too many dimensions and an array that is too long
+        let response = call(vec![
+            matrix(),
+            Variant::from(1i32),
+            matrix(),
+            Variant::from(vec![1i32, 2, 3]),
+        ]);
+        assert_eq!(response.status_code, StatusCode::BadInvalidArgument);
+        assert_eq!(
+            response.input_argument_results,
+            Some(vec![
+                StatusCode::Good,
+                StatusCode::BadTypeMismatch,
+                StatusCode::BadTypeMismatch,
+                StatusCode::BadTypeMismatch
+            ])
+        );
+    });
+}
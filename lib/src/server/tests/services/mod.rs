@@ -1,6 +1,16 @@
-use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
 
 use crate::server::{
+    address_space::{
+        provider::AddressSpaceProvider,
+        references::{Reference, ReferenceDirection},
+    },
     prelude::*,
     services::{monitored_item::MonitoredItemService, subscription::SubscriptionService},
     session::Session,
@@ -13,6 +23,7 @@ struct ServiceTest {
     pub address_space: Arc<RwLock<AddressSpace>>,
     pub session: Arc<RwLock<Session>>,
     pub session_manager: Arc<RwLock<SessionManager>>,
+    pub certificate_store: Arc<RwLock<CertificateStore>>,
 }
 
 impl ServiceTest {
@@ -37,6 +48,7 @@ impl ServiceTest {
             address_space,
             session,
             session_manager,
+            certificate_store: server.certificate_store(),
         }
     }
 
@@ -166,11 +178,149 @@ where
     }
 }
 
+/// Holds the values of its variables in a map instead of an address space, making a node for a
+/// variable each time it is looked up as something like a database would. Lookups are counted.
+struct MockAddressSpaceProvider {
+    values: HashMap<NodeId, Variant>,
+    lookups: AtomicUsize,
+    last_modified: DateTimeUtc,
+}
+
+impl MockAddressSpaceProvider {
+    fn new(values: Vec<(NodeId, Variant)>) -> MockAddressSpaceProvider {
+        MockAddressSpaceProvider {
+            values: values.into_iter().collect(),
+            lookups: AtomicUsize::new(0),
+            last_modified: chrono::Utc::now(),
+        }
+    }
+}
+
+impl AddressSpaceProvider for MockAddressSpaceProvider {
+    fn with_node(&self, node_id: &NodeId, f: &mut dyn FnMut(&NodeType)) {
+        self.lookups.fetch_add(1, Ordering::Relaxed);
+        if let Some(value) = self.values.get(node_id) {
+            let name = format!("{}", node_id);
+            let mut variable = Variable::new(node_id, &name, &name, value.clone());
+            variable.set_access_level(AccessLevel::CURRENT_READ | AccessLevel::CURRENT_WRITE);
+            variable.set_user_access_level(
+                UserAccessLevel::CURRENT_READ | UserAccessLevel::CURRENT_WRITE,
+            );
+            f(&variable.into());
+        }
+    }
+
+    fn set_attribute(
+        &mut self,
+        node_id: &NodeId,
+        attribute_id: AttributeId,
+        index_range: NumericRange,
+        value: Variant,
+    ) -> Result<(), StatusCode> {
+        match self.values.get_mut(node_id) {
+            Some(_) if attribute_id != AttributeId::Value || index_range != NumericRange::None => {
+                Err(StatusCode::BadWriteNotSupported)
+            }
+            Some(stored_value) => {
+                *stored_value = value;
+                Ok(())
+            }
+            None => Err(StatusCode::BadNodeIdUnknown),
+        }
+    }
+
+    fn find_references(
+        &self,
+        _node_id: &NodeId,
+        _reference_filter: Option<(NodeId, bool)>,
+    ) -> Option<Vec<Reference>> {
+        None
+    }
+
+    fn find_references_by_direction(
+        &self,
+        _node_id: &NodeId,
+        _browse_direction: BrowseDirection,
+        _reference_filter: Option<(NodeId, bool)>,
+    ) -> (Vec<Reference>, usize) {
+        (Vec::new(), 0)
+    }
+
+    fn is_subtype(&self, subtype_id: &NodeId, base_type_id: &NodeId) -> bool {
+        subtype_id == base_type_id
+    }
+
+    fn last_modified(&self) -> DateTimeUtc {
+        self.last_modified
+    }
+
+    fn default_namespace(&self) -> u16 {
+        2
+    }
+
+    fn insert(
+        &mut self,
+        _node: NodeType,
+        _references: Option<&[(&NodeId, &NodeId, ReferenceDirection)]>,
+    ) -> bool {
+        false
+    }
+
+    fn delete(&mut self, node_id: &NodeId, _delete_target_references: bool) -> bool {
+        self.values.remove(node_id).is_some()
+    }
+
+    fn insert_reference(
+        &mut self,
+        _node_id: &NodeId,
+        _target_node_id: &NodeId,
+        _reference_type_id: &NodeId,
+    ) {
+    }
+
+    fn delete_reference(
+        &mut self,
+        _node_id: &NodeId,
+        _target_node_id: &NodeId,
+        _reference_type_id: &NodeId,
+    ) -> bool {
+        false
+    }
+
+    fn find_objects_by_type(
+        &self,
+        _object_type_id: &NodeId,
+        _include_subtypes: bool,
+    ) -> Option<Vec<NodeId>> {
+        None
+    }
+
+    fn find_variables_by_type(
+        &self,
+        _variable_type_id: &NodeId,
+        _include_subtypes: bool,
+    ) -> Option<Vec<NodeId>> {
+        None
+    }
+
+    fn call_method(
+        &mut self,
+        _server_state: &ServerState,
+        _session_id: &NodeId,
+        _session_manager: Arc<RwLock<SessionManager>>,
+        _request: &CallMethodRequest,
+    ) -> Result<CallMethodResult, StatusCode> {
+        Err(StatusCode::BadMethodInvalid)
+    }
+}
+
 pub mod attribute;
 pub mod discovery;
+pub mod message_handler;
 pub mod method;
 pub mod monitored_item;
 pub mod node_management;
+pub mod query;
 pub mod session;
 pub mod subscription;
 pub mod view;
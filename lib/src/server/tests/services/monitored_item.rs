@@ -1,11 +1,13 @@
 use std::collections::HashSet;
 use std::ops::Add;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use chrono::{self, Utc};
 
 use super::*;
 use crate::{
     server::{
+        address_space::AttrFnGetter,
         services::{monitored_item::MonitoredItemService, subscription::SubscriptionService},
         subscriptions::{
             monitored_item::*,
@@ -174,12 +176,14 @@ fn set_triggering(
     (response.add_results, response.remove_results)
 }
 
-fn publish_request(
+fn publish_request<P>(
     now: &DateTimeUtc,
     session: Arc<RwLock<Session>>,
-    address_space: Arc<RwLock<AddressSpace>>,
+    address_space: Arc<RwLock<P>>,
     ss: &SubscriptionService,
-) {
+) where
+    P: AddressSpaceProvider + ?Sized,
+{
     let request_id = 1001;
     let request = PublishRequest {
         request_header: RequestHeader::dummy(),
@@ -230,7 +234,7 @@ fn publish_tick_no_response(
     let now = now.add(duration);
     let mut session = trace_write_lock!(session);
     let address_space = trace_read_lock!(address_space);
-    let _ = session.tick_subscriptions(&now, &address_space, TickReason::TickTimerFired);
+    let _ = session.tick_subscriptions(&now, &*address_space, TickReason::TickTimerFired);
     assert_eq!(
         session.subscriptions_mut().publish_response_queue().len(),
         0
@@ -240,15 +244,16 @@ fn publish_tick_no_response(
 
 /// Does a publish, ticks by a duration and then calls the function to handle the response. The
 /// new timestamp is returned so it can be called again.
-fn publish_tick_response<T>(
+fn publish_tick_response<P, T>(
     session: Arc<RwLock<Session>>,
     ss: &SubscriptionService,
-    address_space: Arc<RwLock<AddressSpace>>,
+    address_space: Arc<RwLock<P>>,
     now: DateTimeUtc,
     duration: chrono::Duration,
     handler: T,
 ) -> DateTimeUtc
 where
+    P: AddressSpaceProvider + ?Sized,
     T: FnOnce(PublishResponse),
 {
     publish_request(&now, session.clone(), address_space.clone(), ss);
@@ -256,7 +261,7 @@ where
     {
         let mut session = trace_write_lock!(session);
         let address_space = trace_read_lock!(address_space);
-        let _ = session.tick_subscriptions(&now, &address_space, TickReason::TickTimerFired);
+        let _ = session.tick_subscriptions(&now, &*address_space, TickReason::TickTimerFired);
         assert_eq!(
             session.subscriptions_mut().publish_response_queue().len(),
             1
@@ -303,6 +308,15 @@ fn assert_first_notification_is_i32(monitored_item: &mut MonitoredItem, value: i
     }
 }
 
+fn assert_first_notification_is_f64(monitored_item: &mut MonitoredItem, value: f64) {
+    let notification = monitored_item.oldest_notification_message().unwrap();
+    if let Notification::MonitoredItemNotification(notification) = notification {
+        assert_eq!(notification.value.value.unwrap(), Variant::Double(value));
+    } else {
+        panic!();
+    }
+}
+
 #[test]
 fn data_change_filter_test() {
     let mut filter = DataChangeFilter {
@@ -521,6 +535,419 @@ fn monitored_item_data_change_filter() {
     )
 }
 
+#[test]
+fn disabled_monitored_item_is_not_sampled() {
+    do_subscription_service_test(
+        |server_state,
+         _session,
+         _address_space,
+         _ss: SubscriptionService,
+         _mis: MonitoredItemService| {
+            let mut address_space = make_address_space();
+            let server_state = trace_read_lock!(server_state);
+
+            // The variable counts how many times its value is read
+            let reads = Arc::new(AtomicUsize::new(0));
+            {
+                let reads = reads.clone();
+                address_space
+                    .find_variable_mut(test_var_node_id())
+                    .unwrap()
+                    .set_value_getter(AttrFnGetter::new_boxed(move |_, _, _, _, _, _| {
+                        reads.fetch_add(1, Ordering::Relaxed);
+                        Ok(Some(DataValue::new_now(0u32)))
+                    }));
+            }
+
+            // Sampled whenever the publishing interval elapses
+            let mut monitored_item = MonitoredItem::new(
+                &chrono::Utc::now(),
+                1,
+                TimestampsToReturn::Both,
+                &server_state,
+                &make_create_request_data_change_filter(-1f64, 5),
+            )
+            .unwrap();
+            let now = Utc::now();
+            let _ = monitored_item.tick(&now, &address_space, true, false);
+            assert_eq!(reads.load(Ordering::Relaxed), 1);
+
+            // A disabled item never reads the node, even when data is to be resent
+            monitored_item.set_monitoring_mode(MonitoringMode::Disabled);
+            for resend_data in [false, true] {
+                assert_eq!(
+                    monitored_item.tick(&now, &address_space, true, resend_data),
+                    TickResult::NoChange
+                );
+            }
+            assert_eq!(reads.load(Ordering::Relaxed), 1);
+
+            // Sampling resumes when it is enabled again
+            monitored_item.set_monitoring_mode(MonitoringMode::Sampling);
+            assert_eq!(
+                monitored_item.tick(&now, &address_space, true, false),
+                TickResult::ValueChanged
+            );
+            assert_eq!(reads.load(Ordering::Relaxed), 2);
+        },
+    )
+}
+
+#[test]
+fn monitored_item_sampling_overflow() {
+    do_subscription_service_test(
+        |server_state,
+         _session,
+         _address_space,
+         _ss: SubscriptionService,
+         _mis: MonitoredItemService| {
+            let mut address_space = make_address_space();
+            let server_state = trace_read_lock!(server_state);
+
+            // A value that changes faster than it is published, sampled into a queue of 2
+            [true, false].iter().for_each(|discard_oldest| {
+                let mut request = make_create_request_data_change_filter(100f64, 2);
+                request.requested_parameters.discard_oldest = *discard_oldest;
+                let mut now = Utc::now();
+                let mut monitored_item =
+                    MonitoredItem::new(&now, 1, TimestampsToReturn::Both, &server_state, &request)
+                        .unwrap();
+                let sampling_interval =
+                    chrono::Duration::milliseconds(monitored_item.sampling_interval() as i64);
+                for value in 1..=4u32 {
+                    now = now.add(sampling_interval);
+                    let _ = address_space.set_variable_value(
+                        test_var_node_id(),
+                        value,
+                        &now.into(),
+                        &now.into(),
+                    );
+                    assert_eq!(
+                        monitored_item.tick(&now, &address_space, false, false),
+                        TickResult::ReportValueChanged
+                    );
+                }
+                assert!(monitored_item.queue_overflow());
+
+                // Nothing is sampled until the interval elapses again
+                let _ = address_space.set_variable_value(
+                    test_var_node_id(),
+                    5u32,
+                    &now.into(),
+                    &now.into(),
+                );
+                now = now.add(sampling_interval / 2);
+                let _ = monitored_item.tick(&now, &address_space, false, false);
+                assert_eq!(monitored_item.notification_queue().len(), 2);
+
+                // The overflow bit is set on the value after the discarded one, which is the
+                // oldest one left when discarding oldest and the newest when discarding newest
+                let values = monitored_item
+                    .all_notifications()
+                    .unwrap()
+                    .into_iter()
+                    .map(|notification| {
+                        let Notification::MonitoredItemNotification(notification) = notification
+                        else {
+                            panic!("Expected a data change");
+                        };
+                        let overflow = notification.value.status().contains(StatusCode::OVERFLOW);
+                        (notification.value.value.unwrap(), overflow)
+                    })
+                    .collect::<Vec<(Variant, bool)>>();
+                if *discard_oldest {
+                    assert_eq!(
+                        values,
+                        vec![(Variant::UInt32(3), true), (Variant::UInt32(4), false)]
+                    );
+                } else {
+                    assert_eq!(
+                        values,
+                        vec![(Variant::UInt32(1), false), (Variant::UInt32(4), true)]
+                    );
+                }
+                assert!(!monitored_item.queue_overflow());
+            });
+        },
+    )
+}
+
+fn make_create_request_deadband(
+    node_id: NodeId,
+    deadband_type: DeadbandType,
+    deadband_value: f64,
+) -> MonitoredItemCreateRequest {
+    let filter = ExtensionObject::from_encodable(
+        ObjectId::DataChangeFilter_Encoding_DefaultBinary,
+        &DataChangeFilter {
+            trigger: DataChangeTrigger::StatusValue,
+            deadband_type: deadband_type as u32,
+            deadband_value,
+        },
+    );
+    make_create_request(-1f64, 5, node_id, AttributeId::Value, filter)
+}
+
+fn set_double_value(address_space: &mut AddressSpace, node_id: &NodeId, value: f64) {
+    if let &mut NodeType::Variable(ref mut node) = address_space.find_node_mut(node_id).unwrap() {
+        node.set_value(NumericRange::None, Variant::Double(value))
+            .unwrap();
+    } else {
+        panic!("Expected a variable, didn't get one!!");
+    }
+}
+
+#[test]
+fn monitored_item_data_change_deadband() {
+    do_subscription_service_test(
+        |server_state,
+         _session,
+         _address_space,
+         _ss: SubscriptionService,
+         _mis: MonitoredItemService| {
+            let mut address_space = make_address_space();
+            let node_id = NodeId::new(1, "deadband");
+            VariableBuilder::new(&node_id, "deadband", "deadband")
+                .data_type(DataTypeId::Double)
+                .value(10f64)
+                .organized_by(ObjectId::ObjectsFolder)
+                .insert(&mut address_space);
+
+            let server_state = trace_read_lock!(server_state);
+
+            // A negative deadband is rejected
+            let mut monitored_item = MonitoredItem::new(
+                &chrono::Utc::now(),
+                1,
+                TimestampsToReturn::Both,
+                &server_state,
+                &make_create_request_deadband(node_id.clone(), DeadbandType::Absolute, -1f64),
+            )
+            .unwrap();
+            assert_eq!(
+                monitored_item.validate_filter(&address_space),
+                Err(StatusCode::BadDeadbandFilterInvalid)
+            );
+
+            // Absolute deadband of 1.0
+            let mut monitored_item = MonitoredItem::new(
+                &chrono::Utc::now(),
+                2,
+                TimestampsToReturn::Both,
+                &server_state,
+                &make_create_request_deadband(node_id.clone(), DeadbandType::Absolute, 1f64),
+            )
+            .unwrap();
+            assert!(monitored_item.validate_filter(&address_space).is_ok());
+
+            let now = Utc::now();
+
+            // First value is always reported
+            assert_eq!(
+                monitored_item.tick(&now, &address_space, true, false),
+                TickResult::ReportValueChanged
+            );
+            assert_first_notification_is_f64(&mut monitored_item, 10f64);
+
+            // A change of 0.5 is inside the deadband and is suppressed
+            set_double_value(&mut address_space, &node_id, 10.5f64);
+            assert_eq!(
+                monitored_item.tick(&now, &address_space, true, false),
+                TickResult::NoChange
+            );
+            assert!(monitored_item.notification_queue().is_empty());
+
+            // A change of 2.0 from the last reported value passes
+            set_double_value(&mut address_space, &node_id, 12f64);
+            assert_eq!(
+                monitored_item.tick(&now, &address_space, true, false),
+                TickResult::ReportValueChanged
+            );
+            assert_first_notification_is_f64(&mut monitored_item, 12f64);
+        },
+    )
+}
+
+#[test]
+fn monitored_item_data_change_percent_deadband() {
+    do_subscription_service_test(
+        |server_state,
+         _session,
+         _address_space,
+         _ss: SubscriptionService,
+         _mis: MonitoredItemService| {
+            let mut address_space = make_address_space();
+            let node_id = NodeId::new(1, "percent");
+            VariableBuilder::new(&node_id, "percent", "percent")
+                .data_type(DataTypeId::Double)
+                .value(10f64)
+                .organized_by(ObjectId::ObjectsFolder)
+                .insert(&mut address_space);
+            let no_range_node_id = NodeId::new(1, "no_range");
+            VariableBuilder::new(&no_range_node_id, "no_range", "no_range")
+                .data_type(DataTypeId::Double)
+                .value(10f64)
+                .organized_by(ObjectId::ObjectsFolder)
+                .insert(&mut address_space);
+
+            // An EURange of 0 to 100 on the first variable
+            let eu_range = ExtensionObject::from_encodable(
+                ObjectId::Range_Encoding_DefaultBinary,
+                &Range {
+                    low: 0f64,
+                    high: 100f64,
+                },
+            );
+            VariableBuilder::new(&NodeId::new(1, "percent_eu_range"), "EURange", "EURange")
+                .property_of(node_id.clone())
+                .data_type(DataTypeId::Range)
+                .has_type_definition(VariableTypeId::PropertyType)
+                .value(eu_range)
+                .insert(&mut address_space);
+
+            let server_state = trace_read_lock!(server_state);
+
+            // A percent deadband over 100 is rejected
+            let mut monitored_item = MonitoredItem::new(
+                &chrono::Utc::now(),
+                1,
+                TimestampsToReturn::Both,
+                &server_state,
+                &make_create_request_deadband(node_id.clone(), DeadbandType::Percent, 101f64),
+            )
+            .unwrap();
+            assert_eq!(
+                monitored_item.validate_filter(&address_space),
+                Err(StatusCode::BadDeadbandFilterInvalid)
+            );
+
+            // A percent deadband on a node without an EURange is unsupported
+            let mut monitored_item = MonitoredItem::new(
+                &chrono::Utc::now(),
+                2,
+                TimestampsToReturn::Both,
+                &server_state,
+                &make_create_request_deadband(
+                    no_range_node_id.clone(),
+                    DeadbandType::Percent,
+                    5f64,
+                ),
+            )
+            .unwrap();
+            assert_eq!(
+                monitored_item.validate_filter(&address_space),
+                Err(StatusCode::BadMonitoredItemFilterUnsupported)
+            );
+
+            // Percent deadband of 5% of the EURange, i.e. 5.0
+            let mut monitored_item = MonitoredItem::new(
+                &chrono::Utc::now(),
+                3,
+                TimestampsToReturn::Both,
+                &server_state,
+                &make_create_request_deadband(node_id.clone(), DeadbandType::Percent, 5f64),
+            )
+            .unwrap();
+            assert!(monitored_item.validate_filter(&address_space).is_ok());
+
+            let now = Utc::now();
+            assert_eq!(
+                monitored_item.tick(&now, &address_space, true, false),
+                TickResult::ReportValueChanged
+            );
+            assert_first_notification_is_f64(&mut monitored_item, 10f64);
+
+            // A change of 3.0 is inside the deadband and is suppressed
+            set_double_value(&mut address_space, &node_id, 13f64);
+            assert_eq!(
+                monitored_item.tick(&now, &address_space, true, false),
+                TickResult::NoChange
+            );
+            assert!(monitored_item.notification_queue().is_empty());
+
+            // A change of 6.0 from the last reported value passes
+            set_double_value(&mut address_space, &node_id, 16f64);
+            assert_eq!(
+                monitored_item.tick(&now, &address_space, true, false),
+                TickResult::ReportValueChanged
+            );
+            assert_first_notification_is_f64(&mut monitored_item, 16f64);
+        },
+    )
+}
+
+#[test]
+fn monitored_item_disabled() {
+    do_subscription_service_test(
+        |server_state,
+         _session,
+         _address_space,
+         _ss: SubscriptionService,
+         _mis: MonitoredItemService| {
+            let mut address_space = make_address_space();
+            let server_state = trace_read_lock!(server_state);
+
+            let mut monitored_item = MonitoredItem::new(
+                &chrono::Utc::now(),
+                1,
+                TimestampsToReturn::Both,
+                &server_state,
+                &make_create_request_data_change_filter(-1f64, 5),
+            )
+            .unwrap();
+
+            let now = Utc::now();
+            assert_eq!(
+                monitored_item.tick(&now, &address_space, true, false),
+                TickResult::ReportValueChanged
+            );
+            assert_eq!(monitored_item.notification_queue().len(), 1);
+
+            // Disabling the item throws away what it has queued
+            monitored_item.set_monitoring_mode(MonitoringMode::Disabled);
+            assert!(monitored_item.notification_queue().is_empty());
+
+            // Value changes are not sampled or queued while disabled
+            let set_value = |address_space: &mut AddressSpace, v: u32| {
+                if let NodeType::Variable(node) =
+                    address_space.find_node_mut(&test_var_node_id()).unwrap()
+                {
+                    node.set_value(NumericRange::None, Variant::UInt32(v))
+                        .unwrap();
+                } else {
+                    panic!("Expected a variable, didn't get one!!");
+                }
+            };
+            set_value(&mut address_space, 1);
+            assert_eq!(
+                monitored_item.tick(&now, &address_space, true, false),
+                TickResult::NoChange
+            );
+            set_value(&mut address_space, 2);
+            assert_eq!(
+                monitored_item.tick(&now, &address_space, true, false),
+                TickResult::NoChange
+            );
+            assert!(monitored_item.notification_queue().is_empty());
+
+            // A sampling item queues the latest value but does not report it
+            monitored_item.set_monitoring_mode(MonitoringMode::Sampling);
+            assert_eq!(
+                monitored_item.tick(&now, &address_space, true, false),
+                TickResult::ValueChanged
+            );
+            assert_eq!(monitored_item.notification_queue().len(), 1);
+            if let Some(Notification::MonitoredItemNotification(notification)) =
+                monitored_item.oldest_notification_message()
+            {
+                assert_eq!(notification.value.value.unwrap(), Variant::UInt32(2));
+            } else {
+                panic!();
+            }
+        },
+    )
+}
+
 #[test]
 fn monitored_item_event_filter() {
     // create an address space
@@ -681,15 +1108,16 @@ fn unknown_node_id() {
     );
 }
 
+/// Test to ensure create monitored items revises the sampling interval and queue size to the
+/// server's limits
 #[test]
-fn monitored_item_triggers() {
+fn revised_sampling_interval_and_queue_size() {
     do_subscription_service_test(
         |server_state,
          session,
          address_space,
          ss: SubscriptionService,
          mis: MonitoredItemService| {
-            // Create subscription
             let subscription_id = {
                 let request = create_subscription_request(0, 0);
                 let response: CreateSubscriptionResponse = supported_message_as!(
@@ -699,28 +1127,37 @@ fn monitored_item_triggers() {
                 response.subscription_id
             };
 
-            {
-                let mut session = trace_write_lock!(session);
-                session
-                    .subscriptions_mut()
-                    .get_mut(subscription_id)
-                    .unwrap()
-                    .set_state(SubscriptionState::Normal);
-            }
-
-            let max_monitored_items: usize = 4;
+            let (min_sampling_interval_ms, max_queue_size) = {
+                let server_state = trace_read_lock!(server_state);
+                (
+                    server_state.min_sampling_interval_ms,
+                    server_state.max_monitored_item_queue_size as u32,
+                )
+            };
 
-            let triggering_node = NodeId::new(1, var_name(0));
-            // create 4 monitored items
-            let request = create_monitored_items_request(
+            let mut request = create_monitored_items_request(
                 subscription_id,
                 vec![
-                    triggering_node.clone(),
                     NodeId::new(1, var_name(1)),
                     NodeId::new(1, var_name(2)),
                     NodeId::new(1, var_name(3)),
                 ],
             );
+            {
+                let items_to_create = request.items_to_create.as_mut().unwrap();
+                // 1. Below the minimum sampling interval and above the maximum queue size
+                items_to_create[0].requested_parameters.sampling_interval =
+                    min_sampling_interval_ms / 2.0;
+                items_to_create[0].requested_parameters.queue_size = max_queue_size + 1;
+                // 2. A negative sampling interval and a zero queue size
+                items_to_create[1].requested_parameters.sampling_interval = -5.0;
+                items_to_create[1].requested_parameters.queue_size = 0;
+                // 3. Values within the server limits
+                items_to_create[2].requested_parameters.sampling_interval =
+                    min_sampling_interval_ms * 2.0;
+                items_to_create[2].requested_parameters.queue_size = 2;
+            }
+
             let response: CreateMonitoredItemsResponse = supported_message_as!(
                 mis.create_monitored_items(
                     server_state.clone(),
@@ -730,10 +1167,295 @@ fn monitored_item_triggers() {
                 ),
                 CreateMonitoredItemsResponse
             );
+            let results = response.results.unwrap();
+            assert_eq!(results.len(), 3);
+            assert!(results.iter().all(|r| r.status_code == StatusCode::Good));
 
-            // The first monitored item will be the triggering item, the other 3 will be triggered items
-            let monitored_item_ids: Vec<u32> = response
-                .results
+            assert_eq!(
+                results[0].revised_sampling_interval,
+                min_sampling_interval_ms
+            );
+            assert_eq!(results[0].revised_queue_size, max_queue_size);
+
+            assert_eq!(results[1].revised_sampling_interval, -1.0);
+            assert_eq!(results[1].revised_queue_size, 1);
+
+            assert_eq!(
+                results[2].revised_sampling_interval,
+                min_sampling_interval_ms * 2.0
+            );
+            assert_eq!(results[2].revised_queue_size, 2);
+
+            // Each item should have its own id
+            let ids = results
+                .iter()
+                .map(|r| r.monitored_item_id)
+                .collect::<HashSet<_>>();
+            assert_eq!(ids.len(), 3);
+        },
+    );
+}
+
+#[test]
+fn modify_monitored_items() {
+    do_subscription_service_test(
+        |server_state,
+         session,
+         address_space,
+         ss: SubscriptionService,
+         mis: MonitoredItemService| {
+            let subscription_id = {
+                let request = create_subscription_request(0, 0);
+                let response: CreateSubscriptionResponse = supported_message_as!(
+                    ss.create_subscription(server_state.clone(), session.clone(), &request),
+                    CreateSubscriptionResponse
+                );
+                response.subscription_id
+            };
+
+            let (min_sampling_interval_ms, max_queue_size) = {
+                let server_state = trace_read_lock!(server_state);
+                (
+                    server_state.min_sampling_interval_ms,
+                    server_state.max_monitored_item_queue_size as u32,
+                )
+            };
+
+            let request =
+                create_monitored_items_request(subscription_id, vec![NodeId::new(1, var_name(1))]);
+            let response: CreateMonitoredItemsResponse = supported_message_as!(
+                mis.create_monitored_items(
+                    server_state.clone(),
+                    session.clone(),
+                    address_space.clone(),
+                    &request
+                ),
+                CreateMonitoredItemsResponse
+            );
+            let monitored_item_id = response.results.unwrap()[0].monitored_item_id;
+
+            let modify_request =
+                |queue_size: u32, monitored_item_ids: &[u32]| ModifyMonitoredItemsRequest {
+                    request_header: RequestHeader::dummy(),
+                    subscription_id,
+                    timestamps_to_return: TimestampsToReturn::Both,
+                    items_to_modify: Some(
+                        monitored_item_ids
+                            .iter()
+                            .map(|monitored_item_id| MonitoredItemModifyRequest {
+                                monitored_item_id: *monitored_item_id,
+                                requested_parameters: MonitoringParameters {
+                                    client_handle: 1,
+                                    sampling_interval: min_sampling_interval_ms / 2.0,
+                                    filter: ExtensionObject::null(),
+                                    queue_size,
+                                    discard_oldest: true,
+                                },
+                            })
+                            .collect(),
+                    ),
+                };
+
+            // Grow the queue, including an unknown item
+            let request = modify_request(10, &[monitored_item_id, monitored_item_id + 100]);
+            let response: ModifyMonitoredItemsResponse = supported_message_as!(
+                mis.modify_monitored_items(
+                    server_state.clone(),
+                    session.clone(),
+                    address_space.clone(),
+                    &request
+                ),
+                ModifyMonitoredItemsResponse
+            );
+            let results = response.results.unwrap();
+            assert_eq!(results.len(), 2);
+            assert_eq!(results[0].status_code, StatusCode::Good);
+            assert_eq!(results[0].revised_queue_size, 10);
+            assert_eq!(
+                results[0].revised_sampling_interval,
+                min_sampling_interval_ms
+            );
+            assert_eq!(
+                results[1].status_code,
+                StatusCode::BadMonitoredItemIdInvalid
+            );
+
+            // Exceed the maximum queue size
+            let request = modify_request(max_queue_size + 1, &[monitored_item_id]);
+            let response: ModifyMonitoredItemsResponse = supported_message_as!(
+                mis.modify_monitored_items(
+                    server_state.clone(),
+                    session.clone(),
+                    address_space.clone(),
+                    &request
+                ),
+                ModifyMonitoredItemsResponse
+            );
+            let results = response.results.unwrap();
+            assert_eq!(results[0].status_code, StatusCode::Good);
+            assert_eq!(results[0].revised_queue_size, max_queue_size);
+
+            let session = trace_read_lock!(session);
+            let monitored_item = session
+                .subscriptions()
+                .subscriptions()
+                .get(&subscription_id)
+                .unwrap()
+                .monitored_item(monitored_item_id)
+                .unwrap();
+            assert_eq!(monitored_item.queue_size(), max_queue_size as usize);
+            assert_eq!(monitored_item.client_handle(), 1);
+        },
+    );
+}
+
+#[test]
+fn modify_monitored_item_shrinks_queue() {
+    // Shrinking the queue of a monitored item discards notifications according to its policy
+    do_subscription_service_test(
+        |server_state,
+         _session,
+         address_space,
+         _ss: SubscriptionService,
+         _mis: MonitoredItemService| {
+            let server_state = trace_read_lock!(server_state);
+            let address_space = trace_read_lock!(address_space);
+
+            [true, false].iter().for_each(|discard_oldest| {
+                let mut monitored_item = populate_monitored_item(&server_state, *discard_oldest);
+                let request = MonitoredItemModifyRequest {
+                    monitored_item_id: 1,
+                    requested_parameters: MonitoringParameters {
+                        client_handle: 999,
+                        sampling_interval: -1f64,
+                        filter: ExtensionObject::null(),
+                        queue_size: 2,
+                        discard_oldest: *discard_oldest,
+                    },
+                };
+                assert!(monitored_item
+                    .modify(
+                        &server_state,
+                        &*address_space,
+                        TimestampsToReturn::Both,
+                        &request
+                    )
+                    .is_ok());
+                assert_eq!(monitored_item.queue_size(), 2);
+                if *discard_oldest {
+                    assert_first_notification_is_i32(&mut monitored_item, 4);
+                    assert_first_notification_is_i32(&mut monitored_item, 10);
+                } else {
+                    assert_first_notification_is_i32(&mut monitored_item, 0);
+                    assert_first_notification_is_i32(&mut monitored_item, 1);
+                }
+                assert!(monitored_item.oldest_notification_message().is_none());
+            });
+        },
+    );
+}
+
+/// Test to ensure create monitored items does not exceed the server's limit of items per subscription
+#[test]
+fn too_many_monitored_items() {
+    do_subscription_service_test(
+        |server_state,
+         session,
+         address_space,
+         ss: SubscriptionService,
+         mis: MonitoredItemService| {
+            {
+                let mut server_state = trace_write_lock!(server_state);
+                server_state.max_monitored_items_per_sub = 2;
+            }
+
+            let subscription_id = {
+                let request = create_subscription_request(0, 0);
+                let response: CreateSubscriptionResponse = supported_message_as!(
+                    ss.create_subscription(server_state.clone(), session.clone(), &request),
+                    CreateSubscriptionResponse
+                );
+                response.subscription_id
+            };
+
+            let request = create_monitored_items_request(
+                subscription_id,
+                vec![
+                    NodeId::new(1, var_name(1)),
+                    NodeId::new(1, var_name(2)),
+                    NodeId::new(1, var_name(3)),
+                ],
+            );
+            let response: CreateMonitoredItemsResponse = supported_message_as!(
+                mis.create_monitored_items(
+                    server_state.clone(),
+                    session.clone(),
+                    address_space.clone(),
+                    &request
+                ),
+                CreateMonitoredItemsResponse
+            );
+            let results = response.results.unwrap();
+            assert_eq!(results[0].status_code, StatusCode::Good);
+            assert_eq!(results[1].status_code, StatusCode::Good);
+            assert_eq!(results[2].status_code, StatusCode::BadTooManyMonitoredItems);
+        },
+    );
+}
+
+#[test]
+fn monitored_item_triggers() {
+    do_subscription_service_test(
+        |server_state,
+         session,
+         address_space,
+         ss: SubscriptionService,
+         mis: MonitoredItemService| {
+            // Create subscription
+            let subscription_id = {
+                let request = create_subscription_request(0, 0);
+                let response: CreateSubscriptionResponse = supported_message_as!(
+                    ss.create_subscription(server_state.clone(), session.clone(), &request),
+                    CreateSubscriptionResponse
+                );
+                response.subscription_id
+            };
+
+            {
+                let mut session = trace_write_lock!(session);
+                session
+                    .subscriptions_mut()
+                    .get_mut(subscription_id)
+                    .unwrap()
+                    .set_state(SubscriptionState::Normal);
+            }
+
+            let max_monitored_items: usize = 4;
+
+            let triggering_node = NodeId::new(1, var_name(0));
+            // create 4 monitored items
+            let request = create_monitored_items_request(
+                subscription_id,
+                vec![
+                    triggering_node.clone(),
+                    NodeId::new(1, var_name(1)),
+                    NodeId::new(1, var_name(2)),
+                    NodeId::new(1, var_name(3)),
+                ],
+            );
+            let response: CreateMonitoredItemsResponse = supported_message_as!(
+                mis.create_monitored_items(
+                    server_state.clone(),
+                    session.clone(),
+                    address_space.clone(),
+                    &request
+                ),
+                CreateMonitoredItemsResponse
+            );
+
+            // The first monitored item will be the triggering item, the other 3 will be triggered items
+            let monitored_item_ids: Vec<u32> = response
+                .results
                 .unwrap()
                 .iter()
                 .map(|mir| {
@@ -988,6 +1710,251 @@ fn monitored_item_triggers() {
     );
 }
 
+#[test]
+fn set_triggering_link_results() {
+    do_subscription_service_test(
+        |server_state,
+         session,
+         address_space,
+         ss: SubscriptionService,
+         mis: MonitoredItemService| {
+            let subscription_id = {
+                let request = create_subscription_request(0, 0);
+                let response: CreateSubscriptionResponse = supported_message_as!(
+                    ss.create_subscription(server_state.clone(), session.clone(), &request),
+                    CreateSubscriptionResponse
+                );
+                response.subscription_id
+            };
+            let request = create_monitored_items_request(
+                subscription_id,
+                vec![
+                    NodeId::new(1, var_name(0)),
+                    NodeId::new(1, var_name(1)),
+                    NodeId::new(1, var_name(2)),
+                ],
+            );
+            let response: CreateMonitoredItemsResponse = supported_message_as!(
+                mis.create_monitored_items(
+                    server_state.clone(),
+                    session.clone(),
+                    address_space.clone(),
+                    &request
+                ),
+                CreateMonitoredItemsResponse
+            );
+            let monitored_item_ids: Vec<u32> = response
+                .results
+                .unwrap()
+                .iter()
+                .map(|mir| mir.monitored_item_id)
+                .collect();
+            let (triggering_id, first_id, second_id) = (
+                monitored_item_ids[0],
+                monitored_item_ids[1],
+                monitored_item_ids[2],
+            );
+            let unknown_id = second_id + 100;
+
+            // Links can only be added to items that exist
+            let (add_results, remove_results) = set_triggering(
+                session.clone(),
+                subscription_id,
+                triggering_id,
+                &[first_id, second_id, unknown_id],
+                &[],
+                &mis,
+            );
+            assert_eq!(
+                add_results.unwrap(),
+                vec![
+                    StatusCode::Good,
+                    StatusCode::Good,
+                    StatusCode::BadMonitoredItemIdInvalid
+                ]
+            );
+            assert!(remove_results.is_none());
+
+            // Only links that the triggering item has can be removed
+            let (add_results, remove_results) = set_triggering(
+                session.clone(),
+                subscription_id,
+                triggering_id,
+                &[],
+                &[first_id, first_id, triggering_id, unknown_id],
+                &mis,
+            );
+            assert!(add_results.is_none());
+            assert_eq!(
+                remove_results.unwrap(),
+                vec![
+                    StatusCode::Good,
+                    StatusCode::Good,
+                    StatusCode::BadMonitoredItemIdInvalid,
+                    StatusCode::BadMonitoredItemIdInvalid
+                ]
+            );
+
+            let session = trace_read_lock!(session);
+            let subscription = session
+                .subscriptions()
+                .subscriptions()
+                .get(&subscription_id)
+                .unwrap();
+            let triggered_items = subscription
+                .monitored_item(triggering_id)
+                .unwrap()
+                .triggered_items();
+            assert_eq!(
+                triggered_items.iter().copied().collect::<Vec<u32>>(),
+                vec![second_id]
+            );
+        },
+    );
+}
+
+#[test]
+fn delete_monitored_items() {
+    do_subscription_service_test(
+        |server_state,
+         session,
+         address_space,
+         ss: SubscriptionService,
+         mis: MonitoredItemService| {
+            // Create subscription
+            let subscription_id = {
+                let request = create_subscription_request(0, 0);
+                let response: CreateSubscriptionResponse = supported_message_as!(
+                    ss.create_subscription(server_state.clone(), session.clone(), &request),
+                    CreateSubscriptionResponse
+                );
+                response.subscription_id
+            };
+
+            {
+                let mut session = trace_write_lock!(session);
+                session
+                    .subscriptions_mut()
+                    .get_mut(subscription_id)
+                    .unwrap()
+                    .set_state(SubscriptionState::Normal);
+            }
+
+            // Create 2 monitored items, the first triggering the second
+            let survivor_node = NodeId::new(1, var_name(0));
+            let deleted_node = NodeId::new(1, var_name(1));
+            let request = create_monitored_items_request(
+                subscription_id,
+                vec![survivor_node.clone(), deleted_node.clone()],
+            );
+            let response: CreateMonitoredItemsResponse = supported_message_as!(
+                mis.create_monitored_items(
+                    server_state.clone(),
+                    session.clone(),
+                    address_space.clone(),
+                    &request
+                ),
+                CreateMonitoredItemsResponse
+            );
+            let monitored_item_ids: Vec<u32> = response
+                .results
+                .unwrap()
+                .iter()
+                .map(|mir| mir.monitored_item_id)
+                .collect();
+            let (survivor_id, deleted_id) = (monitored_item_ids[0], monitored_item_ids[1]);
+            let _ = set_triggering(
+                session.clone(),
+                subscription_id,
+                survivor_id,
+                &[deleted_id],
+                &[],
+                &mis,
+            );
+
+            // Delete one of them along with an item that doesn't exist
+            let request = DeleteMonitoredItemsRequest {
+                request_header: RequestHeader::dummy(),
+                subscription_id,
+                monitored_item_ids: Some(vec![deleted_id, deleted_id + 100]),
+            };
+            let response: DeleteMonitoredItemsResponse = supported_message_as!(
+                mis.delete_monitored_items(session.clone(), &request),
+                DeleteMonitoredItemsResponse
+            );
+            assert_eq!(
+                response.results.unwrap(),
+                vec![StatusCode::Good, StatusCode::BadMonitoredItemIdInvalid]
+            );
+
+            // The survivor no longer triggers the deleted item
+            {
+                let session = trace_read_lock!(session);
+                let subscription = session
+                    .subscriptions()
+                    .subscriptions()
+                    .get(&subscription_id)
+                    .unwrap();
+                assert_eq!(subscription.monitored_items_len(), 1);
+                assert!(subscription.monitored_item(deleted_id).is_none());
+                assert!(subscription
+                    .monitored_item(survivor_id)
+                    .unwrap()
+                    .triggered_items()
+                    .is_empty());
+            }
+
+            // Expect only the survivor to report its initial value
+            let now = publish_tick_response(
+                session.clone(),
+                &ss,
+                address_space.clone(),
+                Utc::now(),
+                chrono::Duration::seconds(2),
+                |response| {
+                    let (notifications, _) = response
+                        .notification_message
+                        .notifications(&DecodingOptions::test())
+                        .unwrap();
+                    let monitored_items = notifications[0].monitored_items.as_ref().unwrap();
+                    assert_eq!(monitored_items.len(), 1);
+                    assert_eq!(monitored_items[0].client_handle, 0);
+                },
+            );
+
+            // Change both values and expect only the survivor to report the change
+            {
+                let mut address_space = trace_write_lock!(address_space);
+                [&survivor_node, &deleted_node].iter().for_each(|node_id| {
+                    let _ = address_space.set_variable_value(
+                        (*node_id).clone(),
+                        5,
+                        &DateTime::from(now),
+                        &DateTime::from(now),
+                    );
+                });
+            }
+            let _ = publish_tick_response(
+                session.clone(),
+                &ss,
+                address_space.clone(),
+                now,
+                chrono::Duration::seconds(2),
+                |response| {
+                    let (notifications, _) = response
+                        .notification_message
+                        .notifications(&DecodingOptions::test())
+                        .unwrap();
+                    let monitored_items = notifications[0].monitored_items.as_ref().unwrap();
+                    assert_eq!(monitored_items.len(), 1);
+                    assert_eq!(monitored_items[0].client_handle, 0);
+                    assert_eq!(monitored_items[0].value.value, Some(Variant::Int32(5)));
+                },
+            );
+        },
+    );
+}
+
 #[test]
 fn monitored_item_queue_discard_oldest() {
     // The purpose of this test is to monitor the discard oldest behaviour. Depending on true/false
@@ -1023,3 +1990,83 @@ fn monitored_item_queue_discard_oldest() {
         },
     );
 }
+
+#[test]
+fn monitored_item_through_provider() {
+    do_subscription_service_test(
+        |server_state,
+         session,
+         _address_space,
+         ss: SubscriptionService,
+         mis: MonitoredItemService| {
+            let node_id = NodeId::new(2, "mock");
+            let provider = Arc::new(RwLock::new(MockAddressSpaceProvider::new(vec![(
+                node_id.clone(),
+                Variant::Int32(42),
+            )])));
+
+            let subscription_id = {
+                let request = create_subscription_request(0, 0);
+                let response: CreateSubscriptionResponse = supported_message_as!(
+                    ss.create_subscription(server_state.clone(), session.clone(), &request),
+                    CreateSubscriptionResponse
+                );
+                response.subscription_id
+            };
+
+            // Items are created against the provider's nodes, not the server's address space
+            let request = create_monitored_items_request(
+                subscription_id,
+                vec![node_id.clone(), ObjectId::Server.into()],
+            );
+            let response: CreateMonitoredItemsResponse = supported_message_as!(
+                mis.create_monitored_items(
+                    server_state.clone(),
+                    session.clone(),
+                    provider.clone(),
+                    &request
+                ),
+                CreateMonitoredItemsResponse
+            );
+            let results = response.results.unwrap();
+            assert_eq!(results[0].status_code, StatusCode::Good);
+            assert_eq!(results[1].status_code, StatusCode::BadNodeIdUnknown);
+
+            let notified_value = |response: PublishResponse| {
+                let (notifications, _) = response
+                    .notification_message
+                    .notifications(&DecodingOptions::test())
+                    .unwrap();
+                let monitored_items = notifications[0].monitored_items.as_ref().unwrap();
+                assert_eq!(monitored_items.len(), 1);
+                monitored_items[0].value.value.clone()
+            };
+
+            // The item is sampled from the provider
+            let now = publish_tick_response(
+                session.clone(),
+                &ss,
+                provider.clone(),
+                Utc::now(),
+                chrono::Duration::seconds(2),
+                |response| assert_eq!(notified_value(response), Some(Variant::Int32(42))),
+            );
+
+            // And so are changes to the provider's value
+            let _ = trace_write_lock!(provider).set_attribute(
+                &node_id,
+                AttributeId::Value,
+                NumericRange::None,
+                Variant::Int32(43),
+            );
+            let _ = publish_tick_response(
+                session.clone(),
+                &ss,
+                provider.clone(),
+                now,
+                chrono::Duration::seconds(2),
+                |response| assert_eq!(notified_value(response), Some(Variant::Int32(43))),
+            );
+        },
+    );
+}
@@ -1,4 +1,6 @@
-use crate::server::services::node_management::NodeManagementService;
+use crate::server::services::{
+    attribute::AttributeService, node_management::NodeManagementService, view::ViewService,
+};
 use crate::supported_message_as;
 use crate::sync::*;
 use crate::types::node_ids::{DataTypeId, MethodId, ObjectId, ObjectTypeId, ReferenceTypeId};
@@ -82,10 +84,10 @@ fn do_add_references_test(
             let response = nms.add_references(
                 server_state,
                 session,
-                address_space,
+                address_space.clone(),
                 &AddReferencesRequest {
                     request_header: RequestHeader::dummy(),
-                    references_to_add: Some(vec![item]),
+                    references_to_add: Some(vec![item.clone()]),
                 },
             );
             let response: AddReferencesResponse =
@@ -97,7 +99,17 @@ fn do_add_references_test(
                 format!("{}", expected_status_code)
             );
             if expected_status_code.is_good() {
-                // TODO expect the reference to exist
+                let address_space = trace_read_lock!(address_space);
+                let (source_node_id, target_node_id) = if item.is_forward {
+                    (&item.source_node_id, &item.target_node_id.node_id)
+                } else {
+                    (&item.target_node_id.node_id, &item.source_node_id)
+                };
+                assert!(address_space.has_reference(
+                    source_node_id,
+                    target_node_id,
+                    item.reference_type_id.as_reference_type_id().unwrap()
+                ));
             }
         },
     );
@@ -760,3 +772,350 @@ fn delete_references_target_node_id_invalid() {
         StatusCode::BadTargetNodeIdInvalid,
     );
 }
+
+/// Adds a readable Int32 variable organized by the objects folder
+fn add_variable(
+    server_state: Arc<RwLock<ServerState>>,
+    session: Arc<RwLock<Session>>,
+    address_space: Arc<RwLock<AddressSpace>>,
+    nms: &NodeManagementService,
+    node_id: &NodeId,
+) -> AddNodesResult {
+    let response = nms.add_nodes(
+        server_state,
+        session,
+        address_space,
+        &AddNodesRequest {
+            request_header: RequestHeader::dummy(),
+            nodes_to_add: Some(vec![AddNodesItem {
+                parent_node_id: ObjectId::ObjectsFolder.into(),
+                reference_type_id: ReferenceTypeId::Organizes.into(),
+                requested_new_node_id: node_id.clone().into(),
+                browse_name: QualifiedName::from("added_variable"),
+                node_class: NodeClass::Variable,
+                node_attributes: ExtensionObject::from_encodable(
+                    ObjectId::VariableAttributes_Encoding_DefaultBinary,
+                    &VariableAttributes {
+                        specified_attributes: (AttributesMask::DISPLAY_NAME
+                            | AttributesMask::ACCESS_LEVEL
+                            | AttributesMask::USER_ACCESS_LEVEL
+                            | AttributesMask::DATA_TYPE
+                            | AttributesMask::HISTORIZING
+                            | AttributesMask::VALUE
+                            | AttributesMask::VALUE_RANK)
+                            .bits(),
+                        display_name: "Added variable".into(),
+                        description: LocalizedText::null(),
+                        write_mask: 0,
+                        user_write_mask: 0,
+                        value: Variant::from(42i32),
+                        data_type: DataTypeId::Int32.into(),
+                        value_rank: -1,
+                        array_dimensions: None,
+                        access_level: AccessLevel::CURRENT_READ.bits(),
+                        user_access_level: UserAccessLevel::CURRENT_READ.bits(),
+                        minimum_sampling_interval: 0.0,
+                        historizing: false,
+                    },
+                ),
+                type_definition: VariableTypeId::BaseDataVariableType.into(),
+            }]),
+        },
+    );
+    let response: AddNodesResponse = supported_message_as!(response, AddNodesResponse);
+    response.results.unwrap().remove(0)
+}
+
+fn delete_node(
+    server_state: Arc<RwLock<ServerState>>,
+    session: Arc<RwLock<Session>>,
+    address_space: Arc<RwLock<AddressSpace>>,
+    nms: &NodeManagementService,
+    node_id: &NodeId,
+    delete_target_references: bool,
+) -> StatusCode {
+    let response = nms.delete_nodes(
+        server_state,
+        session,
+        address_space,
+        &DeleteNodesRequest {
+            request_header: RequestHeader::dummy(),
+            nodes_to_delete: Some(vec![DeleteNodesItem {
+                node_id: node_id.clone(),
+                delete_target_references,
+            }]),
+        },
+    );
+    let response: DeleteNodesResponse = supported_message_as!(response, DeleteNodesResponse);
+    response.results.unwrap().remove(0)
+}
+
+#[test]
+fn add_variable_then_read_and_delete() {
+    do_node_management_service_test(true, |server_state, session, address_space, nms| {
+        let node_id = NodeId::new(1, "added_variable");
+        let read_value = || {
+            let response = AttributeService::new().read(
+                server_state.clone(),
+                session.clone(),
+                address_space.clone(),
+                &ReadRequest {
+                    request_header: RequestHeader::dummy(),
+                    max_age: 0f64,
+                    timestamps_to_return: TimestampsToReturn::Neither,
+                    nodes_to_read: Some(vec![node_id.clone().into()]),
+                },
+            );
+            let response: ReadResponse = supported_message_as!(response, ReadResponse);
+            response.results.unwrap().remove(0)
+        };
+
+        // The variable is added under Objects and its value can be read back
+        let result = add_variable(
+            server_state.clone(),
+            session.clone(),
+            address_space.clone(),
+            &nms,
+            &node_id,
+        );
+        assert_eq!(result.status_code, StatusCode::Good);
+        assert_eq!(result.added_node_id, node_id);
+        assert_eq!(read_value().value, Some(Variant::from(42i32)));
+        {
+            let address_space = trace_read_lock!(address_space);
+            assert!(address_space.has_reference(
+                &ObjectId::ObjectsFolder.into(),
+                &node_id,
+                ReferenceTypeId::Organizes
+            ));
+        }
+
+        // The same node id can't be used twice
+        let result = add_variable(
+            server_state.clone(),
+            session.clone(),
+            address_space.clone(),
+            &nms,
+            &node_id,
+        );
+        assert_eq!(result.status_code, StatusCode::BadNodeIdExists);
+
+        // Deleting the node and the references to it leaves nothing behind
+        let status_code = delete_node(
+            server_state.clone(),
+            session.clone(),
+            address_space.clone(),
+            &nms,
+            &node_id,
+            true,
+        );
+        assert_eq!(status_code, StatusCode::Good);
+        assert_eq!(read_value().status, Some(StatusCode::BadNodeIdUnknown));
+        let address_space = trace_read_lock!(address_space);
+        assert!(!address_space.has_reference(
+            &ObjectId::ObjectsFolder.into(),
+            &node_id,
+            ReferenceTypeId::Organizes
+        ));
+        assert!(address_space
+            .find_references(&node_id, None::<(ReferenceTypeId, bool)>)
+            .is_none());
+    });
+}
+
+#[test]
+fn delete_nodes_keeps_target_references() {
+    do_node_management_service_test(true, |server_state, session, address_space, nms| {
+        let node_id = NodeId::new(1, "added_variable");
+        let result = add_variable(
+            server_state.clone(),
+            session.clone(),
+            address_space.clone(),
+            &nms,
+            &node_id,
+        );
+        assert_eq!(result.status_code, StatusCode::Good);
+
+        // The node is gone but the parent still refers to it
+        let status_code = delete_node(
+            server_state,
+            session,
+            address_space.clone(),
+            &nms,
+            &node_id,
+            false,
+        );
+        assert_eq!(status_code, StatusCode::Good);
+        let address_space = trace_read_lock!(address_space);
+        assert!(address_space.find_node(&node_id).is_none());
+        assert!(address_space.has_reference(
+            &ObjectId::ObjectsFolder.into(),
+            &node_id,
+            ReferenceTypeId::Organizes
+        ));
+    });
+}
+
+#[test]
+fn add_and_delete_references_then_browse() {
+    do_node_management_service_test(true, |server_state, session, address_space, nms| {
+        // The nodes organized by the objects folder
+        let browse_objects_folder = || {
+            let response = ViewService::new().browse(
+                server_state.clone(),
+                session.clone(),
+                address_space.clone(),
+                &BrowseRequest {
+                    request_header: RequestHeader::dummy(),
+                    view: ViewDescription {
+                        view_id: NodeId::null(),
+                        timestamp: DateTime::null(),
+                        view_version: 0,
+                    },
+                    requested_max_references_per_node: 0,
+                    nodes_to_browse: Some(vec![BrowseDescription {
+                        node_id: ObjectId::ObjectsFolder.into(),
+                        browse_direction: BrowseDirection::Forward,
+                        reference_type_id: ReferenceTypeId::Organizes.into(),
+                        include_subtypes: false,
+                        node_class_mask: 0,
+                        result_mask: BrowseDescriptionResultMask::all().bits(),
+                    }]),
+                },
+            );
+            let response: BrowseResponse = supported_message_as!(response, BrowseResponse);
+            response.results.unwrap()[0]
+                .references
+                .iter()
+                .flatten()
+                .map(|r| r.node_id.node_id.clone())
+                .collect::<Vec<NodeId>>()
+        };
+        assert!(!browse_objects_folder().contains(&var_node_id(1)));
+        assert!(!browse_objects_folder().contains(&var_node_id(2)));
+
+        // A forward reference from the folder, and an inverse reference from a variable to it
+        let add_references = |references_to_add: Vec<AddReferencesItem>| {
+            let response = nms.add_references(
+                server_state.clone(),
+                session.clone(),
+                address_space.clone(),
+                &AddReferencesRequest {
+                    request_header: RequestHeader::dummy(),
+                    references_to_add: Some(references_to_add),
+                },
+            );
+            let response: AddReferencesResponse =
+                supported_message_as!(response, AddReferencesResponse);
+            response.results.unwrap()
+        };
+        let forward = AddReferencesItem {
+            source_node_id: ObjectId::ObjectsFolder.into(),
+            reference_type_id: ReferenceTypeId::Organizes.into(),
+            is_forward: true,
+            target_server_uri: UAString::null(),
+            target_node_id: var_node_id(1).into(),
+            target_node_class: NodeClass::Variable,
+        };
+        let inverse = AddReferencesItem {
+            source_node_id: var_node_id(2),
+            reference_type_id: ReferenceTypeId::Organizes.into(),
+            is_forward: false,
+            target_server_uri: UAString::null(),
+            target_node_id: ObjectId::ObjectsFolder.into(),
+            target_node_class: NodeClass::Object,
+        };
+        let results = add_references(vec![forward.clone(), inverse.clone()]);
+        assert_eq!(results, vec![StatusCode::Good, StatusCode::Good]);
+        let organized = browse_objects_folder();
+        assert!(organized.contains(&var_node_id(1)));
+        assert!(organized.contains(&var_node_id(2)));
+
+        // Adding either again is a duplicate, whichever direction it was added from
+        let results = add_references(vec![forward, inverse]);
+        assert_eq!(
+            results,
+            vec![
+                StatusCode::BadDuplicateReferenceNotAllowed,
+                StatusCode::BadDuplicateReferenceNotAllowed
+            ]
+        );
+
+        // Deleting the reference removes it from the browse
+        let response = nms.delete_references(
+            server_state.clone(),
+            session.clone(),
+            address_space.clone(),
+            &DeleteReferencesRequest {
+                request_header: RequestHeader::dummy(),
+                references_to_delete: Some(vec![DeleteReferencesItem {
+                    source_node_id: var_node_id(2),
+                    reference_type_id: ReferenceTypeId::Organizes.into(),
+                    is_forward: false,
+                    target_node_id: ObjectId::ObjectsFolder.into(),
+                    delete_bidirectional: false,
+                }]),
+            },
+        );
+        let response: DeleteReferencesResponse =
+            supported_message_as!(response, DeleteReferencesResponse);
+        assert_eq!(response.results.unwrap(), vec![StatusCode::Good]);
+        let organized = browse_objects_folder();
+        assert!(organized.contains(&var_node_id(1)));
+        assert!(!organized.contains(&var_node_id(2)));
+    });
+}
+
+#[test]
+fn add_node_in_configured_namespace() {
+    let st = ServiceTest::new_with_server(ServerBuilder::new_sample().namespace("urn:test:nodes"));
+    {
+        let mut session = trace_write_lock!(st.session);
+        session.set_can_modify_address_space(true);
+    }
+
+    // The namespace array holds the configured namespace after the application uri
+    let namespace_index = {
+        let address_space = trace_read_lock!(st.address_space);
+        let namespace_array = address_space
+            .find_variable(VariableId::Server_NamespaceArray)
+            .unwrap()
+            .value(
+                TimestampsToReturn::Neither,
+                NumericRange::None,
+                &QualifiedName::null(),
+                0.0,
+            );
+        let Some(Variant::Array(namespace_array)) = namespace_array.value else {
+            panic!("namespace array is not an array");
+        };
+        let namespace_array = namespace_array
+            .values
+            .iter()
+            .map(|namespace| namespace.to_string())
+            .collect::<Vec<String>>();
+        assert_eq!(
+            namespace_array,
+            vec![
+                "http://opcfoundation.org/UA/",
+                "urn:OPC UA Sample Server",
+                "urn:test:nodes"
+            ]
+        );
+        let namespace_index = address_space.namespace_index("urn:test:nodes").unwrap();
+        assert_eq!(address_space.default_namespace(), namespace_index);
+        namespace_index
+    };
+
+    // A node added without an id is given one in that namespace
+    let result = add_variable(
+        st.server_state.clone(),
+        st.session.clone(),
+        st.address_space.clone(),
+        &NodeManagementService::new(),
+        &NodeId::null(),
+    );
+    assert_eq!(result.status_code, StatusCode::Good);
+    assert_eq!(result.added_node_id.namespace, namespace_index);
+    assert!(trace_read_lock!(st.address_space).node_exists(&result.added_node_id));
+}
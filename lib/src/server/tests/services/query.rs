@@ -0,0 +1,310 @@
+use std::collections::HashSet;
+
+use crate::server::services::query::QueryService;
+use crate::supported_message_as;
+use crate::sync::*;
+
+use super::*;
+
+// Query service tests
+
+fn temperature_type_id() -> NodeId {
+    NodeId::new(1, "TemperatureType")
+}
+
+/// Adds a variable type and ten variables of that type whose values are 0, 10, 20...
+fn add_temperatures(address_space: Arc<RwLock<AddressSpace>>) -> Vec<NodeId> {
+    let mut address_space = trace_write_lock!(address_space);
+    let _ = VariableTypeBuilder::new(&temperature_type_id(), "TemperatureType", "TemperatureType")
+        .subtype_of(VariableTypeId::BaseDataVariableType)
+        .insert(&mut address_space);
+    (0..10)
+        .map(|i| {
+            let node_id = NodeId::new(1, format!("t{}", i));
+            let _ = VariableBuilder::new(&node_id, format!("t{}", i), "")
+                .data_type(DataTypeId::Int32)
+                .has_type_definition(temperature_type_id())
+                .organized_by(ObjectId::ObjectsFolder)
+                .value(i * 10)
+                .insert(&mut address_space);
+            node_id
+        })
+        .collect()
+}
+
+fn make_query_first_request(
+    type_definition: NodeId,
+    filter: ContentFilter,
+    max_data_sets_to_return: u32,
+) -> QueryFirstRequest {
+    QueryFirstRequest {
+        request_header: make_request_header(),
+        view: ViewDescription {
+            view_id: NodeId::null(),
+            timestamp: DateTime::null(),
+            view_version: 0,
+        },
+        node_types: Some(vec![NodeTypeDescription {
+            type_definition_node: type_definition.into(),
+            include_sub_types: false,
+            data_to_return: Some(vec![QueryDataDescription {
+                relative_path: RelativePath { elements: None },
+                attribute_id: AttributeId::Value as u32,
+                index_range: UAString::null(),
+            }]),
+        }]),
+        filter,
+        max_data_sets_to_return,
+        max_references_to_return: 0,
+    }
+}
+
+fn make_query_next_request(
+    continuation_point: &ByteString,
+    release_continuation_point: bool,
+) -> QueryNextRequest {
+    QueryNextRequest {
+        request_header: make_request_header(),
+        release_continuation_point,
+        continuation_point: continuation_point.clone(),
+    }
+}
+
+/// An operand for the value of the node being queried
+fn value_operand() -> Operand {
+    Operand::AttributeOperand(AttributeOperand {
+        node_id: temperature_type_id(),
+        alias: UAString::null(),
+        browse_path: RelativePath { elements: None },
+        attribute_id: AttributeId::Value as u32,
+        index_range: UAString::null(),
+    })
+}
+
+fn do_query_service_test<F>(f: F)
+where
+    F: FnOnce(
+        Arc<RwLock<ServerState>>,
+        Arc<RwLock<Session>>,
+        Arc<RwLock<AddressSpace>>,
+        Vec<NodeId>,
+        QueryService,
+    ),
+{
+    let st = ServiceTest::new();
+    let node_ids = add_temperatures(st.address_space.clone());
+    f(
+        st.server_state.clone(),
+        st.session.clone(),
+        st.address_space.clone(),
+        node_ids,
+        QueryService::new(),
+    );
+}
+
+fn data_set_node_ids(query_data_sets: &[QueryDataSet]) -> HashSet<NodeId> {
+    query_data_sets
+        .iter()
+        .map(|d| d.node_id.node_id.clone())
+        .collect()
+}
+
+#[test]
+fn query_variables_of_type() {
+    do_query_service_test(|server_state, session, address_space, node_ids, qs| {
+        let request =
+            make_query_first_request(temperature_type_id(), ContentFilter { elements: None }, 0);
+        let response = qs.query_first(server_state, session, address_space, &request);
+        let response = supported_message_as!(response, QueryFirstResponse);
+        assert!(response.response_header.service_result.is_good());
+        assert!(response.continuation_point.is_null());
+        let parsing_results = response.parsing_results.unwrap();
+        assert_eq!(parsing_results.len(), 1);
+        assert!(parsing_results[0].status_code.is_good());
+
+        // Every variable of the type is returned with its value
+        let query_data_sets = response.query_data_sets.unwrap();
+        assert_eq!(
+            data_set_node_ids(&query_data_sets),
+            node_ids.iter().cloned().collect()
+        );
+        query_data_sets.iter().for_each(|d| {
+            assert_eq!(d.type_definition_node.node_id, temperature_type_id());
+            let index = node_ids
+                .iter()
+                .position(|node_id| *node_id == d.node_id.node_id)
+                .unwrap() as i32;
+            assert_eq!(d.values, Some(vec![Variant::from(index * 10)]));
+        });
+    });
+}
+
+#[test]
+fn query_with_filter() {
+    do_query_service_test(|server_state, session, address_space, node_ids, qs| {
+        // Values of 50 and above
+        let filter = ContentFilterBuilder::new()
+            .gte(value_operand(), Operand::literal(50))
+            .build();
+        let request = make_query_first_request(temperature_type_id(), filter, 0);
+        let response = qs.query_first(
+            server_state.clone(),
+            session.clone(),
+            address_space.clone(),
+            &request,
+        );
+        let response = supported_message_as!(response, QueryFirstResponse);
+        assert!(response.response_header.service_result.is_good());
+        assert_eq!(
+            data_set_node_ids(&response.query_data_sets.unwrap()),
+            node_ids[5..].iter().cloned().collect()
+        );
+
+        // Values between 20 and 40 combined with another element
+        let filter = ContentFilterBuilder::new()
+            .and(Operand::element(1), Operand::element(2))
+            .gte(value_operand(), Operand::literal(20))
+            .lte(value_operand(), Operand::literal(40))
+            .build();
+        let request = make_query_first_request(temperature_type_id(), filter, 0);
+        let response = qs.query_first(
+            server_state.clone(),
+            session.clone(),
+            address_space.clone(),
+            &request,
+        );
+        let response = supported_message_as!(response, QueryFirstResponse);
+        assert_eq!(
+            data_set_node_ids(&response.query_data_sets.unwrap()),
+            node_ids[2..5].iter().cloned().collect()
+        );
+
+        // An operator that isn't supported makes the filter invalid
+        let filter = ContentFilterBuilder::new()
+            .bitwise_and(value_operand(), Operand::literal(1))
+            .build();
+        let request = make_query_first_request(temperature_type_id(), filter, 0);
+        let response = qs.query_first(
+            server_state.clone(),
+            session.clone(),
+            address_space.clone(),
+            &request,
+        );
+        let response = supported_message_as!(response, QueryFirstResponse);
+        assert_eq!(
+            response.response_header.service_result,
+            StatusCode::BadContentFilterInvalid
+        );
+        let element_results = response.filter_result.element_results.unwrap();
+        assert_eq!(
+            element_results[0].status_code,
+            StatusCode::BadFilterOperatorUnsupported
+        );
+
+        // A type that doesn't exist fails to parse
+        let request = make_query_first_request(
+            NodeId::new(1, "NoSuchType"),
+            ContentFilter { elements: None },
+            0,
+        );
+        let response = qs.query_first(server_state, session, address_space, &request);
+        let response = supported_message_as!(response, QueryFirstResponse);
+        assert_eq!(
+            response.parsing_results.unwrap()[0].status_code,
+            StatusCode::BadNodeIdUnknown
+        );
+        assert!(response.query_data_sets.unwrap().is_empty());
+    });
+}
+
+#[test]
+fn query_next() {
+    do_query_service_test(|server_state, session, address_space, node_ids, qs| {
+        // Return 4 data sets at a time
+        let request =
+            make_query_first_request(temperature_type_id(), ContentFilter { elements: None }, 4);
+        let response = qs.query_first(
+            server_state.clone(),
+            session.clone(),
+            address_space.clone(),
+            &request,
+        );
+        let response = supported_message_as!(response, QueryFirstResponse);
+        let mut query_data_sets = response.query_data_sets.unwrap();
+        assert_eq!(query_data_sets.len(), 4);
+        assert!(!response.continuation_point.is_null());
+
+        let request = make_query_next_request(&response.continuation_point, false);
+        let response = qs.query_next(
+            server_state.clone(),
+            session.clone(),
+            address_space.clone(),
+            &request,
+        );
+        let response = supported_message_as!(response, QueryNextResponse);
+        let next_data_sets = response.query_data_sets.unwrap();
+        assert_eq!(next_data_sets.len(), 4);
+        assert!(!response.revised_continuation_point.is_null());
+        query_data_sets.extend(next_data_sets);
+
+        let last_continuation_point = response.revised_continuation_point;
+        let request = make_query_next_request(&last_continuation_point, false);
+        let response = qs.query_next(
+            server_state.clone(),
+            session.clone(),
+            address_space.clone(),
+            &request,
+        );
+        let response = supported_message_as!(response, QueryNextResponse);
+        let next_data_sets = response.query_data_sets.unwrap();
+        assert_eq!(next_data_sets.len(), 2);
+        assert!(response.revised_continuation_point.is_null());
+        query_data_sets.extend(next_data_sets);
+
+        // Every variable was returned once
+        assert_eq!(query_data_sets.len(), 10);
+        assert_eq!(
+            data_set_node_ids(&query_data_sets),
+            node_ids.into_iter().collect()
+        );
+
+        // The used continuation point is gone
+        let request = make_query_next_request(&last_continuation_point, false);
+        let response = qs.query_next(
+            server_state.clone(),
+            session.clone(),
+            address_space.clone(),
+            &request,
+        );
+        let response = supported_message_as!(response, ServiceFault);
+        assert_eq!(
+            response.response_header.service_result,
+            StatusCode::BadContinuationPointInvalid
+        );
+
+        // Releasing a continuation point returns nothing and removes it
+        let request =
+            make_query_first_request(temperature_type_id(), ContentFilter { elements: None }, 4);
+        let response = qs.query_first(
+            server_state.clone(),
+            session.clone(),
+            address_space.clone(),
+            &request,
+        );
+        let response = supported_message_as!(response, QueryFirstResponse);
+        let continuation_point = response.continuation_point;
+        let request = make_query_next_request(&continuation_point, true);
+        let response = qs.query_next(
+            server_state.clone(),
+            session.clone(),
+            address_space.clone(),
+            &request,
+        );
+        let response = supported_message_as!(response, QueryNextResponse);
+        assert!(response.query_data_sets.is_none());
+        assert!(response.revised_continuation_point.is_null());
+        let request = make_query_next_request(&continuation_point, false);
+        let response = qs.query_next(server_state, session, address_space, &request);
+        let _ = supported_message_as!(response, ServiceFault);
+    });
+}
@@ -1,10 +1,21 @@
-use crate::crypto::{random, user_identity::make_user_name_identity_token, SecurityPolicy};
-use crate::types::{ActivateSessionRequest, RequestHeader, SignatureData};
+use crate::core::comms::secure_channel::SecureChannel;
+use crate::crypto::{
+    self, random, user_identity::make_user_name_identity_token, PrivateKey, SecurityPolicy,
+    X509Data,
+};
+use crate::supported_message_as;
+use crate::types::{
+    ActivateSessionRequest, ApplicationDescription, CreateSessionRequest, RequestHeader,
+    SignatureData,
+};
 
 use crate::server::{
     builder::ServerBuilder,
+    callbacks::AuthenticationManager,
+    config::{ServerEndpoint, ServerUserToken},
     identity_token::{
         POLICY_ID_USER_PASS_NONE, POLICY_ID_USER_PASS_RSA_15, POLICY_ID_USER_PASS_RSA_OAEP,
+        POLICY_ID_X509,
     },
     services::session::SessionService,
     state::ServerState,
@@ -33,7 +44,7 @@ fn dummy_activate_session_request() -> ActivateSessionRequest {
 /// A helper that sets up a subscription service test
 fn do_session_service_test<T>(pki_dir: Option<&str>, f: T)
 where
-    T: FnOnce(Arc<RwLock<ServerState>>, SessionService),
+    T: FnOnce(Arc<RwLock<ServerState>>, Arc<RwLock<CertificateStore>>),
 {
     crate::console_logging::init();
 
@@ -43,12 +54,13 @@ where
     };
 
     let st = ServiceTest::new_with_server(server_builder);
-    f(st.server_state.clone(), SessionService::new());
+    f(st.server_state.clone(), st.certificate_store.clone());
 }
 
 #[test]
 fn anonymous_user_token() {
-    do_session_service_test(None, |server_state, _session_service| {
+    do_session_service_test(None, |server_state, certificate_store| {
+        let certificate_store = trace_read_lock!(certificate_store);
         let server_state = server_state.read();
 
         // Makes an anonymous token and sticks it into an extension object
@@ -65,6 +77,7 @@ fn anonymous_user_token() {
         let request = dummy_activate_session_request();
 
         let result = server_state.authenticate_endpoint(
+            &certificate_store,
             &request,
             "opc.tcp://localhost:4855/",
             SecurityPolicy::None,
@@ -76,6 +89,7 @@ fn anonymous_user_token() {
         assert!(result.is_ok());
 
         let result = server_state.authenticate_endpoint(
+            &certificate_store,
             &request,
             "opc.tcp://localhost:4855/x",
             SecurityPolicy::None,
@@ -87,6 +101,7 @@ fn anonymous_user_token() {
         assert_eq!(result.unwrap_err(), StatusCode::BadTcpEndpointUrlInvalid);
 
         let result = server_state.authenticate_endpoint(
+            &certificate_store,
             &request,
             "opc.tcp://localhost:4855/noaccess",
             SecurityPolicy::None,
@@ -146,7 +161,8 @@ fn make_unencrypted_user_name_identity_token(user: &str, pass: &str) -> Extensio
 fn user_name_pass_token() {
     do_session_service_test(
         Some("./pki_user_name_pass_token"),
-        |server_state, _session_service| {
+        |server_state, certificate_store| {
+            let certificate_store = trace_read_lock!(certificate_store);
             let server_nonce = random::byte_string(20);
 
             let server_state = server_state.read();
@@ -160,6 +176,7 @@ fn user_name_pass_token() {
             // Test that a good user authenticates in unencrypt and encrypted policies
             let token = make_unencrypted_user_name_identity_token("sample1", "sample1pwd");
             let result = server_state.authenticate_endpoint(
+                &certificate_store,
                 &request,
                 ENDPOINT_URL,
                 SecurityPolicy::None,
@@ -178,6 +195,7 @@ fn user_name_pass_token() {
                 "sample1pwd",
             );
             let result = server_state.authenticate_endpoint(
+                &certificate_store,
                 &request,
                 ENDPOINT_URL,
                 SecurityPolicy::Basic128Rsa15,
@@ -196,6 +214,7 @@ fn user_name_pass_token() {
                 "sample1pwd",
             );
             let result = server_state.authenticate_endpoint(
+                &certificate_store,
                 &request,
                 ENDPOINT_URL,
                 SecurityPolicy::Basic256,
@@ -214,6 +233,7 @@ fn user_name_pass_token() {
                 "sample1pwd",
             );
             let result = server_state.authenticate_endpoint(
+                &certificate_store,
                 &request,
                 ENDPOINT_URL,
                 SecurityPolicy::Basic256Sha256,
@@ -235,6 +255,7 @@ fn user_name_pass_token() {
                 "sample1pwd",
             );
             let result = server_state.authenticate_endpoint(
+                &certificate_store,
                 &request,
                 ENDPOINT_URL,
                 SecurityPolicy::Basic256Sha256,
@@ -254,6 +275,7 @@ fn user_name_pass_token() {
                 "sample1pwd",
             );
             let result = server_state.authenticate_endpoint(
+                &certificate_store,
                 &request,
                 ENDPOINT_URL,
                 SecurityPolicy::Basic256Sha256,
@@ -266,6 +288,7 @@ fn user_name_pass_token() {
             // Invalid user
             let token = make_unencrypted_user_name_identity_token("samplex", "sample1pwd");
             let result = server_state.authenticate_endpoint(
+                &certificate_store,
                 &request,
                 ENDPOINT_URL,
                 SecurityPolicy::None,
@@ -278,6 +301,7 @@ fn user_name_pass_token() {
             // Invalid password
             let token = make_unencrypted_user_name_identity_token("sample1", "sample");
             let result = server_state.authenticate_endpoint(
+                &certificate_store,
                 &request,
                 ENDPOINT_URL,
                 SecurityPolicy::None,
@@ -290,6 +314,7 @@ fn user_name_pass_token() {
             // Empty user
             let token = make_unencrypted_user_name_identity_token("", "sample1pwd");
             let result = server_state.authenticate_endpoint(
+                &certificate_store,
                 &request,
                 ENDPOINT_URL,
                 SecurityPolicy::None,
@@ -309,6 +334,7 @@ fn user_name_pass_token() {
                 "samplexx1",
             );
             let result = server_state.authenticate_endpoint(
+                &certificate_store,
                 &request,
                 ENDPOINT_URL,
                 SecurityPolicy::Basic256Sha256,
@@ -320,3 +346,587 @@ fn user_name_pass_token() {
         },
     );
 }
+
+#[test]
+fn cancel_publish_request() {
+    use crate::server::subscriptions::PublishRequestEntry;
+    use crate::supported_message_as;
+
+    let st = ServiceTest::new();
+    let (server_state, session) = st.get_server_state_and_session();
+    let ss = SessionService::new();
+
+    // Fake some in-flight publish requests
+    {
+        let mut session = trace_write_lock!(session);
+        let publish_request_queue = session.subscriptions_mut().publish_request_queue();
+        [(1001, 5), (1002, 6)]
+            .iter()
+            .for_each(|(request_id, request_handle)| {
+                let mut request_header = RequestHeader::dummy();
+                request_header.request_handle = *request_handle;
+                publish_request_queue.push_front(PublishRequestEntry {
+                    request_id: *request_id,
+                    request: PublishRequest {
+                        request_header,
+                        subscription_acknowledgements: None,
+                    },
+                    results: None,
+                });
+            });
+    }
+
+    let cancel = |request_handle| {
+        let request = CancelRequest {
+            request_header: make_request_header(),
+            request_handle,
+        };
+        let response = ss.cancel(server_state.clone(), session.clone(), &request);
+        supported_message_as!(response, CancelResponse).cancel_count
+    };
+
+    // Nothing matches
+    assert_eq!(cancel(99), 0);
+
+    // Cancel one of the requests
+    assert_eq!(cancel(5), 1);
+    let mut session = trace_write_lock!(session);
+    let subscriptions = session.subscriptions_mut();
+    assert_eq!(subscriptions.publish_request_queue().len(), 1);
+    assert_eq!(
+        subscriptions.publish_request_queue()[0]
+            .request
+            .request_header
+            .request_handle,
+        6
+    );
+    let publish_response = subscriptions.publish_response_queue().pop_back().unwrap();
+    assert_eq!(publish_response.request_id, 1001);
+    let response = supported_message_as!(publish_response.response, ServiceFault);
+    assert_eq!(
+        response.response_header.service_result,
+        StatusCode::BadRequestCancelledByClient
+    );
+}
+
+/// Authenticates against a fixed list of (user, password, enabled) entries
+struct TestAuthenticationManager {
+    users: Vec<(&'static str, &'static str, bool)>,
+}
+
+impl AuthenticationManager for TestAuthenticationManager {
+    fn authenticate_username(
+        &self,
+        _endpoint: &ServerEndpoint,
+        user_name: &str,
+        password: &str,
+    ) -> Result<String, StatusCode> {
+        match self
+            .users
+            .iter()
+            .find(|(user, pass, _)| *user == user_name && *pass == password)
+        {
+            Some((user, _, true)) => Ok(user.to_string()),
+            Some(_) => Err(StatusCode::BadUserAccessDenied),
+            None => Err(StatusCode::BadIdentityTokenRejected),
+        }
+    }
+}
+
+#[test]
+fn authentication_manager() {
+    do_session_service_test(None, |server_state, certificate_store| {
+        let certificate_store = trace_read_lock!(certificate_store);
+        {
+            let mut server_state = trace_write_lock!(server_state);
+            server_state.set_authentication_manager(Box::new(TestAuthenticationManager {
+                users: vec![("alice", "alicepwd", true), ("bob", "bobpwd", false)],
+            }));
+        }
+        let server_state = trace_read_lock!(server_state);
+        let server_nonce = random::byte_string(20);
+        let request = dummy_activate_session_request();
+        let authenticate = |token: &ExtensionObject| {
+            server_state.authenticate_endpoint(
+                &certificate_store,
+                &request,
+                "opc.tcp://localhost:4855/",
+                SecurityPolicy::None,
+                MessageSecurityMode::None,
+                token,
+                &server_nonce,
+            )
+        };
+
+        // Good user name and password
+        let result = authenticate(&make_unencrypted_user_name_identity_token(
+            "alice", "alicepwd",
+        ));
+        assert_eq!(result.unwrap(), "alice");
+
+        // Bad password, and a user only known to the server configuration
+        let result = authenticate(&make_unencrypted_user_name_identity_token("alice", "x"));
+        assert_eq!(result.unwrap_err(), StatusCode::BadIdentityTokenRejected);
+        let result = authenticate(&make_unencrypted_user_name_identity_token(
+            "sample1",
+            "sample1pwd",
+        ));
+        assert_eq!(result.unwrap_err(), StatusCode::BadIdentityTokenRejected);
+
+        // Disabled user
+        let result = authenticate(&make_unencrypted_user_name_identity_token("bob", "bobpwd"));
+        assert_eq!(result.unwrap_err(), StatusCode::BadUserAccessDenied);
+
+        // Anonymous is still decided by the endpoint
+        let token = ExtensionObject::from_encodable(
+            ObjectId::AnonymousIdentityToken_Encoding_DefaultBinary,
+            &AnonymousIdentityToken {
+                policy_id: UAString::from("anonymous"),
+            },
+        );
+        assert!(authenticate(&token).is_ok());
+    });
+}
+
+fn make_user_cert(common_name: &str) -> (X509, PrivateKey) {
+    X509::cert_and_pkey(&X509Data {
+        key_size: 2048,
+        common_name: common_name.to_string(),
+        organization: "x.org".to_string(),
+        organizational_unit: "x.org ops".to_string(),
+        country: "EN".to_string(),
+        state: "London".to_string(),
+        alt_host_names: vec![],
+        certificate_duration_days: 60,
+    })
+    .unwrap()
+}
+
+/// Makes an X509 identity token and the signature of the server certificate and nonce made with
+/// the signing key
+fn make_x509_identity_token(
+    cert: &X509,
+    signing_key: &PrivateKey,
+    server_cert: &X509,
+    server_nonce: &ByteString,
+) -> (ExtensionObject, SignatureData) {
+    let token = X509IdentityToken {
+        policy_id: UAString::from(POLICY_ID_X509),
+        certificate_data: cert.as_byte_string(),
+    };
+    let token =
+        ExtensionObject::from_encodable(ObjectId::X509IdentityToken_Encoding_DefaultBinary, &token);
+    let signature = crypto::create_signature_data(
+        signing_key,
+        SecurityPolicy::Basic128Rsa15,
+        &server_cert.as_byte_string(),
+        server_nonce,
+    )
+    .unwrap();
+    (token, signature)
+}
+
+#[test]
+fn x509_user_token() {
+    let st = ServiceTest::new();
+    let server_state = st.server_state.clone();
+    let (trusted_cert, trusted_key) = make_user_cert("trusted");
+    let (untrusted_cert, untrusted_key) = make_user_cert("untrusted");
+    let (unstored_cert, unstored_key) = make_user_cert("unstored");
+
+    // The first certificate is in the certificate store's trusted folder, the third is not
+    {
+        let certificate_store = trace_read_lock!(st.certificate_store);
+        let mut cert_path = certificate_store.trusted_certs_dir();
+        cert_path.push(CertificateStore::cert_file_name(&trusted_cert));
+        std::fs::write(cert_path, trusted_cert.to_der().unwrap()).unwrap();
+    }
+
+    // Users for the first and third certificates may use the endpoints
+    {
+        let server_state = trace_read_lock!(server_state);
+        let mut config = trace_write_lock!(server_state.config);
+        for (user, cert) in [
+            ("x509_test_user", &trusted_cert),
+            ("x509_unstored_user", &unstored_cert),
+        ] {
+            config.user_tokens.insert(
+                user.to_string(),
+                ServerUserToken {
+                    user: user.to_string(),
+                    pass: None,
+                    x509: Some(format!("{}.der", user)),
+                    thumbprint: Some(cert.thumbprint()),
+                },
+            );
+            config.endpoints.values_mut().for_each(|e| {
+                e.user_token_ids.insert(user.to_string());
+            });
+        }
+    }
+    let server_cert = trace_read_lock!(server_state)
+        .server_certificate
+        .clone()
+        .unwrap();
+
+    // Activates a new session with a token for the certificate, signed by the key
+    let activate = |cert: &X509, signing_key: &PrivateKey| {
+        let mut session = Session::new(server_state.clone());
+        session.set_endpoint_url(UAString::from("opc.tcp://localhost:4855/"));
+        session.set_session_nonce(SecurityPolicy::Basic128Rsa15.random_nonce());
+        let (user_identity_token, user_token_signature) =
+            make_x509_identity_token(cert, signing_key, &server_cert, session.session_nonce());
+        let session = Arc::new(RwLock::new(session));
+        let response = SessionService::new().activate_session(
+            Arc::new(RwLock::new(SecureChannel::new_no_certificate_store())),
+            st.certificate_store.clone(),
+            server_state.clone(),
+            session.clone(),
+            st.address_space.clone(),
+            &ActivateSessionRequest {
+                user_identity_token,
+                user_token_signature,
+                ..dummy_activate_session_request()
+            },
+        );
+        (response, session)
+    };
+
+    // A trusted certificate with a valid signature is authenticated and stored on the session
+    let (response, session) = activate(&trusted_cert, &trusted_key);
+    let _ = supported_message_as!(response, ActivateSessionResponse);
+    {
+        let session = trace_read_lock!(session);
+        assert!(session.is_activated());
+        assert_eq!(session.user_token_id(), Some("x509_test_user"));
+    }
+
+    // A certificate that is not trusted is rejected
+    let (response, session) = activate(&untrusted_cert, &untrusted_key);
+    let response = supported_message_as!(response, ServiceFault);
+    assert_eq!(
+        response.response_header.service_result,
+        StatusCode::BadIdentityTokenRejected
+    );
+    assert!(trace_read_lock!(session).user_token_id().is_none());
+
+    // A certificate that matches a user is still rejected when the store does not trust it
+    let (response, session) = activate(&unstored_cert, &unstored_key);
+    let response = supported_message_as!(response, ServiceFault);
+    assert_eq!(
+        response.response_header.service_result,
+        StatusCode::BadIdentityTokenRejected
+    );
+    assert!(trace_read_lock!(session).user_token_id().is_none());
+
+    // A trusted certificate whose signature was made by another key is invalid
+    let (response, _) = activate(&trusted_cert, &untrusted_key);
+    let response = supported_message_as!(response, ServiceFault);
+    assert_eq!(
+        response.response_header.service_result,
+        StatusCode::BadIdentityTokenInvalid
+    );
+}
+
+#[test]
+fn client_signature() {
+    let st = ServiceTest::new();
+    let server_state = st.server_state.clone();
+    let (client_cert, client_key) = make_user_cert("client");
+    let (_, other_key) = make_user_cert("other");
+    let server_cert = trace_read_lock!(server_state)
+        .server_certificate
+        .clone()
+        .unwrap();
+
+    let security_policy = SecurityPolicy::Basic256Sha256;
+    let mut secure_channel = SecureChannel::new_no_certificate_store();
+    secure_channel.set_security_policy(security_policy);
+    secure_channel.set_security_mode(MessageSecurityMode::SignAndEncrypt);
+    let secure_channel = Arc::new(RwLock::new(secure_channel));
+
+    // A session as create session leaves it, holding the client certificate and a server nonce
+    let session = {
+        let mut session = Session::new(server_state.clone());
+        session.set_endpoint_url(UAString::from("opc.tcp://localhost:4855/"));
+        session.set_client_certificate(Some(client_cert));
+        session.set_session_nonce(security_policy.random_nonce());
+        Arc::new(RwLock::new(session))
+    };
+
+    // Signs the server certificate and nonce with the key, as the client does
+    let sign = |signing_key: &PrivateKey, server_nonce: &ByteString| {
+        crypto::create_signature_data(
+            signing_key,
+            security_policy,
+            &server_cert.as_byte_string(),
+            server_nonce,
+        )
+        .unwrap()
+    };
+    let activate = |client_signature: SignatureData| {
+        SessionService::new().activate_session(
+            secure_channel.clone(),
+            st.certificate_store.clone(),
+            server_state.clone(),
+            session.clone(),
+            st.address_space.clone(),
+            &ActivateSessionRequest {
+                client_signature,
+                user_identity_token: ExtensionObject::from_encodable(
+                    ObjectId::AnonymousIdentityToken_Encoding_DefaultBinary,
+                    &AnonymousIdentityToken {
+                        policy_id: UAString::from("anonymous"),
+                    },
+                ),
+                ..dummy_activate_session_request()
+            },
+        )
+    };
+    let activate_error = |client_signature: SignatureData| {
+        let response = activate(client_signature);
+        supported_message_as!(response, ServiceFault)
+            .response_header
+            .service_result
+    };
+
+    // A valid signature activates the session and rotates the nonce
+    let first_nonce = trace_read_lock!(session).session_nonce().clone();
+    let response = activate(sign(&client_key, &first_nonce));
+    let response = supported_message_as!(response, ActivateSessionResponse);
+    let second_nonce = trace_read_lock!(session).session_nonce().clone();
+    assert!(trace_read_lock!(session).is_activated());
+    assert_ne!(second_nonce, first_nonce);
+    assert_eq!(response.server_nonce, second_nonce);
+
+    // A signature over the previous nonce no longer verifies
+    assert_eq!(
+        activate_error(sign(&client_key, &first_nonce)),
+        StatusCode::BadApplicationSignatureInvalid
+    );
+    assert!(!trace_read_lock!(session).is_activated());
+    assert_eq!(trace_read_lock!(session).session_nonce(), &second_nonce);
+
+    // Nor does one made by a key other than the client certificate's
+    assert_eq!(
+        activate_error(sign(&other_key, &second_nonce)),
+        StatusCode::BadApplicationSignatureInvalid
+    );
+
+    // Nor a tampered signature
+    let mut client_signature = sign(&client_key, &second_nonce);
+    let mut signature = client_signature.signature.as_ref().to_vec();
+    signature[0] ^= 0xff;
+    client_signature.signature = ByteString::from(signature);
+    assert_eq!(
+        activate_error(client_signature),
+        StatusCode::BadApplicationSignatureInvalid
+    );
+
+    // The current nonce activates again
+    let response = activate(sign(&client_key, &second_nonce));
+    let _ = supported_message_as!(response, ActivateSessionResponse);
+    assert_ne!(trace_read_lock!(session).session_nonce(), &second_nonce);
+}
+
+fn create_session_request(endpoint_url: &str) -> CreateSessionRequest {
+    CreateSessionRequest {
+        request_header: make_request_header(),
+        client_description: ApplicationDescription::default(),
+        server_uri: UAString::null(),
+        endpoint_url: UAString::from(endpoint_url),
+        session_name: UAString::from("test"),
+        client_nonce: ByteString::null(),
+        client_certificate: ByteString::null(),
+        requested_session_timeout: 60000f64,
+        max_response_message_size: 0,
+    }
+}
+
+#[test]
+fn sign_only_endpoint_rejects_none_session() {
+    // A server that only offers endpoints which sign messages
+    let mut config = ServerBuilder::new_sample().config();
+    config
+        .endpoints
+        .retain(|_, e| e.message_security_mode() == MessageSecurityMode::Sign);
+    assert!(!config.endpoints.is_empty());
+    let server = ServerBuilder::from_config(config).server().unwrap();
+    let server_state = server.server_state();
+    let endpoint_url = "opc.tcp://localhost:4855/";
+
+    let create_session = |security_policy: SecurityPolicy, security_mode: MessageSecurityMode| {
+        let mut secure_channel = SecureChannel::new_no_certificate_store();
+        secure_channel.set_security_policy(security_policy);
+        secure_channel.set_security_mode(security_mode);
+        let (session, response) = SessionService::new().create_session(
+            Arc::new(RwLock::new(secure_channel)),
+            server.certificate_store(),
+            server_state.clone(),
+            server.address_space(),
+            &create_session_request(endpoint_url),
+        );
+        assert!(session.is_none());
+        let response = supported_message_as!(response, ServiceFault);
+        response.response_header.service_result
+    };
+
+    // No security is rejected
+    assert_eq!(
+        create_session(SecurityPolicy::None, MessageSecurityMode::None),
+        StatusCode::BadSecurityPolicyRejected
+    );
+    // A signed channel gets past the security check and fails on its missing certificate
+    assert_eq!(
+        create_session(SecurityPolicy::Basic256Sha256, MessageSecurityMode::Sign),
+        StatusCode::BadCertificateInvalid
+    );
+
+    // Activate applies the same check
+    let server_state = trace_read_lock!(server_state);
+    assert_eq!(
+        server_state.validate_endpoint_security(
+            endpoint_url,
+            SecurityPolicy::None,
+            MessageSecurityMode::None
+        ),
+        Err(StatusCode::BadSecurityPolicyRejected)
+    );
+    assert_eq!(
+        server_state.validate_endpoint_security(
+            endpoint_url,
+            SecurityPolicy::Basic256Sha256,
+            MessageSecurityMode::SignAndEncrypt
+        ),
+        Err(StatusCode::BadSecurityPolicyRejected)
+    );
+    assert_eq!(
+        server_state.validate_endpoint_security(
+            "opc.tcp://localhost:4855/x",
+            SecurityPolicy::Basic256Sha256,
+            MessageSecurityMode::Sign
+        ),
+        Err(StatusCode::BadTcpEndpointUrlInvalid)
+    );
+    assert!(server_state
+        .validate_endpoint_security(
+            endpoint_url,
+            SecurityPolicy::Basic256Sha256,
+            MessageSecurityMode::Sign
+        )
+        .is_ok());
+}
+
+#[test]
+fn timed_out_session_is_removed() {
+    let st = ServiceTest::new();
+    let now = chrono::Utc::now();
+
+    // The test session has been idle for longer than its timeout and holds a subscription
+    let expired_session_id = {
+        let ss = SubscriptionService::new();
+        let request = create_subscription_request(0, 0);
+        let _: CreateSubscriptionResponse = supported_message_as!(
+            ss.create_subscription(st.server_state.clone(), st.session.clone(), &request),
+            CreateSubscriptionResponse
+        );
+        let mut session = trace_write_lock!(st.session);
+        session.set_session_timeout(1000f64);
+        session.set_last_service_request_timestamp(now - chrono::Duration::seconds(2));
+        assert!(session.is_timed_out(&now));
+        session.session_id().clone()
+    };
+
+    // Another session which is still active
+    let active_session = Arc::new(RwLock::new(Session::new(st.server_state.clone())));
+    {
+        let mut session = trace_write_lock!(active_session);
+        session.set_session_timeout(1000f64);
+        session.set_last_service_request_timestamp(now);
+        assert!(!session.is_timed_out(&now));
+    }
+    {
+        let mut session_manager = trace_write_lock!(st.session_manager);
+        session_manager.register_session(active_session.clone());
+    }
+
+    let mut session_manager = trace_write_lock!(st.session_manager);
+    assert!(session_manager.has_timed_out_sessions(&now));
+    let removed = session_manager.remove_timed_out_sessions(&now, st.address_space.clone());
+    assert_eq!(removed, vec![expired_session_id.clone()]);
+    assert_eq!(session_manager.len(), 1);
+    assert!(session_manager
+        .find_session_by_id(&expired_session_id)
+        .is_none());
+    assert!(!session_manager.sessions_terminated());
+
+    // The expired session is terminated and its subscriptions are gone
+    let session = trace_read_lock!(st.session);
+    assert!(session.is_terminated());
+    assert!(session.subscriptions().is_empty());
+
+    // Nothing else times out
+    assert!(!session_manager.has_timed_out_sessions(&now));
+    assert!(session_manager
+        .remove_timed_out_sessions(&now, st.address_space.clone())
+        .is_empty());
+}
+
+#[test]
+fn anonymous_disallowed() {
+    let st = ServiceTest::new_with_server(ServerBuilder::new_sample().allow_anonymous(false));
+    let server_state = st.server_state.clone();
+    assert!(!trace_read_lock!(server_state).allow_anonymous);
+
+    // No endpoint advertises the anonymous policy
+    let endpoints = trace_read_lock!(server_state)
+        .endpoints(&UAString::from("opc.tcp://localhost:4855/"), &None)
+        .unwrap();
+    assert!(!endpoints.is_empty());
+    assert!(endpoints.iter().all(|e| e
+        .user_identity_tokens
+        .as_ref()
+        .unwrap()
+        .iter()
+        .all(|t| t.token_type != UserTokenType::Anonymous)));
+
+    let secure_channel = Arc::new(RwLock::new(SecureChannel::new_no_certificate_store()));
+    let session = {
+        let mut session = Session::new(server_state.clone());
+        session.set_endpoint_url(UAString::from("opc.tcp://localhost:4855/"));
+        Arc::new(RwLock::new(session))
+    };
+    let activate = || {
+        SessionService::new().activate_session(
+            secure_channel.clone(),
+            st.certificate_store.clone(),
+            server_state.clone(),
+            session.clone(),
+            st.address_space.clone(),
+            &ActivateSessionRequest {
+                user_identity_token: ExtensionObject::from_encodable(
+                    ObjectId::AnonymousIdentityToken_Encoding_DefaultBinary,
+                    &AnonymousIdentityToken {
+                        policy_id: UAString::from("anonymous"),
+                    },
+                ),
+                ..dummy_activate_session_request()
+            },
+        )
+    };
+
+    // Anonymous activation is rejected on an endpoint that otherwise supports it
+    let response = activate();
+    let response = supported_message_as!(response, ServiceFault);
+    assert_eq!(
+        response.response_header.service_result,
+        StatusCode::BadIdentityTokenRejected
+    );
+    assert!(!trace_read_lock!(session).is_activated());
+
+    // And accepted once the server allows it again
+    trace_write_lock!(server_state).allow_anonymous = true;
+    let response = activate();
+    let _ = supported_message_as!(response, ActivateSessionResponse);
+    assert!(trace_read_lock!(session).is_activated());
+}
@@ -72,9 +72,99 @@ fn republish_request(subscription_id: u32, retransmit_sequence_number: u32) -> R
 
 #[test]
 fn create_modify_destroy_subscription() {
-    do_subscription_service_test(|_server_state, _session, _, _ss, _| {
-        // TODO Create a subscription, modify it, destroy it
-        //unimplemented!();
+    do_subscription_service_test(|server_state, session, _, ss, _| {
+        use crate::server::constants::MAX_KEEP_ALIVE_COUNT;
+
+        let subscription_id = create_subscription(server_state.clone(), session.clone(), &ss);
+
+        let min_publishing_interval_ms = {
+            let server_state = trace_read_lock!(server_state);
+            server_state.min_publishing_interval_ms
+        };
+
+        // Modify the subscription with values that are out of range so they must be revised
+        let request = ModifySubscriptionRequest {
+            request_header: make_request_header(),
+            subscription_id,
+            requested_publishing_interval: 0f64,
+            requested_lifetime_count: u32::MAX,
+            requested_max_keep_alive_count: u32::MAX,
+            max_notifications_per_publish: 50,
+            priority: 7,
+        };
+        let response: ModifySubscriptionResponse = supported_message_as!(
+            ss.modify_subscription(server_state.clone(), session.clone(), &request),
+            ModifySubscriptionResponse
+        );
+        assert_eq!(
+            response.revised_publishing_interval,
+            min_publishing_interval_ms
+        );
+        assert_eq!(response.revised_max_keep_alive_count, MAX_KEEP_ALIVE_COUNT);
+        assert_eq!(response.revised_lifetime_count, MAX_KEEP_ALIVE_COUNT * 3);
+
+        // The stored subscription should hold the revised values
+        {
+            let session = trace_read_lock!(session);
+            let subscription = session
+                .subscriptions()
+                .subscriptions()
+                .get(&subscription_id)
+                .unwrap();
+            assert_eq!(
+                subscription.publishing_interval(),
+                min_publishing_interval_ms
+            );
+            assert_eq!(subscription.max_keep_alive_count(), MAX_KEEP_ALIVE_COUNT);
+            assert_eq!(subscription.max_lifetime_count(), MAX_KEEP_ALIVE_COUNT * 3);
+            assert_eq!(subscription.max_notifications_per_publish(), 50);
+            assert_eq!(subscription.priority(), 7);
+        }
+
+        // A lifetime count below 3x the keep alive count is raised to meet it
+        let request = ModifySubscriptionRequest {
+            request_header: make_request_header(),
+            subscription_id,
+            requested_publishing_interval: 5000f64,
+            requested_lifetime_count: 10,
+            requested_max_keep_alive_count: 5,
+            max_notifications_per_publish: 0,
+            priority: 0,
+        };
+        let response: ModifySubscriptionResponse = supported_message_as!(
+            ss.modify_subscription(server_state.clone(), session.clone(), &request),
+            ModifySubscriptionResponse
+        );
+        assert_eq!(response.revised_publishing_interval, 5000f64);
+        assert_eq!(response.revised_max_keep_alive_count, 5);
+        assert_eq!(response.revised_lifetime_count, 15);
+
+        // Modifying an unknown subscription is an error
+        let request = ModifySubscriptionRequest {
+            subscription_id: subscription_id + 100,
+            ..request
+        };
+        let response: ServiceFault = supported_message_as!(
+            ss.modify_subscription(server_state.clone(), session.clone(), &request),
+            ServiceFault
+        );
+        assert_eq!(
+            response.response_header.service_result,
+            StatusCode::BadSubscriptionIdInvalid
+        );
+
+        // Destroy the subscription
+        let request = DeleteSubscriptionsRequest {
+            request_header: make_request_header(),
+            subscription_ids: Some(vec![subscription_id]),
+        };
+        let response: DeleteSubscriptionsResponse = supported_message_as!(
+            ss.delete_subscriptions(session.clone(), &request),
+            DeleteSubscriptionsResponse
+        );
+        assert_eq!(response.results.unwrap(), vec![StatusCode::Good]);
+        let session = trace_read_lock!(session);
+        assert!(!session.subscriptions().contains(subscription_id));
     })
 }
 
@@ -142,6 +232,193 @@ fn test_revised_keep_alive_lifetime_counts() {
     );
 }
 
+#[test]
+fn test_revised_publishing_interval() {
+    // Test that publishing intervals below the server's minimum are raised to it and that the
+    // subscription is stored in the session with the revised values
+    do_subscription_service_test(|server_state, session, _, ss, _| {
+        let min_publishing_interval_ms = {
+            let server_state = trace_read_lock!(server_state);
+            server_state.min_publishing_interval_ms
+        };
+
+        [
+            (-1f64, min_publishing_interval_ms),
+            (0f64, min_publishing_interval_ms),
+            (
+                min_publishing_interval_ms / 2f64,
+                min_publishing_interval_ms,
+            ),
+            (min_publishing_interval_ms, min_publishing_interval_ms),
+            (5000f64, 5000f64),
+        ]
+        .iter()
+        .for_each(|(requested, expected)| {
+            let mut request = create_subscription_request(0, 0);
+            request.requested_publishing_interval = *requested;
+            let response: CreateSubscriptionResponse = supported_message_as!(
+                ss.create_subscription(server_state.clone(), session.clone(), &request),
+                CreateSubscriptionResponse
+            );
+            assert_eq!(response.revised_publishing_interval, *expected);
+
+            let session = trace_read_lock!(session);
+            let subscription = session
+                .subscriptions()
+                .subscriptions()
+                .get(&response.subscription_id)
+                .unwrap();
+            assert_eq!(subscription.publishing_interval(), *expected);
+            assert_eq!(
+                subscription.max_keep_alive_count(),
+                response.revised_max_keep_alive_count
+            );
+            assert_eq!(
+                subscription.max_lifetime_count(),
+                response.revised_lifetime_count
+            );
+        });
+    })
+}
+
+#[test]
+fn set_publishing_mode() {
+    // Toggle publishing on a known subscription and an unknown one in the same request
+    do_subscription_service_test(|server_state, session, _, ss, _| {
+        let subscription_id = create_subscription(server_state, session.clone(), &ss);
+
+        [false, true].iter().for_each(|publishing_enabled| {
+            let request = SetPublishingModeRequest {
+                request_header: RequestHeader::dummy(),
+                publishing_enabled: *publishing_enabled,
+                subscription_ids: Some(vec![subscription_id, subscription_id + 1]),
+            };
+            let response: SetPublishingModeResponse = supported_message_as!(
+                ss.set_publishing_mode(session.clone(), &request),
+                SetPublishingModeResponse
+            );
+            assert_eq!(
+                response.results.unwrap(),
+                vec![StatusCode::Good, StatusCode::BadSubscriptionIdInvalid]
+            );
+
+            let session = trace_read_lock!(session);
+            let subscription = session
+                .subscriptions()
+                .subscriptions()
+                .get(&subscription_id)
+                .unwrap();
+            assert_eq!(subscription.publishing_enabled(), *publishing_enabled);
+        });
+
+        // No subscription ids is nothing to do
+        let request = SetPublishingModeRequest {
+            request_header: RequestHeader::dummy(),
+            publishing_enabled: true,
+            subscription_ids: None,
+        };
+        let response: ServiceFault = supported_message_as!(
+            ss.set_publishing_mode(session.clone(), &request),
+            ServiceFault
+        );
+        assert_eq!(
+            response.response_header.service_result,
+            StatusCode::BadNothingToDo
+        );
+    })
+}
+
+#[test]
+fn delete_subscriptions() {
+    do_subscription_service_test(|server_state, session, _, ss, _| {
+        let subscription_id_1 = create_subscription(server_state.clone(), session.clone(), &ss);
+        let subscription_id_2 = create_subscription(server_state.clone(), session.clone(), &ss);
+
+        // Queue a publish request and an unacknowledged notification for the first subscription
+        {
+            let mut session = trace_write_lock!(session);
+            let subscriptions = session.subscriptions_mut();
+            subscriptions
+                .publish_request_queue()
+                .push_front(PublishRequestEntry {
+                    request_id: 1001,
+                    request: publish_request(None),
+                    results: None,
+                });
+            subscriptions.retransmission_queue().insert(
+                (subscription_id_1, 1),
+                NotificationMessage::keep_alive(1, DateTime::now()),
+            );
+        }
+
+        // Delete a valid and an invalid subscription together
+        let request = DeleteSubscriptionsRequest {
+            request_header: make_request_header(),
+            subscription_ids: Some(vec![subscription_id_1, subscription_id_2 + 100]),
+        };
+        let response: DeleteSubscriptionsResponse = supported_message_as!(
+            ss.delete_subscriptions(session.clone(), &request),
+            DeleteSubscriptionsResponse
+        );
+        assert_eq!(
+            response.results.unwrap(),
+            vec![StatusCode::Good, StatusCode::BadSubscriptionIdInvalid]
+        );
+
+        {
+            let mut session = trace_write_lock!(session);
+            let subscriptions = session.subscriptions_mut();
+            assert!(!subscriptions.contains(subscription_id_1));
+            assert!(subscriptions.contains(subscription_id_2));
+            // The deleted subscription's notifications are gone
+            assert!(subscriptions.retransmission_queue().is_empty());
+            // The other subscription can still use the publish request
+            assert_eq!(subscriptions.publish_request_queue().len(), 1);
+            assert!(subscriptions.publish_response_queue().is_empty());
+        }
+
+        // Deleting the last subscription returns the queued publish request
+        let request = DeleteSubscriptionsRequest {
+            request_header: make_request_header(),
+            subscription_ids: Some(vec![subscription_id_2]),
+        };
+        let response: DeleteSubscriptionsResponse = supported_message_as!(
+            ss.delete_subscriptions(session.clone(), &request),
+            DeleteSubscriptionsResponse
+        );
+        assert_eq!(response.results.unwrap(), vec![StatusCode::Good]);
+
+        {
+            let mut session = trace_write_lock!(session);
+            let subscriptions = session.subscriptions_mut();
+            assert!(subscriptions.is_empty());
+            assert!(subscriptions.publish_request_queue().is_empty());
+            let publish_response = subscriptions.publish_response_queue().pop_back().unwrap();
+            assert_eq!(publish_response.request_id, 1001);
+            let response: ServiceFault =
+                supported_message_as!(publish_response.response, ServiceFault);
+            assert_eq!(
+                response.response_header.service_result,
+                StatusCode::BadNoSubscription
+            );
+        }
+
+        // Nothing to delete
+        let request = DeleteSubscriptionsRequest {
+            request_header: make_request_header(),
+            subscription_ids: None,
+        };
+        let response: ServiceFault = supported_message_as!(
+            ss.delete_subscriptions(session.clone(), &request),
+            ServiceFault
+        );
+        assert_eq!(
+            response.response_header.service_result,
+            StatusCode::BadNothingToDo
+        );
+    })
+}
+
 #[test]
 fn publish_with_no_subscriptions() {
     do_subscription_service_test(|_, session, address_space, ss, _| {
@@ -204,7 +481,7 @@ fn publish_response_subscription() {
 
             let mut session = trace_write_lock!(session);
             let address_space = trace_read_lock!(address_space);
-            let _ = session.tick_subscriptions(&now, &address_space, TickReason::TickTimerFired);
+            let _ = session.tick_subscriptions(&now, &*address_space, TickReason::TickTimerFired);
 
             // Ensure publish request was processed into a publish response
             assert_eq!(session.subscriptions_mut().publish_request_queue().len(), 0);
@@ -260,6 +537,81 @@ fn publish_response_subscription() {
     })
 }
 
+#[test]
+fn publish_response_max_notifications_per_publish() {
+    do_subscription_service_test(|server_state, session, address_space, ss, mis| {
+        let subscription_id = create_subscription(server_state.clone(), session.clone(), &ss);
+
+        // Monitor 5 items, which is more than fit in a publish response
+        let request =
+            create_monitored_items_request(subscription_id, (0..5).map(var_node_id).collect());
+        let _ = mis.create_monitored_items(
+            server_state.clone(),
+            session.clone(),
+            address_space.clone(),
+            &request,
+        );
+        {
+            let mut session = trace_write_lock!(session);
+            let subscription = session
+                .subscriptions_mut()
+                .get_mut(subscription_id)
+                .unwrap();
+            subscription.set_state(SubscriptionState::Normal);
+            subscription.set_max_notifications_per_publish(2);
+        }
+
+        let publish = |now: &DateTimeUtc, request_id| {
+            let _ = ss.async_publish(
+                now,
+                session.clone(),
+                address_space.clone(),
+                request_id,
+                &publish_request(None),
+            );
+        };
+        let take_responses = || {
+            let decoding_options = DecodingOptions::test();
+            let mut session = trace_write_lock!(session);
+            session
+                .subscriptions_mut()
+                .publish_response_queue()
+                .drain(..)
+                .map(|response| supported_message_as!(response.response, PublishResponse))
+                .map(|response| {
+                    let notification_data =
+                        response.notification_message.notification_data.unwrap();
+                    let data_change = notification_data[0]
+                        .decode_inner::<DataChangeNotification>(&decoding_options)
+                        .unwrap();
+                    (
+                        response.notification_message.sequence_number,
+                        data_change.monitored_items.unwrap().len(),
+                        response.more_notifications,
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let now = Utc::now();
+        publish(&now, 1001);
+        publish(&now, 1002);
+        let now = now.add(chrono::Duration::seconds(2));
+        {
+            let mut session = trace_write_lock!(session);
+            let address_space = trace_read_lock!(address_space);
+            let _ = session.tick_subscriptions(&now, &*address_space, TickReason::TickTimerFired);
+        }
+
+        // The notifications are split across responses, each saying if more are to come
+        assert_eq!(take_responses(), vec![(1, 2, true), (2, 2, true)]);
+
+        // The last of them goes with the next publish request
+        publish(&now, 1003);
+        assert_eq!(take_responses(), vec![(3, 1, false)]);
+    })
+}
+
 #[test]
 fn publish_keep_alive() {
     do_subscription_service_test(|server_state, session, address_space, ss, mis| {
@@ -322,7 +674,7 @@ fn publish_keep_alive() {
             // Tick subscriptions to trigger a change
             let now = now.add(chrono::Duration::seconds(2));
 
-            let _ = session.tick_subscriptions(&now, &address_space, TickReason::TickTimerFired);
+            let _ = session.tick_subscriptions(&now, &*address_space, TickReason::TickTimerFired);
 
             // Ensure publish request was processed into a publish response
             assert_eq!(session.subscriptions_mut().publish_request_queue().len(), 0);
@@ -379,30 +731,111 @@ fn multiple_publish_response_subscription() {
     })
 }
 
+/// Enqueues a publish request with the supplied acknowledgements, ticks the subscriptions forward
+/// and returns the publish response that was produced.
+fn publish_and_tick(
+    session: Arc<RwLock<Session>>,
+    address_space: Arc<RwLock<AddressSpace>>,
+    ss: &SubscriptionService,
+    now: &DateTimeUtc,
+    subscription_acknowledgements: Option<Vec<SubscriptionAcknowledgement>>,
+) -> PublishResponse {
+    let request = publish_request(subscription_acknowledgements);
+    let response = ss.async_publish(now, session.clone(), address_space.clone(), 1001, &request);
+    assert!(response.is_none());
+
+    let mut session = trace_write_lock!(session);
+    let address_space = trace_read_lock!(address_space);
+
+    // Keep ticking until the keep alive count expires and a response is produced
+    for i in 1..=100 {
+        let now = now.add(chrono::Duration::milliseconds(i * 200));
+        let _ = session.tick_subscriptions(&now, &*address_space, TickReason::TickTimerFired);
+        if let Some(response) = session
+            .subscriptions_mut()
+            .publish_response_queue()
+            .pop_back()
+        {
+            return supported_message_as!(response.response, PublishResponse);
+        }
+    }
+    panic!("No publish response was produced");
+}
+
 #[test]
 fn acknowledge_unknown_sequence_nr() {
     do_subscription_service_test(|server_state, session, address_space, ss, _mis| {
         let subscription_id = create_subscription(server_state, session.clone(), &ss);
 
+        // Disable publishing so every publish response is a keep-alive
+        {
+            let mut session = trace_write_lock!(session);
+            let subscription = session
+                .subscriptions_mut()
+                .get_mut(subscription_id)
+                .unwrap();
+            subscription.set_state(SubscriptionState::Normal);
+            subscription.set_publishing_enabled(false);
+        }
+
         let now = Utc::now();
-        let request_id = 1001;
 
-        // Acknowledge an unknown seqid, test the response
-        let ack = SubscriptionAcknowledgement {
-            subscription_id,
-            sequence_number: 10001,
-        };
-        let request = publish_request(Some(vec![ack]));
-        let _response = ss.async_publish(
-            &now,
+        // Acknowledge an unknown seqid and an unknown subscription, test the response
+        let acks = vec![
+            SubscriptionAcknowledgement {
+                subscription_id,
+                sequence_number: 10001,
+            },
+            SubscriptionAcknowledgement {
+                subscription_id: subscription_id + 1,
+                sequence_number: 1,
+            },
+        ];
+        let response = publish_and_tick(
             session.clone(),
             address_space.clone(),
-            request_id,
-            &request,
+            &ss,
+            &now,
+            Some(acks),
         );
+        assert_eq!(
+            response.results.unwrap(),
+            vec![
+                StatusCode::BadSequenceNumberUnknown,
+                StatusCode::BadSubscriptionIdInvalid
+            ]
+        );
+        let sequence_number = response.notification_message.sequence_number;
+        assert_eq!(sequence_number, 1);
 
-        // TODO
-        //unimplemented!();
+        // The sent notification is waiting in the retransmission queue until acknowledged
+        {
+            let mut session = trace_write_lock!(session);
+            assert!(session
+                .subscriptions_mut()
+                .retransmission_queue()
+                .contains_key(&(subscription_id, sequence_number)));
+        }
+
+        // Acknowledge the notification that was sent
+        let acks = vec![SubscriptionAcknowledgement {
+            subscription_id,
+            sequence_number,
+        }];
+        let now = now.add(chrono::Duration::seconds(30));
+        let response = publish_and_tick(
+            session.clone(),
+            address_space.clone(),
+            &ss,
+            &now,
+            Some(acks),
+        );
+        assert_eq!(response.results.unwrap(), vec![StatusCode::Good]);
+        let mut session = trace_write_lock!(session);
+        assert!(!session
+            .subscriptions_mut()
+            .retransmission_queue()
+            .contains_key(&(subscription_id, sequence_number)));
     })
 }
 
@@ -459,3 +892,305 @@ fn republish() {
         );
     })
 }
+
+#[test]
+fn republish_published_notifications() {
+    do_subscription_service_test(|server_state, session, address_space, ss, mis| {
+        let subscription_id = create_subscription(server_state.clone(), session.clone(), &ss);
+        create_monitored_item(
+            subscription_id,
+            var_node_id(0),
+            server_state.clone(),
+            session.clone(),
+            address_space.clone(),
+            &mis,
+        );
+        {
+            let mut session = trace_write_lock!(session);
+            session
+                .subscriptions_mut()
+                .get_mut(subscription_id)
+                .unwrap()
+                .set_state(SubscriptionState::Normal);
+        }
+
+        // Publish the initial value, then a changed value, without acknowledging either
+        let now = Utc::now();
+        let first = publish_and_tick(session.clone(), address_space.clone(), &ss, &now, None)
+            .notification_message;
+        let now = now.add(chrono::Duration::seconds(30));
+        {
+            let mut address_space = trace_write_lock!(address_space);
+            let _ = address_space.set_variable_value(
+                var_node_id(0),
+                100,
+                &DateTime::from(now),
+                &DateTime::from(now),
+            );
+        }
+        let second = publish_and_tick(session.clone(), address_space.clone(), &ss, &now, None)
+            .notification_message;
+        assert_eq!(first.sequence_number, 1);
+        assert_eq!(second.sequence_number, 2);
+
+        // The first notification can be republished by its sequence number
+        let request = republish_request(subscription_id, first.sequence_number);
+        let response: RepublishResponse =
+            supported_message_as!(ss.republish(session.clone(), &request), RepublishResponse);
+        assert_eq!(response.notification_message, first);
+
+        // Only the lifetime count worth of notifications are retained, so once the subscription
+        // retains one, the next publish pushes out the older ones
+        {
+            let mut session = trace_write_lock!(session);
+            session
+                .subscriptions_mut()
+                .get_mut(subscription_id)
+                .unwrap()
+                .set_max_lifetime_count(1);
+        }
+        let now = now.add(chrono::Duration::seconds(30));
+        {
+            let mut address_space = trace_write_lock!(address_space);
+            let _ = address_space.set_variable_value(
+                var_node_id(0),
+                200,
+                &DateTime::from(now),
+                &DateTime::from(now),
+            );
+        }
+        let third = publish_and_tick(session.clone(), address_space.clone(), &ss, &now, None)
+            .notification_message;
+        for sequence_number in [first.sequence_number, second.sequence_number] {
+            let request = republish_request(subscription_id, sequence_number);
+            let response: ServiceFault =
+                supported_message_as!(ss.republish(session.clone(), &request), ServiceFault);
+            assert_eq!(
+                response.response_header.service_result,
+                StatusCode::BadMessageNotAvailable
+            );
+        }
+        let request = republish_request(subscription_id, third.sequence_number);
+        let response: RepublishResponse =
+            supported_message_as!(ss.republish(session.clone(), &request), RepublishResponse);
+        assert_eq!(response.notification_message, third);
+    })
+}
+
+#[test]
+fn too_many_subscriptions() {
+    do_subscription_service_test(|server_state, session, _, ss, _| {
+        {
+            let mut server_state = trace_write_lock!(server_state);
+            server_state.max_subscriptions = 2;
+        }
+        let current_subscription_count = || {
+            let server_state = trace_read_lock!(server_state);
+            let diagnostics = trace_read_lock!(server_state.diagnostics);
+            diagnostics
+                .server_diagnostics_summary()
+                .current_subscription_count
+        };
+        let count_before = current_subscription_count();
+
+        let subscription_id = create_subscription(server_state.clone(), session.clone(), &ss);
+        let _ = create_subscription(server_state.clone(), session.clone(), &ss);
+        assert_eq!(current_subscription_count(), count_before + 2);
+
+        // The session is at its limit
+        let request = create_subscription_request(0, 0);
+        let response = ss.create_subscription(server_state.clone(), session.clone(), &request);
+        let response = supported_message_as!(response, ServiceFault);
+        assert_eq!(
+            response.response_header.service_result,
+            StatusCode::BadTooManySubscriptions
+        );
+
+        // Deleting a subscription frees a slot
+        let request = DeleteSubscriptionsRequest {
+            request_header: RequestHeader::dummy(),
+            subscription_ids: Some(vec![subscription_id]),
+        };
+        let _ = ss.delete_subscriptions(session.clone(), &request);
+        assert_eq!(current_subscription_count(), count_before + 1);
+        let response = ss.create_subscription(
+            server_state.clone(),
+            session.clone(),
+            &create_subscription_request(0, 0),
+        );
+        let response = supported_message_as!(response, CreateSubscriptionResponse);
+        assert!(response.response_header.service_result.is_good());
+    });
+}
+
+#[test]
+fn publish_in_priority_order() {
+    do_subscription_service_test(|server_state, session, address_space, ss, mis| {
+        // Two subscriptions with a notification ready, the later one of higher priority
+        let low_priority = create_subscription(server_state.clone(), session.clone(), &ss);
+        let high_priority = create_subscription(server_state.clone(), session.clone(), &ss);
+        for (subscription_id, priority) in [(low_priority, 1), (high_priority, 200)] {
+            create_monitored_item(
+                subscription_id,
+                VariableId::Server_ServerStatus_StartTime,
+                server_state.clone(),
+                session.clone(),
+                address_space.clone(),
+                &mis,
+            );
+            let mut session = trace_write_lock!(session);
+            let subscription = session
+                .subscriptions_mut()
+                .get_mut(subscription_id)
+                .unwrap();
+            subscription.set_priority(priority);
+            subscription.set_state(SubscriptionState::Normal);
+        }
+
+        // Queue two publish requests and tick
+        let now = Utc::now();
+        for request_id in [1001, 1002] {
+            let _ = ss.async_publish(
+                &now,
+                session.clone(),
+                address_space.clone(),
+                request_id,
+                &publish_request(None),
+            );
+        }
+        let now = now.add(chrono::Duration::seconds(2));
+        let mut session = trace_write_lock!(session);
+        let address_space = trace_read_lock!(address_space);
+        let _ = session.tick_subscriptions(&now, &*address_space, TickReason::TickTimerFired);
+
+        // The high priority subscription answers the oldest request and is returned first
+        let responses = session
+            .subscriptions_mut()
+            .publish_response_queue()
+            .drain(..)
+            .map(|entry| {
+                let response = supported_message_as!(entry.response, PublishResponse);
+                assert!(response.notification_message.notification_data.is_some());
+                (entry.request_id, response.subscription_id)
+            })
+            .collect::<Vec<(u32, u32)>>();
+        assert_eq!(responses, vec![(1001, high_priority), (1002, low_priority)]);
+    })
+}
+
+#[test]
+fn shutdown_halts_publish_requests() {
+    do_subscription_service_test(|server_state, session, address_space, ss, _| {
+        let subscription_id = create_subscription(server_state.clone(), session.clone(), &ss);
+
+        // Queue two publish requests that wait for notifications
+        let now = Utc::now();
+        for request_id in [1001, 1002] {
+            let response = ss.async_publish(
+                &now,
+                session.clone(),
+                address_space.clone(),
+                request_id,
+                &publish_request(None),
+            );
+            assert!(response.is_none());
+        }
+
+        // A session on another secure channel is left to its own transport
+        let other_session = Arc::new(RwLock::new(Session::new(server_state.clone())));
+        {
+            let mut other_session = trace_write_lock!(other_session);
+            other_session.set_secure_channel_id(99);
+        }
+        let mut session_manager = SessionManager::default();
+        session_manager.register_session(session.clone());
+        session_manager.register_session(other_session.clone());
+
+        // Shutting down sends the subscription a final status change on the first request and
+        // answers the other with BadServerHalted
+        let secure_channel_id = {
+            let session = trace_read_lock!(session);
+            session.secure_channel_id()
+        };
+        let mut responses = session_manager
+            .shutdown_sessions(secure_channel_id, address_space)
+            .into_iter();
+        let entry = responses.next().unwrap();
+        assert_eq!(entry.request_id, 1001);
+        let response = supported_message_as!(entry.response, PublishResponse);
+        assert_eq!(response.subscription_id, subscription_id);
+        let status_change = response
+            .notification_message
+            .notification_data
+            .unwrap()
+            .remove(0)
+            .decode_inner::<StatusChangeNotification>(&DecodingOptions::test())
+            .unwrap();
+        assert_eq!(status_change.status, StatusCode::BadShutdown);
+        let entry = responses.next().unwrap();
+        assert_eq!(entry.request_id, 1002);
+        let response = supported_message_as!(entry.response, ServiceFault);
+        assert_eq!(
+            response.response_header.service_result,
+            StatusCode::BadServerHalted
+        );
+        assert!(responses.next().is_none());
+
+        // The other session is still open
+        assert_eq!(session_manager.len(), 1);
+        assert!(!session_manager.sessions_terminated());
+        assert!(!trace_read_lock!(other_session).is_terminated());
+
+        // The session is closed and its subscriptions are deleted
+        let session = trace_read_lock!(session);
+        assert!(session.is_terminated());
+        assert!(session.subscriptions().is_empty());
+    })
+}
+
+#[test]
+fn retransmission_queue_limit() {
+    do_subscription_service_test(|server_state, session, address_space, ss, _| {
+        let subscription_id_1 = create_subscription(server_state.clone(), session.clone(), &ss);
+        let subscription_id_2 = create_subscription(server_state.clone(), session.clone(), &ss);
+
+        // Fill the queue up to the limit with the first subscription's notifications, and add
+        // two older ones from the second subscription
+        let now = Utc::now();
+        let max = crate::server::constants::MAX_RETRANSMISSION_QUEUE_SIZE as u32;
+        let keep_alive = |sequence_number: u32, offset_ms: i64| {
+            NotificationMessage::keep_alive(
+                sequence_number,
+                DateTime::from(now + chrono::Duration::milliseconds(offset_ms)),
+            )
+        };
+        {
+            let mut session = trace_write_lock!(session);
+            let retransmission_queue = session.subscriptions_mut().retransmission_queue();
+            for sequence_number in 1..=max {
+                retransmission_queue.insert(
+                    (subscription_id_1, sequence_number),
+                    keep_alive(sequence_number, 10 + sequence_number as i64),
+                );
+            }
+            for sequence_number in 1..=2 {
+                retransmission_queue.insert(
+                    (subscription_id_2, sequence_number),
+                    keep_alive(sequence_number, sequence_number as i64),
+                );
+            }
+        }
+
+        // The oldest notifications are removed to bring the session back to the limit
+        let mut session = trace_write_lock!(session);
+        let address_space = trace_read_lock!(address_space);
+        session
+            .tick_subscriptions(&now, &*address_space, TickReason::TickTimerFired)
+            .unwrap();
+        let retransmission_queue = session.subscriptions_mut().retransmission_queue();
+        assert_eq!(retransmission_queue.len(), max as usize);
+        assert!(retransmission_queue
+            .keys()
+            .all(|(subscription_id, _)| *subscription_id == subscription_id_1));
+    })
+}
@@ -1,6 +1,6 @@
 use std::sync::Weak;
 
-use crate::server::services::view::ViewService;
+use crate::server::services::{attribute::AttributeService, view::ViewService};
 use crate::supported_message_as;
 use crate::sync::*;
 
@@ -228,6 +228,114 @@ fn browse_node_class_mask() {
     });
 }
 
+// This test applies a class mask that excludes objects, so only the variables come back
+#[test]
+fn browse_node_class_mask_excludes_objects() {
+    do_view_service_test(|server_state, session, address_space, vs| {
+        add_sample_vars_to_address_space(address_space.clone());
+
+        let nodes: Vec<NodeId> = vec![ObjectId::Server.into()];
+        let request = make_browse_request(
+            &nodes,
+            NodeClassMask::all() - NodeClassMask::OBJECT,
+            1000,
+            BrowseDirection::Forward,
+            ReferenceTypeId::HasComponent,
+        );
+
+        let response = vs.browse(server_state, session, address_space, &request);
+        let response = supported_message_as!(response, BrowseResponse);
+        let results = response.results.unwrap();
+        let references = results[0].references.as_ref().unwrap();
+
+        // Of the 12 HasComponent values under Server, 5 are not objects
+        assert_eq!(references.len(), 5);
+        references.iter().for_each(|r| {
+            assert_ne!(r.node_class, NodeClass::Object);
+            assert_eq!(r.reference_type_id, ReferenceTypeId::HasComponent.into());
+            assert!(r.is_forward);
+        });
+    });
+}
+
+// This test asks for only some of the fields of each reference description
+#[test]
+fn browse_result_mask() {
+    do_view_service_test(|server_state, session, address_space, vs| {
+        let nodes: Vec<NodeId> = vec![ObjectId::RootFolder.into()];
+        let mut request = make_browse_request(
+            &nodes,
+            NodeClassMask::empty(),
+            1000,
+            BrowseDirection::Forward,
+            ReferenceTypeId::Organizes,
+        );
+        request.nodes_to_browse.as_mut().unwrap()[0].result_mask =
+            (BrowseDescriptionResultMask::RESULT_MASK_NODE_CLASS
+                | BrowseDescriptionResultMask::RESULT_MASK_BROWSE_NAME)
+                .bits();
+
+        let response = vs.browse(server_state, session, address_space, &request);
+        let response = supported_message_as!(response, BrowseResponse);
+        let results = response.results.unwrap();
+        let references = results[0].references.as_ref().unwrap();
+        assert!(!references.is_empty());
+        references.iter().for_each(|r| {
+            assert!(!r.node_id.is_null());
+            assert_eq!(r.node_class, NodeClass::Object);
+            assert!(!r.browse_name.is_null());
+            assert!(r.reference_type_id.is_null());
+            assert_eq!(r.display_name, LocalizedText::null());
+            assert!(r.type_definition.is_null());
+        });
+    });
+}
+
+// This test browses with a reference type that is not a reference type, or a bad direction
+#[test]
+fn browse_invalid_reference_type_or_direction() {
+    do_view_service_test(|server_state, session, address_space, vs| {
+        let nodes: Vec<NodeId> = vec![ObjectId::RootFolder.into()];
+
+        let request = make_browse_request(
+            &nodes,
+            NodeClassMask::empty(),
+            1000,
+            BrowseDirection::Forward,
+            ObjectId::Server,
+        );
+        let response = vs.browse(
+            server_state.clone(),
+            session.clone(),
+            address_space.clone(),
+            &request,
+        );
+        let response = supported_message_as!(response, BrowseResponse);
+        let results = response.results.unwrap();
+        assert_eq!(
+            results[0].status_code,
+            StatusCode::BadReferenceTypeIdInvalid
+        );
+        assert!(results[0].references.is_none());
+
+        let request = make_browse_request(
+            &nodes,
+            NodeClassMask::empty(),
+            1000,
+            BrowseDirection::Invalid,
+            ReferenceTypeId::Organizes,
+        );
+        let response = vs.browse(server_state, session, address_space, &request);
+        let response = supported_message_as!(response, BrowseResponse);
+        let results = response.results.unwrap();
+        assert_eq!(
+            results[0].status_code,
+            StatusCode::BadBrowseDirectionInvalid
+        );
+        assert!(results[0].references.is_none());
+    });
+}
+
 fn verify_references(
     expected: &[(ReferenceTypeId, NodeId, bool)],
     references: &[ReferenceDescription],
@@ -731,7 +839,7 @@ fn browse_next_release_cp() {
         let _references = r1.references.as_ref().unwrap();
         assert!(!r1.continuation_point.is_null());
 
-        // Browse next and release the previous continuation points, expect Null result
+        // Browse next and release the previous continuation points, expect a result with no references
         let response = do_browse_next(
             &vs,
             session.clone(),
@@ -739,7 +847,10 @@ fn browse_next_release_cp() {
             &r1.continuation_point,
             true,
         );
-        assert!(response.results.is_none());
+        let r2 = &response.results.unwrap()[0];
+        assert_eq!(r2.status_code, StatusCode::Good);
+        assert!(r2.continuation_point.is_null());
+        assert!(r2.references.is_none());
 
         // Browse next again with same continuation point, expect BadContinuationPointInvalid
         let response = do_browse_next(
@@ -749,8 +860,54 @@ fn browse_next_release_cp() {
             &r1.continuation_point,
             false,
         );
-        let r1 = &response.results.unwrap()[0];
-        assert_eq!(r1.status_code, StatusCode::BadContinuationPointInvalid);
+        let r3 = &response.results.unwrap()[0];
+        assert_eq!(r3.status_code, StatusCode::BadContinuationPointInvalid);
+
+        // Releasing it again should also say it is invalid
+        let response = do_browse_next(
+            vs,
+            session.clone(),
+            address_space.clone(),
+            &r1.continuation_point,
+            true,
+        );
+        let r3 = &response.results.unwrap()[0];
+        assert_eq!(r3.status_code, StatusCode::BadContinuationPointInvalid);
+    });
+}
+
+#[test]
+fn browse_next_one_at_a_time() {
+    // Browse with requested_max_references_per_node = 1 and page through the rest one by one
+    do_view_service_test(|server_state, session, address_space, vs| {
+        let parent_node_id = add_many_vars_to_address_space(address_space.clone(), 5).0;
+        let nodes = vec![parent_node_id.clone()];
+        let response = do_browse(
+            vs,
+            server_state,
+            session.clone(),
+            address_space.clone(),
+            &nodes,
+            1,
+            BrowseDirection::Forward,
+        );
+        let mut result = response.results.unwrap().remove(0);
+        verify_references_to_many_vars(result.references.as_ref().unwrap(), 1, 0);
+
+        for i in 1..5 {
+            assert!(!result.continuation_point.is_null());
+            let response = do_browse_next(
+                vs,
+                session.clone(),
+                address_space.clone(),
+                &result.continuation_point,
+                false,
+            );
+            result = response.results.unwrap().remove(0);
+            assert_eq!(result.status_code, StatusCode::Good);
+            verify_references_to_many_vars(result.references.as_ref().unwrap(), 1, i);
+        }
+        assert!(result.continuation_point.is_null());
     });
 }
 
@@ -894,6 +1051,101 @@ fn translate_browse_paths_to_node_ids() {
     });
 }
 
+#[test]
+fn translate_browse_paths_to_node_ids_two_hops() {
+    do_view_service_test(|server_state, _session, address_space, vs| {
+        // Two devices with the same browse name, each with a temperature sensor attached through a
+        // custom reference type that is a subtype of HasComponent.
+        let has_sensor = NodeId::new(1, "HasSensor");
+        let has_actuator = NodeId::new(1, "HasActuator");
+        let devices = [NodeId::new(1, "Device1"), NodeId::new(1, "Device2")];
+        let sensors = [NodeId::new(1, "Sensor1"), NodeId::new(1, "Sensor2")];
+        {
+            let mut address_space = trace_write_lock!(address_space);
+            ReferenceTypeBuilder::new(&has_sensor, "HasSensor", "HasSensor")
+                .subtype_of(ReferenceTypeId::HasComponent)
+                .insert(&mut address_space);
+            ReferenceTypeBuilder::new(&has_actuator, "HasActuator", "HasActuator")
+                .subtype_of(ReferenceTypeId::HasComponent)
+                .insert(&mut address_space);
+            devices
+                .iter()
+                .zip(sensors.iter())
+                .for_each(|(device, sensor)| {
+                    ObjectBuilder::new(device, QualifiedName::new(1, "Device"), "Device")
+                        .organized_by(ObjectId::ObjectsFolder)
+                        .insert(&mut address_space);
+                    VariableBuilder::new(
+                        sensor,
+                        QualifiedName::new(1, "Temperature"),
+                        "Temperature",
+                    )
+                    .data_type(DataTypeId::Double)
+                    .value(20f64)
+                    .insert(&mut address_space);
+                    address_space.insert_reference(device, sensor, &has_sensor);
+                });
+        }
+
+        let translate = |reference_type_id: NodeId, include_subtypes: bool| {
+            let element = |reference_type_id: NodeId, target_name: &str| RelativePathElement {
+                reference_type_id,
+                is_inverse: false,
+                include_subtypes,
+                target_name: QualifiedName::new(1, target_name),
+            };
+            let request = TranslateBrowsePathsToNodeIdsRequest {
+                request_header: make_request_header(),
+                browse_paths: Some(vec![BrowsePath {
+                    starting_node: ObjectId::ObjectsFolder.into(),
+                    relative_path: RelativePath {
+                        elements: Some(vec![
+                            element(ReferenceTypeId::Organizes.into(), "Device"),
+                            element(reference_type_id, "Temperature"),
+                        ]),
+                    },
+                }]),
+            };
+            let response = vs.translate_browse_paths_to_node_ids(
+                server_state.clone(),
+                address_space.clone(),
+                &request,
+            );
+            let response: TranslateBrowsePathsToNodeIdsResponse =
+                supported_message_as!(response, TranslateBrowsePathsToNodeIdsResponse);
+            response.results.unwrap().remove(0)
+        };
+
+        let assert_both_sensors = |result: BrowsePathResult| {
+            assert_eq!(result.status_code, StatusCode::Good);
+            let mut targets = result
+                .targets
+                .unwrap()
+                .into_iter()
+                .map(|t| {
+                    assert_eq!(t.remaining_path_index, u32::MAX);
+                    t.target_id.node_id
+                })
+                .collect::<Vec<_>>();
+            targets.sort_by_key(|n| n.to_string());
+            assert_eq!(targets, sensors.to_vec());
+        };
+
+        // Exact match on the custom reference type, with both devices matching the first hop
+        assert_both_sensors(translate(has_sensor.clone(), false));
+
+        // The custom type is only found through a supertype when subtypes are included
+        assert_both_sensors(translate(ReferenceTypeId::HasComponent.into(), true));
+        let result = translate(ReferenceTypeId::HasComponent.into(), false);
+        assert_eq!(result.status_code, StatusCode::BadNoMatch);
+        assert!(result.targets.is_none());
+
+        // An unrelated custom reference type matches nothing
+        let result = translate(has_actuator, true);
+        assert_eq!(result.status_code, StatusCode::BadNoMatch);
+    });
+}
+
 #[test]
 fn translate_browse_paths_to_node_ids2() {
     do_view_service_test(|server_state, _session, address_space, vs| {
@@ -1188,3 +1440,84 @@ fn unregister_nodes() {
         assert_eq!(response.response_header.service_result, StatusCode::Good);
     });
 }
+
+#[test]
+fn registered_node_read() {
+    do_view_service_test(|server_state, session, address_space, vs| {
+        // The callback aliases this node
+        let node_id = NodeId::new(1, 100);
+        {
+            let mut address_space = trace_write_lock!(address_space);
+            let _ = VariableBuilder::new(&node_id, var_name(0), "")
+                .data_type(DataTypeId::Int32)
+                .value(77i32)
+                .organized_by(ObjectId::RootFolder)
+                .insert(&mut address_space);
+        }
+        {
+            let mut server_state = trace_write_lock!(server_state);
+            server_state.set_register_nodes_callbacks(
+                Box::new(RegisterNodesImpl {
+                    session: Weak::new(),
+                }),
+                Box::new(UnregisterNodesImpl {}),
+            );
+        }
+
+        let response = vs.register_nodes(
+            server_state.clone(),
+            session.clone(),
+            &RegisterNodesRequest {
+                request_header: make_request_header(),
+                nodes_to_register: Some(vec![node_id.clone()]),
+            },
+        );
+        let response: RegisterNodesResponse =
+            supported_message_as!(response, RegisterNodesResponse);
+        let registered_node_id = response.registered_node_ids.unwrap()[0].clone();
+        assert_eq!(registered_node_id, NodeId::new(1, 200));
+
+        let read = |server_state, session| {
+            let request = ReadRequest {
+                request_header: make_request_header(),
+                max_age: 0f64,
+                timestamps_to_return: TimestampsToReturn::Neither,
+                nodes_to_read: Some(vec![ReadValueId {
+                    node_id: registered_node_id.clone(),
+                    attribute_id: AttributeId::Value as u32,
+                    index_range: UAString::null(),
+                    data_encoding: QualifiedName::null(),
+                }]),
+            };
+            let response = AttributeService::new().read(
+                server_state,
+                session,
+                address_space.clone(),
+                &request,
+            );
+            let response: ReadResponse = supported_message_as!(response, ReadResponse);
+            response.results.unwrap().remove(0)
+        };
+
+        // Reading the registered node id reads the original node
+        let value = read(server_state.clone(), session.clone());
+        assert_eq!(value.value, Some(Variant::Int32(77)));
+
+        // Unregister the alias along with a node id that was never registered
+        let response = vs.unregister_nodes(
+            server_state.clone(),
+            session.clone(),
+            &UnregisterNodesRequest {
+                request_header: make_request_header(),
+                nodes_to_unregister: Some(vec![registered_node_id.clone(), NodeId::new(1, 300)]),
+            },
+        );
+        let response: UnregisterNodesResponse =
+            supported_message_as!(response, UnregisterNodesResponse);
+        assert_eq!(response.response_header.service_result, StatusCode::Good);
+
+        // The alias no longer resolves
+        let value = read(server_state, session);
+        assert_eq!(value.status, Some(StatusCode::BadNodeIdUnknown));
+    });
+}
@@ -90,6 +90,12 @@ impl From<&str> for LiteralOperand {
     }
 }
 
+impl From<NodeId> for LiteralOperand {
+    fn from(v: NodeId) -> Self {
+        Self::from(Variant::from(v))
+    }
+}
+
 impl From<()> for LiteralOperand {
     fn from(_v: ()) -> Self {
         Self::from(Variant::from(()))
@@ -407,6 +413,13 @@ impl ContentFilterBuilder {
         self.add_element(FilterOperator::BitwiseOr, vec![o1.into(), o2.into()])
     }
 
+    pub fn of_type<T>(self, o1: T) -> Self
+    where
+        T: Into<Operand>,
+    {
+        self.add_element(FilterOperator::OfType, vec![o1.into()])
+    }
+
     pub fn build(self) -> ContentFilter {
         ContentFilter {
             elements: Some(self.elements),
@@ -257,6 +257,91 @@ fn index_of_string() {
     assert_eq!(r, StatusCode::BadIndexRangeNoData);
 }
 
+#[test]
+fn index_of_matrix() {
+    // A 3x2 matrix of [[0, 1], [2, 3], [4, 5]]
+    let vars: Vec<Variant> = (0..6).map(Variant::from).collect();
+    let v = Variant::from((VariantTypeId::Int32, vars, vec![3u32, 2u32]));
+
+    // Rows 0 to 2, column 1
+    let r = v
+        .range_of(NumericRange::from_str("0:2,1").unwrap())
+        .unwrap();
+    match r {
+        Variant::Array(array) => {
+            assert_eq!(array.dimensions, Some(vec![3, 1]));
+            assert_eq!(
+                array.values,
+                vec![Variant::Int32(1), Variant::Int32(3), Variant::Int32(5)]
+            );
+        }
+        _ => panic!(),
+    }
+
+    // Rows 1 to 200 are clamped to the last row, all columns
+    let r = v
+        .range_of(NumericRange::from_str("1:200,0:1").unwrap())
+        .unwrap();
+    match r {
+        Variant::Array(array) => {
+            assert_eq!(array.dimensions, Some(vec![2, 2]));
+            assert_eq!(
+                array.values,
+                vec![
+                    Variant::Int32(2),
+                    Variant::Int32(3),
+                    Variant::Int32(4),
+                    Variant::Int32(5)
+                ]
+            );
+        }
+        _ => panic!(),
+    }
+
+    // Out of bounds
+    let r = v
+        .range_of(NumericRange::from_str("3:4,0").unwrap())
+        .unwrap_err();
+    assert_eq!(r, StatusCode::BadIndexRangeNoData);
+
+    // Wrong number of dimensions
+    let r = v
+        .range_of(NumericRange::from_str("0,0,0").unwrap())
+        .unwrap_err();
+    assert_eq!(r, StatusCode::BadIndexRangeNoData);
+}
+
+#[test]
+fn index_of_string_array() {
+    let vars: Vec<Variant> = ["Hello", "World", "Again"]
+        .iter()
+        .map(|v| Variant::from(*v))
+        .collect();
+    let v = Variant::from((VariantTypeId::String, vars));
+
+    // Elements 1 to 2, characters 0 to 2 of each
+    let r = v
+        .range_of(NumericRange::from_str("1:2,0:2").unwrap())
+        .unwrap();
+    match r {
+        Variant::Array(array) => {
+            assert_eq!(
+                array.values,
+                vec![Variant::from("Wor"), Variant::from("Aga")]
+            );
+        }
+        _ => panic!(),
+    }
+
+    // Multiple ranges on a non-string single dimension array
+    let vars: Vec<Variant> = [1, 2, 3].iter().map(|v| Variant::from(*v)).collect();
+    let v = Variant::from((VariantTypeId::Int32, vars));
+    let r = v
+        .range_of(NumericRange::from_str("0:1,0:1").unwrap())
+        .unwrap_err();
+    assert_eq!(r, StatusCode::BadIndexRangeNoData);
+}
+
 fn ensure_conversion_fails(v: &Variant, convert_to: &[VariantTypeId]) {
     convert_to
         .iter()
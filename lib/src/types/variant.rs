@@ -1639,11 +1639,110 @@ impl Variant {
                     _ => Err(StatusCode::BadIndexRangeNoData),
                 }
             }
-            NumericRange::MultipleRanges(_ranges) => {
-                // Not yet supported
-                error!("Multiple ranges not supported");
-                Err(StatusCode::BadIndexRangeNoData)
+            NumericRange::MultipleRanges(ranges) => match self {
+                Variant::Array(array) => Self::array_range_of_multi(array, &ranges),
+                _ => Err(StatusCode::BadIndexRangeNoData),
+            },
+        }
+    }
+
+    /// Gets the sub-array described by the multiple ranges. A multi-dimensional array takes one
+    /// range per dimension. A single dimension array of strings or byte strings takes a range for
+    /// the array and a second range that is applied to each element of it.
+    fn array_range_of_multi(array: &Array, ranges: &[NumericRange]) -> Result<Variant, StatusCode> {
+        let bounds = |range: &NumericRange| match range {
+            NumericRange::Index(idx) => Ok((*idx as usize, *idx as usize)),
+            NumericRange::Range(min, max) => Ok((*min as usize, *max as usize)),
+            _ => Err(StatusCode::BadIndexRangeInvalid),
+        };
+
+        let dimensions = match array.dimensions {
+            Some(ref dimensions) if dimensions.len() > 1 => dimensions.clone(),
+            _ => {
+                if ranges.len() == 2
+                    && matches!(
+                        array.value_type,
+                        VariantTypeId::String | VariantTypeId::ByteString
+                    )
+                {
+                    let (min, max) = bounds(&ranges[0])?;
+                    if min >= array.values.len() {
+                        return Err(StatusCode::BadIndexRangeNoData);
+                    }
+                    let max = max.min(array.values.len() - 1);
+                    let (sub_min, sub_max) = bounds(&ranges[1])?;
+                    let values = array.values[min..=max]
+                        .iter()
+                        .map(|v| v.substring(sub_min, sub_max))
+                        .collect::<Result<Vec<Variant>, StatusCode>>()?;
+                    return Ok(Variant::from((array.value_type, values)));
+                }
+                error!("Multiple ranges require an array with matching dimensions");
+                return Err(StatusCode::BadIndexRangeNoData);
             }
+        };
+        if dimensions.len() != ranges.len() {
+            error!(
+                "Number of ranges {} does not match array dimensions {}",
+                ranges.len(),
+                dimensions.len()
+            );
+            return Err(StatusCode::BadIndexRangeNoData);
         }
+
+        // Clamp each range to its dimension, the min must be in range
+        let bounds = ranges
+            .iter()
+            .zip(dimensions.iter())
+            .map(|(range, dimension)| {
+                let (min, max) = bounds(range)?;
+                let dimension = *dimension as usize;
+                if min >= dimension {
+                    Err(StatusCode::BadIndexRangeNoData)
+                } else {
+                    Ok((min, max.min(dimension - 1)))
+                }
+            })
+            .collect::<Result<Vec<(usize, usize)>, StatusCode>>()?;
+
+        // Stride of each dimension within the flat values, higher rank dimensions come first
+        let mut strides = vec![1usize; dimensions.len()];
+        for i in (0..dimensions.len() - 1).rev() {
+            strides[i] = strides[i + 1] * dimensions[i + 1] as usize;
+        }
+
+        // Walk every index within the bounds in the order the values are stored
+        let mut values = Vec::new();
+        let mut index: Vec<usize> = bounds.iter().map(|(min, _)| *min).collect();
+        'outer: loop {
+            let offset: usize = index.iter().zip(strides.iter()).map(|(i, s)| i * s).sum();
+            values.push(
+                array
+                    .values
+                    .get(offset)
+                    .cloned()
+                    .ok_or(StatusCode::BadIndexRangeNoData)?,
+            );
+            let mut d = index.len();
+            loop {
+                if d == 0 {
+                    break 'outer;
+                }
+                d -= 1;
+                if index[d] < bounds[d].1 {
+                    index[d] += 1;
+                    break;
+                }
+                index[d] = bounds[d].0;
+            }
+        }
+
+        let dimensions: Vec<u32> = bounds
+            .iter()
+            .map(|(min, max)| (max - min + 1) as u32)
+            .collect();
+        Array::new_multi(array.value_type, values, dimensions)
+            .map(Variant::from)
+            .map_err(|_| StatusCode::BadIndexRangeNoData)
     }
 }
@@ -23,7 +23,7 @@ pub struct EventProvider;
 impl HistoricalDataProvider for DataProvider {
     fn read_raw_modified_details(
         &self,
-        _address_space: Arc<RwLock<AddressSpace>>,
+        _address_space: Arc<RwLock<dyn AddressSpaceProvider>>,
         _request: ReadRawModifiedDetails,
         _timestamps_to_return: TimestampsToReturn,
         _release_continuation_points: bool,
@@ -114,7 +114,7 @@ fn main() {
         log4rs::init_file("log4rs.yaml", Default::default()).unwrap();
 
         // Create an OPC UA server with sample configuration and default node set
-        let mut server = Server::new(ServerConfig::load(&args.config_path).unwrap());
+        let mut server = Server::new(ServerConfig::try_load(&args.config_path).unwrap());
 
         let ns = {
             let address_space = server.address_space();
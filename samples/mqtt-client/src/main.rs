@@ -118,7 +118,7 @@ async fn main() -> Result<(), ()> {
 
     // Use the sample client config to set up a client. The sample config has a number of named
     // endpoints one of which is marked as the default.
-    let mut client = Client::new(ClientConfig::load(&PathBuf::from(config_file)).unwrap());
+    let mut client = Client::new(ClientConfig::try_load(&PathBuf::from(config_file)).unwrap());
     let endpoint_id: Option<&str> = if !endpoint_id.is_empty() {
         Some(&endpoint_id)
     } else {